@@ -0,0 +1,124 @@
+// 规则应用报告
+//
+// 一批规则在同一个 WFP 事务中应用：任何一个过滤器失败都会回滚整个事务，
+// 报告逐条规则、逐个层记录每个过滤器的结果。
+
+use std::fmt;
+use uuid::Uuid;
+use windows::core::GUID;
+use crate::astral_wfp::layer_name;
+use crate::error::AstralError;
+use crate::plan::EnforcementMode;
+
+// 单个层上过滤器的结果
+#[derive(Debug, Clone, PartialEq)]
+pub enum LayerStatus {
+    Applied(u64),          // 已添加并提交
+    RolledBack(u64),       // 添加成功，但事务因其他错误被回滚
+    Failed(AstralError),  // 添加失败，导致事务回滚
+    Skipped,               // 因之前的错误未尝试
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct LayerReport {
+    pub layer_key: GUID,
+    pub status: LayerStatus,
+    pub replaced: Option<u64>, // 被替换的同一规则的旧过滤器
+}
+
+// 一条规则的结果
+#[derive(Debug, Clone, PartialEq)]
+pub struct RuleReport {
+    pub rule_id: Uuid,
+    pub rule_name: String,
+    pub error: Option<AstralError>, // 编译（验证）失败时的错误，此时没有层报告
+    pub layers: Vec<LayerReport>,
+}
+
+impl RuleReport {
+    pub fn is_applied(&self) -> bool {
+        self.error.is_none() && self.layers.iter().all(|l| matches!(l.status, LayerStatus::Applied(_)))
+    }
+}
+
+// 一批规则的应用结果
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct ApplyReport {
+    pub rules: Vec<RuleReport>,
+    pub committed: bool,
+    pub mode: EnforcementMode,
+    pub removed: Vec<u64>, // 同一事务中删除的过滤器（已提交时）
+}
+
+impl ApplyReport {
+    // 已提交的过滤器ID
+    pub fn filter_ids(&self) -> Vec<u64> {
+        self.rules
+            .iter()
+            .flat_map(|r| &r.layers)
+            .filter_map(|l| match l.status {
+                LayerStatus::Applied(id) => Some(id),
+                _ => None,
+            })
+            .collect()
+    }
+
+    // 按规则ID查找规则的结果
+    pub fn rule(&self, rule_id: &Uuid) -> Option<&RuleReport> {
+        self.rules.iter().find(|r| r.rule_id == *rule_id)
+    }
+
+    // 导致整批失败的第一个错误
+    pub fn first_error(&self) -> Option<&AstralError> {
+        self.rules.iter().find_map(|r| {
+            r.error.as_ref().or_else(|| {
+                r.layers.iter().find_map(|l| match &l.status {
+                    LayerStatus::Failed(e) => Some(e),
+                    _ => None,
+                })
+            })
+        })
+    }
+
+    // 回滚后把已添加的过滤器标记为 RolledBack，未尝试的层标记为 Skipped
+    pub(crate) fn mark_rolled_back(&mut self) {
+        self.committed = false;
+        for layer in self.rules.iter_mut().flat_map(|r| &mut r.layers) {
+            if let LayerStatus::Applied(id) = layer.status {
+                layer.status = LayerStatus::RolledBack(id);
+            }
+        }
+    }
+}
+
+impl fmt::Display for ApplyReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.committed {
+            writeln!(f, "✅ 事务已提交，共添加 {} 个过滤器（执行模式: {}）", self.filter_ids().len(), self.mode)?;
+            if !self.removed.is_empty() {
+                writeln!(f, "删除 {} 个过滤器: {:?}", self.removed.len(), self.removed)?;
+            }
+        } else {
+            writeln!(f, "❌ 事务已回滚，引擎状态未改变")?;
+        }
+        for rule in &self.rules {
+            writeln!(f, "规则 '{}' ({}):", rule.rule_name, rule.rule_id)?;
+            if let Some(e) = &rule.error {
+                writeln!(f, "  ❌ {}", e)?;
+            }
+            for layer in &rule.layers {
+                let name = layer_name(&layer.layer_key);
+                match &layer.status {
+                    LayerStatus::Applied(id) => match layer.replaced {
+                        Some(old_id) => writeln!(f, "  ✓ {} (ID: {}, 替换 {})", name, id, old_id)?,
+                        None => writeln!(f, "  ✓ {} (ID: {})", name, id)?,
+                    },
+                    LayerStatus::RolledBack(id) => writeln!(f, "  ↩ {} (ID: {}, 已回滚)", name, id)?,
+                    LayerStatus::Failed(e) => writeln!(f, "  ❌ {}: {}", name, e)?,
+                    LayerStatus::Skipped => writeln!(f, "  - {} (未尝试)", name)?,
+                }
+            }
+        }
+        Ok(())
+    }
+}
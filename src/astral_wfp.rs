@@ -1,17 +1,30 @@
-use std::ffi::OsStr;
-use std::os::windows::ffi::OsStrExt;
-use std::ptr;
-use std::net::{IpAddr, Ipv4Addr, Ipv6Addr}; // 移除未使用的导入 Ipv4Addr 和 Ipv6Addr
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
 use std::fmt;
 use std::time::{SystemTime, UNIX_EPOCH};
 use std::fs;
 use std::path::Path;
 use std::str::FromStr;
 use serde::{Serialize, Deserialize};
+use uuid::Uuid;
+use tracing::{debug, debug_span, error, info, info_span, warn};
 use windows::{
-    Win32::Foundation::*, Win32::NetworkManagement::WindowsFilteringPlatform::*,
-    Win32::System::Rpc::*, core::*,
+    Win32::Foundation::FWP_E_FILTER_NOT_FOUND, Win32::NetworkManagement::WindowsFilteringPlatform::*,
+    core::GUID,
 };
+use crate::apply::{ApplyReport, LayerReport, LayerStatus, RuleReport};
+use crate::backend::{DefaultBackend, FilterScope, FirewallBackend};
+use crate::error::{AstralError, Language, Message, Result};
+use crate::message;
+use crate::drift::{detect_drift, rebuild_recorded, AuditReport, DriftEvent, DriftKind, HealPolicy};
+use crate::metadata::{group_records, InstalledRule, RuleMetadata};
+use crate::icmp::IcmpType;
+use crate::ipset::AddressList;
+use crate::ports::{PortEntry, PortList};
+use crate::services;
+use crate::plan::{layers_for_rule, EnforcementMode, FilterSpec, PlanCompiler};
+use crate::provider::ProviderConfig;
+use crate::reconcile::{ReconcilePlan, ReconcileStep};
+use crate::validation::{validate_rule, validate_rules, Diagnostic};
 
 // CIDR网段结构体
 #[derive(Debug, Clone)]
@@ -24,14 +37,16 @@ impl IpNetwork {
     pub fn new(ip: IpAddr, prefix_len: u8) -> Self {
         Self { ip, prefix_len }
     }
-      pub fn from_cidr(cidr: &str) -> std::result::Result<Self, String> {
+      pub fn from_cidr(cidr: &str) -> Result<Self> {
         let parts: Vec<&str> = cidr.split('/').collect();
         if parts.len() != 2 {
-            return Err("Invalid CIDR format".to_string());
+            return Err(AstralError::parse(Message::plain(cidr), message!("无效的CIDR格式", "invalid CIDR format")));
         }
         
-        let ip: IpAddr = parts[0].parse().map_err(|_| "Invalid IP address")?;
-        let prefix_len: u8 = parts[1].parse().map_err(|_| "Invalid prefix length")?;
+        let ip: IpAddr = parts[0].parse().map_err(|_| AstralError::parse(Message::plain(cidr), message!("无效的IP地址", "invalid IP address")))?;
+        let prefix_len: u8 = parts[1]
+            .parse()
+            .map_err(|_| AstralError::parse(Message::plain(cidr), message!("无效的前缀长度", "invalid prefix length")))?;
         
         // 验证前缀长度
         let max_prefix = match ip {
@@ -40,26 +55,16 @@ impl IpNetwork {
         };
         
         if prefix_len > max_prefix {
-            return Err(format!("Prefix length {} exceeds maximum {}", prefix_len, max_prefix));
+            return Err(AstralError::parse(
+                Message::plain(cidr),
+                message!("前缀长度 {} 超过最大值 {}", "prefix length {} exceeds maximum {}", prefix_len, max_prefix),
+            ));
         }
         
-        // 将IP地址转换为正确的网络地址
+        // 将IP地址转换为正确的网络地址（清除主机位）
         let network_ip = match ip {
-            IpAddr::V4(ipv4) => {
-                let ip_bytes = ipv4.octets();
-                let ip_u32 = u32::from_be_bytes(ip_bytes);
-                let mask = if prefix_len == 0 {
-                    0u32
-                } else if prefix_len == 32 {
-                    u32::MAX
-                } else {
-                    !((1u32 << (32 - prefix_len)) - 1)
-                };
-                let network_u32 = ip_u32 & mask;
-                let network_bytes = network_u32.to_be_bytes();
-                IpAddr::V4(std::net::Ipv4Addr::from(network_bytes))
-            },
-            IpAddr::V6(_) => ip, // IPv6 处理复杂，暂时保持原样
+            IpAddr::V4(ipv4) => IpAddr::V4(Ipv4Addr::from(u32::from(ipv4) & v4_mask(prefix_len))),
+            IpAddr::V6(ipv6) => IpAddr::V6(Ipv6Addr::from(u128::from(ipv6) & v6_mask(prefix_len))),
         };
         
         Ok(Self::new(network_ip, prefix_len))
@@ -68,52 +73,44 @@ impl IpNetwork {
     pub fn contains(&self, ip: &IpAddr) -> bool {
         match (self.ip, ip) {
             (IpAddr::V4(network_ip), IpAddr::V4(test_ip)) => {
-                let mask = !((1u32 << (32 - self.prefix_len)) - 1);
-                let network_addr = u32::from(network_ip) & mask;
-                let test_addr = u32::from(*test_ip) & mask;
-                network_addr == test_addr
+                let mask = v4_mask(self.prefix_len);
+                u32::from(network_ip) & mask == u32::from(*test_ip) & mask
             }
             (IpAddr::V6(network_ip), IpAddr::V6(test_ip)) => {
-                let network_bytes = network_ip.octets();
-                let test_bytes = test_ip.octets();
-                let prefix_bytes = self.prefix_len / 8;
-                let prefix_bits = self.prefix_len % 8;
-                
-                // 比较完整字节
-                for i in 0..prefix_bytes as usize {
-                    if network_bytes[i] != test_bytes[i] {
-                        return false;
-                    }
-                }
-                
-                // 比较部分字节
-                if prefix_bits > 0 {
-                    let mask = 0xFF << (8 - prefix_bits);
-                    let network_byte = network_bytes[prefix_bytes as usize] & mask;
-                    let test_byte = test_bytes[prefix_bytes as usize] & mask;
-                    if network_byte != test_byte {
-                        return false;
-                    }
-                }
-                
-                true
+                let mask = v6_mask(self.prefix_len);
+                u128::from(network_ip) & mask == u128::from(*test_ip) & mask
             }
             _ => false, // IPv4 vs IPv6 不匹配
         }
     }
 }
 
-// WFP 常量定义
-const FWP_ACTION_BLOCK: u32 = 0x00000001 | 0x00001000;
-const FWP_ACTION_PERMIT: u32 = 0x00000002 | 0x00001000;
-static mut WEIGHT_VALUE: u64 = 1000;
-static mut EFFECTIVE_WEIGHT_VALUE: u64 = 0;
+impl fmt::Display for IpNetwork {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}/{}", self.ip, self.prefix_len)
+    }
+}
+
+// 前缀长度对应的掩码，/0 时为全0
+pub(crate) fn v4_mask(prefix_len: u8) -> u32 {
+    u32::MAX.checked_shl(32 - prefix_len.min(32) as u32).unwrap_or(0)
+}
+
+pub(crate) fn v6_mask(prefix_len: u8) -> u128 {
+    u128::MAX.checked_shl(128 - prefix_len.min(128) as u32).unwrap_or(0)
+}
 
 // 缓存结构体，用于提高性能
 #[derive(Debug, Clone)]
 pub struct FilterCache {
     pub app_path_cache: std::collections::HashMap<String, String>, // 原始路径 -> NT路径
-    pub layer_cache: std::collections::HashMap<String, Vec<GUID>>, // 规则签名 -> 层列表
+    pub layer_cache: std::collections::HashMap<Uuid, Vec<GUID>>,   // 规则ID -> 层列表
+}
+
+impl Default for FilterCache {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl FilterCache {
@@ -138,87 +135,297 @@ impl FilterCache {
     }
 }
 
-// 过滤规则结构体
-#[derive(Debug, Clone)]
-// 过滤规则结构体
+// 过滤规则结构体。地址和端口在构建或反序列化时解析，规则中只保存解析后的列表；
+// 规则本身就是配置文件和过滤器元数据的格式，读取时经过 FilterRuleFile 兼容旧版配置
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(from = "FilterRuleFile")]
 pub struct FilterRule {
+    pub id: Uuid,                            // 创建时分配的规则ID，与名称无关；过滤器的 filterKey 由它生成
     pub name: String,                        // 规则名称
     pub app_path: Option<String>,            // 应用程序路径（可选）
-    pub local: Option<String>,    // 本地IP地址/网段，格式如: "192.168.1.1" 或 "192.168.1.0/24"（可选）
-    pub remote: Option<String>,   // 远程IP地址/网段，格式如: "8.8.8.8" 或 "8.8.0.0/16"（可选）
-    pub local_port: Option<u16>,             // 本地端口（可选）
-    pub remote_port: Option<u16>,            // 远程端口（可选）
-    pub local_port_range: Option<(u16, u16)>, // 本地端口范围（可选）
-    pub remote_port_range: Option<(u16, u16)>, // 远程端口范围（可选）
+    pub local: Option<AddressList>,          // 本地地址列表，如 "192.168.1.0/24, !192.168.1.1"（可选）
+    pub remote: Option<AddressList>,         // 远程地址列表（可选）
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub local_ports: Option<PortList>,       // 本地端口列表，如 "80,443,8000-8100,!8080"（可选）
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub remote_ports: Option<PortList>,      // 远程端口列表（可选）
     pub protocol: Option<Protocol>,          // 协议类型（可选）
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub icmp_type: Option<IcmpType>,         // ICMP/ICMPv6 类型（可选，需要协议为 ICMP 或 ICMPv6）
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub icmp_code: Option<u8>,               // ICMP/ICMPv6 代码（可选，需要同时设置类型）
     pub direction: Direction,                // 流量方向
     pub action: FilterAction,                // 过滤动作（允许/阻止）
     pub priority: u32,                       // 规则优先级（数字越大优先级越高）
     pub group: Option<String>,               // 规则分组
     pub enabled: bool,                       // 规则是否启用
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub time_control: Option<TimeControl>,   // 时间控制
     pub description: Option<String>,         // 规则描述
 }
 
-#[derive(Debug, Clone, PartialEq)]
+// 配置文件中的规则。旧版配置总是同时写出 local_port、local_port_range 等四个键（未设置时为 null），
+// 它们各自读取后合并到端口列表中，不能作为同一字段的别名
+#[derive(Deserialize)]
+struct FilterRuleFile {
+    #[serde(default = "Uuid::new_v4", deserialize_with = "deserialize_rule_id")]
+    id: Uuid,
+    name: String,
+    app_path: Option<String>,
+    #[serde(alias = "local_ip")]
+    local: Option<AddressList>,
+    #[serde(alias = "remote_ip")]
+    remote: Option<AddressList>,
+    #[serde(default)]
+    local_ports: Option<PortList>,
+    #[serde(default)]
+    remote_ports: Option<PortList>,
+    #[serde(default)]
+    local_port: Option<PortList>,
+    #[serde(default)]
+    remote_port: Option<PortList>,
+    #[serde(default)]
+    local_port_range: Option<PortList>,
+    #[serde(default)]
+    remote_port_range: Option<PortList>,
+    protocol: Option<Protocol>,
+    #[serde(default)]
+    icmp_type: Option<IcmpType>,
+    #[serde(default)]
+    icmp_code: Option<u8>,
+    direction: Direction,
+    action: FilterAction,
+    priority: u32,
+    group: Option<String>,
+    enabled: bool,
+    #[serde(default)]
+    time_control: Option<TimeControl>,
+    description: Option<String>,
+}
+
+impl From<FilterRuleFile> for FilterRule {
+    fn from(file: FilterRuleFile) -> Self {
+        let merge = |lists: [Option<PortList>; 3]| {
+            let entries: Vec<PortEntry> = lists.into_iter().flatten().flat_map(|list| list.entries).collect();
+            (!entries.is_empty()).then_some(PortList { entries })
+        };
+        FilterRule {
+            id: file.id,
+            name: file.name,
+            app_path: file.app_path,
+            local: file.local,
+            remote: file.remote,
+            local_ports: merge([file.local_ports, file.local_port, file.local_port_range]),
+            remote_ports: merge([file.remote_ports, file.remote_port, file.remote_port_range]),
+            protocol: file.protocol,
+            icmp_type: file.icmp_type,
+            icmp_code: file.icmp_code,
+            direction: file.direction,
+            action: file.action,
+            priority: file.priority,
+            group: file.group,
+            enabled: file.enabled,
+            time_control: file.time_control,
+            description: file.description,
+        }
+    }
+}
+
+// IP 协议（FWPM_CONDITION_IP_PROTOCOL）。常用协议有名称，其余协议用 Other 表示协议号；
+// Any 表示不限制协议，不生成协议条件。相等性按协议号比较，Other(6) 与 Tcp 相同
+#[derive(Debug, Clone, Copy)]
 pub enum Protocol {
-    Tcp,
-    Udp,
-    Icmp,
-    IcmpV6,
-    Igmp,
-    Ah,      // Authentication Header
-    Esp,     // Encapsulating Security Payload
-    Gre,     // Generic Routing Encapsulation
-    Ipsec,   // IP Security
-    Any,     // 任意协议
+    Icmp,      // 1
+    Igmp,      // 2
+    Tcp,       // 6
+    Udp,       // 17
+    Gre,       // 47 Generic Routing Encapsulation
+    Esp,       // 50 Encapsulating Security Payload
+    Ah,        // 51 Authentication Header
+    IcmpV6,    // 58
+    L2tp,      // 115 Layer Two Tunneling Protocol
+    Sctp,      // 132 Stream Control Transmission Protocol
+    Other(u8), // 其他协议号
+    Any,       // 任意协议
+}
+
+impl Protocol {
+    // 按协议号构造，有名称的协议号返回对应的命名变体
+    pub fn from_number(number: u8) -> Self {
+        match number {
+            1 => Protocol::Icmp,
+            2 => Protocol::Igmp,
+            6 => Protocol::Tcp,
+            17 => Protocol::Udp,
+            47 => Protocol::Gre,
+            50 => Protocol::Esp,
+            51 => Protocol::Ah,
+            58 => Protocol::IcmpV6,
+            115 => Protocol::L2tp,
+            132 => Protocol::Sctp,
+            number => Protocol::Other(number),
+        }
+    }
+
+    // IP协议号（FWPM_CONDITION_IP_PROTOCOL 的取值），任意协议没有协议号
+    pub fn ip_protocol(&self) -> Option<u8> {
+        match self {
+            Protocol::Icmp => Some(1),
+            Protocol::Igmp => Some(2),
+            Protocol::Tcp => Some(6),
+            Protocol::Udp => Some(17),
+            Protocol::Gre => Some(47),
+            Protocol::Esp => Some(50),
+            Protocol::Ah => Some(51),
+            Protocol::IcmpV6 => Some(58),
+            Protocol::L2tp => Some(115),
+            Protocol::Sctp => Some(132),
+            Protocol::Other(number) => Some(*number),
+            Protocol::Any => None,
+        }
+    }
+
+    // 有名称的协议号换成命名变体，例如 Other(58) 换成 IcmpV6；按变体匹配协议前先规范化
+    pub fn normalized(self) -> Self {
+        self.ip_protocol().map_or(Protocol::Any, Protocol::from_number)
+    }
+
+    // 配置文件中使用的名称；没有名称的协议返回 None，序列化为协议号
+    pub fn keyword(&self) -> Option<&'static str> {
+        match Protocol::from_number(self.ip_protocol()?) {
+            Protocol::Icmp => Some("icmp"),
+            Protocol::Igmp => Some("igmp"),
+            Protocol::Tcp => Some("tcp"),
+            Protocol::Udp => Some("udp"),
+            Protocol::Gre => Some("gre"),
+            Protocol::Esp => Some("esp"),
+            Protocol::Ah => Some("ah"),
+            Protocol::IcmpV6 => Some("icmpv6"),
+            Protocol::L2tp => Some("l2tp"),
+            Protocol::Sctp => Some("sctp"),
+            Protocol::Other(_) | Protocol::Any => None,
+        }
+    }
+}
+
+impl PartialEq for Protocol {
+    fn eq(&self, other: &Self) -> bool {
+        self.ip_protocol() == other.ip_protocol()
+    }
+}
+
+impl Eq for Protocol {}
+
+impl std::hash::Hash for Protocol {
+    fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
+        self.ip_protocol().hash(state);
+    }
 }
 
 impl fmt::Display for Protocol {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
-            Protocol::Tcp => write!(f, "TCP"),
-            Protocol::Udp => write!(f, "UDP"),
             Protocol::Icmp => write!(f, "ICMP"),
-            Protocol::IcmpV6 => write!(f, "ICMPv6"),
             Protocol::Igmp => write!(f, "IGMP"),
-            Protocol::Ah => write!(f, "AH"),
-            Protocol::Esp => write!(f, "ESP"),
+            Protocol::Tcp => write!(f, "TCP"),
+            Protocol::Udp => write!(f, "UDP"),
             Protocol::Gre => write!(f, "GRE"),
-            Protocol::Ipsec => write!(f, "IPSEC"),
+            Protocol::Esp => write!(f, "ESP"),
+            Protocol::Ah => write!(f, "AH"),
+            Protocol::IcmpV6 => write!(f, "ICMPv6"),
+            Protocol::L2tp => write!(f, "L2TP"),
+            Protocol::Sctp => write!(f, "SCTP"),
+            Protocol::Other(number) => match Protocol::from_number(*number) {
+                Protocol::Other(number) => write!(f, "{}", number),
+                named => write!(f, "{}", named),
+            },
             Protocol::Any => write!(f, "任意协议"),
         }
     }
 }
 
+// 接受名称（不区分大小写）或 0-255 的协议号；"ipsec" 按旧版的取值解析为 ESP
 impl FromStr for Protocol {
-    type Err = String;
-    
-    fn from_str(s: &str) -> Result<Self, Self::Err> {
-        match s.to_lowercase().as_str() {
-            "tcp" => Ok(Protocol::Tcp),
-            "udp" => Ok(Protocol::Udp),
+    type Err = AstralError;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let name = s.trim().to_lowercase();
+        match name.as_str() {
             "icmp" => Ok(Protocol::Icmp),
-            "icmpv6" => Ok(Protocol::IcmpV6),
             "igmp" => Ok(Protocol::Igmp),
-            "ah" => Ok(Protocol::Ah),
-            "esp" => Ok(Protocol::Esp),
+            "tcp" => Ok(Protocol::Tcp),
+            "udp" => Ok(Protocol::Udp),
             "gre" => Ok(Protocol::Gre),
-            "ipsec" => Ok(Protocol::Ipsec),
-            "any" => Ok(Protocol::Any),
-            _ => Err(format!("未知协议: {}", s)),
+            "esp" | "ipsec" => Ok(Protocol::Esp),
+            "ah" => Ok(Protocol::Ah),
+            "icmpv6" | "ipv6-icmp" => Ok(Protocol::IcmpV6),
+            "l2tp" => Ok(Protocol::L2tp),
+            "sctp" => Ok(Protocol::Sctp),
+            "any" | "*" | "任意协议" => Ok(Protocol::Any),
+            _ => name
+                .parse::<u8>()
+                .map(Protocol::from_number)
+                .map_err(|_| {
+                    AstralError::parse(
+                        message!("协议", "protocol"),
+                        message!("未知协议: {} (可以使用协议名或 0-255 的协议号)", "unknown protocol: {} (use a protocol name or a number 0-255)", s),
+                    )
+                }),
+        }
+    }
+}
+
+// 有名称的协议序列化为名称，其余协议序列化为协议号
+impl Serialize for Protocol {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error> {
+        match (self, self.keyword()) {
+            (Protocol::Any, _) => serializer.serialize_str("any"),
+            (_, Some(keyword)) => serializer.serialize_str(keyword),
+            (_, None) => serializer.serialize_u8(self.ip_protocol().unwrap_or_default()),
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for Protocol {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> std::result::Result<Self, D::Error> {
+        struct ProtocolVisitor;
+
+        impl serde::de::Visitor<'_> for ProtocolVisitor {
+            type Value = Protocol;
+
+            fn expecting(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+                write!(f, "协议名或 0-255 的协议号")
+            }
+
+            fn visit_u64<E: serde::de::Error>(self, value: u64) -> std::result::Result<Protocol, E> {
+                u8::try_from(value)
+                    .map(Protocol::from_number)
+                    .map_err(|_| message!("协议号超出范围: {}", "protocol number out of range: {}", value).to_serde())
+            }
+
+            fn visit_i64<E: serde::de::Error>(self, value: i64) -> std::result::Result<Protocol, E> {
+                u8::try_from(value)
+                    .map(Protocol::from_number)
+                    .map_err(|_| message!("协议号超出范围: {}", "protocol number out of range: {}", value).to_serde())
+            }
+
+            fn visit_str<E: serde::de::Error>(self, value: &str) -> std::result::Result<Protocol, E> {
+                value.parse().map_err(|e: AstralError| e.message().to_serde())
+            }
         }
+
+        deserializer.deserialize_any(ProtocolVisitor)
     }
 }
 
 // 流量方向枚举
-#[derive(Debug, Clone, PartialEq)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
 pub enum Direction {
     Inbound,     // 入站流量
     Outbound,    // 出站流量
     Both,        // 双向流量
 }
-#[derive(Debug, Clone, PartialEq)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
 pub enum FilterAction {
     Allow,
     Block,
@@ -227,15 +434,16 @@ pub enum FilterAction {
 impl FilterRule {
     pub fn new(name: &str) -> Self {
         Self {
+            id: Uuid::new_v4(),
             name: name.to_string(),
             app_path: None,
             local: None,
             remote: None,
-            local_port: None,
-            remote_port: None,
-            local_port_range: None,
-            remote_port_range: None,
+            local_ports: None,
+            remote_ports: None,
             protocol: None,
+            icmp_type: None,
+            icmp_code: None,
             direction: Direction::Both,
             action: FilterAction::Block,
             priority: 0,
@@ -246,44 +454,129 @@ impl FilterRule {
         }
     }
 
-    pub fn app_path(mut self, path: &str) -> Self {
-        self.app_path = Some(path.to_string());
+    // 设置规则ID，例如还原已有规则时沿用原来的ID（默认在创建时随机生成）
+    pub fn id(mut self, id: Uuid) -> Self {
+        self.id = id;
         self
     }
 
-    pub fn local_ip(mut self, ip: impl ToString) -> Self {
-        self.local = Some(ip.to_string());
+    // 修改规则名称，规则ID保持不变
+    pub fn name(mut self, name: &str) -> Self {
+        self.name = name.to_string();
         self
     }
 
-    pub fn remote_ip(mut self, ip: impl ToString) -> Self {
-        self.remote = Some(ip.to_string());
+    pub fn app_path(mut self, path: &str) -> Self {
+        self.app_path = Some(path.to_string());
         self
     }
 
+    // 设置本地地址列表，每项可以是地址、网段、"起始-结束" 范围或以 "!" 开头的排除项；无法解析时返回错误
+    pub fn local_ip(mut self, ip: impl ToString) -> Result<Self> {
+        self.local = Some(parse_addresses("local", "本地", &ip.to_string())?);
+        Ok(self)
+    }
+
+    // 设置远程地址列表，格式同 local_ip
+    pub fn remote_ip(mut self, ip: impl ToString) -> Result<Self> {
+        self.remote = Some(parse_addresses("remote", "远程", &ip.to_string())?);
+        Ok(self)
+    }
+
+    // 用多项设置本地地址列表，每项的格式同 local_ip
+    pub fn local_ips<T: ToString>(self, items: impl IntoIterator<Item = T>) -> Result<Self> {
+        self.local_ip(join_addresses(items))
+    }
+
+    // 用多项设置远程地址列表
+    pub fn remote_ips<T: ToString>(self, items: impl IntoIterator<Item = T>) -> Result<Self> {
+        self.remote_ip(join_addresses(items))
+    }
+
+    // 设置远程网段，CIDR格式无效时返回错误
+    pub fn remote_ip_cidr(mut self, cidr: &str) -> Result<Self> {
+        let network = IpNetwork::from_cidr(cidr)
+            .map_err(|e| AstralError::validation("remote", e.message().map(|language, message| match language {
+                Language::Chinese => format!("无效的CIDR网段 {}: {}", cidr, message),
+                Language::English => format!("invalid CIDR network {}: {}", cidr, message),
+            })))?;
+        self.remote = Some(AddressList::from(&network));
+        Ok(self)
+    }
+
+    // 设置本地端口列表，每项可以是端口、"起始-结束" 范围、服务名或以 "!" 开头的排除项；无法解析时返回错误
+    pub fn local_ports(mut self, ports: impl ToString) -> Result<Self> {
+        self.local_ports = Some(parse_ports("local_ports", "本地", &ports.to_string())?);
+        Ok(self)
+    }
+
+    // 设置远程端口列表，格式同 local_ports
+    pub fn remote_ports(mut self, ports: impl ToString) -> Result<Self> {
+        self.remote_ports = Some(parse_ports("remote_ports", "远程", &ports.to_string())?);
+        Ok(self)
+    }
 
+    // 向本地端口列表追加一个端口；端口和端口范围可以多次追加
     pub fn local_port(mut self, port: u16) -> Self {
-        self.local_port = Some(port);
+        append_port(&mut self.local_ports, PortList::port(port));
         self
     }
 
     pub fn remote_port(mut self, port: u16) -> Self {
-        self.remote_port = Some(port);
+        append_port(&mut self.remote_ports, PortList::port(port));
         self
     }
 
-    pub fn local_port_range(mut self, start: u16, end: u16) -> Self {
-        self.local_port_range = Some((start, end));
+    // 追加端口范围，起始端口大于结束端口时返回错误
+    pub fn local_port_range(mut self, start: u16, end: u16) -> Result<Self> {
+        let range = PortList::range(start, end).map_err(|e| AstralError::validation("local_ports", e.message().clone()))?;
+        append_port(&mut self.local_ports, range);
+        Ok(self)
+    }
+
+    pub fn remote_port_range(mut self, start: u16, end: u16) -> Result<Self> {
+        let range = PortList::range(start, end).map_err(|e| AstralError::validation("remote_ports", e.message().clone()))?;
+        append_port(&mut self.remote_ports, range);
+        Ok(self)
+    }
+
+    // 按服务名追加本地端口，例如 "https" 或 "dns"；服务只使用一种协议且规则未设置协议时同时设置协议。
+    // 未知的服务名返回错误
+    pub fn local_service(mut self, name: &str) -> Result<Self> {
+        let entry = service_port("local_ports", name)?;
+        append_port(&mut self.local_ports, entry);
+        self.infer_protocol(name);
+        Ok(self)
+    }
+
+    // 按服务名追加远程端口，规则同 local_service
+    pub fn remote_service(mut self, name: &str) -> Result<Self> {
+        let entry = service_port("remote_ports", name)?;
+        append_port(&mut self.remote_ports, entry);
+        self.infer_protocol(name);
+        Ok(self)
+    }
+
+    fn infer_protocol(&mut self, name: &str) {
+        if self.protocol.is_none()
+            && let Some(protocol) = services::lookup(name).and_then(|service| service.protocol()) {
+            self.protocol = Some(*protocol);
+        }
+    }
+
+    pub fn protocol(mut self, protocol: Protocol) -> Self {
+        self.protocol = Some(protocol.normalized());
         self
     }
 
-    pub fn remote_port_range(mut self, start: u16, end: u16) -> Self {
-        self.remote_port_range = Some((start, end));
+    // 匹配 ICMP 类型，例如 IcmpType::EchoRequest；编号按规则的协议（ICMP 或 ICMPv6）换算
+    pub fn icmp_type(mut self, icmp_type: IcmpType) -> Self {
+        self.icmp_type = Some(icmp_type);
         self
     }
 
-    pub fn protocol(mut self, protocol: Protocol) -> Self {
-        self.protocol = Some(protocol);
+    pub fn icmp_code(mut self, code: u8) -> Self {
+        self.icmp_code = Some(code);
         self
     }
 
@@ -322,860 +615,478 @@ impl FilterRule {
         self
     }
     
-    // 生成规则签名，用于缓存
-    pub fn signature(&self) -> String {
-        format!("{}_{:?}_{:?}_{:?}_{:?}_{:?}_{:?}_{:?}_{:?}",
-            self.name,
-            self.app_path,
-            self.local,
-            self.remote,
-            self.local_port,
-            self.remote_port,
-            self.protocol,
-            self.direction,
-            self.action
-        )
-    }
-
-    fn validate_ip(&self, ip: &IpAddr) -> bool {
-        match ip {
-            IpAddr::V4(ipv4) => {
-                let octets = ipv4.octets();
-                // 检查是否是有效的私有网络地址
-                match octets[0] {
-                    10 => true,  // 10.0.0.0/8
-                    172 => (16..=31).contains(&octets[1]),  // 172.16.0.0/12
-                    192 => octets[1] == 168,  // 192.168.0.0/16
-                    // 对于公网 IP，这里可以添加其他验证规则
-                    _ => true  // 暂时允许其他地址，可以根据需求修改
-                }
-            },
-            IpAddr::V6(_) => true  // IPv6 地址验证逻辑
+    // 返回第一个错误；全部错误和警告见 validation::validate_rule
+    pub fn validate(&self) -> Result<()> {
+        match validate_rule(self).into_iter().find(Diagnostic::is_error) {
+            Some(diagnostic) => Err(diagnostic.to_error()),
+            None => Ok(()),
         }
     }
+}
 
-    pub fn validate(&self) -> std::result::Result<(), String> {
-        // 验证远程 IP
-        if let Some(remote) = &self.remote {
-            // 尝试解析为单个IP地址
-            if let Ok(ip) = remote.parse::<IpAddr>() {
-                if !self.validate_ip(&ip) {
-                    return Err(format!("无效的远程 IP 地址: {}", remote));
-                }
-            } 
-            // 尝试解析为CIDR网段
-            else if let Ok(_network) = IpNetwork::from_cidr(remote) {
-                // CIDR格式有效，通过验证
-            } 
-            // 都不是，报错
-            else {
-                return Err(format!("无法解析的 IP 地址格式: {}", remote));
-            }
-        }
-        
-        // 验证本地 IP（如果存在）
-        if let Some(local) = &self.local {
-            // 尝试解析为单个IP地址
-            if let Ok(ip) = local.parse::<IpAddr>() {
-                if !self.validate_ip(&ip) {
-                    return Err(format!("无效的本地 IP 地址: {}", local));
-                }
-            } 
-            // 尝试解析为CIDR网段
-            else if let Ok(_network) = IpNetwork::from_cidr(local) {
-                // CIDR格式有效，通过验证
-            } 
-            // 都不是，报错
-            else {
-                return Err(format!("无法解析的本地 IP 地址格式: {}", local));
-            }
-        }
-        
-        Ok(())
+// 旧版规则ID是任意字符串（通常是规则名称），按固定命名空间换算成 UUID，同一字符串始终得到同一ID
+const LEGACY_RULE_ID_NAMESPACE: Uuid = Uuid::from_u128(0x3c1f_52a7_8d0e_4b6a_9f21_7e4d_0b9c_5a13);
+
+pub fn legacy_rule_id(id: &str) -> Uuid {
+    Uuid::new_v5(&LEGACY_RULE_ID_NAMESPACE, id.as_bytes())
+}
+
+fn deserialize_rule_id<'de, D: serde::Deserializer<'de>>(deserializer: D) -> std::result::Result<Uuid, D::Error> {
+    let id = String::deserialize(deserializer)?;
+    Ok(Uuid::parse_str(&id).unwrap_or_else(|_| legacy_rule_id(&id)))
+}
+
+fn append_port(ports: &mut Option<PortList>, entry: PortEntry) {
+    match ports {
+        Some(list) => list.entries.push(entry),
+        None => *ports = Some(PortList { entries: vec![entry] }),
     }
 }
 
+fn join_addresses<T: ToString>(items: impl IntoIterator<Item = T>) -> String {
+    items.into_iter().map(|item| item.to_string()).collect::<Vec<_>>().join(", ")
+}
+
+fn parse_addresses(field: &str, name: &str, text: &str) -> Result<AddressList> {
+    AddressList::parse(text).map_err(|e| {
+        AstralError::validation(field, e.message().map(|language, message| match language {
+            Language::Chinese => format!("无法解析的{} IP 地址格式: {} ({})", name, text, message),
+            Language::English => format!("cannot parse {} IP addresses: {} ({})", field, text, message),
+        }))
+    })
+}
+
+fn parse_ports(field: &str, name: &str, text: &str) -> Result<PortList> {
+    PortList::parse(text).map_err(|e| {
+        AstralError::validation(field, e.message().map(|language, message| match language {
+            Language::Chinese => format!("无效的{}端口列表 {:?}: {}", name, text, message),
+            Language::English => format!("invalid {} port list {:?}: {}", field.trim_end_matches("_ports"), text, message),
+        }))
+    })
+}
+
+fn service_port(field: &str, name: &str) -> Result<PortEntry> {
+    let service =
+        services::lookup(name).ok_or_else(|| AstralError::validation(field, message!("未知的服务名: {}", "unknown service name: {}", name)))?;
+    Ok(PortEntry { start: service.port, end: service.port, negated: false, service: Some(service.name) })
+}
+
 // 创建宽字符字符串的辅助函数
 pub fn to_wide_string(s: &str) -> Vec<u16> {
-    OsStr::new(s)
-        .encode_wide()
+    s.encode_utf16()
         .chain(std::iter::once(0))
         .collect()
 }
 
+// 我们识别的全部层及其名称；后端按层枚举过滤器时只枚举这些层
+pub const KNOWN_LAYERS: [(GUID, &str); 20] = [
+    (FWPM_LAYER_ALE_AUTH_CONNECT_V4, "ALE_AUTH_CONNECT_V4"),
+    (FWPM_LAYER_ALE_AUTH_CONNECT_V6, "ALE_AUTH_CONNECT_V6"),
+    (FWPM_LAYER_ALE_AUTH_RECV_ACCEPT_V4, "ALE_AUTH_RECV_ACCEPT_V4"),
+    (FWPM_LAYER_ALE_AUTH_RECV_ACCEPT_V6, "ALE_AUTH_RECV_ACCEPT_V6"),
+    (FWPM_LAYER_ALE_AUTH_LISTEN_V4, "ALE_AUTH_LISTEN_V4"),
+    (FWPM_LAYER_ALE_AUTH_LISTEN_V6, "ALE_AUTH_LISTEN_V6"),
+    (FWPM_LAYER_ALE_RESOURCE_ASSIGNMENT_V4, "ALE_RESOURCE_ASSIGNMENT_V4"),
+    (FWPM_LAYER_ALE_RESOURCE_ASSIGNMENT_V6, "ALE_RESOURCE_ASSIGNMENT_V6"),
+    (FWPM_LAYER_ALE_RESOURCE_RELEASE_V4, "ALE_RESOURCE_RELEASE_V4"),
+    (FWPM_LAYER_ALE_RESOURCE_RELEASE_V6, "ALE_RESOURCE_RELEASE_V6"),
+    (FWPM_LAYER_ALE_ENDPOINT_CLOSURE_V4, "ALE_ENDPOINT_CLOSURE_V4"),
+    (FWPM_LAYER_ALE_ENDPOINT_CLOSURE_V6, "ALE_ENDPOINT_CLOSURE_V6"),
+    (FWPM_LAYER_ALE_CONNECT_REDIRECT_V4, "ALE_CONNECT_REDIRECT_V4"),
+    (FWPM_LAYER_ALE_CONNECT_REDIRECT_V6, "ALE_CONNECT_REDIRECT_V6"),
+    (FWPM_LAYER_ALE_BIND_REDIRECT_V4, "ALE_BIND_REDIRECT_V4"),
+    (FWPM_LAYER_ALE_BIND_REDIRECT_V6, "ALE_BIND_REDIRECT_V6"),
+    (FWPM_LAYER_OUTBOUND_TRANSPORT_V4, "OUTBOUND_TRANSPORT_V4"),
+    (FWPM_LAYER_OUTBOUND_TRANSPORT_V6, "OUTBOUND_TRANSPORT_V6"),
+    (FWPM_LAYER_INBOUND_TRANSPORT_V4, "INBOUND_TRANSPORT_V4"),
+    (FWPM_LAYER_INBOUND_TRANSPORT_V6, "INBOUND_TRANSPORT_V6"),
+];
+
+// 获取层的名称用于调试
+pub fn layer_name(layer_key: &GUID) -> &'static str {
+    KNOWN_LAYERS
+        .iter()
+        .find(|(key, _)| key == layer_key)
+        .map_or("UNKNOWN_LAYER", |(_, name)| name)
+}
+
 // WFP控制器结构体
-pub struct WfpController {
-    engine_handle: HANDLE,
+pub struct WfpController<B: FirewallBackend = DefaultBackend> {
+    backend: B,
+    provider: ProviderConfig,
+    mode: EnforcementMode,
+    compiler: PlanCompiler,
     pub filter_ids: Vec<u64>,
+    applied: Vec<(u64, FilterSpec)>, // 已提交的过滤器及其编译结果，漂移审计以此为准
 }
 
 impl WfpController {
-    // 创建新的WFP控制器实例
+    // 创建使用当前平台默认后端的WFP控制器实例
     pub fn new() -> Result<Self> {
-        Ok(Self {
-            engine_handle: HANDLE::default(),
-            filter_ids: Vec::new(),
-        })
+        Ok(Self::with_backend(DefaultBackend::new()))
     }
+}
 
-    // 初始化WFP引擎
-    pub fn initialize(&mut self) -> Result<()> {
-        unsafe {
-            println!("正在初始化 Windows Filtering Platform...");
-
-            // 创建会话名称
-            let session_name = to_wide_string("AstralWFP Manager");
-            let session_desc = to_wide_string("AstralWFP网络流量管理会话");
-
-            let session = FWPM_SESSION0 {
-                sessionKey: GUID::zeroed(),
-                displayData: FWPM_DISPLAY_DATA0 {
-                    name: PWSTR(session_name.as_ptr() as *mut u16),
-                    description: PWSTR(session_desc.as_ptr() as *mut u16),
-                },
-                flags: FWPM_SESSION_FLAG_DYNAMIC,
-                txnWaitTimeoutInMSec: 0,
-                processId: 0,
-                sid: ptr::null_mut(),
-                username: PWSTR::null(),
-                kernelMode: FALSE,
-            };
-
-            // 打开WFP会话
-            let result = FwpmEngineOpen0(
-                None,
-                RPC_C_AUTHN_DEFAULT as u32,
-                None,
-                Some(&session),
-                &mut self.engine_handle,
-            );
-
-            if WIN32_ERROR(result) == ERROR_SUCCESS {
-                println!("✓ WFP引擎打开成功！");
-                Ok(())
-            } else {
-                println!("❌ 打开WFP引擎失败: {} (可能需要管理员权限)", result);
-                Err(Error::from_win32())
-            }
+impl<B: FirewallBackend> WfpController<B> {
+    // 使用指定后端创建控制器
+    pub fn with_backend(backend: B) -> Self {
+        Self {
+            backend,
+            provider: ProviderConfig::default(),
+            mode: EnforcementMode::Dynamic,
+            compiler: PlanCompiler::new(),
+            filter_ids: Vec::new(),
+            applied: Vec::new(),
         }
     }
 
+    // 使用指定的提供者和子层（例如调整子层权重），需在 initialize 之前设置
+    pub fn with_provider(mut self, provider: ProviderConfig) -> Self {
+        self.compiler = PlanCompiler::with_provider(provider.clone()).with_mode(self.mode);
+        self.provider = provider;
+        self
+    }
 
-    // 添加高级过滤器（支持复杂规则）
-    pub fn add_advanced_filters(&mut self, rules: &[FilterRule]) -> Result<Vec<u64>> {
-        unsafe {
-            let mut added_ids = Vec::new();
-            let mut added_count = 0;
-            
-            for rule in rules {
-                // 验证规则
-                if let Err(e) = rule.validate() {
-                    println!("❌ 规则验证失败: {}", e);
-                    continue;
-                }
-                
-                // 根据方向和IP版本确定需要的层
-                let layers = self.get_layers_for_rule(rule);
-                for layer in layers {
-                    println!("🧪 尝试在层 {} 上添加过滤器...", self.get_layer_name(&layer));
-                    match self.add_advanced_network_filter(rule, layer) {
-                        Ok(filter_id) => {
-                            self.filter_ids.push(filter_id);
-                            added_ids.push(filter_id);
-                            added_count += 1;
-                            println!("✅ 过滤器在层 {} 上添加成功 (ID: {})", self.get_layer_name(&layer), filter_id);
-                        },
-                        Err(e) => {
-                            println!("❌ 过滤器在层 {} 上添加失败: {:?}", self.get_layer_name(&layer), e);
-                        }
-                    }
-                }
-            }
+    // 设置这组规则的执行模式（动态/持久/启动时），需在 initialize 之前设置
+    pub fn with_mode(mut self, mode: EnforcementMode) -> Self {
+        self.compiler = PlanCompiler::with_provider(self.provider.clone()).with_mode(mode);
+        self.mode = mode;
+        self
+    }
 
-            if added_count > 0 {
-                println!(
-                    "\n🔍 网络流量控制已启动，共添加了 {} 个过滤器",
-                    added_count
-                );
-                Ok(added_ids)
-            } else {
-                println!("❌ 没有成功添加任何过滤器");
-                Err(Error::from_win32())
-            }
-        }
+    pub fn provider(&self) -> &ProviderConfig {
+        &self.provider
     }
 
-    // 根据规则获取对应的WFP层 - 测试所有可能的层组合
-    pub fn get_layers_for_rule(&self, rule: &FilterRule) -> Vec<GUID> {
-        let mut layers = Vec::new();
-        
-        // 根据IP地址类型确定IPv4还是IPv6
-        let is_ipv6 = rule.local.as_ref().map_or(false, |ip| ip.contains(":")) || 
-                     rule.remote.as_ref().map_or(false, |ip| ip.contains(":"));
-        
-        println!("🔍 规则分析: {} - 方向: {:?}, IPv6: {}", rule.name, rule.direction, is_ipv6);
-        println!("   APP路径: {:?}", rule.app_path.is_some());
-        if let Some(remote) = &rule.remote {
-            println!("   远程IP: {}", remote);
-        }
-          // 如果有APP_ID + 远程IP的组合，使用测试验证过的层
-        if rule.app_path.is_some() && rule.remote.is_some() {
-            println!("🎯 检测到APP_ID + 远程IP组合，使用测试验证的层...");
-            
-            if !is_ipv6 {
-                // 根据测试结果，只使用成功的IPv4层
-                match rule.direction {
-                    Direction::Outbound => {
-                        // 出站连接使用CONNECT层（测试成功）
-                        layers.push(FWPM_LAYER_ALE_AUTH_CONNECT_V4);
-                        layers.push(FWPM_LAYER_ALE_ENDPOINT_CLOSURE_V4); // 额外保护
-                    },
-                    Direction::Inbound => {
-                        // 入站连接使用RECV_ACCEPT层（测试成功）
-                        layers.push(FWPM_LAYER_ALE_AUTH_RECV_ACCEPT_V4);
-                        layers.push(FWPM_LAYER_ALE_ENDPOINT_CLOSURE_V4); // 额外保护
-                    },
-                    Direction::Both => {
-                        // 双向连接使用两个主要层（都测试成功）
-                        layers.push(FWPM_LAYER_ALE_AUTH_CONNECT_V4);
-                        layers.push(FWPM_LAYER_ALE_AUTH_RECV_ACCEPT_V4);
-                        layers.push(FWPM_LAYER_ALE_ENDPOINT_CLOSURE_V4); // 额外保护
-                        // 可选：如果需要连接重定向功能
-                        // layers.push(FWPM_LAYER_ALE_CONNECT_REDIRECT_V4);
-                    }
-                }
-            } else {
-                // IPv6层（基于IPv4测试结果推断）
-                match rule.direction {
-                    Direction::Outbound => {
-                        layers.push(FWPM_LAYER_ALE_AUTH_CONNECT_V6);
-                        layers.push(FWPM_LAYER_ALE_ENDPOINT_CLOSURE_V6);
-                    },
-                    Direction::Inbound => {
-                        layers.push(FWPM_LAYER_ALE_AUTH_RECV_ACCEPT_V6);
-                        layers.push(FWPM_LAYER_ALE_ENDPOINT_CLOSURE_V6);
-                    },
-                    Direction::Both => {
-                        layers.push(FWPM_LAYER_ALE_AUTH_CONNECT_V6);
-                        layers.push(FWPM_LAYER_ALE_AUTH_RECV_ACCEPT_V6);
-                        layers.push(FWPM_LAYER_ALE_ENDPOINT_CLOSURE_V6);
-                    }
-                }
-            }
-        } else {
-            // 没有APP_ID + 远程IP组合的情况，使用标准层
-            match rule.direction {
-                Direction::Outbound => {
-                    if is_ipv6 {
-                        layers.push(FWPM_LAYER_ALE_AUTH_CONNECT_V6);
-                    } else {
-                        layers.push(FWPM_LAYER_ALE_AUTH_CONNECT_V4);
-                    }
-                },
-                Direction::Inbound => {
-                    if is_ipv6 {
-                        layers.push(FWPM_LAYER_ALE_AUTH_RECV_ACCEPT_V6);
-                    } else {
-                        layers.push(FWPM_LAYER_ALE_AUTH_RECV_ACCEPT_V4);
-                    }
-                },
-                Direction::Both => {
-                    if is_ipv6 {
-                        layers.push(FWPM_LAYER_ALE_AUTH_CONNECT_V6);
-                        layers.push(FWPM_LAYER_ALE_AUTH_RECV_ACCEPT_V6);
-                    } else {
-                        layers.push(FWPM_LAYER_ALE_AUTH_CONNECT_V4);
-                        layers.push(FWPM_LAYER_ALE_AUTH_RECV_ACCEPT_V4);
-                    }
-                }
-            }
-        }
-        
-        println!("   将测试 {} 个层", layers.len());
-        layers
+    pub fn mode(&self) -> EnforcementMode {
+        self.mode
+    }
+
+    pub fn backend(&self) -> &B {
+        &self.backend
     }
 
+    pub fn backend_mut(&mut self) -> &mut B {
+        &mut self.backend
+    }
 
+    // 初始化WFP引擎，并注册我们的提供者和子层
+    pub fn initialize(&mut self) -> Result<()> {
+        self.backend.open_session(self.mode)?;
+        self.backend.register_provider(&self.provider, self.mode)
+    }
 
-    // 清理过滤器
-    pub fn cleanup(&mut self) -> Result<()> {
-        unsafe {
-            println!("\n🛑 停止过滤器，正在清理...");
-
-            // 清理过滤器
-            for filter_id in &self.filter_ids {
-                let delete_result = FwpmFilterDeleteById0(self.engine_handle, *filter_id);
-                if WIN32_ERROR(delete_result) == ERROR_SUCCESS {
-                    println!("✓ 过滤器 {} 已删除", filter_id);
-                } else {
-                    println!("⚠️  删除过滤器 {} 失败: {}", filter_id, delete_result);
-                }
-            }
 
-            // 关闭引擎
-            let result = FwpmEngineClose0(self.engine_handle);
-            if WIN32_ERROR(result) != ERROR_SUCCESS {
-                println!("❌ 关闭WFP引擎失败: {}", result);
-                return Err(Error::from_win32());
-            }
-            println!("✓ WFP引擎已关闭");
-            Ok(())
-        }
+    // 在一个事务中应用一批规则：任何过滤器添加失败都会回滚整批，引擎状态保持不变。
+    // 规则层面的失败记录在报告中；只有事务本身出错时才返回 Err
+    pub fn apply_rules(&mut self, rules: &[FilterRule]) -> Result<ApplyReport> {
+        self.apply_batch(rules, &[])
     }
 
-    // 添加高级网络过滤器的内部方法
-    pub unsafe fn add_advanced_network_filter(
-        &self,
-        rule: &FilterRule,
-        layer_key: GUID,
-    ) -> Result<u64> {
-        // 将过滤器名称转换为宽字符串
-        let filter_name = to_wide_string(&rule.name);
-        // 生成过滤器描述并转换为宽字符串
-        let filter_desc = to_wide_string(&format!("控制 {} 的网络流量", rule.name));
-
-        // 创建过滤条件向量
-        let mut conditions = Vec::new();        // 添加应用程序路径条件
-        let mut _app_id_data = None;
-        let mut should_add_app_id = false;        if let Some(app_path) = &rule.app_path {
-            // 基于测试结果，只在成功验证的层上添加APP_ID条件
-            should_add_app_id = match layer_key {
-                // 测试成功的层：支持APP_ID + 远程IP组合
-                FWPM_LAYER_ALE_AUTH_CONNECT_V4 |
-                FWPM_LAYER_ALE_AUTH_CONNECT_V6 |
-                FWPM_LAYER_ALE_AUTH_RECV_ACCEPT_V4 |
-                FWPM_LAYER_ALE_AUTH_RECV_ACCEPT_V6 |
-                FWPM_LAYER_ALE_ENDPOINT_CLOSURE_V4 |
-                FWPM_LAYER_ALE_ENDPOINT_CLOSURE_V6 |
-                FWPM_LAYER_ALE_CONNECT_REDIRECT_V4 |
-                FWPM_LAYER_ALE_CONNECT_REDIRECT_V6 => true,
-                
-                // 测试失败的层：不支持APP_ID + 远程IP组合（但单独APP_ID可能可以）
-                FWPM_LAYER_ALE_AUTH_LISTEN_V4 |
-                FWPM_LAYER_ALE_AUTH_LISTEN_V6 => {
-                    // 只有在没有远程IP条件时才添加APP_ID
-                    rule.remote.is_none()
-                },
-                
-                // 其他层默认不添加APP_ID
-                _ => false,
-            };
-            
-            if should_add_app_id {
-                let appid_utf16: Vec<u16> = app_path
-                    .encode_utf16()
-                    .chain(std::iter::once(0))
-                    .collect();
-                
-                let app_id = FWP_BYTE_BLOB {
-                    size: (appid_utf16.len() * 2) as u32,
-                    data: appid_utf16.as_ptr() as *mut u8,
-                };
-                
-                conditions.push(FWPM_FILTER_CONDITION0 {
-                    fieldKey: FWPM_CONDITION_ALE_APP_ID,
-                    matchType: FWP_MATCH_EQUAL,
-                    conditionValue: FWP_CONDITION_VALUE0 {
-                        r#type: FWP_BYTE_BLOB_TYPE,
-                        Anonymous: FWP_CONDITION_VALUE0_0 {
-                            byteBlob: &app_id as *const _ as *mut _,
-                        },
+    // 在同一个事务中先删除 remove 中的过滤器，再应用 rules；删除失败时回滚并返回 Err
+    fn apply_batch(&mut self, rules: &[FilterRule], remove: &[u64]) -> Result<ApplyReport> {
+        let _batch = info_span!("apply_rules", rules = rules.len(), remove = remove.len(), mode = %self.mode).entered();
+        let mut report = ApplyReport {
+            mode: self.mode,
+            ..Default::default()
+        };
+
+        // 先编译全部规则，任何规则验证失败都不触碰引擎
+        let compiled: Vec<Result<Vec<FilterSpec>>> = rules.iter().map(|rule| self.compiler.compile(rule)).collect();
+        if compiled.iter().any(|c| c.is_err()) {
+            report.rules = rules
+                .iter()
+                .zip(compiled)
+                .map(|(rule, result)| match result {
+                    Ok(specs) => RuleReport {
+                        rule_id: rule.id,
+                        rule_name: rule.name.clone(),
+                        error: None,
+                        layers: specs
+                            .iter()
+                            .map(|spec| LayerReport { layer_key: spec.layer_key, status: LayerStatus::Skipped, replaced: None })
+                            .collect(),
                     },
-                });
-                
-                _app_id_data = Some((appid_utf16, app_id));
-                println!("✓ APP_ID条件已添加到过滤器: {}", app_path);
-            } else {
-                println!("⚠️ 跳过APP_ID条件（入站连接在此层不适用）");
-            }
+                    Err(e) => {
+                        error!(rule = %rule.id, code = %e.code(), "{}", e);
+                        RuleReport {
+                            rule_id: rule.id,
+                            rule_name: rule.name.clone(),
+                            error: Some(e),
+                            layers: Vec::new(),
+                        }
+                    }
+                })
+                .collect();
+            return Ok(report);
         }
-        
-        // 添加本地IP/网段条件
-        if let Some(local) = &rule.local {
-            if let Ok(ip) = local.parse::<IpAddr>() {
-                match ip {
-                    IpAddr::V4(ipv4) => {
-                        let ip_bytes = ipv4.octets();
-                        let ip_value = u32::from_be_bytes(ip_bytes);
-                        
-                        conditions.push(FWPM_FILTER_CONDITION0 {
-                            fieldKey: FWPM_CONDITION_IP_LOCAL_ADDRESS,
-                            matchType: FWP_MATCH_EQUAL,
-                            conditionValue: FWP_CONDITION_VALUE0 {
-                                r#type: FWP_UINT32,
-                                Anonymous: FWP_CONDITION_VALUE0_0 {
-                                    uint32: ip_value,
-                                },
-                            },
-                        });
-                        println!("✓ 本地IPv4地址条件已添加: {}", ipv4);
-                    },
-                    IpAddr::V6(ipv6) => {
-                        let ip_bytes = ipv6.octets();
-                        let byte_array = FWP_BYTE_ARRAY16 {
-                            byteArray16: ip_bytes,
-                        };
-                        
-                        conditions.push(FWPM_FILTER_CONDITION0 {
-                            fieldKey: FWPM_CONDITION_IP_LOCAL_ADDRESS,
-                            matchType: FWP_MATCH_EQUAL,
-                            conditionValue: FWP_CONDITION_VALUE0 {
-                                r#type: FWP_BYTE_ARRAY16_TYPE,
-                                Anonymous: FWP_CONDITION_VALUE0_0 {
-                                    byteArray16: &byte_array as *const _ as *mut _,
-                                },
-                            },
-                        });
-                        println!("✓ 本地IPv6地址条件已添加: {}", ipv6);
+        let plans = compiled.into_iter().collect::<Result<Vec<_>>>()?;
+
+        self.backend.begin_transaction()?;
+        // 以其他模式重新应用规则时，它以前在启动时模式下留下的启动时过滤器一起删除
+        let mut remove = remove.to_vec();
+        if self.mode != EnforcementMode::BootTime {
+            for rule in rules {
+                let boot_time = match self.boot_time_filter_ids(rule) {
+                    Ok(filter_ids) => filter_ids,
+                    Err(e) => {
+                        self.backend.abort_transaction()?;
+                        return Err(e);
                     }
-                }
-            } else if let Ok(network) = IpNetwork::from_cidr(local) {
-                match network.ip {
-                    IpAddr::V4(network_ip) => {
-                        let network_bytes = network_ip.octets();
-                        // 使用安全的掩码计算方式
-                        let mask = if network.prefix_len == 0 {
-                            0u32 // 对于 0.0.0.0/0，掩码为全0
-                        } else if network.prefix_len == 32 {
-                            u32::MAX // 对于单个IP地址，掩码为全1
-                        } else {
-                            !((1u32 << (32 - network.prefix_len)) - 1)
-                        };
-                        let network_addr = u32::from_be_bytes(network_bytes) & mask;
-                        
-                        let range = FWP_RANGE0 {
-                            valueLow: FWP_VALUE0 {
-                                r#type: FWP_UINT32,
-                                Anonymous: FWP_VALUE0_0 {
-                                    uint32: network_addr,
-                                },
-                            },
-                            valueHigh: FWP_VALUE0 {
-                                r#type: FWP_UINT32,
-                                Anonymous: FWP_VALUE0_0 {
-                                    uint32: network_addr | !mask,
-                                },
-                            },
-                        };
-                        
-                        conditions.push(FWPM_FILTER_CONDITION0 {
-                            fieldKey: FWPM_CONDITION_IP_LOCAL_ADDRESS,
-                            matchType: FWP_MATCH_RANGE,
-                            conditionValue: FWP_CONDITION_VALUE0 {
-                                r#type: FWP_RANGE_TYPE,
-                                Anonymous: FWP_CONDITION_VALUE0_0 {
-                                    rangeValue: &range as *const _ as *mut _,
-                                },
-                            },
-                        });
-                        println!("✓ 本地IPv4网段条件已添加: {}/{}", network_ip, network.prefix_len);
-                    },
-                    IpAddr::V6(_) => {
-                        println!("⚠️ IPv6网段过滤暂不支持，将跳过此条件");
+                };
+                for filter_id in boot_time {
+                    if !remove.contains(&filter_id) {
+                        remove.push(filter_id);
                     }
                 }
             }
         }
-        
-        // 添加远程IP/网段条件
-        if let Some(remote) = &rule.remote {
-            if let Ok(ip) = remote.parse::<IpAddr>() {
-                match ip {
-                    IpAddr::V4(ipv4) => {
-                        let ip_bytes = ipv4.octets();
-                        let ip_value = u32::from_be_bytes(ip_bytes);
-                        
-                        conditions.push(FWPM_FILTER_CONDITION0 {
-                            fieldKey: FWPM_CONDITION_IP_REMOTE_ADDRESS,
-                            matchType: FWP_MATCH_EQUAL,
-                            conditionValue: FWP_CONDITION_VALUE0 {
-                                r#type: FWP_UINT32,
-                                Anonymous: FWP_CONDITION_VALUE0_0 {
-                                    uint32: ip_value,
-                                },
-                            },
-                        });
-                        println!("✓ 远程IPv4地址条件已添加: {}", ipv4);
-                    },
-                    IpAddr::V6(ipv6) => {
-                        let ip_bytes = ipv6.octets();
-                        let byte_array = FWP_BYTE_ARRAY16 {
-                            byteArray16: ip_bytes,
-                        };
-                        
-                        conditions.push(FWPM_FILTER_CONDITION0 {
-                            fieldKey: FWPM_CONDITION_IP_REMOTE_ADDRESS,
-                            matchType: FWP_MATCH_EQUAL,
-                            conditionValue: FWP_CONDITION_VALUE0 {
-                                r#type: FWP_BYTE_ARRAY16_TYPE,
-                                Anonymous: FWP_CONDITION_VALUE0_0 {
-                                    byteArray16: &byte_array as *const _ as *mut _,
-                                },
-                            },
-                        });
-                        println!("✓ 远程IPv6地址条件已添加: {}", ipv6);
-                    }
-                }
-            } else if let Ok(network) = IpNetwork::from_cidr(remote) {
-                match network.ip {
-                    IpAddr::V4(network_ip) => {
-                        let network_bytes = network_ip.octets();
-                        // 使用安全的掩码计算方式
-                        let mask = if network.prefix_len == 0 {
-                            0u32 // 对于 0.0.0.0/0，掩码为全0
-                        } else if network.prefix_len == 32 {
-                            u32::MAX // 对于单个IP地址，掩码为全1
-                        } else {
-                            !((1u32 << (32 - network.prefix_len)) - 1)
-                        };
-                        let network_addr = u32::from_be_bytes(network_bytes) & mask;
-                        
-                        let range = FWP_RANGE0 {
-                            valueLow: FWP_VALUE0 {
-                                r#type: FWP_UINT32,
-                                Anonymous: FWP_VALUE0_0 {
-                                    uint32: network_addr,
-                                },
-                            },
-                            valueHigh: FWP_VALUE0 {
-                                r#type: FWP_UINT32,
-                                Anonymous: FWP_VALUE0_0 {
-                                    uint32: network_addr | !mask,
-                                },
-                            },
-                        };
-                        
-                        conditions.push(FWPM_FILTER_CONDITION0 {
-                            fieldKey: FWPM_CONDITION_IP_REMOTE_ADDRESS,
-                            matchType: FWP_MATCH_RANGE,
-                            conditionValue: FWP_CONDITION_VALUE0 {
-                                r#type: FWP_RANGE_TYPE,
-                                Anonymous: FWP_CONDITION_VALUE0_0 {
-                                    rangeValue: &range as *const _ as *mut _,
-                                },
-                            },
-                        });
-                        println!("✓ 远程IPv4网段条件已添加: {}/{}", network_ip, network.prefix_len);
-                    },
-                    IpAddr::V6(_) => {
-                        println!("⚠️ IPv6网段过滤暂不支持，将跳过此条件");
-                    }
-                }
+        for &filter_id in &remove {
+            if let Err(e) = self.backend.delete_filter(filter_id) {
+                error!(filter_id, code = %e.code(), "删除过滤器失败，正在回滚事务: {}", e);
+                self.backend.abort_transaction()?;
+                return Err(e);
             }
+            debug!(filter_id, "过滤器已删除");
         }
-        
-        // 添加本地端口条件
-        if let Some(local_port) = rule.local_port {
-            conditions.push(FWPM_FILTER_CONDITION0 {
-                fieldKey: FWPM_CONDITION_IP_LOCAL_PORT,
-                matchType: FWP_MATCH_EQUAL,
-                conditionValue: FWP_CONDITION_VALUE0 {
-                    r#type: FWP_UINT16,
-                    Anonymous: FWP_CONDITION_VALUE0_0 {
-                        uint16: local_port,
-                    },
-                },
-            });
-            println!("✓ 本地端口条件已添加: {}", local_port);
-        } else if let Some((start_port, end_port)) = rule.local_port_range {
-            let range = FWP_RANGE0 {
-                valueLow: FWP_VALUE0 {
-                    r#type: FWP_UINT16,
-                    Anonymous: FWP_VALUE0_0 {
-                        uint16: start_port,
-                    },
-                },
-                valueHigh: FWP_VALUE0 {
-                    r#type: FWP_UINT16,
-                    Anonymous: FWP_VALUE0_0 {
-                        uint16: end_port,
-                    },
-                },
+        let mut failed = false;
+        let mut replaced_ids = Vec::new();
+        let mut applied = Vec::new();
+        for (rule, specs) in rules.iter().zip(plans) {
+            let _rule = info_span!("rule", id = %rule.id, name = %rule.name).entered();
+            let mut rule_report = RuleReport {
+                rule_id: rule.id,
+                rule_name: rule.name.clone(),
+                error: None,
+                layers: Vec::new(),
             };
-            
-            conditions.push(FWPM_FILTER_CONDITION0 {
-                fieldKey: FWPM_CONDITION_IP_LOCAL_PORT,
-                matchType: FWP_MATCH_RANGE,
-                conditionValue: FWP_CONDITION_VALUE0 {
-                    r#type: FWP_RANGE_TYPE,
-                    Anonymous: FWP_CONDITION_VALUE0_0 {
-                        rangeValue: &range as *const _ as *mut _,
-                    },
-                },
-            });
-            println!("✓ 本地端口范围条件已添加: {}-{}", start_port, end_port);
+            for spec in specs {
+                let _layer = debug_span!("layer", layer = layer_name(&spec.layer_key)).entered();
+                let mut replaced = None;
+                let status = if failed {
+                    LayerStatus::Skipped
+                } else {
+                    debug!(
+                        weight = spec.weight,
+                        conditions = %spec.conditions.iter().map(services::describe_condition).collect::<Vec<_>>().join("; "),
+                        "添加过滤器"
+                    );
+                    match self.replace_filter(&spec) {
+                        Ok((filter_id, old_id)) => {
+                            info!(filter_id, "过滤器添加成功");
+                            replaced = old_id;
+                            replaced_ids.extend(old_id);
+                            applied.push((filter_id, spec.clone()));
+                            LayerStatus::Applied(filter_id)
+                        },
+                        Err(e) => {
+                            error!(code = %e.code(), "过滤器添加失败: {}", e);
+                            failed = true;
+                            LayerStatus::Failed(e)
+                        }
+                    }
+                };
+                rule_report.layers.push(LayerReport { layer_key: spec.layer_key, status, replaced });
+            }
+            report.rules.push(rule_report);
         }
-        
-        // 添加远程端口条件
-        if let Some(remote_port) = rule.remote_port {
-            conditions.push(FWPM_FILTER_CONDITION0 {
-                fieldKey: FWPM_CONDITION_IP_REMOTE_PORT,
-                matchType: FWP_MATCH_EQUAL,
-                conditionValue: FWP_CONDITION_VALUE0 {
-                    r#type: FWP_UINT16,
-                    Anonymous: FWP_CONDITION_VALUE0_0 {
-                        uint16: remote_port,
-                    },
-                },
-            });
-            println!("✓ 远程端口条件已添加: {}", remote_port);
-        } else if let Some((start_port, end_port)) = rule.remote_port_range {
-            let range = FWP_RANGE0 {
-                valueLow: FWP_VALUE0 {
-                    r#type: FWP_UINT16,
-                    Anonymous: FWP_VALUE0_0 {
-                        uint16: start_port,
-                    },
-                },
-                valueHigh: FWP_VALUE0 {
-                    r#type: FWP_UINT16,
-                    Anonymous: FWP_VALUE0_0 {
-                        uint16: end_port,
-                    },
-                },
-            };
-            
-            conditions.push(FWPM_FILTER_CONDITION0 {
-                fieldKey: FWPM_CONDITION_IP_REMOTE_PORT,
-                matchType: FWP_MATCH_RANGE,
-                conditionValue: FWP_CONDITION_VALUE0 {
-                    r#type: FWP_RANGE_TYPE,
-                    Anonymous: FWP_CONDITION_VALUE0_0 {
-                        rangeValue: &range as *const _ as *mut _,
-                    },
-                },
-            });
-            println!("✓ 远程端口范围条件已添加: {}-{}", start_port, end_port);
+
+        if failed {
+            warn!("正在回滚事务");
+            self.backend.abort_transaction()?;
+            report.mark_rolled_back();
+            return Ok(report);
         }
-        
-        // 添加协议条件
-        if let Some(protocol) = &rule.protocol {
-            let protocol_value = match protocol {
-                Protocol::Tcp => 6u8,
-                Protocol::Udp => 17u8,
-                Protocol::Icmp => 1u8,
-                Protocol::IcmpV6 => 58u8,
-                Protocol::Igmp => 2u8,
-                Protocol::Ah => 51u8,
-                Protocol::Esp => 50u8,
-                Protocol::Gre => 47u8,
-                Protocol::Ipsec => 50u8,
-                Protocol::Any => 0u8,
-            };
-            
-            conditions.push(FWPM_FILTER_CONDITION0 {
-                fieldKey: FWPM_CONDITION_IP_PROTOCOL,
-                matchType: FWP_MATCH_EQUAL,
-                conditionValue: FWP_CONDITION_VALUE0 {
-                    r#type: FWP_UINT8,
-                    Anonymous: FWP_CONDITION_VALUE0_0 {
-                        uint8: protocol_value,
-                    },
-                },
-            });
-            println!("✓ 协议条件已添加: {:?}", protocol);
+
+        if let Err(e) = self.backend.commit_transaction() {
+            error!(code = %e.code(), "提交事务失败: {}", e);
+            return Err(e);
         }
-          // 获取条件数量
-        let num_conditions = conditions.len() as u32;
-        
-        // 确定过滤器动作
-        let action_type = match rule.action {
-            FilterAction::Allow => FWP_ACTION_PERMIT,
-            FilterAction::Block => FWP_ACTION_BLOCK,
-        };
+        report.committed = true;
+        report.removed = remove.clone();
+        info!(filters = report.filter_ids().len(), removed = remove.len(), "事务已提交");
+        self.forget(&replaced_ids);
+        self.forget(&remove);
+        self.filter_ids.extend(report.filter_ids());
+        self.applied.extend(applied);
+        Ok(report)
+    }
+
+    // 不再跟踪已删除的过滤器
+    fn forget(&mut self, filter_ids: &[u64]) {
+        self.filter_ids.retain(|id| !filter_ids.contains(id));
+        self.applied.retain(|(id, _)| !filter_ids.contains(id));
+    }
+
+    // 添加过滤器；同一 filterKey 的旧过滤器（同一规则之前应用的结果）先被删除，
+    // 因此重复应用同一规则不会产生重复的过滤器。返回新ID和被替换的旧ID
+    fn replace_filter(&mut self, spec: &FilterSpec) -> Result<(u64, Option<u64>)> {
+        let old_id = self.backend.find_filter(&spec.filter_key)?;
+        if let Some(old_id) = old_id {
+            debug!(old_id, "替换同一规则的旧过滤器");
+            self.backend.delete_filter(old_id)?;
+        }
+        Ok((self.backend.add_filter(spec)?, old_id))
+    }
 
-        // 根据是否有远程IP条件调整权重
-        let filter_weight = if rule.remote.is_some() {
-            unsafe { WEIGHT_VALUE += 10; WEIGHT_VALUE } // 远程IP过滤器权重更高
+    // 添加高级过滤器（支持复杂规则），全部成功或全部回滚
+    pub fn add_advanced_filters(&mut self, rules: &[FilterRule]) -> Result<Vec<u64>> {
+        let report = self.apply_rules(rules)?;
+        if report.committed {
+            Ok(report.filter_ids())
         } else {
-            unsafe { WEIGHT_VALUE += 1; WEIGHT_VALUE }
-        };
+            Err(report
+                .first_error()
+                .cloned()
+                .unwrap_or_else(|| AstralError::internal(message!("没有成功添加任何过滤器", "no filter was added"))))
+        }
+    }
 
-        // 创建过滤器结构
-        let filter = FWPM_FILTER0 {
-            filterKey: GUID::zeroed(),
-            displayData: FWPM_DISPLAY_DATA0 {
-                name: PWSTR(filter_name.as_ptr() as *mut u16),
-                description: PWSTR(filter_desc.as_ptr() as *mut u16),
-            },
-            flags: FWPM_FILTER_FLAGS(0),
-            providerKey: ptr::null_mut(),
-            providerData: FWP_BYTE_BLOB {
-                size: 0,
-                data: ptr::null_mut(),
-            },
-            layerKey: layer_key,
-            subLayerKey: FWPM_SUBLAYER_UNIVERSAL,            weight: FWP_VALUE0 {
-                r#type: FWP_UINT64,
-                Anonymous: FWP_VALUE0_0 {
-                    uint64: &filter_weight as *const u64 as *mut u64,
-                },
-            },
-            numFilterConditions: num_conditions,
-            filterCondition: if num_conditions > 0 {
-                conditions.as_ptr() as *mut _
-            } else {
-                ptr::null_mut()
-            },
-            action: FWPM_ACTION0 {
-                r#type: action_type,
-                Anonymous: FWPM_ACTION0_0 {
-                    calloutKey: GUID::zeroed(),
-                },
-            },
-            Anonymous: FWPM_FILTER0_0 {
-                rawContext: 0,
-            },
-            reserved: ptr::null_mut(),
-            filterId: 0,
-            effectiveWeight: FWP_VALUE0 {
-                r#type: FWP_UINT64,
-                Anonymous: FWP_VALUE0_0 {
-                    uint64: &raw mut EFFECTIVE_WEIGHT_VALUE as *mut u64,
-                },
-            },
-        };
+    // 预览规则将产生的过滤器，不修改引擎状态（dry run）
+    pub fn plan_filters(&self, rules: &[FilterRule]) -> Result<Vec<FilterSpec>> {
+        self.compiler.compile_all(rules)
+    }
+
+    // 根据规则获取对应的WFP层
+    pub fn get_layers_for_rule(&self, rule: &FilterRule) -> Vec<GUID> {
+        layers_for_rule(rule)
+    }
 
-        // 用于存储新添加的过滤器ID
-        let mut filter_id = 0u64;
-        // 添加过滤器到WFP引擎
-        let add_result = unsafe { FwpmFilterAdd0(self.engine_handle, &filter, None, Some(&mut filter_id)) };
+    // 关闭引擎。动态模式下先删除跟踪的过滤器（会话关闭时它们本来也会消失）；持久和启动时模式的过滤器
+    // 保留在引擎中，进程退出和重启后继续生效，只是不再跟踪。需要删除它们时先调用 remove_all
+    pub fn cleanup(&mut self) -> Result<()> {
+        let _cleanup = info_span!("cleanup", filters = self.filter_ids.len(), mode = %self.mode).entered();
 
-        // 检查添加结果
-        if WIN32_ERROR(add_result) == ERROR_SUCCESS {
-            Ok(filter_id)
+        if self.mode == EnforcementMode::Dynamic {
+            self.remove_all();
         } else {
-            let error_msg = match WIN32_ERROR(add_result) {
-                ERROR_ACCESS_DENIED => "访问被拒绝 - 需要管理员权限",
-                ERROR_INVALID_PARAMETER => "无效参数 - 检查过滤条件组合",
-                ERROR_NOT_SUPPORTED => "不支持的操作 - 检查WFP层和条件兼容性",
-                ERROR_ALREADY_EXISTS => "过滤器已存在",
-                ERROR_NOT_FOUND => "找不到指定的层或条件",
-                _ if add_result == 2150760450 => "FWP_E_INVALID_CONDITION - 条件组合无效，某些层不支持特定条件组合",
-                _ => "未知错误",
-            };
-            println!("❌ 添加过滤器 '{}' 失败: {} (错误代码: {})", rule.name, error_msg, add_result);
-            println!("   层: {:?}", layer_key);
-            println!("   条件数量: {}", num_conditions);
-            if rule.app_path.is_some() {
-                println!("   包含APP_ID条件: {}", should_add_app_id);
-            }
-            if rule.remote.is_some() {
-                println!("   包含远程IP条件: true");
+            info!(filters = self.filter_ids.len(), "保留{}模式的过滤器", self.mode);
+            self.filter_ids.clear();
+            self.applied.clear();
+        }
+
+        // 关闭引擎
+        if let Err(e) = self.backend.close_session() {
+            error!(code = %e.code(), "关闭WFP引擎失败: {}", e);
+            return Err(e);
+        }
+        info!("WFP引擎已关闭");
+        Ok(())
+    }
+
+    // 删除本控制器跟踪的全部过滤器，与执行模式无关；返回删除的数量，删除失败的过滤器只记录警告
+    pub fn remove_all(&mut self) -> u32 {
+        let mut deleted = 0;
+        self.applied.clear();
+        for filter_id in std::mem::take(&mut self.filter_ids) {
+            match self.backend.delete_filter(filter_id) {
+                Ok(()) => {
+                    deleted += 1;
+                    debug!(filter_id, "过滤器已删除");
+                },
+                Err(e) => warn!(filter_id, code = %e.code(), "删除过滤器失败: {}", e),
             }
-            Err(Error::from_win32())
         }
+        deleted
     }
 
     // 获取层的名称用于调试
     pub fn get_layer_name(&self, layer_key: &GUID) -> &'static str {
-        match *layer_key {
-            FWPM_LAYER_ALE_AUTH_CONNECT_V4 => "ALE_AUTH_CONNECT_V4",
-            FWPM_LAYER_ALE_AUTH_CONNECT_V6 => "ALE_AUTH_CONNECT_V6",
-            FWPM_LAYER_ALE_AUTH_RECV_ACCEPT_V4 => "ALE_AUTH_RECV_ACCEPT_V4",
-            FWPM_LAYER_ALE_AUTH_RECV_ACCEPT_V6 => "ALE_AUTH_RECV_ACCEPT_V6",
-            FWPM_LAYER_ALE_AUTH_LISTEN_V4 => "ALE_AUTH_LISTEN_V4",
-            FWPM_LAYER_ALE_AUTH_LISTEN_V6 => "ALE_AUTH_LISTEN_V6",
-            FWPM_LAYER_ALE_RESOURCE_ASSIGNMENT_V4 => "ALE_RESOURCE_ASSIGNMENT_V4",
-            FWPM_LAYER_ALE_RESOURCE_ASSIGNMENT_V6 => "ALE_RESOURCE_ASSIGNMENT_V6",
-            FWPM_LAYER_ALE_RESOURCE_RELEASE_V4 => "ALE_RESOURCE_RELEASE_V4",
-            FWPM_LAYER_ALE_RESOURCE_RELEASE_V6 => "ALE_RESOURCE_RELEASE_V6",
-            FWPM_LAYER_ALE_ENDPOINT_CLOSURE_V4 => "ALE_ENDPOINT_CLOSURE_V4",
-            FWPM_LAYER_ALE_ENDPOINT_CLOSURE_V6 => "ALE_ENDPOINT_CLOSURE_V6",
-            FWPM_LAYER_ALE_CONNECT_REDIRECT_V4 => "ALE_CONNECT_REDIRECT_V4",
-            FWPM_LAYER_ALE_CONNECT_REDIRECT_V6 => "ALE_CONNECT_REDIRECT_V6",
-            FWPM_LAYER_ALE_BIND_REDIRECT_V4 => "ALE_BIND_REDIRECT_V4",
-            FWPM_LAYER_ALE_BIND_REDIRECT_V6 => "ALE_BIND_REDIRECT_V6",
-            FWPM_LAYER_OUTBOUND_TRANSPORT_V4 => "OUTBOUND_TRANSPORT_V4",
-            FWPM_LAYER_OUTBOUND_TRANSPORT_V6 => "OUTBOUND_TRANSPORT_V6",
-            FWPM_LAYER_INBOUND_TRANSPORT_V4 => "INBOUND_TRANSPORT_V4",
-            FWPM_LAYER_INBOUND_TRANSPORT_V6 => "INBOUND_TRANSPORT_V6",
-            _ => "UNKNOWN_LAYER",
-        }
+        layer_name(layer_key)
     }
 
     // 删除指定的过滤器
     pub fn delete_filters(&mut self, filter_ids: &[u64]) -> Result<u32> {
-        unsafe {
-            let mut deleted_count = 0;
-            
-            for &filter_id in filter_ids {
-                let delete_result = FwpmFilterDeleteById0(self.engine_handle, filter_id);
-                if WIN32_ERROR(delete_result) == ERROR_SUCCESS {
+        let mut deleted_count = 0;
+        let mut last_error = None;
+
+        for &filter_id in filter_ids {
+            match self.backend.delete_filter(filter_id) {
+                Ok(()) => {
                     // 从内部列表中移除
-                    if let Some(pos) = self.filter_ids.iter().position(|&id| id == filter_id) {
-                        self.filter_ids.remove(pos);
-                    }
+                    self.forget(&[filter_id]);
                     deleted_count += 1;
-                    println!("✓ 过滤器 {} 已删除", filter_id);
-                } else {
-                    println!("⚠️ 删除过滤器 {} 失败: {}", filter_id, delete_result);
+                    debug!(filter_id, "过滤器已删除");
+                },
+                Err(e) => {
+                    warn!(filter_id, code = %e.code(), "删除过滤器失败: {}", e);
+                    last_error = Some(e);
                 }
             }
-            
-            if deleted_count > 0 {
-                Ok(deleted_count)
-            } else {
-                Err(Error::from_win32())
-            }
+        }
+
+        if deleted_count > 0 {
+            Ok(deleted_count)
+        } else {
+            Err(last_error.unwrap_or_else(|| AstralError::from_hresult(FWP_E_FILTER_NOT_FOUND, message!("没有删除任何过滤器", "no filter was deleted"))))
         }
     }
 
     // 删除单个过滤器
     pub fn remove_filter(&mut self, filter_id: u64) -> Result<()> {
-        unsafe {
-            let delete_result = FwpmFilterDeleteById0(self.engine_handle, filter_id);
-            if WIN32_ERROR(delete_result) == ERROR_SUCCESS {
+        match self.backend.delete_filter(filter_id) {
+            Ok(()) => {
                 // 从内部列表中移除
-                if let Some(pos) = self.filter_ids.iter().position(|&id| id == filter_id) {
-                    self.filter_ids.remove(pos);
-                }
-                println!("✓ 过滤器 {} 已删除", filter_id);
+                self.forget(&[filter_id]);
+                debug!(filter_id, "过滤器已删除");
                 Ok(())
-            } else {
-                println!("⚠️ 删除过滤器 {} 失败: {}", filter_id, delete_result);
-                Err(Error::from_win32())
+            },
+            Err(e) => {
+                warn!(filter_id, code = %e.code(), "删除过滤器失败: {}", e);
+                Err(e)
             }
         }
     }
 
-    // 获取所有规则（简化版本，返回当前添加的规则）
-    pub fn get_rules(&self) -> Result<Vec<FilterRule>> {
-        // 这是一个简化实现，实际应该从WFP引擎查询
-        // 由于WFP API复杂，这里返回一个空列表
-        // 在实际应用中，需要实现完整的WFP枚举功能
-        Ok(Vec::new())
+    // 枚举引擎中属于我们提供者的过滤器，并按来源规则重新分组
+    pub fn installed_rules(&mut self) -> Result<Vec<InstalledRule>> {
+        let records = self.backend.enum_filters(FilterScope::Provider(self.provider.provider_key))?;
+        Ok(group_records(&records, &self.provider.provider_key))
+    }
+
+    // 获取引擎中已安装的所有规则
+    pub fn get_rules(&mut self) -> Result<Vec<FilterRule>> {
+        Ok(self.installed_rules()?.into_iter().map(|installed| installed.rule).collect())
+    }
+
+    // 获取规则对应的过滤器ID，包括启动时模式下的启动时过滤器
+    pub fn get_filter_ids(&mut self, rule_id: &Uuid) -> Result<Vec<u64>> {
+        let Some(installed) = self.installed_rules()?.into_iter().find(|installed| installed.rule.id == *rule_id) else {
+            return Ok(Vec::new());
+        };
+        let mut filter_ids = installed.filter_ids;
+        filter_ids.extend(self.boot_time_filter_ids(&installed.rule)?);
+        Ok(filter_ids)
+    }
+
+    // 规则的启动时过滤器，按固定的 filterKey 查找
+    fn boot_time_filter_ids(&mut self, rule: &FilterRule) -> Result<Vec<u64>> {
+        let mut filter_ids = Vec::new();
+        for layer_key in layers_for_rule(rule) {
+            filter_ids.extend(self.backend.find_filter(&self.provider.boot_time_filter_key(&rule.id, &layer_key))?);
+        }
+        Ok(filter_ids)
     }
 
-    // 获取规则对应的过滤器ID
-    pub fn get_filter_ids(&self, _rule: &FilterRule) -> Result<Vec<u64>> {
-        // 简化实现，返回当前存储的过滤器ID
-        Ok(self.filter_ids.clone())
+    // 删除规则的所有过滤器，返回删除的数量
+    pub fn remove_rule(&mut self, rule_id: &Uuid) -> Result<u32> {
+        let filter_ids = self.get_filter_ids(rule_id)?;
+        if filter_ids.is_empty() {
+            return Err(AstralError::from_hresult(
+                FWP_E_FILTER_NOT_FOUND,
+                message!("规则 {} 没有已安装的过滤器", "rule {} has no installed filters", rule_id),
+            ));
+        }
+        let deleted = self.delete_filters(&filter_ids)?;
+        info!(rule = %rule_id, filters = deleted, "规则已删除");
+        Ok(deleted)
     }
 
     // 导出规则配置
-    pub fn export_rules(&self, file_path: &Path) -> Result<()> {
+    pub fn export_rules(&mut self, file_path: &Path) -> Result<()> {
         let config = RuleConfig {
             version: "1.0".to_string(),
-            rules: self.get_rules()?.into_iter().map(|rule| {
-                FilterRuleConfig {
-                    name: rule.name,
-                    app_path: rule.app_path,
-                    local_ip: rule.local,
-                    remote_ip: rule.remote,
-                    local_port: rule.local_port,
-                    remote_port: rule.remote_port,
-                    local_port_range: rule.local_port_range,
-                    remote_port_range: rule.remote_port_range,
-                    protocol: rule.protocol.map(|p| p.to_string()),
-                    direction: format!("{:?}", rule.direction),
-                    action: format!("{:?}", rule.action),
-                    priority: rule.priority,
-                    group: rule.group,
-                    enabled: rule.enabled,
-                    description: rule.description,
-                }
-            }).collect(),
+            rules: self.get_rules()?,
             groups: vec![], // TODO: 实现分组管理
             metadata: MetadataConfig {
                 created_at: SystemTime::now()
@@ -1189,90 +1100,186 @@ impl WfpController {
             },
         };
         
-        let json = serde_json::to_string_pretty(&config)
-            .map_err(|e| Error::new(windows::core::HRESULT(0x80004005u32 as i32), (&e.to_string()).into()))?;
+        let json = serde_json::to_string_pretty(&config)?;
         
         fs::write(file_path, json)
-            .map_err(|e| Error::new(windows::core::HRESULT(0x80004005u32 as i32), (&e.to_string()).into()))?;
+            .map_err(|e| AstralError::io(&e, message!("无法写入 {}", "cannot write {}", file_path.display())))?;
         
-        println!("✅ 规则配置已导出到: {:?}", file_path);
+        info!(path = %file_path.display(), "规则配置已导出");
         Ok(())
     }
     
-    // 导入规则配置
+    // 导入规则配置；先校验整个文件，有任何错误时不应用任何规则
     pub fn import_rules(&mut self, file_path: &Path) -> Result<()> {
-        let content = fs::read_to_string(file_path)
-            .map_err(|e| Error::new(windows::core::HRESULT(0x80004005u32 as i32), (&e.to_string()).into()))?;
-        
-        let config: RuleConfig = serde_json::from_str(&content)
-            .map_err(|e| Error::new(windows::core::HRESULT(0x80004005u32 as i32), (&e.to_string()).into()))?;
-        
-        let rules: Vec<FilterRule> = config.rules.into_iter().map(|rule_config| {
-            let mut rule = FilterRule::new(&rule_config.name)
-                .priority(rule_config.priority)
-                .enabled(rule_config.enabled);
-            
-            if let Some(app_path) = rule_config.app_path {
-                rule = rule.app_path(&app_path);
-            }
-            if let Some(local_ip) = rule_config.local_ip {
-                rule = rule.local_ip(&local_ip);
-            }
-            if let Some(remote_ip) = rule_config.remote_ip {
-                rule = rule.remote_ip(&remote_ip);
-            }
-            if let Some(local_port) = rule_config.local_port {
-                rule = rule.local_port(local_port);
-            }
-            if let Some(remote_port) = rule_config.remote_port {
-                rule = rule.remote_port(remote_port);
-            }
-            if let Some((start, end)) = rule_config.local_port_range {
-                rule = rule.local_port_range(start, end);
-            }
-            if let Some((start, end)) = rule_config.remote_port_range {
-                rule = rule.remote_port_range(start, end);
-            }
-            if let Some(protocol_str) = rule_config.protocol {
-                if let Ok(protocol) = protocol_str.parse::<Protocol>() {
-                    rule = rule.protocol(protocol);
-                }
-            }
-            
-            // 解析方向和动作
-            match rule_config.direction.as_str() {
-                "Inbound" => rule = rule.direction(Direction::Inbound),
-                "Outbound" => rule = rule.direction(Direction::Outbound),
-                "Both" => rule = rule.direction(Direction::Both),
-                _ => rule = rule.direction(Direction::Both),
-            }
-            
-            match rule_config.action.as_str() {
-                "Allow" => rule = rule.action(FilterAction::Allow),
-                "Block" => rule = rule.action(FilterAction::Block),
-                _ => rule = rule.action(FilterAction::Block),
-            }
-            
-            if let Some(group) = rule_config.group {
-                rule = rule.group(&group);
-            }
-            if let Some(description) = rule_config.description {
-                rule = rule.description(&description);
-            }
-            
-            rule
-        }).collect();
-        
+        let rules = RuleConfig::load(file_path)?.rules;
+        check_rules(&rules, Message::plain(file_path.display().to_string()))?;
+
         // 应用导入的规则
         self.add_advanced_filters(&rules)?;
         
-        println!("✅ 规则配置已从 {:?} 导入，共导入 {} 条规则", file_path, rules.len());
+        info!(path = %file_path.display(), rules = rules.len(), "规则配置已导入");
         Ok(())
     }
+
+    // 计算让引擎与期望规则集一致所需的变更，不修改引擎状态
+    pub fn plan_reconcile(&mut self, desired: &[FilterRule]) -> Result<ReconcilePlan> {
+        check_rules(desired, message!("期望的规则集", "the desired rule set"))?;
+        let installed = self.installed_rules()?;
+        // 启动时过滤器不挂在我们的提供者下，枚举不到，因此不计入
+        let (compiler, provider_key) = (&self.compiler, self.provider.provider_key);
+        let expected_filters = |rule: &FilterRule| {
+            compiler.compile(rule).map_or(0, |specs| specs.iter().filter(|s| s.provider_key == provider_key).count())
+        };
+        let mut plan = ReconcilePlan::new(desired, &installed, self.mode, expected_filters)?;
+        // 启动时过滤器不在分组中，按删除和被替换的规则的固定 filterKey 查找
+        for step in &plan.steps {
+            let installed = match step {
+                ReconcileStep::Remove(installed) => installed,
+                ReconcileStep::Replace { installed, .. } => installed.as_ref(),
+                ReconcileStep::Add(_) => continue,
+            };
+            plan.boot_time_filters.extend(self.boot_time_filter_ids(&installed.rule)?);
+        }
+        Ok(plan)
+    }
+
+    // 在一个事务中执行变更计划：删除和替换的旧过滤器与新过滤器一起提交或一起回滚
+    pub fn apply_reconcile(&mut self, plan: &ReconcilePlan) -> Result<ApplyReport> {
+        let _reconcile = info_span!("reconcile", steps = plan.steps.len(), unchanged = plan.unchanged.len()).entered();
+        self.apply_batch(&plan.rules_to_apply(), &plan.filters_to_remove())
+    }
+
+    // 让引擎中的规则与期望规则集一致，返回执行的计划和应用报告
+    pub fn reconcile(&mut self, desired: &[FilterRule]) -> Result<(ReconcilePlan, ApplyReport)> {
+        let plan = self.plan_reconcile(desired)?;
+        let report = self.apply_reconcile(&plan)?;
+        Ok((plan, report))
+    }
+
+    // 已提交的过滤器ID及其编译结果
+    pub fn applied_filters(&self) -> &[(u64, FilterSpec)] {
+        &self.applied
+    }
+
+    // 把引擎中的过滤器与已应用的记录比较，按 heal 策略立即修复发现的漂移
+    pub fn audit(&mut self, heal: HealPolicy) -> Result<AuditReport> {
+        let _audit = info_span!("audit", filters = self.applied.len(), heal = %heal).entered();
+        // 启动时过滤器不引用我们的提供者和子层，BFE 启动后也会被系统移除，不参与审计
+        let mut audited: Vec<(u64, FilterSpec)> =
+            self.applied.iter().filter(|(_, spec)| spec.mode != EnforcementMode::BootTime).cloned().collect();
+        // 我们的过滤器按提供者枚举；外来过滤器只能在我们的子层中找到
+        let mut records = self.backend.enum_filters(FilterScope::Provider(self.provider.provider_key))?;
+        for record in self.backend.enum_filters(FilterScope::Sublayer(self.provider.sublayer_key))? {
+            if !records.iter().any(|r| r.filter_id == record.filter_id) {
+                records.push(record);
+            }
+        }
+        // 本控制器没有记录的、我们提供者的过滤器按存储的规则重建记录状态
+        let (rebuilt, unrecognized) = rebuild_recorded(&audited, &records, &self.provider);
+        audited.extend(rebuilt);
+        let mut events = detect_drift(&audited, &records, &self.provider);
+        let foreign_at = events.iter().position(|event| event.kind == DriftKind::Foreign).unwrap_or(events.len());
+        events.splice(foreign_at..foreign_at, unrecognized);
+        let mut report = AuditReport {
+            checked: audited.len(),
+            events,
+            healed: None,
+        };
+        for event in &report.events {
+            warn!(
+                kind = %event.kind,
+                filter_id = event.filter_id,
+                filter_key = ?event.filter_key,
+                layer = layer_name(&event.layer_key),
+                rule = ?event.rule_id,
+                "检测到漂移: {}",
+                event.name
+            );
+        }
+        if report.events.iter().any(|event| heal.heals(&event.kind)) {
+            report.healed = Some(self.heal(&report.events, heal == HealPolicy::RestoreAndRemoveForeign)?);
+        }
+        Ok(report)
+    }
+
+    // 在一个事务中修复漂移：重新应用有缺失或被修改过滤器的规则，删除我们提供者下无法识别的过滤器；
+    // remove_foreign 为真时同时删除外来过滤器，否则外来过滤器保持原样。
+    // 本控制器没有记录的规则从引擎中存储的规则元数据取得，执行模式与控制器不同的规则跳过
+    pub fn heal(&mut self, events: &[DriftEvent], remove_foreign: bool) -> Result<ApplyReport> {
+        let mut rules: Vec<FilterRule> = Vec::new();
+        let mut stale = Vec::new();
+        let mut remove = Vec::new();
+        let mut installed: Option<Vec<InstalledRule>> = None;
+        for event in events {
+            match event.kind {
+                DriftKind::Foreign => {
+                    if remove_foreign {
+                        remove.push(event.filter_id);
+                    }
+                    continue;
+                },
+                DriftKind::Unrecognized => {
+                    remove.push(event.filter_id);
+                    continue;
+                },
+                DriftKind::Missing | DriftKind::Modified(_) => {},
+            }
+            // 记录中的旧ID已不在引擎中（或属于替换后的过滤器），修复成功后不再跟踪
+            let recorded = self.applied.iter().find(|(_, spec)| spec.filter_key == event.filter_key);
+            let rule = match recorded {
+                Some((filter_id, spec)) => {
+                    stale.push(*filter_id);
+                    RuleMetadata::from_bytes(&spec.provider_data)?.rule
+                },
+                None => {
+                    if installed.is_none() {
+                        installed = Some(self.installed_rules()?);
+                    }
+                    let found = installed.iter().flatten().find(|installed| Some(installed.rule.id) == event.rule_id);
+                    let Some(found) = found else { continue };
+                    // 以本控制器的模式重新应用会改变规则的执行模式，交给同一模式的控制器修复
+                    if found.mode != self.mode {
+                        warn!(rule = %found.rule.id, mode = %found.mode, "规则的执行模式与控制器不同，跳过修复");
+                        continue;
+                    }
+                    found.rule.clone()
+                },
+            };
+            if !rules.iter().any(|r| r.id == rule.id) {
+                rules.push(rule);
+            }
+        }
+        let report = self.apply_batch(&rules, &remove)?;
+        if report.committed {
+            self.forget(&stale);
+            info!(rules = rules.len(), removed = remove.len(), "漂移已修复");
+        }
+        Ok(report)
+    }
+}
+
+// 校验规则集：警告写入日志，有任何错误时返回汇总全部错误的校验错误
+fn check_rules(rules: &[FilterRule], source: Message) -> Result<()> {
+    let diagnostics = validate_rules(rules);
+    for d in diagnostics.iter().filter(|d| !d.diagnostic.is_error()) {
+        warn!(rule = %d.rule_id, field = %d.diagnostic.field, "{}", d);
+    }
+    let errors: Vec<_> = diagnostics.iter().filter(|d| d.diagnostic.is_error()).collect();
+    if errors.is_empty() {
+        return Ok(());
+    }
+    for d in &errors {
+        error!(rule = %d.rule_id, field = %d.diagnostic.field, "{}", d);
+    }
+    let summary = |language| errors.iter().map(|d| d.localized(language)).collect::<Vec<_>>().join("; ");
+    Err(AstralError::validation("rules", source.map(|language, source| match language {
+        Language::Chinese => format!("{} 中有 {} 个错误: {}", source, errors.len(), summary(language)),
+        Language::English => format!("{} has {} error(s): {}", source, errors.len(), summary(language)),
+    })))
 }
 
 // 时间控制结构体
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
 pub struct TimeControl {
     pub start_time: Option<u64>,    // 开始时间戳（Unix时间戳）
     pub end_time: Option<u64>,      // 结束时间戳（Unix时间戳）
@@ -1280,6 +1287,12 @@ pub struct TimeControl {
     pub hours: Option<(u8, u8)>,    // 小时范围 (start_hour, end_hour)
 }
 
+impl Default for TimeControl {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl TimeControl {
     pub fn new() -> Self {
         Self {
@@ -1317,17 +1330,15 @@ impl TimeControl {
             .as_secs();
         
         // 检查时间范围
-        if let Some(start) = self.start_time {
-            if now < start {
+        if let Some(start) = self.start_time
+            && now < start {
                 return false;
             }
-        }
         
-        if let Some(end) = self.end_time {
-            if now > end {
+        if let Some(end) = self.end_time
+            && now > end {
                 return false;
             }
-        }
         
         // 检查星期几
         if let Some(days) = &self.days_of_week {
@@ -1402,7 +1413,7 @@ impl TrafficStats {
 // 规则统计结构体
 #[derive(Debug, Clone)]
 pub struct RuleStats {
-    pub rule_id: String,
+    pub rule_id: Uuid,
     pub rule_name: String,
     pub traffic_stats: TrafficStats,
     pub hit_count: u64,
@@ -1414,28 +1425,19 @@ pub struct RuleStats {
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct RuleConfig {
     pub version: String,
-    pub rules: Vec<FilterRuleConfig>,
+    pub rules: Vec<FilterRule>,
     pub groups: Vec<GroupConfig>,
     pub metadata: MetadataConfig,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
-pub struct FilterRuleConfig {
-    pub name: String,
-    pub app_path: Option<String>,
-    pub local_ip: Option<String>,
-    pub remote_ip: Option<String>,
-    pub local_port: Option<u16>,
-    pub remote_port: Option<u16>,
-    pub local_port_range: Option<(u16, u16)>,
-    pub remote_port_range: Option<(u16, u16)>,
-    pub protocol: Option<String>,
-    pub direction: String,
-    pub action: String,
-    pub priority: u32,
-    pub group: Option<String>,
-    pub enabled: bool,
-    pub description: Option<String>,
+impl RuleConfig {
+    // 读取规则配置文件
+    pub fn load(file_path: &Path) -> Result<Self> {
+        let content = fs::read_to_string(file_path)
+            .map_err(|e| AstralError::io(&e, message!("无法读取 {}", "cannot read {}", file_path.display())))?;
+        serde_json::from_str(&content)
+            .map_err(|e| AstralError::parse(Message::plain(file_path.display().to_string()), Message::from_serde(&e.to_string())))
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
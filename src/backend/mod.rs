@@ -0,0 +1,100 @@
+// 防火墙后端抽象
+//
+// WfpController 不直接调用 Fwpm* API，而是通过 FirewallBackend 与过滤引擎交互：
+// Windows 上使用真实的 WFP 实现，其他平台（以及测试）使用内存模拟引擎。
+
+use windows::core::GUID;
+use crate::astral_wfp::FilterAction;
+use crate::plan::{EnforcementMode, FilterSpec};
+use crate::provider::ProviderConfig;
+
+mod simulated;
+#[cfg(windows)]
+mod wfp;
+
+pub use simulated::{SimulatedBackend, SimulatedFilter, SimulatedProvider};
+#[cfg(windows)]
+pub use wfp::WfpBackend;
+
+// 当前平台默认使用的后端
+#[cfg(windows)]
+pub type DefaultBackend = WfpBackend;
+#[cfg(not(windows))]
+pub type DefaultBackend = SimulatedBackend;
+
+pub use crate::error::{AstralError, Result};
+
+// 引擎中一个过滤器的摘要信息
+#[derive(Debug, Clone, PartialEq)]
+pub struct FilterRecord {
+    pub filter_id: u64,
+    pub filter_key: GUID,
+    pub provider_key: GUID, // 没有提供者时为全零
+    pub provider_data: Vec<u8>,
+    pub layer_key: GUID,
+    pub sublayer_key: GUID,
+    pub name: String,
+    pub action: FilterAction,
+    pub weight: u64,
+    pub mode: EnforcementMode,
+}
+
+// 枚举过滤器的范围。WFP 只能按层枚举，因此真实后端逐个枚举 KNOWN_LAYERS 中的层，不遍历系统中的全部过滤器
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum FilterScope {
+    Provider(GUID), // 引用指定提供者的过滤器
+    Sublayer(GUID), // 指定子层中的过滤器，包括其他提供者添加的
+}
+
+impl FilterScope {
+    pub fn contains(&self, record: &FilterRecord) -> bool {
+        match self {
+            FilterScope::Provider(provider_key) => record.provider_key == *provider_key,
+            FilterScope::Sublayer(sublayer_key) => record.sublayer_key == *sublayer_key,
+        }
+    }
+}
+
+// 对后端的一次调用，模拟引擎按顺序记录下来供测试断言
+#[derive(Debug, Clone, PartialEq)]
+pub enum BackendCall {
+    OpenSession(EnforcementMode),
+    CloseSession,
+    RegisterProvider { provider_key: GUID, sublayer_key: GUID, sublayer_weight: u16, persistent: bool },
+    FindFilter(GUID),
+    AddFilter { name: String, layer_key: GUID },
+    DeleteFilter(u64),
+    EnumFilters(FilterScope),
+    BeginTransaction,
+    CommitTransaction,
+    AbortTransaction,
+}
+
+// 过滤引擎需要提供的全部操作
+pub trait FirewallBackend {
+    // 打开引擎会话；动态模式使用动态会话，会话关闭时其中添加的对象全部删除
+    fn open_session(&mut self, mode: EnforcementMode) -> Result<()>;
+
+    // 关闭引擎会话
+    fn close_session(&mut self) -> Result<()>;
+
+    // 注册提供者和子层，已存在时视为成功；非动态模式下注册为持久对象
+    fn register_provider(&mut self, provider: &ProviderConfig, mode: EnforcementMode) -> Result<()>;
+
+    // 按 filterKey 查找过滤器ID，不存在时返回 None
+    fn find_filter(&mut self, filter_key: &GUID) -> Result<Option<u64>>;
+
+    // 添加一个编译好的过滤器，返回引擎分配的过滤器ID
+    fn add_filter(&mut self, spec: &FilterSpec) -> Result<u64>;
+
+    // 按ID删除过滤器
+    fn delete_filter(&mut self, filter_id: u64) -> Result<()>;
+
+    // 枚举引擎中在指定范围内的过滤器
+    fn enum_filters(&mut self, scope: FilterScope) -> Result<Vec<FilterRecord>>;
+
+    // 事务：begin 之后的修改在 commit 时一起生效，abort 时全部丢弃
+    fn begin_transaction(&mut self) -> Result<()>;
+    fn commit_transaction(&mut self) -> Result<()>;
+    fn abort_transaction(&mut self) -> Result<()>;
+}
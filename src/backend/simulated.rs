@@ -0,0 +1,349 @@
+// 内存模拟的 WFP 引擎
+//
+// 分配过滤器ID、检查层与条件的兼容性、支持事务，并记录所有调用，
+// 使规则处理逻辑可以在没有管理员权限（或不在 Windows 上）时测试。
+
+use windows::core::GUID;
+use windows::Win32::Foundation::{
+    ERROR_INVALID_HANDLE, FWP_E_ALREADY_EXISTS, FWP_E_CONDITION_NOT_FOUND,
+    FWP_E_DYNAMIC_SESSION_IN_PROGRESS, FWP_E_FILTER_NOT_FOUND, FWP_E_LAYER_NOT_FOUND,
+    FWP_E_LIFETIME_MISMATCH, FWP_E_NO_TXN_IN_PROGRESS, FWP_E_PROVIDER_NOT_FOUND,
+    FWP_E_SUBLAYER_NOT_FOUND, FWP_E_TXN_IN_PROGRESS, FWP_E_TYPE_MISMATCH,
+};
+use windows::Win32::NetworkManagement::WindowsFilteringPlatform::*;
+use crate::astral_wfp::layer_name;
+use crate::plan::{ConditionField, EnforcementMode, FilterSpec};
+use crate::provider::ProviderConfig;
+use crate::error::{AstralError, Result};
+use crate::message;
+use super::{BackendCall, FilterRecord, FilterScope, FirewallBackend};
+
+// 模拟引擎中保存的过滤器
+#[derive(Debug, Clone)]
+pub struct SimulatedFilter {
+    pub record: FilterRecord,
+    pub spec: FilterSpec,
+}
+
+// 模拟引擎中注册的提供者和子层
+#[derive(Debug, Clone)]
+pub struct SimulatedProvider {
+    pub config: ProviderConfig,
+    pub persistent: bool,
+}
+
+#[derive(Debug, Clone)]
+pub struct SimulatedBackend {
+    session_open: bool,
+    dynamic_session: bool,
+    next_filter_id: u64,
+    filters: Vec<SimulatedFilter>,
+    providers: Vec<SimulatedProvider>,
+    transaction: Option<Vec<SimulatedFilter>>, // 事务开始时的快照
+    calls: Vec<BackendCall>,
+}
+
+impl SimulatedBackend {
+    pub fn new() -> Self {
+        Self {
+            session_open: false,
+            dynamic_session: false,
+            next_filter_id: 1,
+            filters: Vec::new(),
+            providers: Vec::new(),
+            transaction: None,
+            calls: Vec::new(),
+        }
+    }
+
+    // 当前引擎中的全部过滤器
+    pub fn filters(&self) -> &[SimulatedFilter] {
+        &self.filters
+    }
+
+    // 按调用顺序记录的所有操作
+    pub fn calls(&self) -> &[BackendCall] {
+        &self.calls
+    }
+
+    // 已注册的提供者和子层
+    pub fn providers(&self) -> &[SimulatedProvider] {
+        &self.providers
+    }
+
+    // 模拟重启：会话关闭，只有持久对象（包括启动时过滤器）保留下来
+    pub fn reboot(&mut self) {
+        self.session_open = false;
+        self.dynamic_session = false;
+        self.transaction = None;
+        self.filters.retain(|f| f.record.mode != EnforcementMode::Dynamic);
+        self.providers.retain(|p| p.persistent);
+    }
+
+    pub fn is_session_open(&self) -> bool {
+        self.session_open
+    }
+
+    pub fn in_transaction(&self) -> bool {
+        self.transaction.is_some()
+    }
+
+    fn ensure_session(&self) -> Result<()> {
+        if self.session_open {
+            Ok(())
+        } else {
+            Err(AstralError::wfp(ERROR_INVALID_HANDLE.0, message!("WFP会话未打开", "the WFP session is not open")))
+        }
+    }
+
+    // 层支持的条件字段和地址族；未知层返回 None
+    fn layer_fields(layer_key: &GUID) -> Option<(&'static [ConditionField], bool)> {
+        use ConditionField::*;
+        const ALE_FULL: &[ConditionField] =
+            &[AppId, LocalAddress, RemoteAddress, LocalPort, RemotePort, IcmpType, IcmpCode, Protocol];
+        const ALE_LISTEN: &[ConditionField] = &[AppId, LocalAddress, LocalPort];
+        const ALE_RESOURCE: &[ConditionField] = &[AppId, LocalAddress, LocalPort, Protocol];
+        const TRANSPORT: &[ConditionField] =
+            &[LocalAddress, RemoteAddress, LocalPort, RemotePort, IcmpType, IcmpCode, Protocol];
+
+        let entry = match *layer_key {
+            FWPM_LAYER_ALE_AUTH_CONNECT_V4
+            | FWPM_LAYER_ALE_AUTH_RECV_ACCEPT_V4
+            | FWPM_LAYER_ALE_ENDPOINT_CLOSURE_V4
+            | FWPM_LAYER_ALE_CONNECT_REDIRECT_V4 => (ALE_FULL, false),
+            FWPM_LAYER_ALE_AUTH_CONNECT_V6
+            | FWPM_LAYER_ALE_AUTH_RECV_ACCEPT_V6
+            | FWPM_LAYER_ALE_ENDPOINT_CLOSURE_V6
+            | FWPM_LAYER_ALE_CONNECT_REDIRECT_V6 => (ALE_FULL, true),
+            FWPM_LAYER_ALE_AUTH_LISTEN_V4 => (ALE_LISTEN, false),
+            FWPM_LAYER_ALE_AUTH_LISTEN_V6 => (ALE_LISTEN, true),
+            FWPM_LAYER_ALE_RESOURCE_ASSIGNMENT_V4 => (ALE_RESOURCE, false),
+            FWPM_LAYER_ALE_RESOURCE_ASSIGNMENT_V6 => (ALE_RESOURCE, true),
+            FWPM_LAYER_OUTBOUND_TRANSPORT_V4 | FWPM_LAYER_INBOUND_TRANSPORT_V4 => (TRANSPORT, false),
+            FWPM_LAYER_OUTBOUND_TRANSPORT_V6 | FWPM_LAYER_INBOUND_TRANSPORT_V6 => (TRANSPORT, true),
+            _ => return None,
+        };
+        Some(entry)
+    }
+
+    // 检查过滤器引用的提供者、子层已注册且生存期兼容，filterKey 没有被占用
+    fn check_references(&self, spec: &FilterSpec) -> Result<()> {
+        if self.dynamic_session && spec.mode != EnforcementMode::Dynamic {
+            return Err(AstralError::from_hresult(
+                FWP_E_DYNAMIC_SESSION_IN_PROGRESS,
+                message!("动态会话中不能添加{}过滤器 '{}'", "cannot add {:?} filter '{}' in a dynamic session", spec.mode, spec.display_name),
+            ));
+        }
+        // 启动时对象只能引用内置对象
+        if spec.mode == EnforcementMode::BootTime
+            && (spec.provider_key != GUID::zeroed() || spec.sublayer_key != FWPM_SUBLAYER_UNIVERSAL) {
+            return Err(AstralError::from_hresult(
+                FWP_E_LIFETIME_MISMATCH,
+                message!(
+                    "启动时过滤器 '{}' 不能引用非内置的提供者或子层",
+                    "boot-time filter '{}' cannot reference a non-builtin provider or sublayer",
+                    spec.display_name
+                ),
+            ));
+        }
+
+        if spec.provider_key != GUID::zeroed() {
+            let provider = self.providers.iter().find(|p| p.config.provider_key == spec.provider_key).ok_or_else(|| {
+                AstralError::from_hresult(FWP_E_PROVIDER_NOT_FOUND, message!("提供者 {:?} 未注册", "provider {:?} is not registered", spec.provider_key))
+            })?;
+            if spec.mode == EnforcementMode::Persistent && !provider.persistent {
+                return Err(AstralError::from_hresult(
+                    FWP_E_LIFETIME_MISMATCH,
+                    message!("持久过滤器 '{}' 不能引用非持久的提供者", "persistent filter '{}' cannot reference a non-persistent provider", spec.display_name),
+                ));
+            }
+        }
+        if spec.sublayer_key != FWPM_SUBLAYER_UNIVERSAL {
+            let sublayer = self.providers.iter().find(|p| p.config.sublayer_key == spec.sublayer_key).ok_or_else(|| {
+                AstralError::from_hresult(FWP_E_SUBLAYER_NOT_FOUND, message!("子层 {:?} 未注册", "sublayer {:?} is not registered", spec.sublayer_key))
+            })?;
+            if spec.mode == EnforcementMode::Persistent && !sublayer.persistent {
+                return Err(AstralError::from_hresult(
+                    FWP_E_LIFETIME_MISMATCH,
+                    message!("持久过滤器 '{}' 不能引用非持久的子层", "persistent filter '{}' cannot reference a non-persistent sublayer", spec.display_name),
+                ));
+            }
+        }
+        if spec.filter_key != GUID::zeroed()
+            && self.filters.iter().any(|f| f.record.filter_key == spec.filter_key) {
+            return Err(AstralError::from_hresult(
+                FWP_E_ALREADY_EXISTS,
+                message!("过滤器 '{}' 的 filterKey 已存在", "the filterKey of filter '{}' already exists", spec.display_name),
+            ));
+        }
+        Ok(())
+    }
+
+    // 检查过滤器的条件能否加在它的层上，错误码与真实 WFP 返回的一致
+    fn check_compatibility(spec: &FilterSpec) -> Result<()> {
+        let layer_key = &spec.layer_key;
+        let (supported, is_v6_layer) = Self::layer_fields(layer_key).ok_or_else(|| {
+            AstralError::from_hresult(FWP_E_LAYER_NOT_FOUND, message!("未知的WFP层: {:?}", "unknown WFP layer: {:?}", layer_key))
+        })?;
+
+        for condition in &spec.conditions {
+            if !supported.contains(&condition.field) {
+                return Err(AstralError::from_hresult(
+                    FWP_E_CONDITION_NOT_FOUND,
+                    message!("层 {} 不支持条件 {}", "layer {} does not support condition {}", layer_name(layer_key), condition.field),
+                ));
+            }
+            if condition.value.is_v6().is_some_and(|is_v6| is_v6 != is_v6_layer) {
+                return Err(AstralError::from_hresult(
+                    FWP_E_TYPE_MISMATCH,
+                    message!("条件 {} 与层 {} 的地址族不匹配", "condition {} does not match the address family of layer {}", condition, layer_name(layer_key)),
+                ));
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl Default for SimulatedBackend {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl FirewallBackend for SimulatedBackend {
+    fn open_session(&mut self, mode: EnforcementMode) -> Result<()> {
+        self.calls.push(BackendCall::OpenSession(mode));
+        self.session_open = true;
+        self.dynamic_session = mode == EnforcementMode::Dynamic;
+        Ok(())
+    }
+
+    fn close_session(&mut self) -> Result<()> {
+        self.calls.push(BackendCall::CloseSession);
+        self.ensure_session()?;
+        // 关闭会话时未提交的事务被丢弃
+        if let Some(snapshot) = self.transaction.take() {
+            self.filters = snapshot;
+        }
+        // 动态会话中添加的对象随会话一起删除
+        if self.dynamic_session {
+            self.filters.retain(|f| f.record.mode != EnforcementMode::Dynamic);
+            self.providers.retain(|p| p.persistent);
+        }
+        self.session_open = false;
+        self.dynamic_session = false;
+        Ok(())
+    }
+
+    fn register_provider(&mut self, provider: &ProviderConfig, mode: EnforcementMode) -> Result<()> {
+        let persistent = mode != EnforcementMode::Dynamic;
+        self.calls.push(BackendCall::RegisterProvider {
+            provider_key: provider.provider_key,
+            sublayer_key: provider.sublayer_key,
+            sublayer_weight: provider.sublayer_weight,
+            persistent,
+        });
+        self.ensure_session()?;
+        if self.dynamic_session && persistent {
+            return Err(AstralError::from_hresult(FWP_E_DYNAMIC_SESSION_IN_PROGRESS, message!("动态会话中不能注册持久的提供者", "cannot register a persistent provider in a dynamic session")));
+        }
+        // 与真实引擎一样，已存在的对象保持原样
+        if !self.providers.iter().any(|p| p.config.sublayer_key == provider.sublayer_key) {
+            self.providers.push(SimulatedProvider {
+                config: provider.clone(),
+                persistent,
+            });
+        }
+        Ok(())
+    }
+
+    fn find_filter(&mut self, filter_key: &GUID) -> Result<Option<u64>> {
+        self.calls.push(BackendCall::FindFilter(*filter_key));
+        self.ensure_session()?;
+        Ok(self
+            .filters
+            .iter()
+            .find(|f| f.record.filter_key == *filter_key)
+            .map(|f| f.record.filter_id))
+    }
+
+    fn add_filter(&mut self, spec: &FilterSpec) -> Result<u64> {
+        self.calls.push(BackendCall::AddFilter {
+            name: spec.display_name.clone(),
+            layer_key: spec.layer_key,
+        });
+        self.ensure_session()?;
+        self.check_references(spec)?;
+        Self::check_compatibility(spec)?;
+
+        let filter_id = self.next_filter_id;
+        self.next_filter_id += 1;
+        self.filters.push(SimulatedFilter {
+            record: FilterRecord {
+                filter_id,
+                filter_key: spec.filter_key,
+                provider_key: spec.provider_key,
+                provider_data: spec.provider_data.clone(),
+                layer_key: spec.layer_key,
+                sublayer_key: spec.sublayer_key,
+                name: spec.display_name.clone(),
+                action: spec.action.clone(),
+                weight: spec.weight,
+                mode: spec.mode,
+            },
+            spec: spec.clone(),
+        });
+        Ok(filter_id)
+    }
+
+    fn delete_filter(&mut self, filter_id: u64) -> Result<()> {
+        self.calls.push(BackendCall::DeleteFilter(filter_id));
+        self.ensure_session()?;
+        let pos = self
+            .filters
+            .iter()
+            .position(|f| f.record.filter_id == filter_id)
+            .ok_or_else(|| {
+                AstralError::from_hresult(FWP_E_FILTER_NOT_FOUND, message!("过滤器 {} 不存在", "filter {} does not exist", filter_id))
+            })?;
+        self.filters.remove(pos);
+        Ok(())
+    }
+
+    fn enum_filters(&mut self, scope: FilterScope) -> Result<Vec<FilterRecord>> {
+        self.calls.push(BackendCall::EnumFilters(scope));
+        self.ensure_session()?;
+        Ok(self.filters.iter().filter(|f| scope.contains(&f.record)).map(|f| f.record.clone()).collect())
+    }
+
+    fn begin_transaction(&mut self) -> Result<()> {
+        self.calls.push(BackendCall::BeginTransaction);
+        self.ensure_session()?;
+        if self.transaction.is_some() {
+            return Err(AstralError::from_hresult(FWP_E_TXN_IN_PROGRESS, message!("事务已在进行中", "a transaction is already in progress")));
+        }
+        self.transaction = Some(self.filters.clone());
+        Ok(())
+    }
+
+    fn commit_transaction(&mut self) -> Result<()> {
+        self.calls.push(BackendCall::CommitTransaction);
+        self.ensure_session()?;
+        self.transaction
+            .take()
+            .map(|_| ())
+            .ok_or_else(|| AstralError::from_hresult(FWP_E_NO_TXN_IN_PROGRESS, message!("没有进行中的事务", "no transaction is in progress")))
+    }
+
+    fn abort_transaction(&mut self) -> Result<()> {
+        self.calls.push(BackendCall::AbortTransaction);
+        self.ensure_session()?;
+        let snapshot = self
+            .transaction
+            .take()
+            .ok_or_else(|| AstralError::from_hresult(FWP_E_NO_TXN_IN_PROGRESS, message!("没有进行中的事务", "no transaction is in progress")))?;
+        self.filters = snapshot;
+        Ok(())
+    }
+}
@@ -0,0 +1,501 @@
+// 真实的 WFP 后端，通过 Fwpm* API 操作 Windows Filtering Platform
+
+use std::ptr;
+use tracing::{debug, error, info};
+use windows::core::{GUID, PWSTR};
+use windows::Win32::Foundation::*;
+use windows::Win32::NetworkManagement::WindowsFilteringPlatform::*;
+use windows::Win32::System::Rpc::RPC_C_AUTHN_DEFAULT;
+use crate::astral_wfp::{layer_name, to_wide_string, v4_mask, FilterAction, KNOWN_LAYERS};
+use crate::plan::{ConditionField, ConditionValue, EnforcementMode, FilterCondition, FilterSpec, MatchType};
+use crate::provider::ProviderConfig;
+use crate::error::{AstralError, Message, Result};
+use crate::message;
+use super::{FilterRecord, FilterScope, FirewallBackend};
+
+// WFP 常量定义
+const FWP_ACTION_BLOCK: u32 = 0x00000001 | 0x00001000;
+const FWP_ACTION_PERMIT: u32 = 0x00000002 | 0x00001000;
+
+// 每次 FwpmFilterEnum0 请求的过滤器数量
+const ENUM_BATCH_SIZE: u32 = 256;
+
+pub struct WfpBackend {
+    engine_handle: HANDLE,
+}
+
+impl WfpBackend {
+    pub fn new() -> Self {
+        Self {
+            engine_handle: HANDLE::default(),
+        }
+    }
+
+    // 把WFP返回的状态码转换为后端错误
+    fn check(status: u32, message: Message) -> Result<()> {
+        if WIN32_ERROR(status) == ERROR_SUCCESS {
+            Ok(())
+        } else {
+            Err(AstralError::wfp(status, message))
+        }
+    }
+
+    // 按模板枚举一个层中的过滤器，每批 ENUM_BATCH_SIZE 个
+    fn enum_layer(&self, template: &FWPM_FILTER_ENUM_TEMPLATE0) -> Result<Vec<FilterRecord>> {
+        let mut records = Vec::new();
+        unsafe {
+            let mut enum_handle = HANDLE::default();
+            let result = FwpmFilterCreateEnumHandle0(self.engine_handle, Some(template), &mut enum_handle);
+            Self::check(
+                result,
+                message!("创建层 {} 的过滤器枚举句柄失败", "failed to create the filter enumeration handle for layer {}", layer_name(&template.layerKey)),
+            )?;
+
+            loop {
+                let mut entries: *mut *mut FWPM_FILTER0 = ptr::null_mut();
+                let mut count = 0u32;
+                let result = FwpmFilterEnum0(self.engine_handle, enum_handle, ENUM_BATCH_SIZE, &mut entries, &mut count);
+                if let Err(e) = Self::check(result, message!("枚举过滤器失败", "failed to enumerate filters")) {
+                    FwpmFilterDestroyEnumHandle0(self.engine_handle, enum_handle);
+                    return Err(e);
+                }
+                for i in 0..count as usize {
+                    records.push(Self::record_from_raw(&**entries.add(i)));
+                }
+                if !entries.is_null() {
+                    FwpmFreeMemory0(&mut entries as *mut _ as *mut *mut std::ffi::c_void);
+                }
+                if count < ENUM_BATCH_SIZE {
+                    break;
+                }
+            }
+
+            let result = FwpmFilterDestroyEnumHandle0(self.engine_handle, enum_handle);
+            Self::check(result, message!("关闭过滤器枚举句柄失败", "failed to close the filter enumeration handle"))?;
+        }
+        Ok(records)
+    }
+
+    // 把 FilterSpec 转换为WFP过滤器结构并添加到引擎
+    unsafe fn add_filter_raw(&self, spec: &FilterSpec) -> Result<u64> {
+        // 将过滤器名称和描述转换为宽字符串
+        let filter_name = to_wide_string(&spec.display_name);
+        let filter_desc = to_wide_string(&spec.description);
+
+        // 条件值引用的数据必须在 FwpmFilterAdd0 返回前保持有效
+        let mut storage = ConditionStorage::default();
+        let conditions: Vec<FWPM_FILTER_CONDITION0> = spec
+            .conditions
+            .iter()
+            .map(|condition| storage.marshal(condition))
+            .collect();
+        let num_conditions = conditions.len() as u32;
+
+        // 确定过滤器动作
+        let action_type = match spec.action {
+            FilterAction::Allow => FWP_ACTION_PERMIT,
+            FilterAction::Block => FWP_ACTION_BLOCK,
+        };
+
+        let mut filter_weight = spec.weight;
+        let mut effective_weight = 0u64;
+        let mut provider_key = spec.provider_key;
+        let mut provider_data = spec.provider_data.clone();
+        let flags = match spec.mode {
+            EnforcementMode::Dynamic => FWPM_FILTER_FLAGS(0),
+            EnforcementMode::Persistent => FWPM_FILTER_FLAG_PERSISTENT,
+            EnforcementMode::BootTime => FWPM_FILTER_FLAG_BOOTTIME,
+        };
+
+        // 创建过滤器结构
+        let filter = FWPM_FILTER0 {
+            filterKey: spec.filter_key,
+            displayData: FWPM_DISPLAY_DATA0 {
+                name: PWSTR(filter_name.as_ptr() as *mut u16),
+                description: PWSTR(filter_desc.as_ptr() as *mut u16),
+            },
+            flags,
+            // 启动时过滤器不引用提供者
+            providerKey: if provider_key == GUID::zeroed() {
+                ptr::null_mut()
+            } else {
+                &mut provider_key
+            },
+            providerData: FWP_BYTE_BLOB {
+                size: provider_data.len() as u32,
+                data: if provider_data.is_empty() {
+                    ptr::null_mut()
+                } else {
+                    provider_data.as_mut_ptr()
+                },
+            },
+            layerKey: spec.layer_key,
+            subLayerKey: spec.sublayer_key,
+            weight: FWP_VALUE0 {
+                r#type: FWP_UINT64,
+                Anonymous: FWP_VALUE0_0 {
+                    uint64: &mut filter_weight,
+                },
+            },
+            numFilterConditions: num_conditions,
+            filterCondition: if num_conditions > 0 {
+                conditions.as_ptr() as *mut _
+            } else {
+                ptr::null_mut()
+            },
+            action: FWPM_ACTION0 {
+                r#type: action_type,
+                Anonymous: FWPM_ACTION0_0 {
+                    calloutKey: GUID::zeroed(),
+                },
+            },
+            Anonymous: FWPM_FILTER0_0 {
+                rawContext: 0,
+            },
+            reserved: ptr::null_mut(),
+            filterId: 0,
+            effectiveWeight: FWP_VALUE0 {
+                r#type: FWP_UINT64,
+                Anonymous: FWP_VALUE0_0 {
+                    uint64: &mut effective_weight,
+                },
+            },
+        };
+
+        // 用于存储新添加的过滤器ID
+        let mut filter_id = 0u64;
+        // 添加过滤器到WFP引擎
+        let add_result = unsafe { FwpmFilterAdd0(self.engine_handle, &filter, None, Some(&mut filter_id)) };
+
+        // 检查添加结果
+        if WIN32_ERROR(add_result) == ERROR_SUCCESS {
+            Ok(filter_id)
+        } else {
+            let (error_msg, english) = match WIN32_ERROR(add_result) {
+                ERROR_ACCESS_DENIED => ("访问被拒绝 - 需要管理员权限", "access denied - administrator rights are required"),
+                ERROR_INVALID_PARAMETER => ("无效参数 - 检查过滤条件组合", "invalid parameter - check the combination of conditions"),
+                ERROR_NOT_SUPPORTED => ("不支持的操作 - 检查WFP层和条件兼容性", "operation not supported - check that the conditions fit the WFP layer"),
+                ERROR_ALREADY_EXISTS => ("过滤器已存在", "the filter already exists"),
+                ERROR_NOT_FOUND => ("找不到指定的层或条件", "the layer or condition was not found"),
+                _ if add_result == FWP_E_CONDITION_NOT_FOUND.0 as u32 => (
+                    "FWP_E_CONDITION_NOT_FOUND - 条件组合无效，某些层不支持特定条件组合",
+                    "FWP_E_CONDITION_NOT_FOUND - invalid combination of conditions, some layers do not support it",
+                ),
+                _ => ("未知错误", "unknown error"),
+            };
+            error!(
+                filter = %spec.display_name,
+                layer = layer_name(&spec.layer_key),
+                status = add_result,
+                "添加过滤器失败: {}",
+                error_msg
+            );
+            for condition in &spec.conditions {
+                debug!("条件: {}", condition);
+            }
+            Err(AstralError::wfp(add_result, Message::new(
+                format!("添加过滤器 '{}' 失败: {}", spec.display_name, error_msg),
+                format!("failed to add filter '{}': {}", spec.display_name, english),
+            )))
+        }
+    }
+
+    // 把引擎返回的过滤器结构转换为摘要信息
+    unsafe fn record_from_raw(raw: &FWPM_FILTER0) -> FilterRecord {
+        let weight = if raw.weight.r#type == FWP_UINT64 && !unsafe { raw.weight.Anonymous.uint64 }.is_null() {
+            unsafe { *raw.weight.Anonymous.uint64 }
+        } else {
+            0
+        };
+        let provider_data = if raw.providerData.size > 0 && !raw.providerData.data.is_null() {
+            unsafe { std::slice::from_raw_parts(raw.providerData.data, raw.providerData.size as usize) }.to_vec()
+        } else {
+            Vec::new()
+        };
+        FilterRecord {
+            filter_id: raw.filterId,
+            filter_key: raw.filterKey,
+            provider_key: if raw.providerKey.is_null() { GUID::zeroed() } else { unsafe { *raw.providerKey } },
+            provider_data,
+            layer_key: raw.layerKey,
+            sublayer_key: raw.subLayerKey,
+            name: unsafe { raw.displayData.name.to_string() }.unwrap_or_default(),
+            action: if raw.action.r#type == FWP_ACTION_PERMIT {
+                FilterAction::Allow
+            } else {
+                FilterAction::Block
+            },
+            weight,
+            mode: if raw.flags.0 & FWPM_FILTER_FLAG_BOOTTIME.0 != 0 {
+                EnforcementMode::BootTime
+            } else if raw.flags.0 & FWPM_FILTER_FLAG_PERSISTENT.0 != 0 {
+                EnforcementMode::Persistent
+            } else {
+                EnforcementMode::Dynamic
+            },
+        }
+    }
+}
+
+// 条件值背后的数据；每个值单独装箱，保证地址在整个添加过程中不变
+#[allow(clippy::vec_box)]
+#[derive(Default)]
+struct ConditionStorage {
+    wide_strings: Vec<Vec<u16>>,
+    blobs: Vec<Box<FWP_BYTE_BLOB>>,
+    byte_arrays: Vec<Box<FWP_BYTE_ARRAY16>>,
+    v4_masks: Vec<Box<FWP_V4_ADDR_AND_MASK>>,
+    v6_masks: Vec<Box<FWP_V6_ADDR_AND_MASK>>,
+    ranges: Vec<Box<FWP_RANGE0>>,
+}
+
+impl ConditionStorage {
+    fn marshal(&mut self, condition: &FilterCondition) -> FWPM_FILTER_CONDITION0 {
+        let field_key = match condition.field {
+            ConditionField::AppId => FWPM_CONDITION_ALE_APP_ID,
+            ConditionField::LocalAddress => FWPM_CONDITION_IP_LOCAL_ADDRESS,
+            ConditionField::RemoteAddress => FWPM_CONDITION_IP_REMOTE_ADDRESS,
+            ConditionField::LocalPort => FWPM_CONDITION_IP_LOCAL_PORT,
+            ConditionField::RemotePort => FWPM_CONDITION_IP_REMOTE_PORT,
+            // FWPM_CONDITION_ICMP_TYPE / FWPM_CONDITION_ICMP_CODE 是端口字段的别名
+            ConditionField::IcmpType => FWPM_CONDITION_IP_LOCAL_PORT,
+            ConditionField::IcmpCode => FWPM_CONDITION_IP_REMOTE_PORT,
+            ConditionField::Protocol => FWPM_CONDITION_IP_PROTOCOL,
+        };
+        let match_type = match condition.match_type {
+            MatchType::Equal => FWP_MATCH_EQUAL,
+            MatchType::NotEqual => FWP_MATCH_NOT_EQUAL,
+            MatchType::Range => FWP_MATCH_RANGE,
+        };
+        let (value_type, value) = match &condition.value {
+            ConditionValue::AppId(app_path) => {
+                let appid_utf16 = to_wide_string(app_path);
+                let mut blob = Box::new(FWP_BYTE_BLOB {
+                    size: (appid_utf16.len() * 2) as u32,
+                    data: appid_utf16.as_ptr() as *mut u8,
+                });
+                let value = FWP_CONDITION_VALUE0_0 { byteBlob: &mut *blob };
+                self.wide_strings.push(appid_utf16);
+                self.blobs.push(blob);
+                (FWP_BYTE_BLOB_TYPE, value)
+            },
+            ConditionValue::V4Addr(ip) => (FWP_UINT32, FWP_CONDITION_VALUE0_0 { uint32: u32::from(*ip) }),
+            ConditionValue::V6Addr(ip) => {
+                let mut byte_array = Box::new(FWP_BYTE_ARRAY16 { byteArray16: ip.octets() });
+                let value = FWP_CONDITION_VALUE0_0 { byteArray16: &mut *byte_array };
+                self.byte_arrays.push(byte_array);
+                (FWP_BYTE_ARRAY16_TYPE, value)
+            },
+            ConditionValue::V4Network(ip, prefix_len) => {
+                let mut addr_mask = Box::new(FWP_V4_ADDR_AND_MASK { addr: u32::from(*ip), mask: v4_mask(*prefix_len) });
+                let value = FWP_CONDITION_VALUE0_0 { v4AddrMask: &mut *addr_mask };
+                self.v4_masks.push(addr_mask);
+                (FWP_V4_ADDR_MASK, value)
+            },
+            ConditionValue::V6Network(ip, prefix_len) => {
+                let mut addr_mask = Box::new(FWP_V6_ADDR_AND_MASK { addr: ip.octets(), prefixLength: *prefix_len });
+                let value = FWP_CONDITION_VALUE0_0 { v6AddrMask: &mut *addr_mask };
+                self.v6_masks.push(addr_mask);
+                (FWP_V6_ADDR_MASK, value)
+            },
+            ConditionValue::V4Range(low, high) => self.range(
+                FWP_VALUE0 { r#type: FWP_UINT32, Anonymous: FWP_VALUE0_0 { uint32: u32::from(*low) } },
+                FWP_VALUE0 { r#type: FWP_UINT32, Anonymous: FWP_VALUE0_0 { uint32: u32::from(*high) } },
+            ),
+            ConditionValue::V6Range(low, high) => {
+                let low = self.byte_array(low.octets());
+                let high = self.byte_array(high.octets());
+                self.range(
+                    FWP_VALUE0 { r#type: FWP_BYTE_ARRAY16_TYPE, Anonymous: FWP_VALUE0_0 { byteArray16: low } },
+                    FWP_VALUE0 { r#type: FWP_BYTE_ARRAY16_TYPE, Anonymous: FWP_VALUE0_0 { byteArray16: high } },
+                )
+            },
+            ConditionValue::Port(port) => (FWP_UINT16, FWP_CONDITION_VALUE0_0 { uint16: *port }),
+            ConditionValue::PortRange(low, high) => self.range(
+                FWP_VALUE0 { r#type: FWP_UINT16, Anonymous: FWP_VALUE0_0 { uint16: *low } },
+                FWP_VALUE0 { r#type: FWP_UINT16, Anonymous: FWP_VALUE0_0 { uint16: *high } },
+            ),
+            ConditionValue::Protocol(number) => (FWP_UINT8, FWP_CONDITION_VALUE0_0 { uint8: *number }),
+        };
+
+        FWPM_FILTER_CONDITION0 {
+            fieldKey: field_key,
+            matchType: match_type,
+            conditionValue: FWP_CONDITION_VALUE0 {
+                r#type: value_type,
+                Anonymous: value,
+            },
+        }
+    }
+
+    fn byte_array(&mut self, octets: [u8; 16]) -> *mut FWP_BYTE_ARRAY16 {
+        let mut byte_array = Box::new(FWP_BYTE_ARRAY16 { byteArray16: octets });
+        let ptr: *mut FWP_BYTE_ARRAY16 = &mut *byte_array;
+        self.byte_arrays.push(byte_array);
+        ptr
+    }
+
+    fn range(&mut self, low: FWP_VALUE0, high: FWP_VALUE0) -> (FWP_DATA_TYPE, FWP_CONDITION_VALUE0_0) {
+        let mut range = Box::new(FWP_RANGE0 { valueLow: low, valueHigh: high });
+        let value = FWP_CONDITION_VALUE0_0 { rangeValue: &mut *range };
+        self.ranges.push(range);
+        (FWP_RANGE_TYPE, value)
+    }
+}
+
+impl Default for WfpBackend {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl FirewallBackend for WfpBackend {
+    fn open_session(&mut self, mode: EnforcementMode) -> Result<()> {
+        unsafe {
+            debug!(mode = %mode, "正在打开WFP引擎");
+
+            // 创建会话名称
+            let session_name = to_wide_string("AstralWFP Manager");
+            let session_desc = to_wide_string("AstralWFP网络流量管理会话");
+
+            let session = FWPM_SESSION0 {
+                sessionKey: GUID::zeroed(),
+                displayData: FWPM_DISPLAY_DATA0 {
+                    name: PWSTR(session_name.as_ptr() as *mut u16),
+                    description: PWSTR(session_desc.as_ptr() as *mut u16),
+                },
+                // 只有动态模式使用动态会话，否则持久对象无法添加
+                flags: if mode == EnforcementMode::Dynamic { FWPM_SESSION_FLAG_DYNAMIC } else { 0 },
+                txnWaitTimeoutInMSec: 0,
+                processId: 0,
+                sid: ptr::null_mut(),
+                username: PWSTR::null(),
+                kernelMode: FALSE,
+            };
+
+            // 打开WFP会话
+            let result = FwpmEngineOpen0(
+                None,
+                RPC_C_AUTHN_DEFAULT as u32,
+                None,
+                Some(&session),
+                &mut self.engine_handle,
+            );
+
+            if WIN32_ERROR(result) == ERROR_SUCCESS {
+                info!(mode = %mode, "WFP引擎已打开");
+            }
+            Self::check(result, message!("打开WFP引擎失败 (可能需要管理员权限)", "failed to open the WFP engine (administrator rights may be required)"))
+        }
+    }
+
+    fn close_session(&mut self) -> Result<()> {
+        let result = unsafe { FwpmEngineClose0(self.engine_handle) };
+        Self::check(result, message!("关闭WFP引擎失败", "failed to close the WFP engine"))?;
+        self.engine_handle = HANDLE::default();
+        Ok(())
+    }
+
+    fn register_provider(&mut self, provider: &ProviderConfig, mode: EnforcementMode) -> Result<()> {
+        let persistent = mode != EnforcementMode::Dynamic;
+        let name = to_wide_string(&provider.name);
+        let desc = to_wide_string(&provider.description);
+        let display_data = FWPM_DISPLAY_DATA0 {
+            name: PWSTR(name.as_ptr() as *mut u16),
+            description: PWSTR(desc.as_ptr() as *mut u16),
+        };
+        let mut provider_key = provider.provider_key;
+
+        let wfp_provider = FWPM_PROVIDER0 {
+            providerKey: provider.provider_key,
+            displayData: display_data,
+            flags: if persistent { FWPM_PROVIDER_FLAG_PERSISTENT } else { 0 },
+            providerData: FWP_BYTE_BLOB {
+                size: 0,
+                data: ptr::null_mut(),
+            },
+            serviceName: PWSTR::null(),
+        };
+        let result = unsafe { FwpmProviderAdd0(self.engine_handle, &wfp_provider, None) };
+        if result != FWP_E_ALREADY_EXISTS.0 as u32 {
+            Self::check(result, message!("注册WFP提供者失败", "failed to register the WFP provider"))?;
+        }
+
+        let sublayer = FWPM_SUBLAYER0 {
+            subLayerKey: provider.sublayer_key,
+            displayData: display_data,
+            flags: if persistent { FWPM_SUBLAYER_FLAG_PERSISTENT } else { 0 },
+            providerKey: &mut provider_key,
+            providerData: FWP_BYTE_BLOB {
+                size: 0,
+                data: ptr::null_mut(),
+            },
+            weight: provider.sublayer_weight,
+        };
+        let result = unsafe { FwpmSubLayerAdd0(self.engine_handle, &sublayer, None) };
+        if result != FWP_E_ALREADY_EXISTS.0 as u32 {
+            Self::check(result, message!("注册WFP子层失败", "failed to register the WFP sublayer"))?;
+        }
+        info!(sublayer_weight = provider.sublayer_weight, "已注册提供者和子层");
+        Ok(())
+    }
+
+    fn find_filter(&mut self, filter_key: &GUID) -> Result<Option<u64>> {
+        unsafe {
+            let mut filter: *mut FWPM_FILTER0 = ptr::null_mut();
+            let result = FwpmFilterGetByKey0(self.engine_handle, filter_key, &mut filter);
+            if result == FWP_E_FILTER_NOT_FOUND.0 as u32 {
+                return Ok(None);
+            }
+            Self::check(result, message!("查询过滤器 {:?} 失败", "failed to look up filter {:?}", filter_key))?;
+            let filter_id = (*filter).filterId;
+            FwpmFreeMemory0(&mut filter as *mut _ as *mut *mut std::ffi::c_void);
+            Ok(Some(filter_id))
+        }
+    }
+
+    fn add_filter(&mut self, spec: &FilterSpec) -> Result<u64> {
+        unsafe { self.add_filter_raw(spec) }
+    }
+
+    fn delete_filter(&mut self, filter_id: u64) -> Result<()> {
+        let result = unsafe { FwpmFilterDeleteById0(self.engine_handle, filter_id) };
+        Self::check(result, message!("删除过滤器 {} 失败", "failed to delete filter {}", filter_id))
+    }
+
+    fn enum_filters(&mut self, scope: FilterScope) -> Result<Vec<FilterRecord>> {
+        // 按提供者枚举时由引擎过滤；子层不在枚举模板中，只能枚举层后按子层筛选
+        let mut provider_key = match scope {
+            FilterScope::Provider(provider_key) => Some(provider_key),
+            FilterScope::Sublayer(_) => None,
+        };
+        let mut records = Vec::new();
+        for (layer_key, _) in KNOWN_LAYERS {
+            let template = FWPM_FILTER_ENUM_TEMPLATE0 {
+                providerKey: provider_key.as_mut().map_or(ptr::null_mut(), |key| key as *mut GUID),
+                layerKey: layer_key,
+                enumType: FWP_FILTER_ENUM_OVERLAPPING,
+                actionMask: u32::MAX,
+                ..Default::default()
+            };
+            records.extend(self.enum_layer(&template)?.into_iter().filter(|record| scope.contains(record)));
+        }
+        debug!(?scope, filters = records.len(), "枚举过滤器");
+        Ok(records)
+    }
+
+    fn begin_transaction(&mut self) -> Result<()> {
+        let result = unsafe { FwpmTransactionBegin0(self.engine_handle, 0) };
+        Self::check(result, message!("开始WFP事务失败", "failed to begin the WFP transaction"))
+    }
+
+    fn commit_transaction(&mut self) -> Result<()> {
+        let result = unsafe { FwpmTransactionCommit0(self.engine_handle) };
+        Self::check(result, message!("提交WFP事务失败", "failed to commit the WFP transaction"))
+    }
+
+    fn abort_transaction(&mut self) -> Result<()> {
+        let result = unsafe { FwpmTransactionAbort0(self.engine_handle) };
+        Self::check(result, message!("回滚WFP事务失败", "failed to abort the WFP transaction"))
+    }
+}
@@ -0,0 +1,297 @@
+// 规则冲突与遮蔽分析
+//
+// 把每条规则看成它能匹配的连接集合（方向 × 应用程序 × 本地/远程地址 × 协议 × 本地/远程端口），
+// 再按 WFP 在同一子层内的仲裁语义（权重最高的匹配过滤器决定结果）比较规则两两之间的关系：
+//   - 完全重复：匹配范围和动作都相同；
+//   - 完全遮蔽：被权重更高、动作相反的规则覆盖全部匹配范围，永远不会生效；
+//   - 冗余子集：被动作相同的规则覆盖，删除后任何连接的结果都不变；
+//   - 部分重叠：允许和阻止规则互不包含但有交集，交集由权重更高的规则决定。
+// 地址集合按规则实际编译的地址族计算（没有地址条件的规则只使用 IPv4 层），
+// ICMP 类型和代码与 WFP 一样按本地和远程端口处理。未通过校验的规则不参与分析。
+
+use std::fmt;
+use uuid::Uuid;
+use crate::astral_wfp::{Direction, FilterAction, FilterRule, Protocol};
+use crate::ipset::{IpSet, Ranges};
+use crate::plan::rule_families;
+use crate::weight::WeightAllocator;
+
+// 规则能匹配的连接集合，各维度之间取乘积
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MatchSpace {
+    inbound: bool,
+    outbound: bool,
+    app_path: Option<String>, // 小写；None 表示所有程序
+    local: IpSet,
+    remote: IpSet,
+    protocol: Option<u8>,     // None 表示所有协议
+    local_ports: Ranges,      // ICMP 规则中是类型
+    remote_ports: Ranges,     // ICMP 规则中是代码
+}
+
+impl MatchSpace {
+    pub fn of(rule: &FilterRule) -> Self {
+        let (v4, v6) = rule_families(rule).unwrap_or((true, false));
+        let mut families = IpSet::new();
+        if v4 {
+            families = families.union(&IpSet::all_v4());
+        }
+        if v6 {
+            families = families.union(&IpSet::all_v6());
+        }
+        let addresses = |list: &Option<crate::ipset::AddressList>| match list {
+            Some(list) => list.effective().intersection(&families),
+            None => families.clone(),
+        };
+        let ports = |list: &Option<crate::ports::PortList>| match list {
+            Some(list) => Ranges::normalized(list.effective().iter().map(|&(s, e)| (s as u128, e as u128)).collect()),
+            None => all_ports(),
+        };
+        let single = |value: u16| Ranges::normalized(vec![(value as u128, value as u128)]);
+
+        let is_v6 = rule.protocol == Some(Protocol::IcmpV6);
+        let mut local_ports = ports(&rule.local_ports);
+        let mut remote_ports = ports(&rule.remote_ports);
+        if let Some(number) = rule.icmp_type.and_then(|t| t.number(is_v6)) {
+            local_ports = single(number as u16);
+        }
+        if let Some(code) = rule.icmp_code {
+            remote_ports = single(code as u16);
+        }
+
+        Self {
+            inbound: rule.direction != Direction::Outbound,
+            outbound: rule.direction != Direction::Inbound,
+            app_path: rule.app_path.as_ref().map(|path| path.to_lowercase()),
+            local: addresses(&rule.local),
+            remote: addresses(&rule.remote),
+            protocol: rule.protocol.and_then(|p| p.ip_protocol()),
+            local_ports,
+            remote_ports,
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        !(self.inbound || self.outbound)
+            || self.local.is_empty()
+            || self.remote.is_empty()
+            || self.local_ports.is_empty()
+            || self.remote_ports.is_empty()
+    }
+
+    // other 匹配的每个连接本集合都匹配
+    pub fn is_superset(&self, other: &MatchSpace) -> bool {
+        (self.inbound || !other.inbound)
+            && (self.outbound || !other.outbound)
+            && (self.app_path.is_none() || self.app_path == other.app_path)
+            && self.local.is_superset(&other.local)
+            && self.remote.is_superset(&other.remote)
+            && (self.protocol.is_none() || self.protocol == other.protocol)
+            && self.local_ports.is_superset(&other.local_ports)
+            && self.remote_ports.is_superset(&other.remote_ports)
+    }
+
+    // 两个集合都匹配的连接，没有时返回 None
+    pub fn intersection(&self, other: &MatchSpace) -> Option<MatchSpace> {
+        let app_path = match (&self.app_path, &other.app_path) {
+            (Some(a), Some(b)) if a != b => return None,
+            (a, b) => a.clone().or_else(|| b.clone()),
+        };
+        let protocol = match (self.protocol, other.protocol) {
+            (Some(a), Some(b)) if a != b => return None,
+            (a, b) => a.or(b),
+        };
+        let space = MatchSpace {
+            inbound: self.inbound && other.inbound,
+            outbound: self.outbound && other.outbound,
+            app_path,
+            local: self.local.intersection(&other.local),
+            remote: self.remote.intersection(&other.remote),
+            protocol,
+            local_ports: self.local_ports.intersection(&other.local_ports),
+            remote_ports: self.remote_ports.intersection(&other.remote_ports),
+        };
+        (!space.is_empty()).then_some(space)
+    }
+}
+
+fn all_ports() -> Ranges {
+    Ranges::normalized(vec![(0, u16::MAX as u128)])
+}
+
+fn all_addresses(set: &IpSet) -> bool {
+    [IpSet::all_v4(), IpSet::all_v6(), IpSet::all()].contains(set)
+}
+
+// 只列出有限制的维度，例如 "出站, 远程地址 10.1.0.0/16, 协议 TCP, 远程端口 80"
+impl fmt::Display for MatchSpace {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let ports = |ranges: &Ranges| {
+            let items: Vec<String> = ranges
+                .iter()
+                .map(|(s, e)| if s == e { s.to_string() } else { format!("{}-{}", s, e) })
+                .collect();
+            items.join(",")
+        };
+        let mut parts = Vec::new();
+        match (self.inbound, self.outbound) {
+            (true, false) => parts.push("入站".to_string()),
+            (false, true) => parts.push("出站".to_string()),
+            _ => parts.push("双向".to_string()),
+        }
+        if let Some(path) = &self.app_path {
+            parts.push(format!("程序 {}", path));
+        }
+        if !all_addresses(&self.local) {
+            parts.push(format!("本地地址 {}", self.local));
+        }
+        if !all_addresses(&self.remote) {
+            parts.push(format!("远程地址 {}", self.remote));
+        }
+        if let Some(protocol) = self.protocol {
+            parts.push(format!("协议 {}", Protocol::from_number(protocol)));
+        }
+        if self.local_ports != all_ports() {
+            parts.push(format!("本地端口 {}", ports(&self.local_ports)));
+        }
+        if self.remote_ports != all_ports() {
+            parts.push(format!("远程端口 {}", ports(&self.remote_ports)));
+        }
+        write!(f, "{}", parts.join(", "))
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConflictKind {
+    Duplicate,      // 与另一条规则完全相同
+    Shadowed,       // 被权重更高、动作相反的规则完全覆盖，永远不会生效
+    Redundant,      // 被动作相同的规则覆盖，删除后结果不变
+    PartialOverlap, // 允许和阻止规则部分重叠
+}
+
+impl fmt::Display for ConflictKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConflictKind::Duplicate => write!(f, "重复"),
+            ConflictKind::Shadowed => write!(f, "遮蔽"),
+            ConflictKind::Redundant => write!(f, "冗余"),
+            ConflictKind::PartialOverlap => write!(f, "部分重叠"),
+        }
+    }
+}
+
+// 分析结果中引用的规则
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RuleRef {
+    pub id: Uuid,
+    pub name: String,
+    pub action: FilterAction,
+    pub weight: u64,
+}
+
+impl RuleRef {
+    fn of(rule: &FilterRule, weight: u64) -> Self {
+        Self { id: rule.id, name: rule.name.clone(), action: rule.action.clone(), weight }
+    }
+}
+
+impl fmt::Display for RuleRef {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let action = match self.action {
+            FilterAction::Allow => "允许",
+            FilterAction::Block => "阻止",
+        };
+        write!(f, "'{}'（{}，权重 {}）", self.name, action, self.weight)
+    }
+}
+
+// 一项发现：rule 是受影响的规则，by 是造成问题的规则，overlap 是两者都匹配的连接
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Conflict {
+    pub kind: ConflictKind,
+    pub rule: RuleRef,
+    pub by: RuleRef,
+    pub overlap: MatchSpace,
+}
+
+impl fmt::Display for Conflict {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.kind {
+            ConflictKind::Duplicate => write!(f, "[{}] 规则 {} 与规则 {} 的匹配范围和动作完全相同", self.kind, self.rule, self.by),
+            ConflictKind::Shadowed => write!(
+                f,
+                "[{}] 规则 {} 永远不会生效：规则 {} 权重更高、动作相反，覆盖了它的全部匹配范围（{}）",
+                self.kind, self.rule, self.by, self.overlap
+            ),
+            ConflictKind::Redundant => write!(
+                f,
+                "[{}] 规则 {} 可以删除：规则 {} 以相同的动作覆盖了它的全部匹配范围（{}）",
+                self.kind, self.rule, self.by, self.overlap
+            ),
+            ConflictKind::PartialOverlap => write!(
+                f,
+                "[{}] 规则 {} 与规则 {} 部分重叠，重叠部分（{}）由权重更高的规则 '{}' 决定",
+                self.kind, self.rule, self.by, self.overlap, self.by.name
+            ),
+        }
+    }
+}
+
+// 分析规则集，按规则顺序返回全部发现
+pub fn find_conflicts(rules: &[FilterRule]) -> Vec<Conflict> {
+    let allocator = WeightAllocator::new();
+    let analyzed: Vec<(&FilterRule, MatchSpace, u64)> = rules
+        .iter()
+        .filter(|rule| rule.validate().is_ok())
+        .map(|rule| (rule, MatchSpace::of(rule), allocator.weight(rule)))
+        .collect();
+
+    // x 被动作相同的 y 覆盖时是否多余：y 权重不低于 x 时 x 从不单独决定结果；
+    // 否则要求权重介于两者之间的规则中没有与 x 重叠的相反动作，删除 x 后这些连接仍由 y 决定
+    let redundant = |x: usize, y: usize| {
+        let ((rule, space, weight), (_, _, cover_weight)) = (&analyzed[x], &analyzed[y]);
+        cover_weight >= weight
+            || !analyzed.iter().any(|(other, other_space, other_weight)| {
+                other.action != rule.action
+                    && cover_weight < other_weight
+                    && other_weight < weight
+                    && space.intersection(other_space).is_some()
+            })
+    };
+
+    let mut conflicts = Vec::new();
+    for i in 0..analyzed.len() {
+        for j in i + 1..analyzed.len() {
+            let ((a, a_space, a_weight), (b, b_space, b_weight)) = (&analyzed[i], &analyzed[j]);
+            let Some(overlap) = a_space.intersection(b_space) else { continue };
+            let conflict = |kind, rule: &FilterRule, rule_weight, by: &FilterRule, by_weight, overlap| Conflict {
+                kind,
+                rule: RuleRef::of(rule, rule_weight),
+                by: RuleRef::of(by, by_weight),
+                overlap,
+            };
+
+            if a.action == b.action {
+                if a_space == b_space {
+                    conflicts.push(conflict(ConflictKind::Duplicate, b, *b_weight, a, *a_weight, overlap));
+                } else if a_space.is_superset(b_space) && redundant(j, i) {
+                    conflicts.push(conflict(ConflictKind::Redundant, b, *b_weight, a, *a_weight, overlap));
+                } else if b_space.is_superset(a_space) && redundant(i, j) {
+                    conflicts.push(conflict(ConflictKind::Redundant, a, *a_weight, b, *b_weight, overlap));
+                }
+                continue;
+            }
+
+            // 动作不同时权重一定不同；权重低的规则被完全包含时被遮蔽，
+            // 权重高的规则被包含则是有意的例外，不报告
+            let ((high, high_space, high_weight), (low, low_space, low_weight)) =
+                if a_weight > b_weight { (&analyzed[i], &analyzed[j]) } else { (&analyzed[j], &analyzed[i]) };
+            if high_space.is_superset(low_space) {
+                conflicts.push(conflict(ConflictKind::Shadowed, low, *low_weight, high, *high_weight, overlap));
+            } else if !low_space.is_superset(high_space) {
+                conflicts.push(conflict(ConflictKind::PartialOverlap, low, *low_weight, high, *high_weight, overlap));
+            }
+        }
+    }
+    conflicts
+}
@@ -0,0 +1,481 @@
+// 规则配置的语义差异
+//
+// 比较两个规则集（通常是一次策略修改前后的 RuleConfig），而不是比较 JSON 文本：
+//   - 规则按ID配对；ID 找不到时按内容哈希配对，再按名称配对，因此调整顺序、没有写ID的规则
+//     和字段别名（如 remote_ip / remote）都不会产生差异；
+//   - 逐字段列出修改，按字段的含义判断是放宽还是收紧：匹配范围扩大的允许规则、缩小的阻止规则是放宽，
+//     反之是收紧；写法不同但匹配范围相同的修改（如 "80,81" 改成 "80-81"）标记为等价；
+//   - 估算影响：用两边规则中出现的程序、地址和端口构造 TCP/UDP 连接样本，分别评估修改前后的结果，
+//     列出新可达和新被阻止的（程序, 端口）组合。端口样本包括范围两端、紧挨着范围外的端口和规则中
+//     没有列出的"其他端口"；样本只覆盖边界值，是估算而不是穷举，样本数超过上限时截断。
+// enabled 和 time_control 按声明的意图分类，影响估算与引擎一样不考虑它们。
+
+use std::collections::HashMap;
+use std::fmt;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use serde_json::Value;
+use crate::astral_wfp::{Direction, FilterAction, FilterRule, Protocol, RuleConfig};
+use crate::conflicts::MatchSpace;
+use crate::error::Result;
+use crate::evaluator::{Connection, Evaluator};
+use crate::ports::PortList;
+use crate::reconcile::content_hash;
+
+// 修改对策略的影响方向
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Effect {
+    Loosening,  // 放宽：允许更多连接
+    Tightening, // 收紧：阻止更多连接
+    Mixed,      // 有松有紧，或者无法判断
+    Neutral,    // 不影响任何连接的结果
+}
+
+impl Effect {
+    fn combine(self, other: Effect) -> Effect {
+        match (self, other) {
+            (Effect::Neutral, effect) | (effect, Effect::Neutral) => effect,
+            (a, b) if a == b => a,
+            _ => Effect::Mixed,
+        }
+    }
+
+    // 动作为 action 的规则匹配更多连接时的影响
+    fn widening(action: &FilterAction) -> Effect {
+        match action {
+            FilterAction::Allow => Effect::Loosening,
+            FilterAction::Block => Effect::Tightening,
+        }
+    }
+
+    fn reversed(self) -> Effect {
+        match self {
+            Effect::Loosening => Effect::Tightening,
+            Effect::Tightening => Effect::Loosening,
+            effect => effect,
+        }
+    }
+}
+
+impl fmt::Display for Effect {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Effect::Loosening => write!(f, "放宽"),
+            Effect::Tightening => write!(f, "收紧"),
+            Effect::Mixed => write!(f, "有松有紧"),
+            Effect::Neutral => write!(f, "无影响"),
+        }
+    }
+}
+
+// 一个字段的修改，取值按配置文件中的写法显示
+#[derive(Debug, Clone, PartialEq)]
+pub struct FieldChange {
+    pub field: String,
+    pub old: String,
+    pub new: String,
+    pub effect: Effect,
+    pub equivalent: bool, // 写法不同但匹配范围相同
+}
+
+impl fmt::Display for FieldChange {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {} → {}（", self.field, self.old, self.new)?;
+        if self.equivalent { write!(f, "等价）") } else { write!(f, "{}）", self.effect) }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum RuleChange {
+    Added(FilterRule),
+    Removed(FilterRule),
+    Modified { old: Box<FilterRule>, new: FilterRule, fields: Vec<FieldChange> },
+}
+
+impl RuleChange {
+    // 整条规则的影响：新增的允许规则放宽，删除的允许规则收紧，修改取各字段影响的合并
+    pub fn effect(&self) -> Effect {
+        match self {
+            RuleChange::Added(rule) => Effect::widening(&rule.action),
+            RuleChange::Removed(rule) => Effect::widening(&rule.action).reversed(),
+            RuleChange::Modified { fields, .. } => fields.iter().fold(Effect::Neutral, |acc, c| acc.combine(c.effect)),
+        }
+    }
+}
+
+impl fmt::Display for RuleChange {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RuleChange::Added(rule) => write!(f, "+ 新增 '{}' ({}): {}", rule.name, rule.id, self.effect()),
+            RuleChange::Removed(rule) => write!(f, "- 删除 '{}' ({}): {}", rule.name, rule.id, self.effect()),
+            RuleChange::Modified { new, fields, .. } => {
+                write!(f, "~ 修改 '{}' ({}): {}", new.name, new.id, self.effect())?;
+                for field in fields {
+                    write!(f, "\n    {}", field)?;
+                }
+                Ok(())
+            },
+        }
+    }
+}
+
+// 端口所在的一端
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PortSide {
+    Local,
+    Remote,
+}
+
+impl PortSide {
+    // 没有列出端口时样本放在连接的服务端：出站是远程端口，入站是本地端口
+    fn default_for(direction: &Direction) -> Self {
+        if *direction == Direction::Inbound { PortSide::Local } else { PortSide::Remote }
+    }
+}
+
+// 结果改变的一个（程序, 方向, 协议, 端口）组合
+#[derive(Debug, Clone, PartialEq)]
+pub struct Reachability {
+    pub app_path: Option<String>, // None 表示规则中没有列出的其他程序
+    pub direction: Direction,     // Inbound 或 Outbound
+    pub protocol: Protocol,
+    pub port: Option<u16>,        // port_side 一端的端口；None 表示规则中没有列出的其他端口
+    pub port_side: PortSide,      // 样本来自 local_ports 时是本地端口，来自 remote_ports 时是远程端口
+    pub addresses: Vec<IpAddr>,   // 结果改变的远程地址样本
+    pub reachable: bool,          // true 表示修改前被阻止、修改后允许
+}
+
+impl fmt::Display for Reachability {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let direction = if self.direction == Direction::Inbound { "入站" } else { "出站" };
+        let port = match (self.port, self.port_side) {
+            (Some(port), PortSide::Local) => format!("本地端口 {}", port),
+            (Some(port), PortSide::Remote) => format!("远程端口 {}", port),
+            (None, _) => "其他端口".to_string(),
+        };
+        let addresses: Vec<String> = self.addresses.iter().map(IpAddr::to_string).collect();
+        write!(
+            f,
+            "{} {} {} {} {}（远程地址 {}）",
+            if self.reachable { "+ 新可达" } else { "- 新阻止" },
+            self.app_path.as_deref().unwrap_or("其他程序"),
+            direction,
+            self.protocol,
+            port,
+            addresses.join(", ")
+        )
+    }
+}
+
+// 两个规则集之间的差异；修改和新增按新规则集的顺序排列，删除在最后
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct PolicyDiff {
+    pub changes: Vec<RuleChange>,
+    pub unchanged: usize,
+    pub impact: Vec<Reachability>,
+    pub truncated: bool, // 影响估算的样本过多，只评估了一部分
+}
+
+impl PolicyDiff {
+    pub fn is_empty(&self) -> bool {
+        self.changes.is_empty()
+    }
+
+    // 新可达的组合
+    pub fn newly_reachable(&self) -> impl Iterator<Item = &Reachability> {
+        self.impact.iter().filter(|r| r.reachable)
+    }
+
+    // 整个修改的影响
+    pub fn effect(&self) -> Effect {
+        self.changes.iter().fold(Effect::Neutral, |acc, c| acc.combine(c.effect()))
+    }
+}
+
+impl fmt::Display for PolicyDiff {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for change in &self.changes {
+            writeln!(f, "{}", change)?;
+        }
+        if !self.impact.is_empty() || self.truncated {
+            let note = if self.truncated { "，样本过多已截断" } else { "" };
+            writeln!(f, "影响（按规则中出现的程序、地址和端口抽样{}）:", note)?;
+            for reachability in &self.impact {
+                writeln!(f, "  {}", reachability)?;
+            }
+        }
+        let count = |pick: fn(&RuleChange) -> bool| self.changes.iter().filter(|c| pick(c)).count();
+        writeln!(
+            f,
+            "新增 {}，删除 {}，修改 {}，不变 {}；整体{}",
+            count(|c| matches!(c, RuleChange::Added(_))),
+            count(|c| matches!(c, RuleChange::Removed(_))),
+            count(|c| matches!(c, RuleChange::Modified { .. })),
+            self.unchanged,
+            self.effect()
+        )
+    }
+}
+
+// 比较两个规则配置
+pub fn diff_configs(old: &RuleConfig, new: &RuleConfig) -> Result<PolicyDiff> {
+    diff_rules(&old.rules, &new.rules)
+}
+
+// 比较两个规则集
+pub fn diff_rules(old: &[FilterRule], new: &[FilterRule]) -> Result<PolicyDiff> {
+    let mut remaining: Vec<Option<&FilterRule>> = old.iter().map(Some).collect();
+    let by_id: HashMap<_, usize> = old.iter().enumerate().map(|(i, r)| (r.id, i)).collect();
+    let hashes = old.iter().map(content_hash).collect::<Result<Vec<u64>>>()?;
+
+    // 依次按ID、内容哈希和名称配对，每一轮只处理上一轮没有配对的规则
+    let mut pairs: Vec<Option<usize>> =
+        new.iter().map(|rule| by_id.get(&rule.id).copied().filter(|&i| remaining[i].take().is_some())).collect();
+    for (rule, pair) in new.iter().zip(pairs.iter_mut()).filter(|(_, pair)| pair.is_none()) {
+        let hash = content_hash(rule)?;
+        *pair = (0..old.len()).find(|&i| hashes[i] == hash && remaining[i].take().is_some());
+    }
+    for (rule, pair) in new.iter().zip(pairs.iter_mut()).filter(|(_, pair)| pair.is_none()) {
+        *pair = (0..old.len()).find(|&i| old[i].name == rule.name && remaining[i].take().is_some());
+    }
+
+    let mut diff = PolicyDiff::default();
+    for (rule, pair) in new.iter().zip(pairs) {
+        match pair.map(|i| &old[i]) {
+            None => diff.changes.push(RuleChange::Added(rule.clone())),
+            Some(previous) => {
+                let fields = field_changes(previous, rule);
+                if fields.is_empty() {
+                    diff.unchanged += 1;
+                } else {
+                    diff.changes.push(RuleChange::Modified { old: Box::new(previous.clone()), new: rule.clone(), fields });
+                }
+            },
+        }
+    }
+    diff.changes.extend(remaining.into_iter().flatten().map(|r| RuleChange::Removed(r.clone())));
+    if !diff.is_empty() {
+        (diff.impact, diff.truncated) = estimate_impact(old, new)?;
+    }
+    Ok(diff)
+}
+
+// 按序列化后的字段比较，与字段别名和内存中的表示无关；不比较ID
+fn field_changes(old: &FilterRule, new: &FilterRule) -> Vec<FieldChange> {
+    let (Ok(Value::Object(old_fields)), Ok(Value::Object(new_fields))) = (serde_json::to_value(old), serde_json::to_value(new)) else {
+        return Vec::new();
+    };
+    let mut names: Vec<&String> = old_fields.keys().chain(new_fields.keys()).filter(|&name| name != "id").collect();
+    names.sort();
+    names.dedup();
+
+    let mut changes = Vec::new();
+    for name in names {
+        let (before, after) = (old_fields.get(name).unwrap_or(&Value::Null), new_fields.get(name).unwrap_or(&Value::Null));
+        if before == after {
+            continue;
+        }
+        let (effect, equivalent) = classify(name, old, new, &old_fields, after);
+        changes.push(FieldChange { field: name.clone(), old: show(before), new: show(after), effect, equivalent });
+    }
+    changes
+}
+
+fn show(value: &Value) -> String {
+    match value {
+        Value::Null => "无".to_string(),
+        Value::String(text) => text.clone(),
+        value => value.to_string(),
+    }
+}
+
+// 判断一个字段的修改是放宽还是收紧，返回影响和是否等价
+fn classify(
+    name: &str,
+    old: &FilterRule,
+    new: &FilterRule,
+    old_fields: &serde_json::Map<String, Value>,
+    after: &Value,
+) -> (Effect, bool) {
+    let effect = match name {
+        "name" | "description" | "group" => Effect::Neutral,
+        "action" => Effect::widening(&new.action),
+        // 优先级提高使规则在重叠时胜出，效果与扩大匹配范围相同
+        "priority" if new.priority > old.priority => Effect::widening(&old.action),
+        "priority" => Effect::widening(&old.action).reversed(),
+        "enabled" if new.enabled => Effect::widening(&old.action),
+        "enabled" => Effect::widening(&old.action).reversed(),
+        // 加上时间限制使规则只在部分时间生效
+        "time_control" => match (&old.time_control, &new.time_control) {
+            (None, Some(_)) => Effect::widening(&old.action).reversed(),
+            (Some(_), None) => Effect::widening(&old.action),
+            _ => Effect::Mixed,
+        },
+        // 其余字段决定匹配范围：只修改这一个字段后比较修改前后的范围
+        _ => {
+            let mut fields = old_fields.clone();
+            fields.insert(name.to_string(), after.clone());
+            let Ok(probe) = serde_json::from_value::<FilterRule>(Value::Object(fields)) else {
+                return (Effect::Mixed, false);
+            };
+            let (before, after) = (MatchSpace::of(old), MatchSpace::of(&probe));
+            if before == after {
+                return (Effect::Neutral, true);
+            }
+            match (after.is_superset(&before), before.is_superset(&after)) {
+                (true, _) => Effect::widening(&old.action),
+                (_, true) => Effect::widening(&old.action).reversed(),
+                _ => Effect::Mixed,
+            }
+        },
+    };
+    (effect, false)
+}
+
+// 影响估算最多评估的连接样本数；超过时每次把样本最多的字段减半，保留排在最前面的哨兵
+const MAX_PROBES: usize = 10_000;
+
+// 端口样本：port 放在 side 一端；另一端使用同一规则另一端端口列表的起点，
+// 两端都列出端口的规则也能被匹配到，没有时为 0
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct PortSample {
+    side: PortSide,
+    port: u16,
+    companion: u16,
+}
+
+// 用两边规则中出现的值构造连接样本，列出修改前后结果不同的组合；第二个返回值表示样本是否被截断
+fn estimate_impact(old: &[FilterRule], new: &[FilterRule]) -> Result<(Vec<Reachability>, bool)> {
+    let compilable = |rules: &[FilterRule]| -> Vec<FilterRule> { rules.iter().filter(|r| r.validate().is_ok()).cloned().collect() };
+    let (before, after) = (Evaluator::from_rules(&compilable(old))?, Evaluator::from_rules(&compilable(new))?);
+    let rules: Vec<&FilterRule> = old.iter().chain(new).collect();
+
+    // 每个字段的第一个样本是哨兵：规则中没有列出的程序、地址和端口
+    let mut apps: Vec<Option<String>> = vec![None];
+    let mut protocols: Vec<Protocol> = Vec::new();
+    let mut remotes: Vec<IpAddr> = vec![Ipv4Addr::new(192, 0, 2, 1).into()];
+    let mut locals: Vec<IpAddr> = vec![Ipv4Addr::UNSPECIFIED.into()];
+    let mut ports: Vec<Option<PortSample>> = vec![None];
+    let mut port_ranges: Vec<(u16, u16)> = Vec::new();
+    for rule in &rules {
+        push_unique(&mut apps, rule.app_path.clone());
+        match rule.protocol {
+            Some(protocol) if protocol == Protocol::Tcp || protocol == Protocol::Udp => push_unique(&mut protocols, protocol),
+            None | Some(Protocol::Any) => {
+                push_unique(&mut protocols, Protocol::Tcp);
+                push_unique(&mut protocols, Protocol::Udp);
+            },
+            Some(_) => {},
+        }
+        for (list, samples) in [(&rule.remote, &mut remotes), (&rule.local, &mut locals)] {
+            let Some(list) = list else { continue };
+            for (start, end) in list.effective().ranges().into_iter().chain(list.excluded().ranges()) {
+                push_unique(samples, start);
+                push_unique(samples, end);
+            }
+        }
+        // 端口范围的两端和紧挨着范围外的端口，放在列出它们的那一端
+        let first_port = |list: &Option<PortList>| list.as_ref().and_then(|l| l.entries.first()).map_or(0, |entry| entry.start);
+        for (side, list, other) in [
+            (PortSide::Local, &rule.local_ports, &rule.remote_ports),
+            (PortSide::Remote, &rule.remote_ports, &rule.local_ports),
+        ] {
+            let Some(list) = list else { continue };
+            let companion = first_port(other);
+            for entry in &list.entries {
+                port_ranges.push((entry.start, entry.end));
+                for port in [entry.start.checked_sub(1), Some(entry.start), Some(entry.end), entry.end.checked_add(1)].into_iter().flatten() {
+                    push_unique(&mut ports, Some(PortSample { side, port, companion }));
+                }
+            }
+        }
+    }
+    if remotes.iter().chain(&locals).any(IpAddr::is_ipv6) {
+        let (remote, local) = (Ipv6Addr::new(0x2001, 0xdb8, 0, 0, 0, 0, 0, 1).into(), Ipv6Addr::UNSPECIFIED.into());
+        remotes.retain(|a| *a != remote);
+        remotes.insert(1, remote);
+        locals.retain(|a| *a != local);
+        locals.insert(1, local);
+    }
+    // “其他端口”的哨兵取第一个不在任何端口列表中的端口；所有端口都被列出时取 0
+    let other_port = (1..=u16::MAX).find(|p| !port_ranges.iter().any(|(start, end)| start <= p && p <= end)).unwrap_or(0);
+
+    let mut truncated = false;
+    loop {
+        let lens = [apps.len(), ports.len(), remotes.len(), locals.len()];
+        if lens.iter().product::<usize>() * 2 * protocols.len() <= MAX_PROBES {
+            break;
+        }
+        let largest = (0..lens.len()).max_by_key(|&i| lens[i]).unwrap_or(0);
+        let keep = lens[largest].div_ceil(2);
+        match largest {
+            0 => apps.truncate(keep),
+            1 => ports.truncate(keep),
+            2 => remotes.truncate(keep),
+            _ => locals.truncate(keep),
+        }
+        truncated = true;
+    }
+
+    let mut impact: Vec<Reachability> = Vec::new();
+    for app in &apps {
+        for direction in [Direction::Outbound, Direction::Inbound] {
+            for &protocol in &protocols {
+                for &sample in &ports {
+                    let (port, port_side, companion) = match sample {
+                        Some(sample) => (Some(sample.port), sample.side, sample.companion),
+                        None => (None, PortSide::default_for(&direction), 0),
+                    };
+                    let sample_port = port.unwrap_or(other_port);
+                    let (local_port, remote_port) = match port_side {
+                        PortSide::Local => (sample_port, companion),
+                        PortSide::Remote => (companion, sample_port),
+                    };
+                    for &remote in &remotes {
+                        for &local in locals.iter().filter(|l| l.is_ipv6() == remote.is_ipv6()) {
+                            let (local, remote_socket) = (SocketAddr::new(local, local_port), SocketAddr::new(remote, remote_port));
+                            let mut connection = Connection::new(direction.clone(), protocol, local, remote_socket);
+                            connection.app_path = app.clone();
+                            let was_blocked = before.evaluate(&connection)?.is_blocked();
+                            let is_blocked = after.evaluate(&connection)?.is_blocked();
+                            if was_blocked == is_blocked {
+                                continue;
+                            }
+                            let reachable = was_blocked;
+                            let existing = impact.iter_mut().find(|r| {
+                                r.app_path == *app
+                                    && r.direction == direction
+                                    && r.protocol == protocol
+                                    && r.port == port
+                                    && r.port_side == port_side
+                                    && r.reachable == reachable
+                            });
+                            match existing {
+                                Some(entry) => push_unique(&mut entry.addresses, remote),
+                                None => impact.push(Reachability {
+                                    app_path: app.clone(),
+                                    direction: direction.clone(),
+                                    protocol,
+                                    port,
+                                    port_side,
+                                    addresses: vec![remote],
+                                    reachable,
+                                }),
+                            }
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    // 与“其他端口”结果相同的具体端口不单独列出
+    let other: Vec<Reachability> = impact.iter().filter(|r| r.port.is_none()).cloned().collect();
+    impact.retain(|r| r.port.is_none() || !other.iter().any(|o| Reachability { port: r.port, port_side: r.port_side, ..o.clone() } == *r));
+    Ok((impact, truncated))
+}
+
+fn push_unique<T: PartialEq>(items: &mut Vec<T>, item: T) {
+    if !items.contains(&item) {
+        items.push(item);
+    }
+}
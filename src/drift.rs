@@ -0,0 +1,314 @@
+// 漂移检测与定期完整性审计
+//
+// 其他软件或管理员可能绕过我们删除、替换过滤器，或者往我们的子层中添加过滤器。审计把引擎中的
+// 过滤器与记录的已应用状态比较。记录的状态首先是控制器提交时的 FilterSpec 和过滤器ID；控制器没有
+// 记录的、属于我们提供者的过滤器（以前的运行或其他控制器应用的），按 providerData 中存储的规则和
+// 执行模式重新编译，得到它们应有的状态（见 rebuild_recorded）：
+//   - 缺失：记录的过滤器在引擎中找不到；
+//   - 被修改：filterKey 相同，但ID、层、子层、动作、权重、执行模式、名称或规则元数据不同
+//     （WFP 不能原地修改过滤器条件，被修改的过滤器一定是删除后重新添加的，ID 随之改变）；
+//   - 无法识别：属于我们提供者，但规则元数据无法还原，或者不是存储规则的编译结果；
+//   - 外来：在我们的子层中、但不属于我们提供者的过滤器。
+// 一条规则的过滤器全部被删除时没有留下可以重建的元数据，只有控制器记录过它们时才能发现。
+// 外来过滤器可能是其他产品或管理员有意添加的，默认只报告；只有 HealPolicy::RestoreAndRemoveForeign 才删除。
+// DriftMonitor 在后台线程中定期审计，把发现漂移的审计结果发送到通道，可选地自动修复。
+
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, Sender};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+use tracing::{error, info};
+use uuid::Uuid;
+use windows::core::GUID;
+use crate::apply::ApplyReport;
+use crate::astral_wfp::{layer_name, WfpController};
+use crate::backend::{FilterRecord, FirewallBackend};
+use crate::metadata::RuleMetadata;
+use crate::plan::{FilterSpec, PlanCompiler};
+use crate::provider::ProviderConfig;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum DriftKind {
+    Missing,                     // 记录的过滤器被删除
+    Modified(Vec<&'static str>), // 被替换，列出不同的字段
+    Unrecognized,                // 我们提供者下无法对应到存储规则的过滤器
+    Foreign,                     // 其他提供者添加到我们子层中的过滤器
+}
+
+impl fmt::Display for DriftKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DriftKind::Missing => write!(f, "缺失"),
+            DriftKind::Modified(fields) => write!(f, "被修改（{}）", fields.join(", ")),
+            DriftKind::Unrecognized => write!(f, "无法识别"),
+            DriftKind::Foreign => write!(f, "外来"),
+        }
+    }
+}
+
+// 一个漂移事件；缺失时 filter_id 是记录的ID（重建的记录没有ID，为 0），其他情况是引擎中的ID
+#[derive(Debug, Clone, PartialEq)]
+pub struct DriftEvent {
+    pub kind: DriftKind,
+    pub filter_id: u64,
+    pub filter_key: GUID,
+    pub layer_key: GUID,
+    pub name: String,
+    pub rule_id: Option<Uuid>, // 来源规则；外来过滤器没有
+}
+
+impl fmt::Display for DriftEvent {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "[{}] 过滤器 '{}' (ID: {}, {})", self.kind, self.name, self.filter_id, layer_name(&self.layer_key))?;
+        if let Some(rule_id) = self.rule_id {
+            write!(f, "，规则 {}", rule_id)?;
+        }
+        Ok(())
+    }
+}
+
+// 一次审计的结果
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct AuditReport {
+    pub checked: usize, // 检查的已记录过滤器数量
+    pub events: Vec<DriftEvent>,
+    pub healed: Option<ApplyReport>, // 自动修复的应用结果
+}
+
+impl AuditReport {
+    pub fn is_clean(&self) -> bool {
+        self.events.is_empty()
+    }
+
+    fn count(&self, pick: fn(&DriftKind) -> bool) -> usize {
+        self.events.iter().filter(|e| pick(&e.kind)).count()
+    }
+}
+
+impl fmt::Display for AuditReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for event in &self.events {
+            writeln!(f, "{}", event)?;
+        }
+        writeln!(
+            f,
+            "审计 {} 个过滤器: 缺失 {}，被修改 {}，外来 {}，无法识别 {}",
+            self.checked,
+            self.count(|k| *k == DriftKind::Missing),
+            self.count(|k| matches!(k, DriftKind::Modified(_))),
+            self.count(|k| *k == DriftKind::Foreign),
+            self.count(|k| *k == DriftKind::Unrecognized)
+        )?;
+        if let Some(healed) = &self.healed {
+            write!(f, "自动修复: {}", healed)?;
+        }
+        Ok(())
+    }
+}
+
+// 从我们提供者的过滤器重建 applied 没有覆盖的记录状态：按 providerData 中存储的规则和执行模式重新编译，
+// 得到这些规则的过滤器应有的 FilterSpec，过滤器ID取引擎中同一 filterKey 的过滤器（引擎中没有时为 0）。
+// 同时返回无法还原规则元数据、或者不是存储规则编译结果的过滤器的漂移事件
+pub fn rebuild_recorded(
+    applied: &[(u64, FilterSpec)],
+    records: &[FilterRecord],
+    provider: &ProviderConfig,
+) -> (Vec<(u64, FilterSpec)>, Vec<DriftEvent>) {
+    let covered = |filter_key: &GUID| applied.iter().any(|(_, spec)| spec.filter_key == *filter_key);
+    let mut rebuilt: Vec<(u64, FilterSpec)> = Vec::new();
+    let mut compiled: Vec<Uuid> = Vec::new();
+    let mut events = Vec::new();
+
+    for record in records.iter().filter(|r| r.provider_key == provider.provider_key && !covered(&r.filter_key)) {
+        let metadata = RuleMetadata::from_bytes(&record.provider_data).ok();
+        if let Some(metadata) = &metadata
+            && !compiled.contains(&metadata.rule.id) {
+            compiled.push(metadata.rule.id);
+            // 启动时模式的启动时过滤器不引用我们的提供者，只重建持久的部分
+            let compiler = PlanCompiler::with_provider(provider.clone()).with_mode(metadata.mode);
+            let specs = compiler.compile(&metadata.rule).unwrap_or_default();
+            for spec in specs.into_iter().filter(|s| s.provider_key == provider.provider_key && !covered(&s.filter_key)) {
+                let filter_id = records.iter().find(|r| r.filter_key == spec.filter_key).map_or(0, |r| r.filter_id);
+                rebuilt.push((filter_id, spec));
+            }
+        }
+        if !rebuilt.iter().any(|(_, spec)| spec.filter_key == record.filter_key) {
+            events.push(DriftEvent {
+                kind: DriftKind::Unrecognized,
+                filter_id: record.filter_id,
+                filter_key: record.filter_key,
+                layer_key: record.layer_key,
+                name: record.name.clone(),
+                rule_id: metadata.map(|metadata| metadata.rule.id),
+            });
+        }
+    }
+    (rebuilt, events)
+}
+
+// 比较记录的过滤器和引擎枚举到的过滤器，事件按记录的顺序排列，外来过滤器在最后
+pub fn detect_drift(applied: &[(u64, FilterSpec)], records: &[FilterRecord], provider: &ProviderConfig) -> Vec<DriftEvent> {
+    let by_key: HashMap<GUID, &FilterRecord> = records.iter().map(|r| (r.filter_key, r)).collect();
+    let mut events = Vec::new();
+
+    for (filter_id, spec) in applied {
+        let (kind, filter_id) = match by_key.get(&spec.filter_key) {
+            None => (DriftKind::Missing, *filter_id),
+            Some(record) => {
+                let fields = changed_fields(*filter_id, spec, record);
+                if fields.is_empty() {
+                    continue;
+                }
+                (DriftKind::Modified(fields), record.filter_id)
+            },
+        };
+        events.push(DriftEvent {
+            kind,
+            filter_id,
+            filter_key: spec.filter_key,
+            layer_key: spec.layer_key,
+            name: spec.display_name.clone(),
+            rule_id: Some(spec.rule_id),
+        });
+    }
+
+    events.extend(
+        records
+            .iter()
+            .filter(|r| r.sublayer_key == provider.sublayer_key && r.provider_key != provider.provider_key)
+            .filter(|r| !applied.iter().any(|(_, spec)| spec.filter_key == r.filter_key))
+            .map(|record| DriftEvent {
+                kind: DriftKind::Foreign,
+                filter_id: record.filter_id,
+                filter_key: record.filter_key,
+                layer_key: record.layer_key,
+                name: record.name.clone(),
+                rule_id: None,
+            }),
+    );
+    events
+}
+
+// 引擎中的过滤器与记录不同的字段；规则元数据按内容比较，与序列化细节无关
+fn changed_fields(filter_id: u64, spec: &FilterSpec, record: &FilterRecord) -> Vec<&'static str> {
+    let metadata = |data: &[u8]| RuleMetadata::from_bytes(data).ok();
+    [
+        ("filter_id", filter_id != record.filter_id),
+        ("layer_key", spec.layer_key != record.layer_key),
+        ("sublayer_key", spec.sublayer_key != record.sublayer_key),
+        ("provider_key", spec.provider_key != record.provider_key),
+        ("action", spec.action != record.action),
+        ("weight", spec.weight != record.weight),
+        ("mode", spec.mode != record.mode),
+        ("name", spec.display_name != record.name),
+        ("provider_data", metadata(&spec.provider_data) != metadata(&record.provider_data)),
+    ]
+    .into_iter()
+    .filter_map(|(field, changed)| changed.then_some(field))
+    .collect()
+}
+
+// 发现漂移时的处理方式
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum HealPolicy {
+    #[default]
+    Report,                  // 只报告
+    Restore,                 // 重新应用有缺失或被修改过滤器的规则，删除无法识别的过滤器，外来过滤器只报告
+    RestoreAndRemoveForeign, // 同时删除外来过滤器
+}
+
+impl HealPolicy {
+    // 这一策略会处理的漂移
+    pub fn heals(&self, kind: &DriftKind) -> bool {
+        match self {
+            HealPolicy::Report => false,
+            HealPolicy::Restore => *kind != DriftKind::Foreign,
+            HealPolicy::RestoreAndRemoveForeign => true,
+        }
+    }
+}
+
+impl fmt::Display for HealPolicy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            HealPolicy::Report => write!(f, "只报告"),
+            HealPolicy::Restore => write!(f, "恢复规则"),
+            HealPolicy::RestoreAndRemoveForeign => write!(f, "恢复规则并删除外来过滤器"),
+        }
+    }
+}
+
+// 定期审计的配置
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct AuditOptions {
+    pub interval: Duration,
+    pub heal: HealPolicy,
+}
+
+impl Default for AuditOptions {
+    fn default() -> Self {
+        Self {
+            interval: Duration::from_secs(60),
+            heal: HealPolicy::Report,
+        }
+    }
+}
+
+// 后台审计线程；stop 或 drop 时停止并等待线程退出
+pub struct DriftMonitor {
+    stop: Option<Sender<()>>,
+    handle: Option<JoinHandle<()>>,
+}
+
+impl DriftMonitor {
+    // 启动后台审计，发现漂移的审计结果发送到返回的通道；接收端被丢弃时线程退出
+    pub fn spawn<B>(controller: Arc<Mutex<WfpController<B>>>, options: AuditOptions) -> (Self, Receiver<AuditReport>)
+    where
+        B: FirewallBackend + Send + 'static,
+    {
+        let (stop_tx, stop_rx) = mpsc::channel::<()>();
+        let (report_tx, report_rx) = mpsc::channel();
+        let handle = thread::spawn(move || {
+            info!(interval = ?options.interval, heal = %options.heal, "漂移审计已启动");
+            while let Err(RecvTimeoutError::Timeout) = stop_rx.recv_timeout(options.interval) {
+                let Ok(mut controller) = controller.lock() else {
+                    error!("控制器锁已失效，停止漂移审计");
+                    break;
+                };
+                let report = match controller.audit(options.heal) {
+                    Ok(report) => report,
+                    Err(e) => {
+                        error!(code = %e.code(), "漂移审计失败: {}", e);
+                        continue;
+                    }
+                };
+                drop(controller);
+                if !report.is_clean() && report_tx.send(report).is_err() {
+                    break;
+                }
+            }
+            info!("漂移审计已停止");
+        });
+        (Self { stop: Some(stop_tx), handle: Some(handle) }, report_rx)
+    }
+
+    pub fn stop(mut self) {
+        self.shutdown();
+    }
+
+    fn shutdown(&mut self) {
+        // 关闭发送端即可唤醒线程
+        self.stop.take();
+        if let Some(handle) = self.handle.take() {
+            let _ = handle.join();
+        }
+    }
+}
+
+impl Drop for DriftMonitor {
+    fn drop(&mut self) {
+        self.shutdown();
+    }
+}
@@ -0,0 +1,372 @@
+// 统一的错误类型
+//
+// 所有公开 API 都返回 AstralError：规则验证、WFP 状态码、文件读写和配置解析各有自己的变体。
+// 每个错误都有稳定的机器可读代码（ErrorCode），GUI 和脚本可以据此处理特定的失败，
+// 而不必匹配错误消息文本；面向用户的提示通过 localized() 以指定语言生成。
+// 错误消息在构造时按中英文两个模板分别代入参数（message! 宏），localized() 按语言选择，
+// 系统错误消息、路径和用户输入等与语言无关的文本用 Message::plain 保存。
+
+use std::fmt;
+use std::io;
+use std::str::FromStr;
+use windows::core::HRESULT;
+use windows::Win32::Foundation::{
+    E_ACCESSDENIED, ERROR_ACCESS_DENIED, ERROR_INVALID_HANDLE, FWP_E_ALREADY_EXISTS, FWP_E_CONDITION_NOT_FOUND,
+    FWP_E_DYNAMIC_SESSION_IN_PROGRESS, FWP_E_FILTER_NOT_FOUND, FWP_E_INCOMPATIBLE_LAYER, FWP_E_INVALID_NET_MASK,
+    FWP_E_INVALID_RANGE, FWP_E_INVALID_WEIGHT, FWP_E_LAYER_NOT_FOUND, FWP_E_LIFETIME_MISMATCH,
+    FWP_E_NO_TXN_IN_PROGRESS, FWP_E_PROVIDER_NOT_FOUND, FWP_E_SUBLAYER_NOT_FOUND, FWP_E_TXN_ABORTED,
+    FWP_E_TXN_IN_PROGRESS, FWP_E_TYPE_MISMATCH,
+};
+
+pub type Result<T> = std::result::Result<T, AstralError>;
+
+// 用中文和英文两个格式模板构造 Message，两个模板使用相同的参数
+#[macro_export]
+macro_rules! message {
+    ($chinese:literal, $english:literal $(, $arg:expr)* $(,)?) => {
+        $crate::error::Message::new(format!($chinese $(, $arg)*), format!($english $(, $arg)*))
+    };
+}
+
+// 错误消息的中英文文本
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Message {
+    chinese: Box<str>,
+    english: Box<str>,
+}
+
+impl Message {
+    pub fn new(chinese: impl Into<String>, english: impl Into<String>) -> Self {
+        Self {
+            chinese: chinese.into().into_boxed_str(),
+            english: english.into().into_boxed_str(),
+        }
+    }
+
+    // 与语言无关的文本
+    pub fn plain(text: impl Into<String>) -> Self {
+        let text = text.into().into_boxed_str();
+        Self {
+            chinese: text.clone(),
+            english: text,
+        }
+    }
+
+    pub fn get(&self, language: Language) -> &str {
+        match language {
+            Language::Chinese => &self.chinese,
+            Language::English => &self.english,
+        }
+    }
+
+    // 按语言分别变换两种文本，用于在消息外面再包一层上下文
+    pub fn map(&self, f: impl Fn(Language, &str) -> String) -> Self {
+        Self::new(f(Language::Chinese, &self.chinese), f(Language::English, &self.english))
+    }
+
+    // serde 的自定义错误只能携带一段文本：两种文本用分隔符连在一起，转换回来时由 from_serde 拆开
+    pub fn to_serde<E: serde::de::Error>(&self) -> E {
+        E::custom(format!("{}{}{}", self.chinese, SERDE_SEPARATOR, self.english))
+    }
+
+    // 拆开 to_serde 生成的错误文本；serde_json 追加在末尾的位置信息两种语言都保留
+    pub fn from_serde(text: &str) -> Self {
+        let Some((chinese, english)) = text.split_once(SERDE_SEPARATOR) else {
+            return Self::plain(text);
+        };
+        let location = english.rfind(" at line ").map_or("", |i| &english[i..]);
+        Self::new(format!("{}{}", chinese, location), english)
+    }
+}
+
+const SERDE_SEPARATOR: char = '\u{1f}';
+
+impl fmt::Display for Message {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.chinese)
+    }
+}
+
+// 错误消息的语言
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Language {
+    #[default]
+    Chinese,
+    English,
+}
+
+impl FromStr for Language {
+    type Err = AstralError;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let lower = s.to_ascii_lowercase();
+        if lower.starts_with("zh") {
+            Ok(Language::Chinese)
+        } else if lower.starts_with("en") {
+            Ok(Language::English)
+        } else {
+            Err(AstralError::parse(message!("语言", "language"), message!("未知的语言: {} (可选: zh, en)", "unknown language: {} (expected zh or en)", s)))
+        }
+    }
+}
+
+// 机器可读的错误代码
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ErrorCode {
+    Validation,
+    Parse,
+    Io,
+    InvalidCondition,  // 条件字段、类型、范围或掩码无效
+    LayerNotFound,
+    IncompatibleLayer, // 条件不能用于该层
+    AlreadyExists,
+    AccessDenied,
+    FilterNotFound,
+    ProviderNotFound,
+    SublayerNotFound,
+    LifetimeMismatch,
+    DynamicSession,    // 动态会话中不能创建持久对象
+    Transaction,
+    SessionNotOpen,
+    InvalidWeight,
+    Wfp,               // 其他 WFP / Win32 状态码
+    Internal,
+}
+
+impl ErrorCode {
+    // 稳定的字符串代码，供脚本使用
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ErrorCode::Validation => "validation",
+            ErrorCode::Parse => "parse",
+            ErrorCode::Io => "io",
+            ErrorCode::InvalidCondition => "wfp.invalid_condition",
+            ErrorCode::LayerNotFound => "wfp.layer_not_found",
+            ErrorCode::IncompatibleLayer => "wfp.incompatible_layer",
+            ErrorCode::AlreadyExists => "wfp.already_exists",
+            ErrorCode::AccessDenied => "wfp.access_denied",
+            ErrorCode::FilterNotFound => "wfp.filter_not_found",
+            ErrorCode::ProviderNotFound => "wfp.provider_not_found",
+            ErrorCode::SublayerNotFound => "wfp.sublayer_not_found",
+            ErrorCode::LifetimeMismatch => "wfp.lifetime_mismatch",
+            ErrorCode::DynamicSession => "wfp.dynamic_session",
+            ErrorCode::Transaction => "wfp.transaction",
+            ErrorCode::SessionNotOpen => "wfp.session_not_open",
+            ErrorCode::InvalidWeight => "wfp.invalid_weight",
+            ErrorCode::Wfp => "wfp.other",
+            ErrorCode::Internal => "internal",
+        }
+    }
+
+    // 把 WFP / Win32 状态码归类
+    pub fn from_status(status: u32) -> Self {
+        let is = |code: HRESULT| status == code.0 as u32;
+        if is(FWP_E_CONDITION_NOT_FOUND) || is(FWP_E_TYPE_MISMATCH) || is(FWP_E_INVALID_RANGE) || is(FWP_E_INVALID_NET_MASK) {
+            ErrorCode::InvalidCondition
+        } else if is(FWP_E_LAYER_NOT_FOUND) {
+            ErrorCode::LayerNotFound
+        } else if is(FWP_E_INCOMPATIBLE_LAYER) {
+            ErrorCode::IncompatibleLayer
+        } else if is(FWP_E_ALREADY_EXISTS) {
+            ErrorCode::AlreadyExists
+        } else if is(E_ACCESSDENIED) || status == ERROR_ACCESS_DENIED.0 {
+            ErrorCode::AccessDenied
+        } else if is(FWP_E_FILTER_NOT_FOUND) {
+            ErrorCode::FilterNotFound
+        } else if is(FWP_E_PROVIDER_NOT_FOUND) {
+            ErrorCode::ProviderNotFound
+        } else if is(FWP_E_SUBLAYER_NOT_FOUND) {
+            ErrorCode::SublayerNotFound
+        } else if is(FWP_E_LIFETIME_MISMATCH) {
+            ErrorCode::LifetimeMismatch
+        } else if is(FWP_E_DYNAMIC_SESSION_IN_PROGRESS) {
+            ErrorCode::DynamicSession
+        } else if is(FWP_E_TXN_IN_PROGRESS) || is(FWP_E_NO_TXN_IN_PROGRESS) || is(FWP_E_TXN_ABORTED) {
+            ErrorCode::Transaction
+        } else if status == ERROR_INVALID_HANDLE.0 {
+            ErrorCode::SessionNotOpen
+        } else if is(FWP_E_INVALID_WEIGHT) {
+            ErrorCode::InvalidWeight
+        } else {
+            ErrorCode::Wfp
+        }
+    }
+
+    // 错误类别的简短说明
+    pub fn summary(&self, language: Language) -> &'static str {
+        match language {
+            Language::Chinese => match self {
+                ErrorCode::Validation => "规则验证失败",
+                ErrorCode::Parse => "解析失败",
+                ErrorCode::Io => "文件读写失败",
+                ErrorCode::InvalidCondition => "过滤条件无效",
+                ErrorCode::LayerNotFound => "WFP层不存在",
+                ErrorCode::IncompatibleLayer => "条件与WFP层不兼容",
+                ErrorCode::AlreadyExists => "对象已存在",
+                ErrorCode::AccessDenied => "访问被拒绝，请以管理员身份运行",
+                ErrorCode::FilterNotFound => "过滤器不存在",
+                ErrorCode::ProviderNotFound => "提供者未注册",
+                ErrorCode::SublayerNotFound => "子层未注册",
+                ErrorCode::LifetimeMismatch => "对象生存期不匹配",
+                ErrorCode::DynamicSession => "动态会话中不能创建持久对象",
+                ErrorCode::Transaction => "事务错误",
+                ErrorCode::SessionNotOpen => "WFP会话未打开",
+                ErrorCode::InvalidWeight => "权重无效",
+                ErrorCode::Wfp => "WFP调用失败",
+                ErrorCode::Internal => "内部错误",
+            },
+            Language::English => match self {
+                ErrorCode::Validation => "rule validation failed",
+                ErrorCode::Parse => "parse error",
+                ErrorCode::Io => "I/O error",
+                ErrorCode::InvalidCondition => "invalid filter condition",
+                ErrorCode::LayerNotFound => "WFP layer not found",
+                ErrorCode::IncompatibleLayer => "condition is incompatible with the WFP layer",
+                ErrorCode::AlreadyExists => "object already exists",
+                ErrorCode::AccessDenied => "access denied, run as administrator",
+                ErrorCode::FilterNotFound => "filter not found",
+                ErrorCode::ProviderNotFound => "provider not registered",
+                ErrorCode::SublayerNotFound => "sublayer not registered",
+                ErrorCode::LifetimeMismatch => "object lifetime mismatch",
+                ErrorCode::DynamicSession => "persistent objects cannot be created in a dynamic session",
+                ErrorCode::Transaction => "transaction error",
+                ErrorCode::SessionNotOpen => "WFP session is not open",
+                ErrorCode::InvalidWeight => "invalid weight",
+                ErrorCode::Wfp => "WFP call failed",
+                ErrorCode::Internal => "internal error",
+            },
+        }
+    }
+}
+
+impl fmt::Display for ErrorCode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AstralError {
+    // 规则或输入不合法，field 是出错的字段
+    Validation { field: String, message: Message },
+    // WFP / Win32 调用返回的原始状态码
+    Wfp { status: u32, message: Message },
+    // 文件读写失败
+    Io { kind: io::ErrorKind, message: Message },
+    // 配置、地址、端口等文本无法解析，what 说明正在解析什么
+    Parse { what: Message, message: Message },
+    // 不属于以上类别的内部错误
+    Internal(Message),
+}
+
+impl AstralError {
+    pub fn validation(field: impl Into<String>, message: Message) -> Self {
+        AstralError::Validation {
+            field: field.into(),
+            message,
+        }
+    }
+
+    pub fn wfp(status: u32, message: Message) -> Self {
+        AstralError::Wfp {
+            status,
+            message,
+        }
+    }
+
+    pub fn from_hresult(code: HRESULT, message: Message) -> Self {
+        Self::wfp(code.0 as u32, message)
+    }
+
+    pub fn io(error: &io::Error, context: Message) -> Self {
+        AstralError::Io {
+            kind: error.kind(),
+            message: context.map(|_, context| format!("{}: {}", context, error)),
+        }
+    }
+
+    pub fn parse(what: Message, message: Message) -> Self {
+        AstralError::Parse {
+            what,
+            message,
+        }
+    }
+
+    pub fn internal(message: Message) -> Self {
+        AstralError::Internal(message)
+    }
+
+    pub fn code(&self) -> ErrorCode {
+        match self {
+            AstralError::Validation { .. } => ErrorCode::Validation,
+            AstralError::Wfp { status, .. } => ErrorCode::from_status(*status),
+            AstralError::Io { .. } => ErrorCode::Io,
+            AstralError::Parse { .. } => ErrorCode::Parse,
+            AstralError::Internal(_) => ErrorCode::Internal,
+        }
+    }
+
+    // 原始的 WFP / Win32 状态码
+    pub fn status(&self) -> Option<u32> {
+        match self {
+            AstralError::Wfp { status, .. } => Some(*status),
+            _ => None,
+        }
+    }
+
+    // 反序列化中的错误，保留两种语言的完整消息
+    pub fn to_serde<E: serde::de::Error>(&self) -> E {
+        Message::new(self.localized(Language::Chinese), self.localized(Language::English)).to_serde()
+    }
+
+    pub fn is(&self, code: HRESULT) -> bool {
+        self.status() == Some(code.0 as u32)
+    }
+
+    // 错误的详细信息（不含类别说明）
+    pub fn message(&self) -> &Message {
+        match self {
+            AstralError::Validation { message, .. }
+            | AstralError::Wfp { message, .. }
+            | AstralError::Io { message, .. }
+            | AstralError::Parse { message, .. }
+            | AstralError::Internal(message) => message,
+        }
+    }
+
+    // 以指定语言生成面向用户的消息
+    pub fn localized(&self, language: Language) -> String {
+        let summary = self.code().summary(language);
+        let message = self.message().get(language);
+        match (self, language) {
+            (AstralError::Validation { field, .. }, _) => format!("{} [{}]: {}", summary, field, message),
+            (AstralError::Wfp { status, .. }, Language::Chinese) => format!("{}: {} (错误代码: 0x{:08X})", summary, message, status),
+            (AstralError::Wfp { status, .. }, Language::English) => format!("{}: {} (status: 0x{:08X})", summary, message, status),
+            (AstralError::Parse { what, .. }, _) => format!("{} ({}): {}", summary, what.get(language), message),
+            _ => format!("{}: {}", summary, message),
+        }
+    }
+}
+
+impl fmt::Display for AstralError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.localized(Language::Chinese))
+    }
+}
+
+impl std::error::Error for AstralError {}
+
+impl From<io::Error> for AstralError {
+    fn from(error: io::Error) -> Self {
+        AstralError::Io {
+            kind: error.kind(),
+            message: Message::plain(error.to_string()),
+        }
+    }
+}
+
+impl From<serde_json::Error> for AstralError {
+    fn from(error: serde_json::Error) -> Self {
+        AstralError::parse(Message::plain("JSON"), Message::from_serde(&error.to_string()))
+    }
+}
@@ -0,0 +1,303 @@
+// 连接评估模拟器
+//
+// 回答“这个连接会不会被阻止？”：用与 get_layers_for_rule 相同的层选择把规则编译成过滤器，
+// 再按 WFP 的仲裁语义评估一个连接：
+//   - 只有连接所在的授权层（ALE_AUTH_CONNECT / ALE_AUTH_RECV_ACCEPT）上的过滤器参与；
+//   - 同一个子层内，权重最高的匹配过滤器决定该子层的结果；
+//   - 各子层之间阻止优先于允许；没有任何过滤器匹配时默认允许。
+
+use std::fmt;
+use std::net::{IpAddr, SocketAddr};
+use uuid::Uuid;
+use windows::Win32::NetworkManagement::WindowsFilteringPlatform::*;
+use windows::core::GUID;
+use crate::astral_wfp::{layer_name, Direction, FilterAction, FilterRule, IpNetwork, Protocol};
+use crate::backend::SimulatedBackend;
+use crate::error::{AstralError, Result};
+use crate::message;
+use crate::plan::{ConditionField, ConditionValue, FilterCondition, FilterSpec, MatchType, PlanCompiler};
+
+// 待评估的连接
+#[derive(Debug, Clone)]
+pub struct Connection {
+    pub app_path: Option<String>, // NT格式的应用程序路径
+    pub local: SocketAddr,
+    pub remote: SocketAddr,
+    pub protocol: Protocol,
+    pub direction: Direction,      // 只能是 Inbound 或 Outbound
+}
+
+impl Connection {
+    pub fn new(direction: Direction, protocol: Protocol, local: SocketAddr, remote: SocketAddr) -> Self {
+        Self {
+            app_path: None,
+            local,
+            remote,
+            protocol,
+            direction,
+        }
+    }
+
+    // 出站连接，本地地址使用对应地址族的未指定地址
+    pub fn outbound(protocol: Protocol, remote: SocketAddr) -> Self {
+        let local = match remote.ip() {
+            IpAddr::V4(_) => SocketAddr::from(([0, 0, 0, 0], 0)),
+            IpAddr::V6(_) => SocketAddr::from(([0u16; 8], 0)),
+        };
+        Self::new(Direction::Outbound, protocol, local, remote)
+    }
+
+    // 入站连接
+    pub fn inbound(protocol: Protocol, local: SocketAddr, remote: SocketAddr) -> Self {
+        Self::new(Direction::Inbound, protocol, local, remote)
+    }
+
+    // ICMP/ICMPv6 报文，与 WFP 一样把类型放在本地端口、代码放在远程端口
+    pub fn icmp(direction: Direction, protocol: Protocol, local: IpAddr, remote: IpAddr, icmp_type: u8, code: u8) -> Self {
+        Self::new(direction, protocol, SocketAddr::new(local, icmp_type as u16), SocketAddr::new(remote, code as u16))
+    }
+
+    pub fn app_path(mut self, path: &str) -> Self {
+        self.app_path = Some(path.to_string());
+        self
+    }
+
+    pub fn is_ipv6(&self) -> bool {
+        self.remote.is_ipv6()
+    }
+
+    // 连接在WFP中被授权的层
+    pub fn auth_layer(&self) -> Result<GUID> {
+        if self.local.is_ipv6() != self.remote.is_ipv6() {
+            return Err(AstralError::validation("local", message!("本地地址和远程地址的IP版本不一致", "local and remote addresses use different IP versions")));
+        }
+        match (&self.direction, self.is_ipv6()) {
+            (Direction::Outbound, false) => Ok(FWPM_LAYER_ALE_AUTH_CONNECT_V4),
+            (Direction::Outbound, true) => Ok(FWPM_LAYER_ALE_AUTH_CONNECT_V6),
+            (Direction::Inbound, false) => Ok(FWPM_LAYER_ALE_AUTH_RECV_ACCEPT_V4),
+            (Direction::Inbound, true) => Ok(FWPM_LAYER_ALE_AUTH_RECV_ACCEPT_V6),
+            (Direction::Both, _) => Err(AstralError::validation("direction", message!("连接方向必须是入站或出站", "connection direction must be inbound or outbound"))),
+        }
+    }
+
+    // 单个条件是否匹配
+    fn matches_condition(&self, condition: &FilterCondition) -> bool {
+        let value = &condition.value;
+        let matched = match condition.field {
+            ConditionField::AppId => match (&self.app_path, value) {
+                (Some(path), ConditionValue::AppId(expected)) => path.eq_ignore_ascii_case(expected),
+                _ => false,
+            },
+            ConditionField::LocalAddress => address_matches(self.local.ip(), value),
+            ConditionField::RemoteAddress => address_matches(self.remote.ip(), value),
+            ConditionField::LocalPort => port_matches(self.local.port(), value),
+            ConditionField::RemotePort => port_matches(self.remote.port(), value),
+            // ICMP 连接的类型和代码在端口字段中
+            ConditionField::IcmpType => port_matches(self.local.port(), value),
+            ConditionField::IcmpCode => port_matches(self.remote.port(), value),
+            ConditionField::Protocol => {
+                matches!(value, ConditionValue::Protocol(number) if self.protocol.ip_protocol() == Some(*number))
+            },
+        };
+        matched != (condition.match_type == MatchType::NotEqual)
+    }
+
+    // 过滤器是否匹配：不同字段之间是 AND，同一字段的多个条件之间是 OR
+    pub fn matches(&self, spec: &FilterSpec) -> bool {
+        let mut fields: Vec<ConditionField> = Vec::new();
+        for condition in &spec.conditions {
+            if !fields.contains(&condition.field) {
+                fields.push(condition.field);
+            }
+        }
+        fields.iter().all(|field| {
+            spec.conditions
+                .iter()
+                .filter(|c| c.field == *field)
+                .any(|c| self.matches_condition(c))
+        })
+    }
+}
+
+fn address_matches(ip: IpAddr, value: &ConditionValue) -> bool {
+    match (ip, value) {
+        (IpAddr::V4(ip), ConditionValue::V4Addr(expected)) => ip == *expected,
+        (IpAddr::V4(ip), ConditionValue::V4Range(low, high)) => *low <= ip && ip <= *high,
+        (IpAddr::V4(_), ConditionValue::V4Network(network, prefix_len)) => {
+            IpNetwork::new(IpAddr::V4(*network), *prefix_len).contains(&ip)
+        },
+        (IpAddr::V6(ip), ConditionValue::V6Addr(expected)) => ip == *expected,
+        (IpAddr::V6(ip), ConditionValue::V6Range(low, high)) => *low <= ip && ip <= *high,
+        (IpAddr::V6(_), ConditionValue::V6Network(network, prefix_len)) => {
+            IpNetwork::new(IpAddr::V6(*network), *prefix_len).contains(&ip)
+        },
+        _ => false,
+    }
+}
+
+fn port_matches(port: u16, value: &ConditionValue) -> bool {
+    match value {
+        ConditionValue::Port(expected) => port == *expected,
+        ConditionValue::PortRange(low, high) => *low <= port && port <= *high,
+        _ => false,
+    }
+}
+
+// 参与评估的过滤器
+#[derive(Debug, Clone)]
+pub struct EvaluatedFilter {
+    pub spec: FilterSpec,
+    pub filter_id: Option<u64>, // 已添加到引擎时的过滤器ID
+}
+
+// 决定评估结果的过滤器；规则按 rule_id 识别，rule_name 只用于显示
+#[derive(Debug, Clone, PartialEq)]
+pub struct Decision {
+    pub rule_id: Uuid,
+    pub rule_name: String,
+    pub layer_key: GUID,
+    pub sublayer_key: GUID,
+    pub weight: u64,
+    pub filter_id: Option<u64>,
+}
+
+// 评估结果
+#[derive(Debug, Clone, PartialEq)]
+pub struct Verdict {
+    pub action: FilterAction,
+    pub decided_by: Option<Decision>, // None 表示没有过滤器匹配，使用默认动作
+    pub matched: Vec<Decision>,       // 所有匹配的过滤器，按评估顺序排列
+}
+
+impl Verdict {
+    pub fn is_blocked(&self) -> bool {
+        self.action == FilterAction::Block
+    }
+}
+
+impl fmt::Display for Verdict {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.decided_by {
+            Some(decision) => write!(
+                f,
+                "{:?} (规则 '{}' ({}), 层 {}, 权重 {})",
+                self.action,
+                decision.rule_name,
+                decision.rule_id,
+                layer_name(&decision.layer_key),
+                decision.weight
+            ),
+            None => write!(f, "{:?} (没有匹配的过滤器，使用默认动作)", self.action),
+        }
+    }
+}
+
+// 连接评估器
+#[derive(Debug, Clone, Default)]
+pub struct Evaluator {
+    filters: Vec<EvaluatedFilter>,
+}
+
+impl Evaluator {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // 把规则编译成过滤器后用于评估
+    pub fn from_rules(rules: &[FilterRule]) -> Result<Self> {
+        let specs = PlanCompiler::new().compile_all(rules)?;
+        Ok(Self::from_specs(specs))
+    }
+
+    pub fn from_specs(specs: Vec<FilterSpec>) -> Self {
+        Self {
+            filters: specs
+                .into_iter()
+                .map(|spec| EvaluatedFilter { spec, filter_id: None })
+                .collect(),
+        }
+    }
+
+    // 使用模拟引擎中实际存在的过滤器
+    pub fn from_backend(backend: &SimulatedBackend) -> Self {
+        Self {
+            filters: backend
+                .filters()
+                .iter()
+                .map(|f| EvaluatedFilter {
+                    spec: f.spec.clone(),
+                    filter_id: Some(f.record.filter_id),
+                })
+                .collect(),
+        }
+    }
+
+    pub fn add_filter(&mut self, spec: FilterSpec, filter_id: Option<u64>) {
+        self.filters.push(EvaluatedFilter { spec, filter_id });
+    }
+
+    pub fn filters(&self) -> &[EvaluatedFilter] {
+        &self.filters
+    }
+
+    // 评估一个连接
+    pub fn evaluate(&self, connection: &Connection) -> Result<Verdict> {
+        let layer_key = connection.auth_layer()?;
+
+        // 该层上匹配的过滤器，按子层分组（保持首次出现的顺序）
+        let mut sublayers: Vec<(GUID, Vec<&EvaluatedFilter>)> = Vec::new();
+        for filter in self.filters.iter().filter(|f| f.spec.layer_key == layer_key) {
+            if !connection.matches(&filter.spec) {
+                continue;
+            }
+            match sublayers.iter_mut().find(|(key, _)| *key == filter.spec.sublayer_key) {
+                Some((_, filters)) => filters.push(filter),
+                None => sublayers.push((filter.spec.sublayer_key, vec![filter])),
+            }
+        }
+
+        let mut matched = Vec::new();
+        let mut decided_by: Option<Decision> = None;
+        let mut action = FilterAction::Allow;
+
+        for (_, filters) in &mut sublayers {
+            // 子层内按权重从高到低评估，权重相同时保持添加顺序
+            filters.sort_by_key(|f| std::cmp::Reverse(f.spec.weight));
+            matched.extend(filters.iter().map(|f| decision_for(f)));
+
+            let winner = filters[0];
+            let sublayer_decision = decision_for(winner);
+            match winner.spec.action {
+                // 阻止优先：任一子层阻止则连接被阻止
+                FilterAction::Block => {
+                    if action != FilterAction::Block {
+                        action = FilterAction::Block;
+                        decided_by = Some(sublayer_decision);
+                    }
+                },
+                FilterAction::Allow => {
+                    if decided_by.is_none() {
+                        decided_by = Some(sublayer_decision);
+                    }
+                },
+            }
+        }
+
+        Ok(Verdict {
+            action,
+            decided_by,
+            matched,
+        })
+    }
+}
+
+fn decision_for(filter: &EvaluatedFilter) -> Decision {
+    Decision {
+        rule_id: filter.spec.rule_id,
+        rule_name: filter.spec.rule_name.clone(),
+        layer_key: filter.spec.layer_key,
+        sublayer_key: filter.spec.sublayer_key,
+        weight: filter.spec.weight,
+        filter_id: filter.filter_id,
+    }
+}
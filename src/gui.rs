@@ -1,7 +1,15 @@
 use eframe::egui;
 use std::sync::{Arc, Mutex};
+use uuid::Uuid;
+use tracing::{error, warn};
 use crate::astral_wfp::{WfpController, FilterRule, Direction, FilterAction, Protocol};
+use crate::error::{AstralError, ErrorCode};
+use crate::icmp::IcmpType;
+use crate::message;
+use crate::services;
 use crate::nt::get_nt_path;
+use crate::plan::EnforcementMode;
+use crate::validation::{Diagnostic, RuleDraft, Severity};
 
 // 规则信息结构体
 #[derive(Debug, Clone)]
@@ -9,6 +17,7 @@ pub struct RuleInfo {
     pub rule: FilterRule,
     pub filter_ids: Vec<u64>,  // 存储该规则对应的所有过滤器ID
     pub is_active: bool,       // 规则是否激活
+    pub mode: EnforcementMode, // 规则应用时使用的执行模式
 }
 
 pub struct WfpGui {
@@ -21,6 +30,7 @@ pub struct WfpGui {
     
     // 规则管理
     rules: Vec<RuleInfo>,
+    selected_mode: EnforcementMode, // 初始化时使用的执行模式
 
     // 规则添加表单
     rule_name: String,
@@ -30,8 +40,11 @@ pub struct WfpGui {
     local_port: String,
     remote_port: String,
     selected_protocol: Option<Protocol>,
+    selected_icmp_type: Option<IcmpType>,
+    icmp_code: String,
     selected_direction: Direction,
     selected_action: FilterAction,
+    form_diagnostics: Vec<Diagnostic>, // 表单最近一次校验的全部诊断
 }
 
 impl Default for WfpGui {
@@ -42,6 +55,7 @@ impl Default for WfpGui {
             status_message: "准备就绪".to_string(),
             status_color: egui::Color32::GREEN,
             rules: Vec::new(),
+            selected_mode: EnforcementMode::Dynamic,
             rule_name: "新规则".to_string(),
             app_path: "".to_string(),
             local_ip: "".to_string(),
@@ -49,8 +63,11 @@ impl Default for WfpGui {
             local_port: "".to_string(),
             remote_port: "".to_string(),
             selected_protocol: None,
+            selected_icmp_type: None,
+            icmp_code: "".to_string(),
             selected_direction: Direction::Both,
             selected_action: FilterAction::Block,
+            form_diagnostics: Vec::new(),
         }
     }
 }
@@ -81,6 +98,7 @@ impl eframe::App for WfpGui {
                 ui.label("状态:");
                 if self.is_initialized {
                     ui.colored_label(egui::Color32::GREEN, "✅ 已初始化");
+                    ui.label(format!("执行模式: {}", self.selected_mode));
                 } else {
                     ui.colored_label(egui::Color32::RED, "❌ 未初始化");
                 }
@@ -90,73 +108,77 @@ impl eframe::App for WfpGui {
             // 规则添加表单卡片
             egui::Frame::group(ui.style()).show(ui, |ui| {
                 ui.heading("➕ 添加规则");
-                let mut input_error = None;
+                let mut edited = false;
                 ui.horizontal(|ui| {
                     ui.label("名称:");
-                    ui.text_edit_singleline(&mut self.rule_name);
+                    edited |= ui.text_edit_singleline(&mut self.rule_name).lost_focus();
                 });
                 ui.horizontal(|ui| {
                     ui.label("应用程序路径:");
-                    ui.text_edit_singleline(&mut self.app_path);
+                    edited |= ui.text_edit_singleline(&mut self.app_path).lost_focus();
                 });
                 ui.horizontal(|ui| {
                     ui.label("本地IP:");
-                    if ui.text_edit_singleline(&mut self.local_ip).lost_focus() && !self.local_ip.is_empty() {
-                        if self.local_ip.parse::<std::net::IpAddr>().is_err() && !self.local_ip.contains('/') {
-                            input_error = Some("本地IP格式错误");
-                        }
-                    }
+                    edited |= ui.text_edit_singleline(&mut self.local_ip).lost_focus();
                     ui.label("本地端口:");
-                    if ui.text_edit_singleline(&mut self.local_port).lost_focus() && !self.local_port.is_empty() {
-                        if self.local_port.parse::<u16>().is_err() {
-                            input_error = Some("本地端口格式错误");
-                        }
-                    }
+                    edited |= ui.text_edit_singleline(&mut self.local_port).lost_focus();
                 });
                 ui.horizontal(|ui| {
                     ui.label("远程IP:");
-                    if ui.text_edit_singleline(&mut self.remote_ip).lost_focus() && !self.remote_ip.is_empty() {
-                        if self.remote_ip.parse::<std::net::IpAddr>().is_err() && !self.remote_ip.contains('/') {
-                            input_error = Some("远程IP格式错误");
-                        }
-                    }
+                    edited |= ui.text_edit_singleline(&mut self.remote_ip).lost_focus();
                     ui.label("远程端口:");
-                    if ui.text_edit_singleline(&mut self.remote_port).lost_focus() && !self.remote_port.is_empty() {
-                        if self.remote_port.parse::<u16>().is_err() {
-                            input_error = Some("远程端口格式错误");
-                        }
-                    }
+                    edited |= ui.text_edit_singleline(&mut self.remote_port).lost_focus();
                 });
                 ui.horizontal(|ui| {
                     ui.label("协议:");
                     egui::ComboBox::from_id_source("protocol")
-                        .selected_text(match self.selected_protocol {
-                            Some(Protocol::Tcp) => "TCP",
-                            Some(Protocol::Udp) => "UDP",
-                            Some(Protocol::Icmp) => "ICMP",
-                            Some(Protocol::IcmpV6) => "ICMPv6",
-                            Some(Protocol::Igmp) => "IGMP",
-                            Some(Protocol::Ah) => "AH",
-                            Some(Protocol::Esp) => "ESP",
-                            Some(Protocol::Gre) => "GRE",
-                            Some(Protocol::Ipsec) => "IPSEC",
-                            Some(Protocol::Any) => "任意协议",
-                            None => "任意协议",
+                        .selected_text(match &self.selected_protocol {
+                            Some(protocol) => protocol.to_string(),
+                            None => Protocol::Any.to_string(),
                         })
                         .show_ui(ui, |ui| {
-                            ui.selectable_value(&mut self.selected_protocol, Some(Protocol::Tcp), "TCP");
-                            ui.selectable_value(&mut self.selected_protocol, Some(Protocol::Udp), "UDP");
-                            ui.selectable_value(&mut self.selected_protocol, Some(Protocol::Icmp), "ICMP");
-                            ui.selectable_value(&mut self.selected_protocol, Some(Protocol::IcmpV6), "ICMPv6");
-                            ui.selectable_value(&mut self.selected_protocol, Some(Protocol::Igmp), "IGMP");
-                            ui.selectable_value(&mut self.selected_protocol, Some(Protocol::Ah), "AH");
-                            ui.selectable_value(&mut self.selected_protocol, Some(Protocol::Esp), "ESP");
-                            ui.selectable_value(&mut self.selected_protocol, Some(Protocol::Gre), "GRE");
-                            ui.selectable_value(&mut self.selected_protocol, Some(Protocol::Ipsec), "IPSEC");
-                            ui.selectable_value(&mut self.selected_protocol, Some(Protocol::Any), "任意协议");
-                            ui.selectable_value(&mut self.selected_protocol, None, "任意协议");
+                            for protocol in services::PROTOCOLS {
+                                ui.selectable_value(&mut self.selected_protocol, Some(*protocol), protocol.to_string());
+                            }
+                        });
+                    // 选择内置服务时填入远程端口，服务只使用一种协议时同时选择协议
+                    ui.label("服务:");
+                    egui::ComboBox::from_id_source("service")
+                        .selected_text("选择服务")
+                        .show_ui(ui, |ui| {
+                            for service in services::SERVICES {
+                                let label = format!("{} ({}/{}) {}", service.name, service.port, service.protocol_names(), service.description);
+                                if ui.selectable_label(self.remote_port == service.name, label).clicked() {
+                                    self.remote_port = service.name.to_string();
+                                    if let Some(protocol) = service.protocol() {
+                                        self.selected_protocol = Some(*protocol);
+                                    }
+                                }
+                            }
                         });
                 });
+                // ICMP 类型按所选协议显示对应的编号
+                if let Some(protocol @ (Protocol::Icmp | Protocol::IcmpV6)) = self.selected_protocol.map(Protocol::normalized) {
+                    let is_v6 = protocol == Protocol::IcmpV6;
+                    ui.horizontal(|ui| {
+                        ui.label("ICMP类型:");
+                        egui::ComboBox::from_id_source("icmp_type")
+                            .selected_text(match self.selected_icmp_type {
+                                Some(icmp_type) => icmp_type.to_string(),
+                                None => "任意类型".to_string(),
+                            })
+                            .show_ui(ui, |ui| {
+                                ui.selectable_value(&mut self.selected_icmp_type, None, "任意类型");
+                                for icmp_type in IcmpType::named() {
+                                    if let Some(number) = icmp_type.number(is_v6) {
+                                        ui.selectable_value(&mut self.selected_icmp_type, Some(icmp_type), format!("{} ({})", icmp_type, number));
+                                    }
+                                }
+                            });
+                        ui.label("代码:");
+                        edited |= ui.text_edit_singleline(&mut self.icmp_code).lost_focus();
+                    });
+                }
                 ui.horizontal(|ui| {
                     ui.label("方向:");
                     egui::ComboBox::from_id_source("direction")
@@ -183,10 +205,21 @@ impl eframe::App for WfpGui {
                             ui.selectable_value(&mut self.selected_action, FilterAction::Block, "阻止");
                         });
                 });
-                if let Some(err) = input_error {
-                    ui.colored_label(egui::Color32::RED, err);
+                // 输入框失去焦点时校验整个表单，列出全部错误和警告
+                if edited {
+                    self.form_diagnostics = match self.rule_draft() {
+                        Ok(draft) => draft.build().1,
+                        Err(diagnostic) => vec![diagnostic],
+                    };
                 }
-                if ui.add_enabled(input_error.is_none(), egui::Button::new("添加规则")).clicked() {
+                for diagnostic in &self.form_diagnostics {
+                    let color = match diagnostic.severity {
+                        Severity::Error => egui::Color32::RED,
+                        Severity::Warning => egui::Color32::YELLOW,
+                    };
+                    ui.colored_label(color, diagnostic.to_string());
+                }
+                if ui.button("添加规则").clicked() {
                     self.add_rule();
                 }
             });
@@ -201,12 +234,12 @@ impl eframe::App for WfpGui {
                         if self.rules.is_empty() {
                             ui.label("暂无规则");
                         } else {
-                            let mut to_remove: Option<usize> = None;
+                            let mut to_remove: Option<Uuid> = None;
                             let available_width = ui.available_width();
                             let card_width = 280.0; // 卡片宽度
                             let cards_per_row = (available_width / card_width).max(1.0) as usize;
                             
-                            for (i, rule_info) in self.rules.iter().enumerate() {
+                            for (i, _rule_info) in self.rules.iter().enumerate() {
                                 if i % cards_per_row == 0 {
                                     ui.horizontal(|ui| {
                                         for j in 0..cards_per_row {
@@ -218,33 +251,41 @@ impl eframe::App for WfpGui {
                                                         .show(ui, |ui| {
                                                             ui.set_min_size(egui::vec2(card_width - 10.0, 160.0));
                                                             ui.horizontal(|ui| {
-                                                                ui.label(format!("规则 {}", rule_index + 1));
+                                                                ui.label(format!("规则 {}", rule_index + 1))
+                                                                    .on_hover_text(format!("ID: {}", rule_info.rule.id));
                                                                 ui.with_layout(egui::Layout::right_to_left(egui::Align::Center), |ui| {
                                                                     if ui.button("🗑️").clicked() {
-                                                                        to_remove = Some(rule_index);
+                                                                        to_remove = Some(rule_info.rule.id);
                                                                     }
                                                                 });
                                                             });
                                                             ui.label(format!("名称: {}", rule_info.rule.name));
                                                             ui.label(format!("动作: {:?}", rule_info.rule.action));
                                                             ui.label(format!("方向: {:?}", rule_info.rule.direction));
+                                                            ui.label(format!("执行模式: {}", rule_info.mode));
                                                             if let Some(app_path) = &rule_info.rule.app_path {
                                                                 ui.label(format!("应用程序: {}", app_path));
                                                             }
                                                             if let Some(protocol) = &rule_info.rule.protocol {
                                                                 ui.label(format!("协议: {}", protocol));
                                                             }
+                                                            if let Some(icmp_type) = &rule_info.rule.icmp_type {
+                                                                match rule_info.rule.icmp_code {
+                                                                    Some(code) => ui.label(format!("ICMP: {} 代码 {}", icmp_type, code)),
+                                                                    None => ui.label(format!("ICMP: {}", icmp_type)),
+                                                                };
+                                                            }
                                                             if let Some(ip) = &rule_info.rule.local {
                                                                 ui.label(format!("本地IP: {}", ip));
                                                             }
                                                             if let Some(ip) = &rule_info.rule.remote {
                                                                 ui.label(format!("远程IP: {}", ip));
                                                             }
-                                                            if let Some(port) = rule_info.rule.local_port {
-                                                                ui.label(format!("本地端口: {}", port));
+                                                            if let Some(ports) = &rule_info.rule.local_ports {
+                                                                ui.label(format!("本地端口: {}", services::describe_ports(ports, rule_info.rule.protocol.as_ref())));
                                                             }
-                                                            if let Some(port) = rule_info.rule.remote_port {
-                                                                ui.label(format!("远程端口: {}", port));
+                                                            if let Some(ports) = &rule_info.rule.remote_ports {
+                                                                ui.label(format!("远程端口: {}", services::describe_ports(ports, rule_info.rule.protocol.as_ref())));
                                                             }
                                                         });
                                                 });
@@ -257,10 +298,9 @@ impl eframe::App for WfpGui {
                                     ui.add_space(8.0);
                                 }
                             }
-                            if let Some(index) = to_remove {
-                                if let Err(e) = self.remove_rule(index) {
-                                    eprintln!("删除规则失败: {}", e);
-                                }
+                            if let Some(rule_id) = to_remove
+                                && let Err(e) = self.remove_rule(rule_id) {
+                                warn!("删除规则失败: {}", e);
                             }
                         }
                     });
@@ -271,10 +311,18 @@ impl eframe::App for WfpGui {
                 if ui.button("🔄 刷新规则").clicked() {
                     self.refresh_rules();
                 }
-                if ui.button("🚀 初始化防火墙").clicked() {
-                    if let Err(e) = self.initialize_wfp() {
-                        eprintln!("初始化失败: {}", e);
-                    }
+                ui.add_enabled_ui(!self.is_initialized, |ui| {
+                    egui::ComboBox::from_id_source("mode")
+                        .selected_text(format!("执行模式: {}", self.selected_mode))
+                        .show_ui(ui, |ui| {
+                            ui.selectable_value(&mut self.selected_mode, EnforcementMode::Dynamic, "动态（进程退出后失效）");
+                            ui.selectable_value(&mut self.selected_mode, EnforcementMode::Persistent, "持久（重启后仍然有效）");
+                            ui.selectable_value(&mut self.selected_mode, EnforcementMode::BootTime, "启动时（系统启动即生效）");
+                        });
+                });
+                if ui.button("🚀 初始化防火墙").clicked()
+                    && let Err(e) = self.initialize_wfp() {
+                    error!(code = %e.code(), "初始化失败: {}", e);
                 }
             });
         });
@@ -282,96 +330,118 @@ impl eframe::App for WfpGui {
 }
 
 impl WfpGui {
-    fn initialize_wfp(&mut self) -> Result<(), String> {
-        let mut controller = WfpController::new().map_err(|e| e.to_string())?;
+    // 使用指定的默认执行模式创建界面
+    pub fn with_mode(mode: EnforcementMode) -> Self {
+        Self {
+            selected_mode: mode,
+            ..Default::default()
+        }
+    }
+
+    fn initialize_wfp(&mut self) -> Result<(), AstralError> {
+        let mut controller = WfpController::new()?.with_mode(self.selected_mode);
         match controller.initialize() {
             Ok(()) => {
                 *self.wfp_controller.lock().unwrap() = Some(controller);
                 self.is_initialized = true;
-                self.status_message = "WFP已初始化".to_string();
+                self.status_message = format!("WFP已初始化（执行模式: {}）", self.selected_mode);
                 self.status_color = egui::Color32::GREEN;
                 self.refresh_rules();
                 Ok(())
             }
             Err(e) => {
-                self.status_message = format!("初始化失败: {:?}", e);
+                self.status_message = match e.code() {
+                    // 没有管理员权限时只提示如何解决，不显示状态码
+                    ErrorCode::AccessDenied => "初始化失败: 请右键选择“以管理员身份运行” AstralWFP".to_string(),
+                    _ => format!("初始化失败: {}", e),
+                };
                 self.status_color = egui::Color32::RED;
-                Err(e.to_string())
+                Err(e)
             }
         }
     }
     
+    // 表单对应的规则草稿；应用程序路径转换成 NT 路径，转换失败时返回诊断
+    fn rule_draft(&self) -> Result<RuleDraft, Diagnostic> {
+        let mut draft = RuleDraft::new(&self.rule_name);
+        if !self.app_path.is_empty() {
+            let nt_path = self.app_path.get(..2).and_then(|_| get_nt_path(&self.app_path)).ok_or_else(|| {
+                Diagnostic::error("app_path", message!("应用程序路径转换失败: {}", "cannot convert the application path: {}", self.app_path))
+                    .suggest(message!(
+                        "填写以盘符开头的完整路径，例如 C:\\Program Files\\app.exe",
+                        "enter a full path starting with a drive letter, e.g. C:\\Program Files\\app.exe"
+                    ))
+            })?;
+            draft.app_path = nt_path;
+        }
+        draft.local = self.local_ip.clone();
+        draft.remote = self.remote_ip.clone();
+        draft.local_ports = self.local_port.clone();
+        draft.remote_ports = self.remote_port.clone();
+        draft.protocol = self.selected_protocol;
+        if let Some(Protocol::Icmp | Protocol::IcmpV6) = self.selected_protocol.map(Protocol::normalized) {
+            draft.icmp_type = self.selected_icmp_type;
+            draft.icmp_code = self.icmp_code.clone();
+        }
+        draft.direction = self.selected_direction.clone();
+        draft.action = self.selected_action.clone();
+        Ok(draft)
+    }
+
     fn add_rule(&mut self) {
         if !self.is_initialized {
             self.status_message = "请先初始化WFP".to_string();
             self.status_color = egui::Color32::RED;
             return;
         }
-        let mut rule = FilterRule::new(&self.rule_name)
-            .direction(self.selected_direction.clone())
-            .action(self.selected_action.clone());
-        if !self.app_path.is_empty() {
-            // 对应用程序路径进行NT转换
-            let nt_path = match get_nt_path(&self.app_path) {
-                Some(path) => path,
-                None => {
-                    self.status_message = format!("应用程序路径转换失败: {}", self.app_path);
-                    self.status_color = egui::Color32::RED;
-                    return;
-                }
-            };
-            rule = rule.app_path(&nt_path);
-        }
-        if !self.local_ip.is_empty() {
-            rule = rule.local_ip(&self.local_ip);
-        }
-        if !self.remote_ip.is_empty() {
-            rule = rule.remote_ip(&self.remote_ip);
-        }
-        if let Ok(port) = self.local_port.parse::<u16>() {
-            rule = rule.local_port(port);
-        }
-        if let Ok(port) = self.remote_port.parse::<u16>() {
-            rule = rule.remote_port(port);
-        }
-        if let Some(protocol) = &self.selected_protocol {
-            rule = rule.protocol(protocol.clone());
-        }
+        let (rule, diagnostics) = match self.rule_draft() {
+            Ok(draft) => draft.build(),
+            Err(diagnostic) => (None, vec![diagnostic]),
+        };
+        let errors = diagnostics.iter().filter(|d| d.is_error()).count();
+        let warnings = diagnostics.len() - errors;
+        self.form_diagnostics = diagnostics;
+        let Some(rule) = rule else {
+            self.status_message = format!("添加规则失败: 表单有 {} 个错误", errors);
+            self.status_color = egui::Color32::RED;
+            return;
+        };
         if let Some(controller) = &mut *self.wfp_controller.lock().unwrap() {
-            match controller.add_advanced_filters(&[rule.clone()]) {
+            match controller.add_advanced_filters(std::slice::from_ref(&rule)) {
                 Ok(filter_ids) => {
                     let rule_info = RuleInfo {
                         rule,
                         filter_ids,
                         is_active: true,
+                        mode: controller.mode(),
                     };
                     self.rules.push(rule_info);
-                    self.status_message = "规则添加成功".to_string();
-                    self.status_color = egui::Color32::GREEN;
+                    if warnings > 0 {
+                        self.status_message = format!("规则添加成功，有 {} 个警告", warnings);
+                        self.status_color = egui::Color32::YELLOW;
+                    } else {
+                        self.status_message = "规则添加成功".to_string();
+                        self.status_color = egui::Color32::GREEN;
+                    }
                 }
                 Err(e) => {
-                    self.status_message = format!("添加规则失败: {:?}", e);
+                    self.status_message = format!("添加规则失败: {}", e);
                     self.status_color = egui::Color32::RED;
                 }
             }
         }
     }
     
-    fn remove_rule(&mut self, index: usize) -> Result<(), String> {
-        if index < self.rules.len() {
-            let rule_info = &self.rules[index];
-            if let Some(controller) = &mut *self.wfp_controller.lock().unwrap() {
-                for &filter_id in &rule_info.filter_ids {
-                    if let Err(e) = controller.remove_filter(filter_id) {
-                        eprintln!("删除过滤器 {} 失败: {}", filter_id, e);
-                    }
-                }
-            }
-            self.rules.remove(index);
-            Ok(())
-        } else {
-            Err(format!("索引 {} 超出范围", index))
+    fn remove_rule(&mut self, rule_id: Uuid) -> Result<(), String> {
+        let Some(index) = self.rules.iter().position(|r| r.rule.id == rule_id) else {
+            return Err(format!("规则 {} 不存在", rule_id));
+        };
+        if let Some(controller) = &mut *self.wfp_controller.lock().unwrap()
+            && let Err(e) = controller.remove_rule(&rule_id) {
+            warn!(rule = %rule_id, code = %e.code(), "删除规则的过滤器失败: {}", e);
         }
+        self.rules.remove(index);
+        Ok(())
     }
     
     fn refresh_rules(&mut self) {
@@ -381,15 +451,15 @@ impl WfpGui {
             return;
         }
         if let Some(controller) = &mut *self.wfp_controller.lock().unwrap() {
-            match controller.get_rules() {
-                Ok(rules) => {
+            match controller.installed_rules() {
+                Ok(installed) => {
                     self.rules.clear();
-                    for rule in rules {
-                        let filter_ids = controller.get_filter_ids(&rule).unwrap_or_default();
+                    for installed_rule in installed {
                         let rule_info = RuleInfo {
-                            rule,
-                            filter_ids,
+                            rule: installed_rule.rule,
+                            filter_ids: installed_rule.filter_ids,
                             is_active: true,
+                            mode: installed_rule.mode,
                         };
                         self.rules.push(rule_info);
                     }
@@ -397,7 +467,7 @@ impl WfpGui {
                     self.status_color = egui::Color32::GREEN;
                 }
                 Err(e) => {
-                    self.status_message = format!("刷新规则失败: {:?}", e);
+                    self.status_message = format!("刷新规则失败: {}", e);
                     self.status_color = egui::Color32::RED;
                 }
             }
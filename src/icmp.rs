@@ -0,0 +1,149 @@
+// ICMP 类型
+//
+// WFP 没有单独的 ICMP 条件字段：ICMP 类型通过 IP_LOCAL_PORT、代码通过 IP_REMOTE_PORT 传递
+// （头文件中的 FWPM_CONDITION_ICMP_TYPE / FWPM_CONDITION_ICMP_CODE 只是这两个字段的别名）。
+// 同名类型在 ICMP 和 ICMPv6 中的编号不同，因此 IcmpType 按名称保存，编译时按规则的协议换算编号。
+
+use std::fmt;
+use std::str::FromStr;
+use serde::{Deserialize, Serialize};
+use crate::error::{AstralError, Result};
+use crate::message;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum IcmpType {
+    EchoReply,
+    DestinationUnreachable,
+    SourceQuench,
+    Redirect,
+    EchoRequest,
+    RouterAdvertisement,
+    RouterSolicitation,
+    TimeExceeded,
+    ParameterProblem,
+    Timestamp,
+    TimestampReply,
+    PacketTooBig,
+    NeighborSolicitation,
+    NeighborAdvertisement,
+    Other(u8), // 按编号指定的类型，不随地址族换算
+}
+
+// (类型, 名称, ICMP 编号, ICMPv6 编号)；只在一个地址族中定义的类型另一侧为 None
+const TYPES: &[(IcmpType, &str, Option<u8>, Option<u8>)] = &[
+    (IcmpType::EchoReply, "echo-reply", Some(0), Some(129)),
+    (IcmpType::DestinationUnreachable, "destination-unreachable", Some(3), Some(1)),
+    (IcmpType::SourceQuench, "source-quench", Some(4), None),
+    (IcmpType::Redirect, "redirect", Some(5), Some(137)),
+    (IcmpType::EchoRequest, "echo-request", Some(8), Some(128)),
+    (IcmpType::RouterAdvertisement, "router-advertisement", Some(9), Some(134)),
+    (IcmpType::RouterSolicitation, "router-solicitation", Some(10), Some(133)),
+    (IcmpType::TimeExceeded, "time-exceeded", Some(11), Some(3)),
+    (IcmpType::ParameterProblem, "parameter-problem", Some(12), Some(4)),
+    (IcmpType::Timestamp, "timestamp", Some(13), None),
+    (IcmpType::TimestampReply, "timestamp-reply", Some(14), None),
+    (IcmpType::PacketTooBig, "packet-too-big", None, Some(2)),
+    (IcmpType::NeighborSolicitation, "neighbor-solicitation", None, Some(135)),
+    (IcmpType::NeighborAdvertisement, "neighbor-advertisement", None, Some(136)),
+];
+
+impl IcmpType {
+    // 所有命名类型，按 ICMP 编号排列
+    pub fn named() -> impl Iterator<Item = IcmpType> {
+        TYPES.iter().map(|(icmp_type, ..)| *icmp_type)
+    }
+
+    pub fn name(&self) -> Option<&'static str> {
+        TYPES.iter().find(|(t, ..)| t == self).map(|(_, name, ..)| *name)
+    }
+
+    // 类型在 ICMP（is_v6 为 false）或 ICMPv6 中的编号；该地址族没有定义此类型时返回 None
+    pub fn number(&self, is_v6: bool) -> Option<u8> {
+        match self {
+            IcmpType::Other(number) => Some(*number),
+            named => TYPES
+                .iter()
+                .find(|(t, ..)| t == named)
+                .and_then(|(_, _, v4, v6)| if is_v6 { *v6 } else { *v4 }),
+        }
+    }
+
+    // 按编号查找命名类型，未命名的编号返回 Other
+    pub fn from_number(number: u8, is_v6: bool) -> Self {
+        TYPES
+            .iter()
+            .find(|(_, _, v4, v6)| (if is_v6 { *v6 } else { *v4 }) == Some(number))
+            .map(|(t, ..)| *t)
+            .unwrap_or(IcmpType::Other(number))
+    }
+}
+
+impl fmt::Display for IcmpType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match (self, self.name()) {
+            (IcmpType::Other(number), _) => write!(f, "{}", number),
+            (_, Some(name)) => write!(f, "{}", name),
+            (_, None) => unreachable!("命名类型都在 TYPES 中"),
+        }
+    }
+}
+
+// 接受名称（不区分大小写，"-"、"_" 和空格等价）或 0-255 的编号
+impl FromStr for IcmpType {
+    type Err = AstralError;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let name = s.trim().to_lowercase().replace(['_', ' '], "-");
+        if let Some((icmp_type, ..)) = TYPES.iter().find(|(_, n, ..)| *n == name) {
+            return Ok(*icmp_type);
+        }
+        name.parse::<u8>()
+            .map(IcmpType::Other)
+            .map_err(|_| AstralError::parse(
+                    message!("ICMP 类型", "ICMP type"),
+                    message!("未知的 ICMP 类型: {} (可以使用类型名或 0-255 的编号)", "unknown ICMP type: {} (use a type name or a number 0-255)", s),
+                ))
+    }
+}
+
+// 命名类型序列化为名称，其余类型序列化为编号
+impl Serialize for IcmpType {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error> {
+        match self {
+            IcmpType::Other(number) => serializer.serialize_u8(*number),
+            named => serializer.serialize_str(&named.to_string()),
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for IcmpType {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> std::result::Result<Self, D::Error> {
+        struct IcmpTypeVisitor;
+
+        impl serde::de::Visitor<'_> for IcmpTypeVisitor {
+            type Value = IcmpType;
+
+            fn expecting(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+                write!(f, "ICMP 类型名或 0-255 的编号")
+            }
+
+            fn visit_u64<E: serde::de::Error>(self, value: u64) -> std::result::Result<IcmpType, E> {
+                u8::try_from(value)
+                    .map(IcmpType::Other)
+                    .map_err(|_| message!("ICMP 类型超出范围: {}", "ICMP type out of range: {}", value).to_serde())
+            }
+
+            fn visit_i64<E: serde::de::Error>(self, value: i64) -> std::result::Result<IcmpType, E> {
+                u8::try_from(value)
+                    .map(IcmpType::Other)
+                    .map_err(|_| message!("ICMP 类型超出范围: {}", "ICMP type out of range: {}", value).to_serde())
+            }
+
+            fn visit_str<E: serde::de::Error>(self, value: &str) -> std::result::Result<IcmpType, E> {
+                value.parse().map_err(|e: AstralError| e.message().to_serde())
+            }
+        }
+
+        deserializer.deserialize_any(IcmpTypeVisitor)
+    }
+}
@@ -0,0 +1,466 @@
+// IP 地址集合
+//
+// IpSet 同时容纳 IPv4 和 IPv6 地址，内部把每个地址族保存为有序、互不相交且互不相邻的闭区间列表，
+// 因此任意集合都有唯一的规范形式：并集、交集、差集的结果可以直接比较是否相等，
+// 成员检查是一次二分查找。区间和最少数量的 CIDR 网段之间可以互相转换。
+
+use std::fmt;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use std::str::FromStr;
+use serde::{Deserialize, Serialize};
+use crate::astral_wfp::IpNetwork;
+use crate::error::{AstralError, Message, Result};
+use crate::message;
+
+const V4_MAX: u128 = u32::MAX as u128;
+const V6_MAX: u128 = u128::MAX;
+
+// 一个地址族内的规范化区间列表，地址统一用 u128 表示；端口列表也复用它
+#[derive(Debug, Clone, Default, PartialEq, Eq, Hash)]
+pub(crate) struct Ranges(Vec<(u128, u128)>);
+
+impl Ranges {
+    // 排序并合并重叠或相邻的区间
+    pub(crate) fn normalized(mut ranges: Vec<(u128, u128)>) -> Self {
+        ranges.sort_unstable();
+        let mut merged: Vec<(u128, u128)> = Vec::with_capacity(ranges.len());
+        for (start, end) in ranges {
+            match merged.last_mut() {
+                Some(last) if start <= last.1.saturating_add(1) => last.1 = last.1.max(end),
+                _ => merged.push((start, end)),
+            }
+        }
+        Ranges(merged)
+    }
+
+    pub(crate) fn union(&self, other: &Ranges) -> Ranges {
+        Ranges::normalized(self.0.iter().chain(&other.0).copied().collect())
+    }
+
+    pub(crate) fn intersection(&self, other: &Ranges) -> Ranges {
+        let (mut i, mut j) = (0, 0);
+        let mut result = Vec::new();
+        while i < self.0.len() && j < other.0.len() {
+            let (a, b) = (self.0[i], other.0[j]);
+            let start = a.0.max(b.0);
+            let end = a.1.min(b.1);
+            if start <= end {
+                result.push((start, end));
+            }
+            if a.1 < b.1 {
+                i += 1;
+            } else {
+                j += 1;
+            }
+        }
+        Ranges(result)
+    }
+
+    pub(crate) fn difference(&self, other: &Ranges) -> Ranges {
+        let mut result = Vec::new();
+        let mut j = 0;
+        for &(start, end) in &self.0 {
+            let mut current = start;
+            let mut exhausted = false;
+            // 跳过完全在当前区间之前的减数
+            while j < other.0.len() && other.0[j].1 < start {
+                j += 1;
+            }
+            let mut k = j;
+            while k < other.0.len() && other.0[k].0 <= end {
+                let (cut_start, cut_end) = other.0[k];
+                if cut_start > current {
+                    result.push((current, cut_start - 1));
+                }
+                if cut_end >= end {
+                    exhausted = true;
+                    break;
+                }
+                current = current.max(cut_end + 1);
+                k += 1;
+            }
+            if !exhausted {
+                result.push((current, end));
+            }
+        }
+        Ranges(result)
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    pub(crate) fn is_superset(&self, other: &Ranges) -> bool {
+        other.difference(self).is_empty()
+    }
+
+    pub(crate) fn iter(&self) -> impl Iterator<Item = (u128, u128)> + '_ {
+        self.0.iter().copied()
+    }
+
+    pub(crate) fn contains(&self, value: u128) -> bool {
+        let index = self.0.partition_point(|&(_, end)| end < value);
+        self.0.get(index).is_some_and(|&(start, _)| start <= value)
+    }
+
+    // 地址数量，全部 IPv6 地址（2^128 个）时饱和为 u128::MAX
+    pub(crate) fn size(&self) -> u128 {
+        self.0.iter().fold(0u128, |total, &(start, end)| total.saturating_add((end - start).saturating_add(1)))
+    }
+}
+
+// 把一个区间拆成最少数量的 CIDR 网段，返回 (网络地址, 前缀长度)
+fn range_cidrs(mut start: u128, end: u128, width: u32) -> Vec<(u128, u8)> {
+    let mut cidrs = Vec::new();
+    loop {
+        // 网段大小受起始地址的对齐和剩余地址数两方面限制
+        let alignment = if start == 0 { width } else { start.trailing_zeros().min(width) };
+        let remaining = end - start; // 剩余地址数减一
+        let fit = if remaining == u128::MAX { u128::BITS } else { u128::BITS - 1 - (remaining + 1).leading_zeros() };
+        let bits = alignment.min(fit);
+        cidrs.push((start, (width - bits) as u8));
+
+        let block_end = start + if bits == u128::BITS { u128::MAX } else { (1u128 << bits) - 1 };
+        if block_end >= end {
+            break;
+        }
+        start = block_end + 1;
+    }
+    cidrs
+}
+
+fn to_v4(value: u128) -> IpAddr {
+    IpAddr::V4(Ipv4Addr::from(value as u32))
+}
+
+fn to_v6(value: u128) -> IpAddr {
+    IpAddr::V6(Ipv6Addr::from(value))
+}
+
+// IPv4 和 IPv6 地址集合
+#[derive(Debug, Clone, Default, PartialEq, Eq, Hash)]
+pub struct IpSet {
+    v4: Ranges,
+    v6: Ranges,
+}
+
+impl IpSet {
+    // 空集合
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // 全部 IPv4 地址
+    pub fn all_v4() -> Self {
+        Self { v4: Ranges(vec![(0, V4_MAX)]), v6: Ranges::default() }
+    }
+
+    // 全部 IPv6 地址
+    pub fn all_v6() -> Self {
+        Self { v4: Ranges::default(), v6: Ranges(vec![(0, V6_MAX)]) }
+    }
+
+    // 两个地址族的全部地址
+    pub fn all() -> Self {
+        Self::all_v4().union(&Self::all_v6())
+    }
+
+    pub fn from_addr(ip: IpAddr) -> Self {
+        Self::from_range(ip, ip).unwrap_or_default()
+    }
+
+    pub fn from_network(network: &IpNetwork) -> Self {
+        let (start, end) = network.range();
+        Self::from_range(start, end).unwrap_or_default()
+    }
+
+    // 任意闭区间 [start, end]，两端必须属于同一地址族且 start <= end
+    pub fn from_range(start: IpAddr, end: IpAddr) -> Result<Self> {
+        let mut set = Self::new();
+        match (start, end) {
+            (IpAddr::V4(low), IpAddr::V4(high)) if low <= high => {
+                set.v4 = Ranges(vec![(u32::from(low) as u128, u32::from(high) as u128)]);
+            },
+            (IpAddr::V6(low), IpAddr::V6(high)) if low <= high => {
+                set.v6 = Ranges(vec![(u128::from(low), u128::from(high))]);
+            },
+            (IpAddr::V4(_), IpAddr::V4(_)) | (IpAddr::V6(_), IpAddr::V6(_)) => {
+                return Err(AstralError::parse(
+                    Message::plain(format!("{}-{}", start, end)),
+                    message!("地址范围的起始地址大于结束地址", "the start of the address range is greater than its end"),
+                ));
+            },
+            _ => {
+                return Err(AstralError::parse(
+                    Message::plain(format!("{}-{}", start, end)),
+                    message!("地址范围两端的IP版本不一致", "the ends of the address range use different IP versions"),
+                ));
+            },
+        }
+        Ok(set)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.v4.0.is_empty() && self.v6.0.is_empty()
+    }
+
+    pub fn has_v4(&self) -> bool {
+        !self.v4.0.is_empty()
+    }
+
+    pub fn has_v6(&self) -> bool {
+        !self.v6.0.is_empty()
+    }
+
+    // 地址数量（饱和到 u128::MAX）
+    pub fn size(&self) -> u128 {
+        self.v4.size().saturating_add(self.v6.size())
+    }
+
+    pub fn contains(&self, ip: &IpAddr) -> bool {
+        match ip {
+            IpAddr::V4(ip) => self.v4.contains(u32::from(*ip) as u128),
+            IpAddr::V6(ip) => self.v6.contains(u128::from(*ip)),
+        }
+    }
+
+    // other 是否是本集合的子集
+    pub fn is_superset(&self, other: &IpSet) -> bool {
+        other.difference(self).is_empty()
+    }
+
+    pub fn is_disjoint(&self, other: &IpSet) -> bool {
+        self.intersection(other).is_empty()
+    }
+
+    pub fn union(&self, other: &IpSet) -> IpSet {
+        IpSet { v4: self.v4.union(&other.v4), v6: self.v6.union(&other.v6) }
+    }
+
+    pub fn intersection(&self, other: &IpSet) -> IpSet {
+        IpSet { v4: self.v4.intersection(&other.v4), v6: self.v6.intersection(&other.v6) }
+    }
+
+    // 属于本集合但不属于 other 的地址
+    pub fn difference(&self, other: &IpSet) -> IpSet {
+        IpSet { v4: self.v4.difference(&other.v4), v6: self.v6.difference(&other.v6) }
+    }
+
+    // 规范化的区间列表，IPv4 在前
+    pub fn ranges(&self) -> Vec<(IpAddr, IpAddr)> {
+        let v4 = self.v4.0.iter().map(|&(start, end)| (to_v4(start), to_v4(end)));
+        let v6 = self.v6.0.iter().map(|&(start, end)| (to_v6(start), to_v6(end)));
+        v4.chain(v6).collect()
+    }
+
+    // 覆盖集合的最少 CIDR 网段，IPv4 在前
+    pub fn to_cidrs(&self) -> Vec<IpNetwork> {
+        let mut networks = Vec::new();
+        for &(start, end) in &self.v4.0 {
+            networks.extend(range_cidrs(start, end, 32).into_iter().map(|(ip, len)| IpNetwork::new(to_v4(ip), len)));
+        }
+        for &(start, end) in &self.v6.0 {
+            networks.extend(range_cidrs(start, end, 128).into_iter().map(|(ip, len)| IpNetwork::new(to_v6(ip), len)));
+        }
+        networks
+    }
+
+    // 解析单个地址、CIDR 网段或 "起始-结束" 形式的范围
+    fn parse_item(item: &str) -> Result<IpSet> {
+        if let Some((start, end)) = item.split_once('-') {
+            let parse = |s: &str| {
+                s.trim().parse::<IpAddr>().map_err(|_| AstralError::parse(Message::plain(item), message!("无效的IP地址: {}", "invalid IP address: {}", s.trim())))
+            };
+            return IpSet::from_range(parse(start)?, parse(end)?);
+        }
+        if item.contains('/') {
+            return Ok(IpSet::from_network(&IpNetwork::from_cidr(item)?));
+        }
+        item.parse::<IpAddr>()
+            .map(IpSet::from_addr)
+            .map_err(|_| AstralError::parse(Message::plain(item), message!("无效的IP地址", "invalid IP address")))
+    }
+}
+
+impl IpNetwork {
+    // 网段的第一个和最后一个地址
+    pub fn range(&self) -> (IpAddr, IpAddr) {
+        match self.ip {
+            IpAddr::V4(ip) => {
+                let mask = crate::astral_wfp::v4_mask(self.prefix_len);
+                let start = u32::from(ip) & mask;
+                (IpAddr::V4(Ipv4Addr::from(start)), IpAddr::V4(Ipv4Addr::from(start | !mask)))
+            },
+            IpAddr::V6(ip) => {
+                let mask = crate::astral_wfp::v6_mask(self.prefix_len);
+                let start = u128::from(ip) & mask;
+                (IpAddr::V6(Ipv6Addr::from(start)), IpAddr::V6(Ipv6Addr::from(start | !mask)))
+            },
+        }
+    }
+}
+
+impl From<IpAddr> for IpSet {
+    fn from(ip: IpAddr) -> Self {
+        IpSet::from_addr(ip)
+    }
+}
+
+impl From<&IpNetwork> for IpSet {
+    fn from(network: &IpNetwork) -> Self {
+        IpSet::from_network(network)
+    }
+}
+
+impl FromIterator<IpSet> for IpSet {
+    fn from_iter<I: IntoIterator<Item = IpSet>>(iter: I) -> Self {
+        iter.into_iter().fold(IpSet::new(), |set, other| set.union(&other))
+    }
+}
+
+// 逗号分隔的地址、网段和范围，例如 "10.0.0.0/8, 1.2.3.4-1.2.3.77, 2001:db8::1"
+impl FromStr for IpSet {
+    type Err = AstralError;
+
+    fn from_str(s: &str) -> Result<Self> {
+        s.split(',')
+            .map(str::trim)
+            .filter(|item| !item.is_empty())
+            .map(IpSet::parse_item)
+            .collect()
+    }
+}
+
+// 以最短的形式输出每个区间：单个地址、CIDR 网段或 "起始-结束"
+impl fmt::Display for IpSet {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut items = Vec::new();
+        for (ranges, width, to_ip) in [(&self.v4, 32, to_v4 as fn(u128) -> IpAddr), (&self.v6, 128, to_v6)] {
+            for &(start, end) in &ranges.0 {
+                let cidrs = range_cidrs(start, end, width);
+                items.push(if start == end {
+                    to_ip(start).to_string()
+                } else if cidrs.len() == 1 {
+                    format!("{}/{}", to_ip(start), cidrs[0].1)
+                } else {
+                    format!("{}-{}", to_ip(start), to_ip(end))
+                });
+            }
+        }
+        write!(f, "{}", items.join(", "))
+    }
+}
+
+// 地址列表中的一项
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct AddressEntry {
+    pub set: IpSet,
+    pub negated: bool, // 以 "!" 开头的排除项
+}
+
+// 一个端点的地址列表：逗号分隔的单个地址、CIDR 网段、"起始-结束" 范围，
+// 以及以 "!" 开头的排除项，例如 "10.0.0.0/8, !10.1.0.0/16, 2001:db8::1"
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct AddressList {
+    pub entries: Vec<AddressEntry>,
+}
+
+impl AddressList {
+    pub fn parse(text: &str) -> Result<Self> {
+        let entries = text
+            .split(',')
+            .map(str::trim)
+            .filter(|item| !item.is_empty())
+            .map(|item| match item.strip_prefix('!') {
+                Some(excluded) => Ok(AddressEntry { set: IpSet::parse_item(excluded.trim())?, negated: true }),
+                None => Ok(AddressEntry { set: IpSet::parse_item(item)?, negated: false }),
+            })
+            .collect::<Result<Vec<_>>>()?;
+        if entries.is_empty() {
+            return Err(AstralError::parse(Message::plain(text), message!("地址列表为空", "the address list is empty")));
+        }
+        Ok(Self { entries })
+    }
+
+    // 所有排除项的并集
+    pub fn excluded(&self) -> IpSet {
+        self.entries.iter().filter(|e| e.negated).map(|e| e.set.clone()).collect()
+    }
+
+    // 列表实际表示的地址：包含项的并集减去排除项；
+    // 只有排除项时，以排除项涉及的地址族的全部地址为基础
+    pub fn effective(&self) -> IpSet {
+        let included: IpSet = self.entries.iter().filter(|e| !e.negated).map(|e| e.set.clone()).collect();
+        let excluded = self.excluded();
+        let base = if self.entries.iter().any(|e| !e.negated) {
+            included
+        } else {
+            let mut base = IpSet::new();
+            if excluded.has_v4() {
+                base = base.union(&IpSet::all_v4());
+            }
+            if excluded.has_v6() {
+                base = base.union(&IpSet::all_v6());
+            }
+            base
+        };
+        base.difference(&excluded)
+    }
+
+    // 列表在哪些地址族上非空：(IPv4, IPv6)
+    pub fn families(&self) -> (bool, bool) {
+        let effective = self.effective();
+        (effective.has_v4(), effective.has_v6())
+    }
+}
+
+impl FromStr for AddressList {
+    type Err = AstralError;
+
+    fn from_str(s: &str) -> Result<Self> {
+        AddressList::parse(s)
+    }
+}
+
+impl From<IpAddr> for AddressList {
+    fn from(ip: IpAddr) -> Self {
+        Self { entries: vec![AddressEntry { set: IpSet::from_addr(ip), negated: false }] }
+    }
+}
+
+impl From<&IpNetwork> for AddressList {
+    fn from(network: &IpNetwork) -> Self {
+        Self { entries: vec![AddressEntry { set: IpSet::from_network(network), negated: false }] }
+    }
+}
+
+impl fmt::Display for AddressEntry {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.negated {
+            write!(f, "!")?;
+        }
+        write!(f, "{}", self.set)
+    }
+}
+
+// 按书写顺序输出每一项，解析后得到相同的列表
+impl fmt::Display for AddressList {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let items: Vec<String> = self.entries.iter().map(|e| e.to_string()).collect();
+        write!(f, "{}", items.join(", "))
+    }
+}
+
+// 配置文件中以字符串保存，读取时解析，无效的列表直接报错
+impl Serialize for AddressList {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+impl<'de> Deserialize<'de> for AddressList {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> std::result::Result<Self, D::Error> {
+        let text = String::deserialize(deserializer)?;
+        text.parse::<AddressList>().map_err(|e| e.to_serde())
+    }
+}
@@ -0,0 +1,26 @@
+pub mod apply;
+mod astral_wfp;
+pub mod backend;
+pub mod conflicts;
+pub mod diff;
+pub mod drift;
+pub mod error;
+pub mod evaluator;
+pub mod gui;
+pub mod icmp;
+pub mod ipset;
+pub mod logging;
+pub mod metadata;
+pub mod nt;
+pub mod optimizer;
+pub mod plan;
+pub mod ports;
+pub mod provider;
+pub mod reconcile;
+pub mod services;
+pub mod validation;
+pub mod weight;
+#[cfg(test)]
+mod test;
+
+pub use astral_wfp::*;
@@ -0,0 +1,180 @@
+// 日志输出配置
+//
+// 库本身只通过 tracing 产生事件和 span（每批规则、每条规则、每个层各一个），
+// 不安装任何订阅者，因此默认不输出任何内容。应用程序用 LogConfig 选择级别和输出目标：
+// 控制台、按时间滚动的文本文件、按时间滚动的 JSON Lines 文件，可以同时启用多个。
+
+use std::path::PathBuf;
+use std::str::FromStr;
+use tracing::Subscriber;
+use tracing_appender::non_blocking::WorkerGuard;
+use tracing_appender::rolling::{RollingFileAppender, Rotation};
+use tracing_subscriber::filter::EnvFilter;
+use tracing_subscriber::layer::SubscriberExt;
+use tracing_subscriber::{Layer, Registry};
+use crate::error::{AstralError, Result};
+use crate::message;
+
+// 覆盖日志级别的环境变量，语法与 RUST_LOG 相同
+pub const LOG_ENV: &str = "ASTRAL_WFP_LOG";
+
+// 日志文件的滚动周期
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum LogRotation {
+    Hourly,
+    #[default]
+    Daily,
+    Never,
+}
+
+impl LogRotation {
+    fn rotation(&self) -> Rotation {
+        match self {
+            LogRotation::Hourly => Rotation::HOURLY,
+            LogRotation::Daily => Rotation::DAILY,
+            LogRotation::Never => Rotation::NEVER,
+        }
+    }
+}
+
+impl FromStr for LogRotation {
+    type Err = AstralError;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_lowercase().as_str() {
+            "hourly" => Ok(LogRotation::Hourly),
+            "daily" => Ok(LogRotation::Daily),
+            "never" => Ok(LogRotation::Never),
+            _ => Err(AstralError::parse(
+                message!("日志滚动周期", "log rotation"),
+                message!("未知的滚动周期: {} (可选: hourly, daily, never)", "unknown rotation: {} (expected hourly, daily or never)", s),
+            )),
+        }
+    }
+}
+
+// 日志输出目标
+#[derive(Debug, Clone, PartialEq)]
+pub enum LogSink {
+    // 标准错误输出，人类可读格式
+    Console,
+    // 滚动的文本文件：<directory>/<prefix>.<日期>
+    RollingFile { directory: PathBuf, prefix: String, rotation: LogRotation },
+    // 滚动的 JSON Lines 文件，每行一个事件，包含所在 span 的字段
+    JsonLines { directory: PathBuf, prefix: String, rotation: LogRotation },
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct LogConfig {
+    pub level: String, // EnvFilter 指令，例如 "info" 或 "wfp=debug"
+    pub sinks: Vec<LogSink>,
+}
+
+impl Default for LogConfig {
+    fn default() -> Self {
+        Self {
+            level: "info".to_string(),
+            sinks: Vec::new(),
+        }
+    }
+}
+
+// 持有后台写线程；丢弃时把缓冲的日志写入文件
+#[must_use = "丢弃 LogGuard 会停止写入日志文件"]
+pub struct LogGuard {
+    _workers: Vec<WorkerGuard>,
+}
+
+impl LogConfig {
+    pub fn new(level: &str) -> Self {
+        Self {
+            level: level.to_string(),
+            ..Default::default()
+        }
+    }
+
+    pub fn level(mut self, level: &str) -> Self {
+        self.level = level.to_string();
+        self
+    }
+
+    pub fn console(mut self) -> Self {
+        self.sinks.push(LogSink::Console);
+        self
+    }
+
+    pub fn rolling_file(mut self, directory: impl Into<PathBuf>, prefix: &str, rotation: LogRotation) -> Self {
+        self.sinks.push(LogSink::RollingFile {
+            directory: directory.into(),
+            prefix: prefix.to_string(),
+            rotation,
+        });
+        self
+    }
+
+    pub fn json_lines(mut self, directory: impl Into<PathBuf>, prefix: &str, rotation: LogRotation) -> Self {
+        self.sinks.push(LogSink::JsonLines {
+            directory: directory.into(),
+            prefix: prefix.to_string(),
+            rotation,
+        });
+        self
+    }
+
+    // 环境变量 ASTRAL_WFP_LOG 优先于配置中的级别
+    fn filter(&self) -> Result<EnvFilter> {
+        let directive = std::env::var(LOG_ENV).unwrap_or_else(|_| self.level.clone());
+        EnvFilter::try_new(&directive).map_err(|e| AstralError::parse(message!("日志级别", "log level"), message!("{}: {}", "{}: {}", directive, e)))
+    }
+
+    fn appender(directory: &PathBuf, prefix: &str, rotation: LogRotation) -> Result<RollingFileAppender> {
+        std::fs::create_dir_all(directory)
+            .map_err(|e| AstralError::io(&e, message!("无法创建日志目录 {}", "cannot create log directory {}", directory.display())))?;
+        Ok(RollingFileAppender::new(rotation.rotation(), directory, prefix))
+    }
+
+    // 构造订阅者但不安装，可用于 tracing::subscriber::with_default
+    pub fn build(&self) -> Result<(Box<dyn Subscriber + Send + Sync>, LogGuard)> {
+        let mut layers: Vec<Box<dyn Layer<Registry> + Send + Sync>> = Vec::new();
+        let mut workers = Vec::new();
+        for sink in &self.sinks {
+            let layer = match sink {
+                LogSink::Console => tracing_subscriber::fmt::layer()
+                    .with_writer(std::io::stderr)
+                    .with_filter(self.filter()?)
+                    .boxed(),
+                LogSink::RollingFile { directory, prefix, rotation } => {
+                    let (writer, guard) = tracing_appender::non_blocking(Self::appender(directory, prefix, *rotation)?);
+                    workers.push(guard);
+                    tracing_subscriber::fmt::layer()
+                        .with_writer(writer)
+                        .with_ansi(false)
+                        .with_filter(self.filter()?)
+                        .boxed()
+                },
+                LogSink::JsonLines { directory, prefix, rotation } => {
+                    let (writer, guard) = tracing_appender::non_blocking(Self::appender(directory, prefix, *rotation)?);
+                    workers.push(guard);
+                    tracing_subscriber::fmt::layer()
+                        .json()
+                        .with_current_span(true)
+                        .with_span_list(true)
+                        .with_writer(writer)
+                        .with_filter(self.filter()?)
+                        .boxed()
+                },
+            };
+            layers.push(layer);
+        }
+        let subscriber = Registry::default().with(layers);
+        Ok((Box::new(subscriber), LogGuard { _workers: workers }))
+    }
+
+    // 安装为全局订阅者；进程中只能安装一次
+    pub fn init(&self) -> Result<LogGuard> {
+        let (subscriber, guard) = self.build()?;
+        tracing::subscriber::set_global_default(subscriber)
+            .map_err(|e| AstralError::internal(message!("无法安装日志订阅者: {}", "cannot install the log subscriber: {}", e)))?;
+        Ok(guard)
+    }
+}
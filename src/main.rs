@@ -1,11 +1,17 @@
-mod astral_wfp;
-mod nt;
-mod gui;
-
-use windows::core::*;
-use crate::nt::get_nt_path;
-use crate::gui::WfpGui;
-use tokio::io::{self, AsyncBufReadExt};
+use std::path::Path;
+use std::process::ExitCode;
+use std::time::Duration;
+use wfp::error::{AstralError, Language, Message, Result};
+use wfp::message;
+use wfp::logging::{LogConfig, LogRotation};
+use wfp::nt::get_nt_path;
+use wfp::plan::EnforcementMode;
+use wfp::conflicts::find_conflicts;
+use wfp::diff::diff_configs;
+use wfp::drift::{AuditOptions, DriftMonitor, HealPolicy};
+use wfp::optimizer::optimize;
+use wfp::validation::validate_rules;
+use wfp::gui::WfpGui;
 use eframe::NativeOptions;
 
 fn test_nt_path_conversion() {
@@ -32,8 +38,8 @@ fn test_nt_path_conversion() {
     println!("========================\n");
 }
 
-fn test_app_id_remote_ip_filter() -> windows::core::Result<()> {
-    use astral_wfp::*;
+fn test_app_id_remote_ip_filter(mode: EnforcementMode) -> Result<()> {
+    use wfp::*;
     let path = r"C:\Program Files\Google\Chrome\Application\chrome.exe";
     let nt_path = match get_nt_path(path) {
         Some(path) => path,
@@ -45,7 +51,7 @@ fn test_app_id_remote_ip_filter() -> windows::core::Result<()> {
 
     let nt_path: &'static str = Box::leak(nt_path.into_boxed_str());
     // 创建WFP控制器实例
-    let mut wfp_controller = WfpController::new()?;
+    let mut wfp_controller = WfpController::new()?.with_mode(mode);
 
     // 初始化WFP引擎
     wfp_controller.initialize()?;
@@ -56,7 +62,7 @@ fn test_app_id_remote_ip_filter() -> windows::core::Result<()> {
     let rules: Vec<FilterRule> = vec![        // 测试1: 阻止Edge访问特定IP (双向阻止)
         FilterRule::new("阻止Edge访问124.71.134.95")
             .app_path(nt_path)
-            .remote_ip("183.131.147.29")
+            .remote_ip("183.131.147.29")?
             .direction(Direction::Outbound)  // 改回 Outbound 或使用 Both
             .action(FilterAction::Block),
 
@@ -65,7 +71,7 @@ fn test_app_id_remote_ip_filter() -> windows::core::Result<()> {
     match wfp_controller.add_advanced_filters(&rules) {
         Ok(filter_ids) => {
             println!("\n✅ 过滤规则添加成功！");
-            println!("共添加了 {} 个过滤器", filter_ids.len());
+            println!("共添加了 {} 个过滤器（执行模式: {}）", filter_ids.len(), mode);
             println!("现在可以测试Edge是否无法访问124.71.134.95");
             println!("按Ctrl+C或回车键结束测试...");
         },
@@ -77,35 +83,19 @@ fn test_app_id_remote_ip_filter() -> windows::core::Result<()> {
     Ok(())
 }
 
-fn test_common_protocols() -> windows::core::Result<()> {
-    use astral_wfp::*;
+fn test_common_protocols(mode: EnforcementMode) -> Result<()> {
+    use wfp::*;
     
     println!("🌐 常见协议拦截示例");
     println!("====================");
     
     // 创建WFP控制器实例
-    let mut wfp_controller = WfpController::new()?;
+    let mut wfp_controller = WfpController::new()?.with_mode(mode);
     wfp_controller.initialize()?;
 
-    // 常见协议和端口定义
-    let common_protocols = vec![
-        ("HTTP", 80, Protocol::Tcp),
-        ("HTTPS", 443, Protocol::Tcp),
-        ("FTP", 21, Protocol::Tcp),
-        ("SSH", 22, Protocol::Tcp),
-        ("Telnet", 23, Protocol::Tcp),
-        ("SMTP", 25, Protocol::Tcp),
-        ("DNS", 53, Protocol::Udp),
-        ("DHCP", 67, Protocol::Udp),
-        ("DHCP", 68, Protocol::Udp),
-        ("NTP", 123, Protocol::Udp),
-        ("SNMP", 161, Protocol::Udp),
-        ("SNMP", 162, Protocol::Udp),
-    ];
-
-    println!("支持的协议和端口：");
-    for (name, port, protocol) in &common_protocols {
-        println!("  - {} (端口 {}, {})", name, port, protocol);
+    println!("内置服务：");
+    for service in services::SERVICES {
+        println!("  - {} (端口 {}, {}) {}", service.name, service.port, service.protocol_names(), service.description);
     }
     println!();
 
@@ -113,21 +103,20 @@ fn test_common_protocols() -> windows::core::Result<()> {
     let example_rules = vec![
         // 阻止HTTP流量
         FilterRule::new("阻止HTTP")
-            .remote_port(80)
-            .protocol(Protocol::Tcp)
+            .remote_service("http")?
             .direction(Direction::Outbound)
             .action(FilterAction::Block),
             
         // 阻止HTTPS流量
         FilterRule::new("阻止HTTPS")
-            .remote_port(443)
+            .remote_service("https")?
             .protocol(Protocol::Tcp)
             .direction(Direction::Outbound)
             .action(FilterAction::Block),
             
         // 阻止DNS查询
         FilterRule::new("阻止DNS")
-            .remote_port(53)
+            .remote_service("dns")?
             .protocol(Protocol::Udp)
             .direction(Direction::Outbound)
             .action(FilterAction::Block),
@@ -142,7 +131,7 @@ fn test_common_protocols() -> windows::core::Result<()> {
     match wfp_controller.add_advanced_filters(&example_rules) {
         Ok(filter_ids) => {
             println!("✅ 常见协议拦截规则添加成功！");
-            println!("共添加了 {} 个过滤器", filter_ids.len());
+            println!("共添加了 {} 个过滤器（执行模式: {}）", filter_ids.len(), mode);
             println!("现在可以测试以下拦截效果：");
             println!("  - HTTP (端口80) 被阻止");
             println!("  - HTTPS (端口443) 被阻止");
@@ -162,35 +151,35 @@ fn test_common_protocols() -> windows::core::Result<()> {
     Ok(())
 }
 
-fn test_port_ranges() -> windows::core::Result<()> {
-    use astral_wfp::*;
+fn test_port_ranges(mode: EnforcementMode) -> Result<()> {
+    use wfp::*;
     
     println!("🎯 端口范围拦截示例");
     println!("====================");
     
     // 创建WFP控制器实例
-    let mut wfp_controller = WfpController::new()?;
+    let mut wfp_controller = WfpController::new()?.with_mode(mode);
     wfp_controller.initialize()?;
 
     // 测试端口范围拦截规则
     let port_range_rules = vec![
         // 阻止常用Web服务端口范围
         FilterRule::new("阻止Web服务端口")
-            .remote_port_range(80, 89)
+            .remote_port_range(80, 89)?
             .protocol(Protocol::Tcp)
             .direction(Direction::Outbound)
             .action(FilterAction::Block),
             
         // 阻止常用游戏端口范围
         FilterRule::new("阻止游戏端口")
-            .remote_port_range(27015, 27020)
+            .remote_port_range(27015, 27020)?
             .protocol(Protocol::Udp)
             .direction(Direction::Both)
             .action(FilterAction::Block),
             
         // 阻止动态端口范围
         FilterRule::new("阻止动态端口")
-            .remote_port_range(49152, 65535)
+            .remote_port_range(49152, 65535)?
             .direction(Direction::Outbound)
             .action(FilterAction::Block),
     ];
@@ -198,7 +187,7 @@ fn test_port_ranges() -> windows::core::Result<()> {
     match wfp_controller.add_advanced_filters(&port_range_rules) {
         Ok(filter_ids) => {
             println!("✅ 端口范围拦截规则添加成功！");
-            println!("共添加了 {} 个过滤器", filter_ids.len());
+            println!("共添加了 {} 个过滤器（执行模式: {}）", filter_ids.len(), mode);
             println!("现在可以测试以下拦截效果：");
             println!("  - Web服务端口 80-89 被阻止");
             println!("  - 游戏端口 27015-27020 被阻止");
@@ -216,7 +205,110 @@ fn test_port_ranges() -> windows::core::Result<()> {
     Ok(())
 }
 
-fn run_gui() -> Result<()> {
+// 输出规则文件的全部诊断；有错误时失败，只有警告时成功
+fn validate_file(path: &Path) -> Result<()> {
+    use wfp::*;
+
+    let config = RuleConfig::load(path)?;
+    let diagnostics = validate_rules(&config.rules);
+    for diagnostic in &diagnostics {
+        println!("{}", diagnostic);
+    }
+    let errors = diagnostics.iter().filter(|d| d.diagnostic.is_error()).count();
+    println!("共 {} 条规则，{} 个错误，{} 个警告", config.rules.len(), errors, diagnostics.len() - errors);
+    if errors > 0 {
+        return Err(AstralError::validation("rules", message!("{} 中有 {} 个错误", "{} has {} error(s)", path.display(), errors)));
+    }
+    Ok(())
+}
+
+// 输出规则文件中规则之间的冲突、遮蔽和冗余
+fn analyze_file(path: &Path) -> Result<()> {
+    use wfp::*;
+
+    let config = RuleConfig::load(path)?;
+    let conflicts = find_conflicts(&config.rules);
+    for conflict in &conflicts {
+        println!("{}", conflict);
+    }
+    println!("共 {} 条规则，{} 项发现", config.rules.len(), conflicts.len());
+    Ok(())
+}
+
+// 输出优化的逐项变更和节省的过滤器数量，output 不为空时写入优化后的配置
+fn optimize_file(path: &Path, output: Option<&Path>) -> Result<()> {
+    use wfp::*;
+
+    let mut config = RuleConfig::load(path)?;
+    let report = optimize(&config.rules)?;
+    print!("{}", report);
+    if let Some(output) = output {
+        config.rules = report.rules;
+        let json = serde_json::to_string_pretty(&config)?;
+        std::fs::write(output, json).map_err(|e| AstralError::io(&e, message!("无法写入 {}", "cannot write {}", output.display())))?;
+        println!("优化后的配置已写入 {}", output.display());
+    }
+    Ok(())
+}
+
+// 输出两个规则文件之间的语义差异和影响估算
+fn diff_files(old: &Path, new: &Path) -> Result<()> {
+    use wfp::*;
+
+    let diff = diff_configs(&RuleConfig::load(old)?, &RuleConfig::load(new)?)?;
+    print!("{}", diff);
+    Ok(())
+}
+
+// 输出同步计划，不是 dry_run 时在一个事务中执行
+fn reconcile_file(path: &Path, mode: EnforcementMode, dry_run: bool) -> Result<()> {
+    use wfp::*;
+
+    let config = RuleConfig::load(path)?;
+    let mut wfp_controller = WfpController::new()?.with_mode(mode);
+    wfp_controller.initialize()?;
+    let plan = wfp_controller.plan_reconcile(&config.rules)?;
+    print!("{}", plan);
+    if dry_run || plan.is_empty() {
+        return Ok(());
+    }
+    let report = wfp_controller.apply_reconcile(&plan)?;
+    print!("{}", report);
+    match report.first_error() {
+        Some(e) if !report.committed => Err(e.clone()),
+        _ => Ok(()),
+    }
+}
+
+// 应用规则文件后定期审计漂移，按 heal 策略修复，输出发现漂移的审计结果，按回车停止
+fn audit_file(path: &Path, mode: EnforcementMode, options: AuditOptions) -> Result<()> {
+    use std::sync::mpsc::{self, RecvTimeoutError};
+    use std::sync::{Arc, Mutex};
+    use wfp::*;
+
+    let mut wfp_controller = WfpController::new()?.with_mode(mode);
+    wfp_controller.initialize()?;
+    wfp_controller.import_rules(path)?;
+    let (monitor, reports) = DriftMonitor::spawn(Arc::new(Mutex::new(wfp_controller)), options);
+    println!("每 {} 秒审计一次（{}），按回车停止", options.interval.as_secs(), options.heal);
+
+    let (stop_tx, stop_rx) = mpsc::channel();
+    std::thread::spawn(move || {
+        let _ = std::io::stdin().read_line(&mut String::new());
+        let _ = stop_tx.send(());
+    });
+    while stop_rx.try_recv().is_err() {
+        match reports.recv_timeout(Duration::from_millis(200)) {
+            Ok(report) => print!("{}", report),
+            Err(RecvTimeoutError::Timeout) => {},
+            Err(RecvTimeoutError::Disconnected) => break,
+        }
+    }
+    monitor.stop();
+    Ok(())
+}
+
+fn run_gui(mode: EnforcementMode) -> Result<()> {
     let options = NativeOptions {
         ..Default::default()
     };
@@ -224,11 +316,39 @@ fn run_gui() -> Result<()> {
     eframe::run_native(
         "AstralWFP",
         options,
-        Box::new(|_cc| Box::new(WfpGui::default())),
-    ).map_err(|e| Error::new(windows::core::HRESULT(0x80004005u32 as i32), (&e.to_string()).into()))
+        Box::new(move |_cc| Box::new(WfpGui::with_mode(mode))),
+    ).map_err(|e| AstralError::internal(message!("GUI启动失败: {}", "failed to start the GUI: {}", e)))
+}
+
+// 取出并移除形如 --name=value 的参数
+fn take_arg(args: &mut Vec<String>, prefix: &str) -> Option<String> {
+    let index = args.iter().position(|arg| arg.starts_with(prefix))?;
+    Some(args.remove(index)[prefix.len()..].to_string())
+}
+
+// 取出并移除开关参数，返回它是否出现过
+fn take_flag(args: &mut Vec<String>, flag: &str) -> bool {
+    let count = args.len();
+    args.retain(|arg| arg != flag);
+    args.len() != count
+}
+
+// 失败时以 “[错误代码] 消息” 的格式输出，脚本可以根据错误代码判断失败原因
+fn main() -> ExitCode {
+    let language = std::env::args()
+        .find_map(|arg| arg.strip_prefix("--lang=").map(str::to_string))
+        .and_then(|value| value.parse::<Language>().ok())
+        .unwrap_or_default();
+    match run() {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("❌ [{}] {}", e.code(), e.localized(language));
+            ExitCode::FAILURE
+        }
+    }
 }
 
-fn main() -> Result<()> {
+fn run() -> Result<()> {
     // 设置Windows控制台为UTF-8，防止中文乱码
     #[cfg(windows)]
     {
@@ -238,8 +358,23 @@ fn main() -> Result<()> {
             SetConsoleCP(65001);
         }
     }
-    // 检查命令行参数
-    let args: Vec<String> = std::env::args().collect();
+    // 检查命令行参数，--mode=<dynamic|persistent|boot-time> 选择执行模式
+    let mut args: Vec<String> = std::env::args().collect();
+    let mode = match take_arg(&mut args, "--mode=") {
+        Some(value) => value.parse::<EnforcementMode>()?,
+        None => EnforcementMode::Dynamic,
+    };
+    take_arg(&mut args, "--lang=");
+
+    // 日志始终输出到控制台，可以额外写入滚动的文本文件或 JSON Lines 文件
+    let mut log_config = LogConfig::new(&take_arg(&mut args, "--log-level=").unwrap_or_else(|| "info".to_string())).console();
+    if let Some(directory) = take_arg(&mut args, "--log-file=") {
+        log_config = log_config.rolling_file(directory, "astral-wfp.log", LogRotation::Daily);
+    }
+    if let Some(directory) = take_arg(&mut args, "--log-json=") {
+        log_config = log_config.json_lines(directory, "astral-wfp.jsonl", LogRotation::Daily);
+    }
+    let _log_guard = log_config.init()?;
     
     if args.len() > 1 {
         match args[1].as_str() {
@@ -247,10 +382,11 @@ fn main() -> Result<()> {
                 // 命令行模式
                 println!("🌐 AstralWFP 网络流量控制器 - 命令行模式");
                 println!("==========================================");
+                println!("🔒 执行模式: {}", mode);
                 test_nt_path_conversion();
-                test_app_id_remote_ip_filter()?;
-                test_common_protocols()?;
-                test_port_ranges()?;
+                test_app_id_remote_ip_filter(mode)?;
+                test_common_protocols(mode)?;
+                test_port_ranges(mode)?;
 
                 println!("按回车键退出程序...");
                 let mut input = String::new();
@@ -269,7 +405,7 @@ fn main() -> Result<()> {
                 // 测试协议拦截功能
                 println!("🧪 协议拦截测试模式");
                 println!("====================");
-                test_common_protocols()?;
+                test_common_protocols(mode)?;
                 println!("测试完成，按回车键退出...");
                 let mut input = String::new();
                 std::io::stdin().read_line(&mut input).unwrap();
@@ -278,18 +414,85 @@ fn main() -> Result<()> {
                 // 测试端口范围拦截功能
                 println!("🧪 端口范围拦截测试模式");
                 println!("========================");
-                test_port_ranges()?;
+                test_port_ranges(mode)?;
                 println!("测试完成，按回车键退出...");
                 let mut input = String::new();
                 std::io::stdin().read_line(&mut input).unwrap();
             },
+            "--validate" => {
+                // 校验规则配置文件，列出全部错误和警告
+                let Some(path) = args.get(2) else {
+                    return Err(AstralError::parse(Message::plain("--validate"), message!("缺少规则文件路径", "missing the rules file path")));
+                };
+                validate_file(Path::new(path))?;
+            },
+            "--analyze" => {
+                // 分析规则配置文件中的冲突、遮蔽和冗余
+                let Some(path) = args.get(2) else {
+                    return Err(AstralError::parse(Message::plain("--analyze"), message!("缺少规则文件路径", "missing the rules file path")));
+                };
+                analyze_file(Path::new(path))?;
+            },
+            "--optimize" => {
+                // 优化规则配置文件，输出变更前后的差异；指定输出文件时写入优化后的配置
+                let Some(path) = args.get(2) else {
+                    return Err(AstralError::parse(Message::plain("--optimize"), message!("缺少规则文件路径", "missing the rules file path")));
+                };
+                optimize_file(Path::new(path), args.get(3).map(Path::new))?;
+            },
+            "--diff" => {
+                // 比较两个规则配置文件，逐字段列出修改并判断放宽还是收紧
+                let (Some(old), Some(new)) = (args.get(2), args.get(3)) else {
+                    return Err(AstralError::parse(Message::plain("--diff"), message!("需要旧规则文件和新规则文件两个路径", "expected the old and the new rules file paths")));
+                };
+                diff_files(Path::new(old), Path::new(new))?;
+            },
+            "--reconcile" => {
+                // 把本程序已应用的规则同步为规则文件中的规则；--dry-run 只输出变更计划
+                let dry_run = take_flag(&mut args, "--dry-run");
+                let Some(path) = args.get(2) else {
+                    return Err(AstralError::parse(Message::plain("--reconcile"), message!("缺少规则文件路径", "missing the rules file path")));
+                };
+                reconcile_file(Path::new(path), mode, dry_run)?;
+            },
+            "--audit" => {
+                // 应用规则文件并定期审计漂移；--heal 恢复被删除或修改的过滤器，--remove-foreign 同时删除外来过滤器
+                let interval = match take_arg(&mut args, "--interval=") {
+                    Some(value) => match value.parse::<u64>() {
+                        Ok(seconds) if seconds > 0 => Duration::from_secs(seconds),
+                        _ => return Err(AstralError::parse(Message::plain("--interval"), message!("无效的秒数: {}", "invalid number of seconds: {}", value))),
+                    },
+                    None => AuditOptions::default().interval,
+                };
+                let restore = take_flag(&mut args, "--heal");
+                let heal = if take_flag(&mut args, "--remove-foreign") {
+                    HealPolicy::RestoreAndRemoveForeign
+                } else if restore {
+                    HealPolicy::Restore
+                } else {
+                    HealPolicy::Report
+                };
+                let Some(path) = args.get(2) else {
+                    return Err(AstralError::parse(Message::plain("--audit"), message!("缺少规则文件路径", "missing the rules file path")));
+                };
+                audit_file(Path::new(path), mode, AuditOptions { interval, heal })?;
+            },
             _ => {
                 println!("🌐 AstralWFP 网络流量控制器");
                 println!("使用 --cli 参数启动命令行模式");
                 println!("使用 --test-nt 参数测试NT路径转换");
                 println!("使用 --test-protocol 参数测试协议拦截");
                 println!("使用 --test-port-ranges 参数测试端口范围拦截");
-                run_gui()?;
+                println!("使用 --validate <规则文件> 参数校验规则配置文件");
+                println!("使用 --analyze <规则文件> 参数分析规则之间的冲突和遮蔽");
+                println!("使用 --optimize <规则文件> [输出文件] 参数合并和去重规则，减少过滤器数量");
+                println!("使用 --diff <旧规则文件> <新规则文件> 参数比较两个规则文件的语义差异");
+                println!("使用 --reconcile <规则文件> [--dry-run] 参数把已应用的规则同步为规则文件中的规则");
+                println!("使用 --audit <规则文件> [--interval=秒] [--heal] [--remove-foreign] 参数应用规则后定期审计漂移（默认 60 秒，只报告）");
+                println!("使用 --mode=<dynamic|persistent|boot-time> 参数选择执行模式（默认 dynamic）");
+                println!("使用 --lang=<zh|en> 参数选择错误消息的语言（默认 zh）");
+                println!("使用 --log-level=<级别> --log-file=<目录> --log-json=<目录> 参数配置日志（默认 info，仅控制台）");
+                run_gui(mode)?;
             }
         }
     } else {
@@ -299,7 +502,16 @@ fn main() -> Result<()> {
         println!("使用 --test-nt 参数测试NT路径转换");
         println!("使用 --test-protocol 参数测试协议拦截");
         println!("使用 --test-port-ranges 参数测试端口范围拦截");
-        run_gui()?;
+        println!("使用 --validate <规则文件> 参数校验规则配置文件");
+        println!("使用 --analyze <规则文件> 参数分析规则之间的冲突和遮蔽");
+        println!("使用 --optimize <规则文件> [输出文件] 参数合并和去重规则，减少过滤器数量");
+        println!("使用 --diff <旧规则文件> <新规则文件> 参数比较两个规则文件的语义差异");
+        println!("使用 --reconcile <规则文件> [--dry-run] 参数把已应用的规则同步为规则文件中的规则");
+        println!("使用 --audit <规则文件> [--interval=秒] [--heal] [--remove-foreign] 参数应用规则后定期审计漂移（默认 60 秒，只报告）");
+        println!("使用 --mode=<dynamic|persistent|boot-time> 参数选择执行模式（默认 dynamic）");
+        println!("使用 --lang=<zh|en> 参数选择错误消息的语言（默认 zh）");
+        println!("使用 --log-level=<级别> --log-file=<目录> --log-json=<目录> 参数配置日志（默认 info，仅控制台）");
+        run_gui(mode)?;
     }
 
     Ok(())
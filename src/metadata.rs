@@ -0,0 +1,80 @@
+// 过滤器中保存的规则元数据
+//
+// 每个过滤器的 providerData 中保存其来源规则的完整配置，枚举引擎时可以把
+// 属于我们提供者的过滤器无损地还原成 FilterRule。
+
+use serde::{Serialize, Deserialize};
+use tracing::warn;
+use windows::core::GUID;
+use crate::astral_wfp::FilterRule;
+use crate::backend::FilterRecord;
+use crate::error::{AstralError, Message, Result};
+use crate::message;
+use crate::plan::EnforcementMode;
+
+// 元数据格式版本
+pub const METADATA_VERSION: u32 = 1;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RuleMetadata {
+    pub version: u32,
+    pub mode: EnforcementMode,
+    pub rule: FilterRule,
+}
+
+impl RuleMetadata {
+    pub fn new(rule: &FilterRule, mode: EnforcementMode) -> Self {
+        Self {
+            version: METADATA_VERSION,
+            mode,
+            rule: rule.clone(),
+        }
+    }
+
+    pub fn to_bytes(&self) -> Result<Vec<u8>> {
+        Ok(serde_json::to_vec(self)?)
+    }
+
+    pub fn from_bytes(data: &[u8]) -> Result<Self> {
+        let metadata: Self = serde_json::from_slice(data).map_err(|e| AstralError::parse(message!("规则元数据", "rule metadata"), Message::from_serde(&e.to_string())))?;
+        if metadata.version != METADATA_VERSION {
+            return Err(AstralError::parse(message!("规则元数据", "rule metadata"), message!("不支持的版本: {}", "unsupported version: {}", metadata.version)));
+        }
+        Ok(metadata)
+    }
+}
+
+// 引擎中已安装的一条规则及其过滤器
+#[derive(Debug, Clone, PartialEq)]
+pub struct InstalledRule {
+    pub rule: FilterRule,
+    pub mode: EnforcementMode,
+    pub filter_ids: Vec<u64>,
+}
+
+// 把属于指定提供者的过滤器按来源规则重新分组，规则按其最小过滤器ID排序
+pub fn group_records(records: &[FilterRecord], provider_key: &GUID) -> Vec<InstalledRule> {
+    let mut records: Vec<&FilterRecord> = records.iter().filter(|r| r.provider_key == *provider_key).collect();
+    records.sort_by_key(|r| r.filter_id);
+
+    let mut installed: Vec<InstalledRule> = Vec::new();
+    for record in records {
+        let metadata = match RuleMetadata::from_bytes(&record.provider_data) {
+            Ok(metadata) => metadata,
+            Err(e) => {
+                warn!(filter_id = record.filter_id, name = %record.name, "跳过无法还原的过滤器: {}", e);
+                continue;
+            }
+        };
+        let rule = metadata.rule;
+        match installed.iter_mut().find(|r| r.rule.id == rule.id) {
+            Some(existing) => existing.filter_ids.push(record.filter_id),
+            None => installed.push(InstalledRule {
+                rule,
+                mode: metadata.mode,
+                filter_ids: vec![record.filter_id],
+            }),
+        }
+    }
+    installed
+}
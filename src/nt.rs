@@ -1,8 +1,11 @@
+#[cfg(windows)]
 use std::ffi::OsStr;
+#[cfg(windows)]
 use std::os::windows::ffi::OsStrExt;
-use std::ptr::null_mut;
+#[cfg(windows)]
 use windows::Win32::Storage::FileSystem::QueryDosDeviceW;
-use windows::core::{PCWSTR, PWSTR};
+#[cfg(windows)]
+use windows::core::PCWSTR;
 
 #[cfg(not(windows))]
 pub fn get_nt_path(_dos_path: &str) -> Option<String> {
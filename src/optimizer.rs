@@ -0,0 +1,234 @@
+// 规则集优化
+//
+// 在编译前减少过滤器数量，结果与原规则集对任何连接的评估结果相同：
+//   - 去重：除ID、名称和描述外完全相同的规则只保留第一条；
+//   - 合并方向：只有方向不同的入站和出站规则合并成一条双向规则；
+//   - 合并列表：只在一个地址或端口列表上不同的规则合并成一条，列表取并集，相邻的网段和端口范围随之合并。
+// 去重和合并方向不改变匹配范围和权重。合并列表会改变具体程度，从而改变权重，因此只在
+// 不改变与任何相反动作规则的先后关系时合并（见 preserves_verdicts）。每一步都保持等价，逐步应用直到不能再优化。
+// 优化是可选的：optimize 返回优化后的规则和逐项变更，由调用方决定是否使用。
+
+use std::collections::HashMap;
+use std::fmt;
+use uuid::Uuid;
+use crate::astral_wfp::{Direction, FilterAction, FilterRule};
+use crate::conflicts::MatchSpace;
+use crate::error::Result;
+use crate::ipset::{AddressEntry, AddressList, IpSet};
+use crate::plan::PlanCompiler;
+use crate::ports::{PortEntry, PortList};
+use crate::weight::WeightAllocator;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ChangeKind {
+    Duplicate,           // 删除重复的规则
+    DirectionFold,       // 入站和出站规则合并成双向规则
+    Merge(&'static str), // 合并指定字段的列表
+}
+
+impl fmt::Display for ChangeKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ChangeKind::Duplicate => write!(f, "去重"),
+            ChangeKind::DirectionFold => write!(f, "合并方向"),
+            ChangeKind::Merge(field) => write!(f, "合并 {}", field),
+        }
+    }
+}
+
+// 一项变更：before 中的规则被 after 替换；after 沿用 before 中第一条规则的ID和名称
+#[derive(Debug, Clone, PartialEq)]
+pub struct Change {
+    pub kind: ChangeKind,
+    pub before: Vec<FilterRule>,
+    pub after: FilterRule,
+}
+
+// 优化结果
+#[derive(Debug, Clone, PartialEq)]
+pub struct OptimizeReport {
+    pub rules: Vec<FilterRule>, // 优化后的规则，保持原来的顺序
+    pub changes: Vec<Change>,
+    pub filters_before: usize,
+    pub filters_after: usize,
+}
+
+impl OptimizeReport {
+    pub fn filters_saved(&self) -> usize {
+        self.filters_before.saturating_sub(self.filters_after)
+    }
+}
+
+fn describe(rule: &FilterRule) -> String {
+    let action = match rule.action {
+        FilterAction::Allow => "允许",
+        FilterAction::Block => "阻止",
+    };
+    format!("'{}' ({}) {}: {}", rule.name, rule.id, action, MatchSpace::of(rule))
+}
+
+// 逐项输出变更前后的规则，最后是过滤器数量
+impl fmt::Display for OptimizeReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for change in &self.changes {
+            writeln!(f, "[{}] {} 条规则 → 1 条", change.kind, change.before.len())?;
+            for rule in &change.before {
+                writeln!(f, "  - {}", describe(rule))?;
+            }
+            writeln!(f, "  + {}", describe(&change.after))?;
+        }
+        writeln!(
+            f,
+            "规则 {} → {}，过滤器 {} → {}（减少 {} 个）",
+            self.rules.len() + self.changes.iter().map(|c| c.before.len() - 1).sum::<usize>(),
+            self.rules.len(),
+            self.filters_before,
+            self.filters_after,
+            self.filters_saved()
+        )
+    }
+}
+
+// 优化规则集；规则集必须能够编译
+pub fn optimize(rules: &[FilterRule]) -> Result<OptimizeReport> {
+    let compiler = PlanCompiler::new();
+    let filters_before = compiler.compile_all(rules)?.len();
+
+    let mut current = rules.to_vec();
+    let mut changes = Vec::new();
+    while let Some(change) = collapse_duplicates(&mut current)
+        .or_else(|| fold_directions(&mut current))
+        .or_else(|| MERGE_FIELDS.iter().find_map(|&field| merge_lists(&mut current, field)))
+    {
+        changes.push(change);
+    }
+
+    let filters_after = compiler.compile_all(&current)?.len();
+    Ok(OptimizeReport { rules: current, changes, filters_before, filters_after })
+}
+
+// 比较时忽略ID、名称和描述
+fn content_key(rule: &FilterRule) -> FilterRule {
+    FilterRule { id: Uuid::nil(), name: String::new(), description: None, ..rule.clone() }
+}
+
+// 按 key 分组，保持首次出现的顺序；key 为 None 的规则不参与
+fn groups(rules: &[FilterRule], key: impl Fn(&FilterRule) -> Option<FilterRule>) -> Vec<Vec<usize>> {
+    let mut index: HashMap<FilterRule, usize> = HashMap::new();
+    let mut groups: Vec<Vec<usize>> = Vec::new();
+    for (i, rule) in rules.iter().enumerate() {
+        let Some(key) = key(rule) else { continue };
+        match index.get(&key) {
+            Some(&group) => groups[group].push(i),
+            None => {
+                index.insert(key, groups.len());
+                groups.push(vec![i]);
+            },
+        }
+    }
+    groups
+}
+
+// 用 merged 替换 members 中的第一条规则，删除其余规则
+fn replace(rules: &mut Vec<FilterRule>, kind: ChangeKind, members: &[usize], merged: FilterRule) -> Change {
+    let before = members.iter().map(|&i| rules[i].clone()).collect();
+    rules[members[0]] = merged.clone();
+    for &i in members[1..].iter().rev() {
+        rules.remove(i);
+    }
+    Change { kind, before, after: merged }
+}
+
+fn collapse_duplicates(rules: &mut Vec<FilterRule>) -> Option<Change> {
+    let members = groups(rules, |rule| Some(content_key(rule))).into_iter().find(|g| g.len() > 1)?;
+    let kept = rules[members[0]].clone();
+    Some(replace(rules, ChangeKind::Duplicate, &members, kept))
+}
+
+fn fold_directions(rules: &mut Vec<FilterRule>) -> Option<Change> {
+    let pairs = groups(rules, |rule| {
+        (rule.direction != Direction::Both).then(|| FilterRule { direction: Direction::Both, ..content_key(rule) })
+    });
+    for group in pairs {
+        let inbound = group.iter().find(|&&i| rules[i].direction == Direction::Inbound);
+        let outbound = group.iter().find(|&&i| rules[i].direction == Direction::Outbound);
+        if let (Some(&a), Some(&b)) = (inbound, outbound) {
+            let members = [a.min(b), a.max(b)];
+            let merged = rules[members[0]].clone().direction(Direction::Both);
+            return Some(replace(rules, ChangeKind::DirectionFold, &members, merged));
+        }
+    }
+    None
+}
+
+const MERGE_FIELDS: [&str; 4] = ["remote", "local", "remote_ports", "local_ports"];
+
+fn merge_lists(rules: &mut Vec<FilterRule>, field: &'static str) -> Option<Change> {
+    // 字段为空的规则匹配全部地址或端口，不参与合并
+    let candidates = groups(rules, |rule| {
+        let mut key = content_key(rule);
+        let present = match field {
+            "remote" => key.remote.take().is_some(),
+            "local" => key.local.take().is_some(),
+            "remote_ports" => key.remote_ports.take().is_some(),
+            _ => key.local_ports.take().is_some(),
+        };
+        present.then_some(key)
+    });
+    for members in candidates.into_iter().filter(|g| g.len() > 1) {
+        let mut merged = rules[members[0]].clone();
+        match field {
+            "remote" | "local" => {
+                let set: IpSet = members
+                    .iter()
+                    .filter_map(|&i| if field == "remote" { rules[i].remote.as_ref() } else { rules[i].local.as_ref() })
+                    .map(AddressList::effective)
+                    .collect();
+                let list = Some(AddressList { entries: vec![AddressEntry { set, negated: false }] });
+                if field == "remote" { merged.remote = list } else { merged.local = list }
+            },
+            _ => {
+                let ranges: Vec<(u16, u16)> = members
+                    .iter()
+                    .filter_map(|&i| if field == "remote_ports" { rules[i].remote_ports.as_ref() } else { rules[i].local_ports.as_ref() })
+                    .flat_map(PortList::effective)
+                    .collect();
+                let list = Some(port_list(ranges));
+                if field == "remote_ports" { merged.remote_ports = list } else { merged.local_ports = list }
+            },
+        }
+        if merged.validate().is_ok() && preserves_verdicts(rules, &members, &merged) {
+            return Some(replace(rules, ChangeKind::Merge(field), &members, merged));
+        }
+    }
+    None
+}
+
+// 合并端口区间，相邻或重叠的区间合成一项
+fn port_list(mut ranges: Vec<(u16, u16)>) -> PortList {
+    ranges.sort_unstable();
+    let mut entries: Vec<PortEntry> = Vec::new();
+    for (start, end) in ranges {
+        match entries.last_mut() {
+            Some(last) if start as u32 <= last.end as u32 + 1 => last.end = last.end.max(end),
+            _ => entries.push(PortEntry { start, end, negated: false, service: None }),
+        }
+    }
+    PortList { entries }
+}
+
+// 用 merged 替换 members 后每个连接的评估结果不变：同一子层内结果由匹配的最高权重规则决定，
+// 因此只要与成员重叠的每条相反动作规则，相对 merged 的先后和相对该成员的先后一致即可
+// （相反动作的权重最低位不同，不会相等）
+fn preserves_verdicts(rules: &[FilterRule], members: &[usize], merged: &FilterRule) -> bool {
+    let weights = WeightAllocator::new();
+    let merged_weight = weights.weight(merged);
+    let members: Vec<(MatchSpace, u64)> =
+        members.iter().map(|&i| (MatchSpace::of(&rules[i]), weights.weight(&rules[i]))).collect();
+    rules.iter().filter(|rule| rule.action != merged.action).all(|other| {
+        let (space, weight) = (MatchSpace::of(other), weights.weight(other));
+        members
+            .iter()
+            .all(|(member, member_weight)| (weight > *member_weight) == (weight > merged_weight) || member.intersection(&space).is_none())
+    })
+}
@@ -0,0 +1,569 @@
+// 过滤计划编译
+//
+// 把 FilterRule 编译成纯数据的 FilterSpec 列表（层、条件、动作、权重、显示信息），
+// 所有决策都在这里完成，后端只负责把 FilterSpec 转换成各自的引擎结构。
+
+use std::fmt;
+use std::str::FromStr;
+use serde::{Serialize, Deserialize};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use windows::Win32::NetworkManagement::WindowsFilteringPlatform::*;
+use uuid::Uuid;
+use windows::core::GUID;
+use crate::astral_wfp::{layer_name, Direction, FilterAction, FilterRule, IpNetwork, Protocol};
+use crate::error::{AstralError, Result};
+use crate::message;
+use crate::ipset::{AddressList, IpSet};
+use crate::metadata::RuleMetadata;
+use crate::ports::PortList;
+use crate::provider::ProviderConfig;
+use crate::weight::WeightAllocator;
+
+// 过滤条件字段
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ConditionField {
+    AppId,
+    LocalAddress,
+    RemoteAddress,
+    LocalPort,
+    RemotePort,
+    IcmpType, // 与 LocalPort 是同一个 WFP 字段
+    IcmpCode, // 与 RemotePort 是同一个 WFP 字段
+    Protocol,
+}
+
+impl fmt::Display for ConditionField {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            ConditionField::AppId => "ALE_APP_ID",
+            ConditionField::LocalAddress => "IP_LOCAL_ADDRESS",
+            ConditionField::RemoteAddress => "IP_REMOTE_ADDRESS",
+            ConditionField::LocalPort => "IP_LOCAL_PORT",
+            ConditionField::RemotePort => "IP_REMOTE_PORT",
+            ConditionField::IcmpType => "ICMP_TYPE",
+            ConditionField::IcmpCode => "ICMP_CODE",
+            ConditionField::Protocol => "IP_PROTOCOL",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+// 条件值，与 WFP 的 FWP_CONDITION_VALUE0 类型一一对应
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ConditionValue {
+    AppId(String),                // FWP_BYTE_BLOB_TYPE（UTF-16 应用程序路径）
+    V4Addr(Ipv4Addr),             // FWP_UINT32
+    V4Range(Ipv4Addr, Ipv4Addr),  // FWP_RANGE_TYPE (FWP_UINT32)
+    V4Network(Ipv4Addr, u8),      // FWP_V4_ADDR_MASK（网络地址和前缀长度）
+    V6Addr(Ipv6Addr),             // FWP_BYTE_ARRAY16_TYPE
+    V6Range(Ipv6Addr, Ipv6Addr),  // FWP_RANGE_TYPE (FWP_BYTE_ARRAY16_TYPE)
+    V6Network(Ipv6Addr, u8),      // FWP_V6_ADDR_MASK（网络地址和前缀长度）
+    Port(u16),                    // FWP_UINT16
+    PortRange(u16, u16),          // FWP_RANGE_TYPE (FWP_UINT16)
+    Protocol(u8),                 // FWP_UINT8
+}
+
+impl ConditionValue {
+    // 值是否属于 IPv6 地址族；与地址族无关的值返回 None
+    pub fn is_v6(&self) -> Option<bool> {
+        match self {
+            ConditionValue::V4Addr(_) | ConditionValue::V4Range(..) | ConditionValue::V4Network(..) => Some(false),
+            ConditionValue::V6Addr(_) | ConditionValue::V6Range(..) | ConditionValue::V6Network(..) => Some(true),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for ConditionValue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConditionValue::AppId(path) => write!(f, "{}", path),
+            ConditionValue::V4Addr(ip) => write!(f, "{}", ip),
+            ConditionValue::V4Range(low, high) => write!(f, "{}-{}", low, high),
+            ConditionValue::V4Network(ip, prefix_len) => write!(f, "{}/{}", ip, prefix_len),
+            ConditionValue::V6Addr(ip) => write!(f, "{}", ip),
+            ConditionValue::V6Range(low, high) => write!(f, "{}-{}", low, high),
+            ConditionValue::V6Network(ip, prefix_len) => write!(f, "{}/{}", ip, prefix_len),
+            ConditionValue::Port(port) => write!(f, "{}", port),
+            ConditionValue::PortRange(low, high) => write!(f, "{}-{}", low, high),
+            ConditionValue::Protocol(number) => write!(f, "{}", number),
+        }
+    }
+}
+
+// 执行模式，决定过滤器的生存期
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum EnforcementMode {
+    #[default]
+    Dynamic,    // 动态：会话关闭（进程退出）时删除
+    Persistent, // 持久：进程退出和重启后仍然存在
+    BootTime,   // 启动时：系统启动后、BFE服务启动前即生效
+}
+
+impl fmt::Display for EnforcementMode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            EnforcementMode::Dynamic => write!(f, "动态"),
+            EnforcementMode::Persistent => write!(f, "持久"),
+            EnforcementMode::BootTime => write!(f, "启动时"),
+        }
+    }
+}
+
+impl FromStr for EnforcementMode {
+    type Err = AstralError;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_lowercase().as_str() {
+            "dynamic" => Ok(EnforcementMode::Dynamic),
+            "persistent" => Ok(EnforcementMode::Persistent),
+            "boot-time" | "boottime" => Ok(EnforcementMode::BootTime),
+            _ => Err(AstralError::parse(message!("执行模式", "enforcement mode"), message!("未知执行模式: {}", "unknown enforcement mode: {}", s))),
+        }
+    }
+}
+
+// 匹配方式
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MatchType {
+    Equal,    // FWP_MATCH_EQUAL
+    NotEqual, // FWP_MATCH_NOT_EQUAL
+    Range,    // FWP_MATCH_RANGE
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FilterCondition {
+    pub field: ConditionField,
+    pub match_type: MatchType,
+    pub value: ConditionValue,
+}
+
+impl FilterCondition {
+    pub fn equal(field: ConditionField, value: ConditionValue) -> Self {
+        Self { field, match_type: MatchType::Equal, value }
+    }
+
+    pub fn not_equal(field: ConditionField, value: ConditionValue) -> Self {
+        Self { field, match_type: MatchType::NotEqual, value }
+    }
+
+    pub fn range(field: ConditionField, value: ConditionValue) -> Self {
+        Self { field, match_type: MatchType::Range, value }
+    }
+}
+
+impl fmt::Display for FilterCondition {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let op = match self.match_type {
+            MatchType::Equal => "==",
+            MatchType::NotEqual => "!=",
+            MatchType::Range => "in",
+        };
+        write!(f, "{} {} {}", self.field, op, self.value)
+    }
+}
+
+// 一个待添加到引擎的过滤器
+#[derive(Debug, Clone, PartialEq)]
+pub struct FilterSpec {
+    pub rule_id: Uuid,
+    pub rule_name: String,
+    pub filter_key: GUID,   // 由规则ID和层确定性生成
+    pub provider_key: GUID,
+    pub layer_key: GUID,
+    pub sublayer_key: GUID,
+    pub conditions: Vec<FilterCondition>,
+    pub action: FilterAction,
+    pub weight: u64,
+    pub mode: EnforcementMode, // 过滤器自身的生存期；启动时模式下包含持久过滤器和启动时过滤器
+    pub display_name: String,
+    pub description: String,
+    pub provider_data: Vec<u8>, // 序列化的规则元数据（RuleMetadata）
+}
+
+impl fmt::Display for FilterSpec {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} {:?} weight={} name={:?}",
+            layer_name(&self.layer_key),
+            self.action,
+            self.weight,
+            self.display_name
+        )?;
+        if self.mode != EnforcementMode::Dynamic {
+            write!(f, " mode={}", self.mode)?;
+        }
+        writeln!(f)?;
+        for condition in &self.conditions {
+            writeln!(f, "  {}", condition)?;
+        }
+        Ok(())
+    }
+}
+
+// 把过滤计划渲染成稳定的文本形式，用于对比和预览
+pub fn render_plan(specs: &[FilterSpec]) -> String {
+    specs.iter().map(|spec| spec.to_string()).collect()
+}
+
+// 规则编译器，负责层选择、条件构建和权重分配
+#[derive(Debug, Clone)]
+pub struct PlanCompiler {
+    weights: WeightAllocator,
+    provider: ProviderConfig,
+    mode: EnforcementMode,
+}
+
+impl Default for PlanCompiler {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl PlanCompiler {
+    pub fn new() -> Self {
+        Self::with_provider(ProviderConfig::default())
+    }
+
+    // 过滤器挂在指定的提供者和子层下
+    pub fn with_provider(provider: ProviderConfig) -> Self {
+        Self {
+            weights: WeightAllocator::new(),
+            provider,
+            mode: EnforcementMode::Dynamic,
+        }
+    }
+
+    // 设置编译出的过滤器的执行模式
+    pub fn with_mode(mut self, mode: EnforcementMode) -> Self {
+        self.mode = mode;
+        self
+    }
+
+    // 把一条规则编译成过滤计划
+    pub fn compile(&self, rule: &FilterRule) -> Result<Vec<FilterSpec>> {
+        rule.validate()?;
+
+        let weight = self.weights.weight(rule);
+        let provider_data = RuleMetadata::new(rule, self.mode).to_bytes()?;
+        let mut specs = Vec::new();
+        for layer_key in layers_for_rule(rule) {
+            let spec = FilterSpec {
+                rule_id: rule.id,
+                rule_name: rule.name.clone(),
+                filter_key: self.provider.filter_key(&rule.id, &layer_key),
+                provider_key: self.provider.provider_key,
+                layer_key,
+                sublayer_key: self.provider.sublayer_key,
+                conditions: conditions_for_layer(rule, &layer_key)?,
+                action: rule.action.clone(),
+                weight,
+                mode: self.mode,
+                display_name: rule.name.clone(),
+                description: format!("控制 {} 的网络流量", rule.name),
+                provider_data: provider_data.clone(),
+            };
+
+            if self.mode == EnforcementMode::BootTime {
+                // 启动时过滤器在BFE启动后被移除，因此同时添加一个持久过滤器接替它；
+                // 启动时过滤器只能引用内置对象，不能挂在我们的提供者和子层下
+                let boot_time = FilterSpec {
+                    filter_key: self.provider.boot_time_filter_key(&rule.id, &layer_key),
+                    provider_key: GUID::zeroed(),
+                    sublayer_key: FWPM_SUBLAYER_UNIVERSAL,
+                    ..spec.clone()
+                };
+                specs.push(FilterSpec { mode: EnforcementMode::Persistent, ..spec });
+                specs.push(boot_time);
+            } else {
+                specs.push(spec);
+            }
+        }
+        Ok(specs)
+    }
+
+    // 编译一组规则
+    pub fn compile_all(&self, rules: &[FilterRule]) -> Result<Vec<FilterSpec>> {
+        let mut specs = Vec::new();
+        for rule in rules {
+            specs.extend(self.compile(rule)?);
+        }
+        Ok(specs)
+    }
+}
+
+// 规则涉及的地址族 (IPv4, IPv6)：各端点地址列表非空的地址族的交集；
+// 没有地址条件的规则只使用 IPv4 层
+pub fn address_families(rule: &FilterRule) -> Result<(bool, bool)> {
+    let mut families: Option<(bool, bool)> = None;
+    for list in [&rule.local, &rule.remote].into_iter().flatten() {
+        let (v4, v6) = list.families();
+        families = Some(match families {
+            Some((other_v4, other_v6)) => (v4 && other_v4, v6 && other_v6),
+            None => (v4, v6),
+        });
+    }
+    Ok(families.unwrap_or((true, false)))
+}
+
+// 规则使用的地址族：地址涉及的地址族，再由 ICMP（只有 IPv4）和 ICMPv6（只有 IPv6）进一步限制
+pub fn rule_families(rule: &FilterRule) -> Result<(bool, bool)> {
+    let protocol_families = match rule.protocol.map(Protocol::normalized) {
+        Some(Protocol::Icmp) => (true, false),
+        Some(Protocol::IcmpV6) => (false, true),
+        _ => return address_families(rule),
+    };
+    if rule.local.is_none() && rule.remote.is_none() {
+        return Ok(protocol_families);
+    }
+    let (v4, v6) = address_families(rule)?;
+    Ok((v4 && protocol_families.0, v6 && protocol_families.1))
+}
+
+// 根据规则获取对应的WFP层；地址列表同时包含IPv4和IPv6时，两组层都需要过滤器
+pub fn layers_for_rule(rule: &FilterRule) -> Vec<GUID> {
+    let (v4, v6) = rule_families(rule).unwrap_or((true, false));
+    let mut layers = Vec::new();
+    if v4 {
+        layers.extend(layers_for_family(rule, false));
+    }
+    if v6 {
+        layers.extend(layers_for_family(rule, true));
+    }
+    layers
+}
+
+// 规则在一个地址族上使用的层
+fn layers_for_family(rule: &FilterRule, is_ipv6: bool) -> Vec<GUID> {
+    let mut layers = Vec::new();
+
+    // 如果有APP_ID + 远程IP的组合，使用测试验证过的层
+    if rule.app_path.is_some() && rule.remote.is_some() {
+        if !is_ipv6 {
+            // 根据测试结果，只使用成功的IPv4层
+            match rule.direction {
+                Direction::Outbound => {
+                    // 出站连接使用CONNECT层（测试成功）
+                    layers.push(FWPM_LAYER_ALE_AUTH_CONNECT_V4);
+                    layers.push(FWPM_LAYER_ALE_ENDPOINT_CLOSURE_V4); // 额外保护
+                },
+                Direction::Inbound => {
+                    // 入站连接使用RECV_ACCEPT层（测试成功）
+                    layers.push(FWPM_LAYER_ALE_AUTH_RECV_ACCEPT_V4);
+                    layers.push(FWPM_LAYER_ALE_ENDPOINT_CLOSURE_V4); // 额外保护
+                },
+                Direction::Both => {
+                    // 双向连接使用两个主要层（都测试成功）
+                    layers.push(FWPM_LAYER_ALE_AUTH_CONNECT_V4);
+                    layers.push(FWPM_LAYER_ALE_AUTH_RECV_ACCEPT_V4);
+                    layers.push(FWPM_LAYER_ALE_ENDPOINT_CLOSURE_V4); // 额外保护
+                    // 可选：如果需要连接重定向功能
+                    // layers.push(FWPM_LAYER_ALE_CONNECT_REDIRECT_V4);
+                }
+            }
+        } else {
+            // IPv6层（基于IPv4测试结果推断）
+            match rule.direction {
+                Direction::Outbound => {
+                    layers.push(FWPM_LAYER_ALE_AUTH_CONNECT_V6);
+                    layers.push(FWPM_LAYER_ALE_ENDPOINT_CLOSURE_V6);
+                },
+                Direction::Inbound => {
+                    layers.push(FWPM_LAYER_ALE_AUTH_RECV_ACCEPT_V6);
+                    layers.push(FWPM_LAYER_ALE_ENDPOINT_CLOSURE_V6);
+                },
+                Direction::Both => {
+                    layers.push(FWPM_LAYER_ALE_AUTH_CONNECT_V6);
+                    layers.push(FWPM_LAYER_ALE_AUTH_RECV_ACCEPT_V6);
+                    layers.push(FWPM_LAYER_ALE_ENDPOINT_CLOSURE_V6);
+                }
+            }
+        }
+    } else {
+        // 没有APP_ID + 远程IP组合的情况，使用标准层
+        match rule.direction {
+            Direction::Outbound => {
+                if is_ipv6 {
+                    layers.push(FWPM_LAYER_ALE_AUTH_CONNECT_V6);
+                } else {
+                    layers.push(FWPM_LAYER_ALE_AUTH_CONNECT_V4);
+                }
+            },
+            Direction::Inbound => {
+                if is_ipv6 {
+                    layers.push(FWPM_LAYER_ALE_AUTH_RECV_ACCEPT_V6);
+                } else {
+                    layers.push(FWPM_LAYER_ALE_AUTH_RECV_ACCEPT_V4);
+                }
+            },
+            Direction::Both => {
+                if is_ipv6 {
+                    layers.push(FWPM_LAYER_ALE_AUTH_CONNECT_V6);
+                    layers.push(FWPM_LAYER_ALE_AUTH_RECV_ACCEPT_V6);
+                } else {
+                    layers.push(FWPM_LAYER_ALE_AUTH_CONNECT_V4);
+                    layers.push(FWPM_LAYER_ALE_AUTH_RECV_ACCEPT_V4);
+                }
+            }
+        }
+    }
+
+    layers
+}
+
+// 规则在指定层上是否应该带APP_ID条件
+fn should_add_app_id(rule: &FilterRule, layer_key: &GUID) -> bool {
+    if rule.app_path.is_none() {
+        return false;
+    }
+    // 基于测试结果，只在成功验证的层上添加APP_ID条件
+    match *layer_key {
+        // 测试成功的层：支持APP_ID + 远程IP组合
+        FWPM_LAYER_ALE_AUTH_CONNECT_V4 |
+        FWPM_LAYER_ALE_AUTH_CONNECT_V6 |
+        FWPM_LAYER_ALE_AUTH_RECV_ACCEPT_V4 |
+        FWPM_LAYER_ALE_AUTH_RECV_ACCEPT_V6 |
+        FWPM_LAYER_ALE_ENDPOINT_CLOSURE_V4 |
+        FWPM_LAYER_ALE_ENDPOINT_CLOSURE_V6 |
+        FWPM_LAYER_ALE_CONNECT_REDIRECT_V4 |
+        FWPM_LAYER_ALE_CONNECT_REDIRECT_V6 => true,
+
+        // 测试失败的层：不支持APP_ID + 远程IP组合（但单独APP_ID可能可以）
+        FWPM_LAYER_ALE_AUTH_LISTEN_V4 |
+        FWPM_LAYER_ALE_AUTH_LISTEN_V6 => {
+            // 只有在没有远程IP条件时才添加APP_ID
+            rule.remote.is_none()
+        },
+
+        // 其他层默认不添加APP_ID
+        _ => false,
+    }
+}
+
+// 层是否属于 IPv6 地址族
+fn is_v6_layer(layer_key: &GUID) -> bool {
+    matches!(
+        *layer_key,
+        FWPM_LAYER_ALE_AUTH_CONNECT_V6 |
+        FWPM_LAYER_ALE_AUTH_RECV_ACCEPT_V6 |
+        FWPM_LAYER_ALE_AUTH_LISTEN_V6 |
+        FWPM_LAYER_ALE_ENDPOINT_CLOSURE_V6 |
+        FWPM_LAYER_ALE_CONNECT_REDIRECT_V6
+    )
+}
+
+// 地址列表在一个地址族上的条件。WFP 对同一字段的多个条件取“或”，
+// 因此把列表实际表示的地址集合拆成若干个地址、网段或范围条件；
+// 排除后只剩一个网段的补集时，改用一个 NOT_EQUAL 条件。
+// 集合覆盖整个地址族时不需要条件
+fn address_conditions(field: ConditionField, list: &AddressList, is_v6: bool) -> Vec<FilterCondition> {
+    let family = if is_v6 { IpSet::all_v6() } else { IpSet::all_v4() };
+    let set = list.effective().intersection(&family);
+    if set == family {
+        return Vec::new();
+    }
+
+    let ranges = set.ranges();
+    if ranges.len() > 1
+        && let [excluded] = family.difference(&set).to_cidrs().as_slice() {
+        return vec![FilterCondition::not_equal(field, network_value(excluded))];
+    }
+    ranges.into_iter().map(|(start, end)| range_condition(field, start, end)).collect()
+}
+
+// 单个地址或网段的条件值
+fn network_value(network: &IpNetwork) -> ConditionValue {
+    match network.ip {
+        IpAddr::V4(ip) if network.prefix_len == 32 => ConditionValue::V4Addr(ip),
+        IpAddr::V4(ip) => ConditionValue::V4Network(ip, network.prefix_len),
+        IpAddr::V6(ip) if network.prefix_len == 128 => ConditionValue::V6Addr(ip),
+        IpAddr::V6(ip) => ConditionValue::V6Network(ip, network.prefix_len),
+    }
+}
+
+// 一段连续地址的条件：单个地址和 IPv6 网段用 EQUAL，其余用 RANGE
+fn range_condition(field: ConditionField, start: IpAddr, end: IpAddr) -> FilterCondition {
+    match (start, end) {
+        (IpAddr::V4(ip), _) if start == end => FilterCondition::equal(field, ConditionValue::V4Addr(ip)),
+        (IpAddr::V6(ip), _) if start == end => FilterCondition::equal(field, ConditionValue::V6Addr(ip)),
+        (IpAddr::V4(start), IpAddr::V4(end)) => FilterCondition::range(field, ConditionValue::V4Range(start, end)),
+        (IpAddr::V6(start), IpAddr::V6(end)) => {
+            let size = u128::from(end) - u128::from(start);
+            // 对齐且大小为 2 的幂的范围正好是一个网段
+            if (size.wrapping_add(1)).is_power_of_two() && u128::from(start) & size == 0 {
+                FilterCondition::equal(field, ConditionValue::V6Network(start, (size.wrapping_add(1)).leading_zeros() as u8 + 1))
+            } else {
+                FilterCondition::range(field, ConditionValue::V6Range(start, end))
+            }
+        },
+        _ => unreachable!("IpSet 的范围不会跨地址族"),
+    }
+}
+
+// 端口列表条件：按实际表示的端口集合拆成若干个端口或端口范围条件，
+// 排除后只剩一个端口的补集时改用一个 NOT_EQUAL 条件；覆盖全部端口时不需要条件
+fn port_conditions(field: ConditionField, list: &PortList) -> Vec<FilterCondition> {
+    let ranges = list.effective();
+    match ranges.as_slice() {
+        [(0, u16::MAX)] => Vec::new(),
+        [(0, before), (after, u16::MAX)] if *after == *before + 2 => {
+            vec![FilterCondition::not_equal(field, ConditionValue::Port(before + 1))]
+        },
+        _ => ranges
+            .iter()
+            .map(|&(start, end)| {
+                if start == end {
+                    FilterCondition::equal(field, ConditionValue::Port(start))
+                } else {
+                    FilterCondition::range(field, ConditionValue::PortRange(start, end))
+                }
+            })
+            .collect(),
+    }
+}
+
+// 构建规则在指定层上的过滤条件
+fn conditions_for_layer(rule: &FilterRule, layer_key: &GUID) -> Result<Vec<FilterCondition>> {
+    let mut conditions = Vec::new();
+
+    // 添加应用程序路径条件
+    if let Some(app_path) = &rule.app_path
+        && should_add_app_id(rule, layer_key) {
+        conditions.push(FilterCondition::equal(ConditionField::AppId, ConditionValue::AppId(app_path.clone())));
+    }
+
+    // 添加本地/远程地址列表条件
+    let is_v6 = is_v6_layer(layer_key);
+    if let Some(local) = &rule.local {
+        conditions.extend(address_conditions(ConditionField::LocalAddress, local, is_v6));
+    }
+    if let Some(remote) = &rule.remote {
+        conditions.extend(address_conditions(ConditionField::RemoteAddress, remote, is_v6));
+    }
+
+    // 添加端口条件
+    if let Some(ports) = &rule.local_ports {
+        conditions.extend(port_conditions(ConditionField::LocalPort, ports));
+    }
+    if let Some(ports) = &rule.remote_ports {
+        conditions.extend(port_conditions(ConditionField::RemotePort, ports));
+    }
+
+    // 添加协议条件
+    // 添加 ICMP 类型和代码条件，编号按协议换算；校验已保证协议是 ICMP 或 ICMPv6
+    if let Some(number) = rule.icmp_type.and_then(|t| t.number(rule.protocol == Some(Protocol::IcmpV6))) {
+        conditions.push(FilterCondition::equal(ConditionField::IcmpType, ConditionValue::Port(number as u16)));
+    }
+    if let Some(code) = rule.icmp_code {
+        conditions.push(FilterCondition::equal(ConditionField::IcmpCode, ConditionValue::Port(code as u16)));
+    }
+
+    // 添加协议条件，任意协议不限制
+    if let Some(number) = rule.protocol.and_then(|p| p.ip_protocol()) {
+        conditions.push(FilterCondition::equal(ConditionField::Protocol, ConditionValue::Protocol(number)));
+    }
+
+    Ok(conditions)
+}
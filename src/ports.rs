@@ -0,0 +1,194 @@
+// 端口列表
+//
+// 一个端点的端口规格：逗号分隔的单个端口、"起始-结束" 范围、内置服务名，以及以 "!" 开头的排除项，
+// 例如 "80,https,8000-8100,!8080"。列表实际表示的端口集合是包含项的并集减去排除项
+// （只有排除项时以全部端口为基础），编译时拆成若干个同一字段的端口条件。
+
+use std::fmt;
+use std::str::FromStr;
+use serde::{Deserialize, Serialize};
+use crate::error::{AstralError, Result};
+use crate::message;
+use crate::ipset::Ranges;
+use crate::services;
+
+// 端口列表中的一项，单个端口的 start 和 end 相同
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct PortEntry {
+    pub start: u16,
+    pub end: u16,
+    pub negated: bool,                  // 以 "!" 开头的排除项
+    pub service: Option<&'static str>, // 以服务名书写时的服务名
+}
+
+impl fmt::Display for PortEntry {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.negated {
+            write!(f, "!")?;
+        }
+        if let Some(service) = self.service {
+            write!(f, "{}", service)
+        } else if self.start == self.end {
+            write!(f, "{}", self.start)
+        } else {
+            write!(f, "{}-{}", self.start, self.end)
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct PortList {
+    pub entries: Vec<PortEntry>,
+}
+
+impl PortList {
+    // 解析端口规格；空列表、空项、无法解析的端口和倒置的范围都是错误
+    pub fn parse(text: &str) -> Result<Self> {
+        if text.trim().is_empty() {
+            return Err(AstralError::parse(message!("端口", "ports"), message!("端口列表为空", "the port list is empty")));
+        }
+        let entries = text.split(',').map(|item| Self::parse_item(item.trim())).collect::<Result<Vec<_>>>()?;
+        Ok(Self { entries })
+    }
+
+    // 单个端口
+    pub fn port(port: u16) -> PortEntry {
+        PortEntry { start: port, end: port, negated: false, service: None }
+    }
+
+    // "起始-结束" 范围，倒置的范围是错误
+    pub fn range(start: u16, end: u16) -> Result<PortEntry> {
+        if start > end {
+            return Err(AstralError::parse(
+                message!("端口", "ports"),
+                message!("倒置的端口范围: {}-{} (起始端口大于结束端口)", "inverted port range: {}-{} (start is greater than end)", start, end),
+            ));
+        }
+        Ok(PortEntry { start, end, negated: false, service: None })
+    }
+
+    fn parse_item(item: &str) -> Result<PortEntry> {
+        let (negated, body) = match item.strip_prefix('!') {
+            Some(body) => (true, body.trim()),
+            None => (false, item),
+        };
+        if body.is_empty() {
+            return Err(AstralError::parse(message!("端口", "ports"), message!("空的端口项: {:?}", "empty port entry: {:?}", item)));
+        }
+        let port = |text: &str| {
+            text.trim()
+                .parse::<u16>()
+                .map_err(|_| AstralError::parse(message!("端口", "ports"), message!("无效的端口: {} (应为 0-65535)", "invalid port: {} (expected 0-65535)", text.trim())))
+        };
+        // 服务名可能含有 "-"（如 http-alt），因此先于范围解析
+        if let Some(service) = services::lookup(body) {
+            return Ok(PortEntry { start: service.port, end: service.port, negated, service: Some(service.name) });
+        }
+        let (start, end) = match body.split_once('-') {
+            Some((start, end)) => (port(start)?, port(end)?),
+            None if !body.chars().all(|c| c.is_ascii_digit()) => {
+                return Err(AstralError::parse(message!("端口", "ports"), message!("未知的服务名: {}", "unknown service name: {}", body)));
+            },
+            None => {
+                let single = port(body)?;
+                (single, single)
+            },
+        };
+        if start > end {
+            return Err(AstralError::parse(
+                message!("端口", "ports"),
+                message!("倒置的端口范围: {} (起始端口大于结束端口)", "inverted port range: {} (start is greater than end)", body),
+            ));
+        }
+        Ok(PortEntry { start, end, negated, service: None })
+    }
+
+    fn ranges(&self, negated: bool) -> Ranges {
+        Ranges::normalized(
+            self.entries
+                .iter()
+                .filter(|e| e.negated == negated)
+                .map(|e| (e.start as u128, e.end as u128))
+                .collect(),
+        )
+    }
+
+    // 列表实际表示的端口，按从小到大排列的互不相邻的闭区间
+    pub fn effective(&self) -> Vec<(u16, u16)> {
+        let base = if self.entries.iter().any(|e| !e.negated) {
+            self.ranges(false)
+        } else {
+            Ranges::normalized(vec![(0, u16::MAX as u128)])
+        };
+        base.difference(&self.ranges(true))
+            .iter()
+            .map(|(start, end)| (start as u16, end as u16))
+            .collect()
+    }
+
+    // 列表实际表示的端口数量
+    pub fn size(&self) -> u32 {
+        self.effective().iter().map(|&(start, end)| (end - start) as u32 + 1).sum()
+    }
+
+    pub fn contains(&self, port: u16) -> bool {
+        self.effective().iter().any(|&(start, end)| start <= port && port <= end)
+    }
+}
+
+impl FromStr for PortList {
+    type Err = AstralError;
+
+    fn from_str(s: &str) -> Result<Self> {
+        PortList::parse(s)
+    }
+}
+
+impl fmt::Display for PortList {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let items: Vec<String> = self.entries.iter().map(|e| e.to_string()).collect();
+        write!(f, "{}", items.join(","))
+    }
+}
+
+// 配置文件中以字符串保存；读取时还接受旧版配置的单个端口（数字）和端口范围（[起始, 结束]）
+impl Serialize for PortList {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+impl<'de> Deserialize<'de> for PortList {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> std::result::Result<Self, D::Error> {
+        struct PortListVisitor;
+
+        impl<'de> serde::de::Visitor<'de> for PortListVisitor {
+            type Value = PortList;
+
+            fn expecting(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+                write!(f, "端口列表字符串、端口号或 [起始, 结束] 端口范围")
+            }
+
+            fn visit_u64<E: serde::de::Error>(self, value: u64) -> std::result::Result<PortList, E> {
+                u16::try_from(value)
+                    .map(|port| PortList { entries: vec![PortList::port(port)] })
+                    .map_err(|_| message!("端口超出范围: {}", "port out of range: {}", value).to_serde())
+            }
+
+            fn visit_str<E: serde::de::Error>(self, value: &str) -> std::result::Result<PortList, E> {
+                PortList::parse(value).map_err(|e| e.to_serde())
+            }
+
+            fn visit_seq<A: serde::de::SeqAccess<'de>>(self, mut seq: A) -> std::result::Result<PortList, A::Error> {
+                let mut bound = || -> std::result::Result<u16, A::Error> {
+                    seq.next_element()?.ok_or_else(|| message!("端口范围需要起始和结束端口", "a port range needs a start and an end port").to_serde())
+                };
+                let (start, end) = (bound()?, bound()?);
+                let entry = PortList::range(start, end).map_err(|e| e.to_serde())?;
+                Ok(PortList { entries: vec![entry] })
+            }
+        }
+
+        deserializer.deserialize_any(PortListVisitor)
+    }
+}
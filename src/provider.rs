@@ -0,0 +1,59 @@
+// AstralWFP 的提供者（provider）和子层（sublayer）
+//
+// 所有过滤器都挂在我们自己的提供者和子层下，不再与其他产品在 FWPM_SUBLAYER_UNIVERSAL 中竞争；
+// 过滤器的 filterKey 由稳定的规则ID和层确定性地生成，重复应用同一规则时可以找到并替换旧过滤器。
+
+use uuid::Uuid;
+use windows::core::GUID;
+
+// AstralWFP 提供者
+pub const ASTRAL_PROVIDER_KEY: GUID = GUID::from_u128(0x4a5f7c21_9d3e_4b8a_a6f1_2c9e0d47b315);
+
+// AstralWFP 子层
+pub const ASTRAL_SUBLAYER_KEY: GUID = GUID::from_u128(0x8e2b1f64_53c7_4d09_b8a2_7f3de156c9a4);
+
+// 子层默认权重（0-0xFFFF，越大越先评估）
+pub const DEFAULT_SUBLAYER_WEIGHT: u16 = 0x8000;
+
+// 提供者和子层配置
+#[derive(Debug, Clone, PartialEq)]
+pub struct ProviderConfig {
+    pub provider_key: GUID,
+    pub sublayer_key: GUID,
+    pub sublayer_weight: u16,
+    pub name: String,
+    pub description: String,
+}
+
+impl Default for ProviderConfig {
+    fn default() -> Self {
+        Self {
+            provider_key: ASTRAL_PROVIDER_KEY,
+            sublayer_key: ASTRAL_SUBLAYER_KEY,
+            sublayer_weight: DEFAULT_SUBLAYER_WEIGHT,
+            name: "AstralWFP".to_string(),
+            description: "AstralWFP网络流量管理".to_string(),
+        }
+    }
+}
+
+impl ProviderConfig {
+    pub fn sublayer_weight(mut self, weight: u16) -> Self {
+        self.sublayer_weight = weight;
+        self
+    }
+
+    // 由规则ID和层生成过滤器的 filterKey，同一提供者下结果始终相同
+    pub fn filter_key(&self, rule_id: &Uuid, layer_key: &GUID) -> GUID {
+        let namespace = Uuid::from_u128(self.provider_key.to_u128());
+        let mut name = rule_id.as_bytes().to_vec();
+        name.extend_from_slice(&layer_key.to_u128().to_be_bytes());
+        GUID::from_u128(Uuid::new_v5(&namespace, &name).as_u128())
+    }
+
+    // 启动时模式下与持久过滤器成对添加的启动时过滤器的 filterKey。启动时过滤器不挂在我们的提供者下，
+    // 枚举时无法按提供者找到，删除和替换规则时按这个固定的 filterKey 查找
+    pub fn boot_time_filter_key(&self, rule_id: &Uuid, layer_key: &GUID) -> GUID {
+        self.filter_key(&Uuid::new_v5(rule_id, b"boot-time"), layer_key)
+    }
+}
@@ -0,0 +1,177 @@
+// 期望状态同步
+//
+// 把引擎中我们的提供者已安装的规则与期望的规则集（通常来自规则文件）比较，得到最少的变更计划：
+//   - 按规则ID配对；ID 找不到时按内容哈希配对，因此没有写ID的规则文件重复同步也不会变化；
+//   - 配对的规则内容、执行模式或过滤器数量不一致时替换，一致时保持不变；
+//   - 没有配对的期望规则添加，没有配对的已安装规则删除；
+//   - 删除和替换的规则在启动时模式下留下的启动时过滤器按固定的 filterKey 一起删除。
+// 计划由 WfpController::apply_reconcile 在一个事务中执行，任何一步失败引擎状态都不变。
+
+use std::collections::HashMap;
+use std::fmt;
+use uuid::Uuid;
+use crate::astral_wfp::FilterRule;
+use crate::error::Result;
+use crate::metadata::InstalledRule;
+use crate::plan::EnforcementMode;
+
+// 规则内容（不含ID）的 FNV-1a 哈希，按配置文件中的 JSON 形式计算，与字段在内存中的表示无关
+pub fn content_hash(rule: &FilterRule) -> Result<u64> {
+    let content = FilterRule { id: Uuid::nil(), ..rule.clone() };
+    let bytes = serde_json::to_vec(&content)?;
+    Ok(bytes.iter().fold(0xcbf2_9ce4_8422_2325, |hash, &byte| (hash ^ byte as u64).wrapping_mul(0x0100_0000_01b3)))
+}
+
+// 替换已安装规则的原因
+#[derive(Debug, Clone, PartialEq)]
+pub enum ReplaceReason {
+    Content,                                                       // 规则内容改变
+    Mode { installed: EnforcementMode, desired: EnforcementMode }, // 执行模式改变
+    Filters { installed: usize, expected: usize },                 // 过滤器缺失或多余
+}
+
+impl fmt::Display for ReplaceReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ReplaceReason::Content => write!(f, "内容已改变"),
+            ReplaceReason::Mode { installed, desired } => write!(f, "执行模式 {} → {}", installed, desired),
+            ReplaceReason::Filters { installed, expected } => write!(f, "过滤器数量 {}，应为 {}", installed, expected),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ReconcileStep {
+    Add(FilterRule),
+    Remove(InstalledRule),
+    Replace { installed: Box<InstalledRule>, desired: FilterRule, reason: ReplaceReason },
+}
+
+impl fmt::Display for ReconcileStep {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ReconcileStep::Add(rule) => write!(f, "+ 添加 '{}' ({})", rule.name, rule.id),
+            ReconcileStep::Remove(installed) => write!(
+                f,
+                "- 删除 '{}' ({})，{} 个过滤器",
+                installed.rule.name,
+                installed.rule.id,
+                installed.filter_ids.len()
+            ),
+            ReconcileStep::Replace { installed, desired, reason } => {
+                write!(f, "~ 替换 '{}' ({}): {}", desired.name, desired.id, reason)?;
+                if installed.rule.name != desired.name {
+                    write!(f, "，原名称 '{}'", installed.rule.name)?;
+                }
+                Ok(())
+            },
+        }
+    }
+}
+
+// 变更计划，步骤按期望规则的顺序排列，删除在最后
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct ReconcilePlan {
+    pub steps: Vec<ReconcileStep>,
+    pub unchanged: Vec<Uuid>, // 保持不变的已安装规则
+    pub boot_time_filters: Vec<u64>, // 删除和被替换的规则的启动时过滤器，它们不挂在我们的提供者下
+}
+
+impl ReconcilePlan {
+    // expected_filters 返回期望规则编译后挂在我们提供者下的过滤器数量
+    pub fn new(
+        desired: &[FilterRule],
+        installed: &[InstalledRule],
+        mode: EnforcementMode,
+        expected_filters: impl Fn(&FilterRule) -> usize,
+    ) -> Result<Self> {
+        let mut remaining: Vec<Option<&InstalledRule>> = installed.iter().map(Some).collect();
+        let by_id: HashMap<Uuid, usize> = installed.iter().enumerate().map(|(i, r)| (r.rule.id, i)).collect();
+        let hashes = installed.iter().map(|r| content_hash(&r.rule)).collect::<Result<Vec<u64>>>()?;
+        let desired_hashes = desired.iter().map(content_hash).collect::<Result<Vec<u64>>>()?;
+
+        let mut plan = ReconcilePlan::default();
+        // 先按ID配对全部规则，再为剩下的规则按内容哈希配对，避免内容相同的规则抢占别人的ID
+        let mut pairs: Vec<Option<usize>> = desired
+            .iter()
+            .map(|rule| by_id.get(&rule.id).copied().filter(|&i| remaining[i].take().is_some()))
+            .collect();
+        for (&hash, pair) in desired_hashes.iter().zip(pairs.iter_mut()).filter(|(_, pair)| pair.is_none()) {
+            *pair = (0..installed.len()).find(|&i| hashes[i] == hash && remaining[i].take().is_some());
+        }
+
+        for ((rule, hash), pair) in desired.iter().zip(desired_hashes).zip(pairs) {
+            let Some(i) = pair else {
+                plan.steps.push(ReconcileStep::Add(rule.clone()));
+                continue;
+            };
+            let current = &installed[i];
+            let expected = expected_filters(rule);
+            let reason = if current.rule.id == rule.id && hashes[i] != hash {
+                Some(ReplaceReason::Content)
+            } else if current.mode != mode {
+                Some(ReplaceReason::Mode { installed: current.mode, desired: mode })
+            } else if current.filter_ids.len() != expected {
+                Some(ReplaceReason::Filters { installed: current.filter_ids.len(), expected })
+            } else {
+                None
+            };
+            match reason {
+                // 按内容配对的规则沿用已安装的ID，替换时也使用它，过滤器标识不变
+                Some(reason) => plan.steps.push(ReconcileStep::Replace {
+                    installed: Box::new(current.clone()),
+                    desired: FilterRule { id: current.rule.id, ..rule.clone() },
+                    reason,
+                }),
+                None => plan.unchanged.push(current.rule.id),
+            }
+        }
+        plan.steps.extend(remaining.into_iter().flatten().map(|r| ReconcileStep::Remove(r.clone())));
+        Ok(plan)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.steps.is_empty()
+    }
+
+    // 需要添加的规则：新增的和替换后的
+    pub fn rules_to_apply(&self) -> Vec<FilterRule> {
+        self.steps
+            .iter()
+            .filter_map(|step| match step {
+                ReconcileStep::Add(rule) | ReconcileStep::Replace { desired: rule, .. } => Some(rule.clone()),
+                ReconcileStep::Remove(_) => None,
+            })
+            .collect()
+    }
+
+    // 需要删除的过滤器：删除的和被替换的规则的全部过滤器，包括启动时过滤器
+    pub fn filters_to_remove(&self) -> Vec<u64> {
+        self.steps
+            .iter()
+            .flat_map(|step| match step {
+                ReconcileStep::Remove(installed) => installed.filter_ids.clone(),
+                ReconcileStep::Replace { installed, .. } => installed.filter_ids.clone(),
+                ReconcileStep::Add(_) => Vec::new(),
+            })
+            .chain(self.boot_time_filters.iter().copied())
+            .collect()
+    }
+}
+
+impl fmt::Display for ReconcilePlan {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for step in &self.steps {
+            writeln!(f, "{}", step)?;
+        }
+        let count = |pick: fn(&ReconcileStep) -> bool| self.steps.iter().filter(|s| pick(s)).count();
+        writeln!(
+            f,
+            "添加 {}，替换 {}，删除 {}，不变 {}",
+            count(|s| matches!(s, ReconcileStep::Add(_))),
+            count(|s| matches!(s, ReconcileStep::Replace { .. })),
+            count(|s| matches!(s, ReconcileStep::Remove(_))),
+            self.unchanged.len()
+        )
+    }
+}
@@ -0,0 +1,136 @@
+// 内置服务注册表
+//
+// 服务名与协议/端口的对应关系，取自 IANA 服务名称和端口号注册表（与 /etc/services 相同的名称），
+// 另加常用别名。端口列表解析、配置导入、GUI 和日志都从这里查询，不再各自维护一份协议端口表。
+
+use crate::astral_wfp::Protocol;
+use crate::plan::{ConditionField, ConditionValue, FilterCondition};
+use crate::ports::PortList;
+
+// 一个服务；同时使用 TCP 和 UDP 的服务列出两种协议
+#[derive(Debug, PartialEq)]
+pub struct Service {
+    pub name: &'static str,               // IANA 服务名
+    pub aliases: &'static [&'static str], // 常用别名
+    pub port: u16,
+    pub protocols: &'static [Protocol],
+    pub description: &'static str,
+}
+
+impl Service {
+    // 服务只使用一种传输协议时返回该协议
+    pub fn protocol(&self) -> Option<&'static Protocol> {
+        match self.protocols {
+            [protocol] => Some(protocol),
+            _ => None,
+        }
+    }
+
+    pub fn protocol_names(&self) -> String {
+        self.protocols.iter().map(|p| p.to_string()).collect::<Vec<_>>().join("/")
+    }
+}
+
+const TCP: &[Protocol] = &[Protocol::Tcp];
+const UDP: &[Protocol] = &[Protocol::Udp];
+const TCP_UDP: &[Protocol] = &[Protocol::Tcp, Protocol::Udp];
+
+// 按端口排列的内置服务
+pub static SERVICES: &[Service] = &[
+    Service { name: "ftp-data", aliases: &[], port: 20, protocols: TCP, description: "文件传输（数据）" },
+    Service { name: "ftp", aliases: &[], port: 21, protocols: TCP, description: "文件传输" },
+    Service { name: "ssh", aliases: &[], port: 22, protocols: TCP, description: "安全Shell" },
+    Service { name: "telnet", aliases: &[], port: 23, protocols: TCP, description: "远程登录" },
+    Service { name: "smtp", aliases: &[], port: 25, protocols: TCP, description: "邮件发送" },
+    Service { name: "domain", aliases: &["dns"], port: 53, protocols: TCP_UDP, description: "域名解析" },
+    Service { name: "bootps", aliases: &["dhcp", "dhcp-server"], port: 67, protocols: UDP, description: "动态主机配置（服务器）" },
+    Service { name: "bootpc", aliases: &["dhcp-client"], port: 68, protocols: UDP, description: "动态主机配置（客户端）" },
+    Service { name: "tftp", aliases: &[], port: 69, protocols: UDP, description: "简单文件传输" },
+    Service { name: "http", aliases: &["www"], port: 80, protocols: TCP, description: "Web服务" },
+    Service { name: "kerberos", aliases: &[], port: 88, protocols: TCP_UDP, description: "Kerberos 认证" },
+    Service { name: "pop3", aliases: &[], port: 110, protocols: TCP, description: "邮件接收" },
+    Service { name: "ntp", aliases: &[], port: 123, protocols: UDP, description: "网络时间同步" },
+    Service { name: "netbios-ns", aliases: &[], port: 137, protocols: UDP, description: "NetBIOS 名称服务" },
+    Service { name: "netbios-dgm", aliases: &[], port: 138, protocols: UDP, description: "NetBIOS 数据报" },
+    Service { name: "netbios-ssn", aliases: &[], port: 139, protocols: TCP, description: "NetBIOS 会话" },
+    Service { name: "imap", aliases: &[], port: 143, protocols: TCP, description: "邮件访问" },
+    Service { name: "snmp", aliases: &[], port: 161, protocols: UDP, description: "网络管理" },
+    Service { name: "snmptrap", aliases: &[], port: 162, protocols: UDP, description: "网络管理（陷阱）" },
+    Service { name: "ldap", aliases: &[], port: 389, protocols: TCP_UDP, description: "目录服务" },
+    Service { name: "https", aliases: &[], port: 443, protocols: TCP_UDP, description: "安全Web服务" },
+    Service { name: "microsoft-ds", aliases: &["smb"], port: 445, protocols: TCP, description: "文件共享" },
+    Service { name: "submission", aliases: &[], port: 587, protocols: TCP, description: "邮件提交" },
+    Service { name: "ldaps", aliases: &[], port: 636, protocols: TCP, description: "安全目录服务" },
+    Service { name: "imaps", aliases: &[], port: 993, protocols: TCP, description: "安全邮件访问" },
+    Service { name: "pop3s", aliases: &[], port: 995, protocols: TCP, description: "安全邮件接收" },
+    Service { name: "openvpn", aliases: &[], port: 1194, protocols: TCP_UDP, description: "OpenVPN" },
+    Service { name: "ms-sql-s", aliases: &["mssql"], port: 1433, protocols: TCP, description: "SQL Server" },
+    Service { name: "mysql", aliases: &[], port: 3306, protocols: TCP, description: "MySQL" },
+    Service { name: "ms-wbt-server", aliases: &["rdp"], port: 3389, protocols: TCP_UDP, description: "远程桌面" },
+    Service { name: "postgresql", aliases: &[], port: 5432, protocols: TCP, description: "PostgreSQL" },
+    Service { name: "http-alt", aliases: &[], port: 8080, protocols: TCP, description: "备用Web服务" },
+];
+
+// 有名称的协议，按 IANA 协议号排列；其他协议号用 Protocol::Other 表示
+pub static PROTOCOLS: &[Protocol] = &[
+    Protocol::Icmp,
+    Protocol::Igmp,
+    Protocol::Tcp,
+    Protocol::Udp,
+    Protocol::Gre,
+    Protocol::Esp,
+    Protocol::Ah,
+    Protocol::IcmpV6,
+    Protocol::L2tp,
+    Protocol::Sctp,
+    Protocol::Any,
+];
+
+// 按服务名或别名查找，不区分大小写
+pub fn lookup(name: &str) -> Option<&'static Service> {
+    SERVICES
+        .iter()
+        .find(|s| s.name.eq_ignore_ascii_case(name) || s.aliases.iter().any(|a| a.eq_ignore_ascii_case(name)))
+}
+
+// 按端口查找；指定协议时只返回使用该协议的服务
+pub fn by_port(port: u16, protocol: Option<&Protocol>) -> Option<&'static Service> {
+    SERVICES
+        .iter()
+        .find(|s| s.port == port && protocol.is_none_or(|p| s.protocols.contains(p)))
+}
+
+// 端口的显示形式，已知服务附带服务名，例如 "443 (https)"
+pub fn port_label(port: u16, protocol: Option<&Protocol>) -> String {
+    match by_port(port, protocol) {
+        Some(service) => format!("{} ({})", port, service.name),
+        None => port.to_string(),
+    }
+}
+
+// 端口列表的显示形式，单个端口附带服务名，例如 "80 (http), 8000-8100, !8080 (http-alt)"
+pub fn describe_ports(list: &PortList, protocol: Option<&Protocol>) -> String {
+    list.entries
+        .iter()
+        .map(|entry| {
+            let negation = if entry.negated { "!" } else { "" };
+            if entry.start == entry.end {
+                format!("{}{}", negation, port_label(entry.start, protocol))
+            } else {
+                format!("{}{}-{}", negation, entry.start, entry.end)
+            }
+        })
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+// 条件的显示形式，端口条件附带服务名，用于日志
+pub fn describe_condition(condition: &FilterCondition) -> String {
+    match (condition.field, &condition.value) {
+        (ConditionField::LocalPort | ConditionField::RemotePort, ConditionValue::Port(port)) => match by_port(*port, None) {
+            Some(service) => format!("{} ({})", condition, service.name),
+            None => condition.to_string(),
+        },
+        _ => condition.to_string(),
+    }
+}
@@ -6,11 +6,44 @@ use crate::{
     Direction,
     FilterAction,
     Protocol,
-    IpNetwork
+    IpNetwork,
+    TimeControl,
+    legacy_rule_id
 };
 use crate::nt::get_nt_path;
-use std::net::IpAddr;
-use windows::core::Result;
+use crate::optimizer::{optimize, ChangeKind};
+use crate::apply::LayerStatus;
+use crate::conflicts::{find_conflicts, ConflictKind};
+use crate::diff::{diff_rules, Effect, PortSide, RuleChange};
+use crate::drift::{AuditOptions, DriftKind, DriftMonitor, HealPolicy};
+use crate::backend::{BackendCall, FilterRecord, FilterScope, FirewallBackend, SimulatedBackend};
+use crate::error::{AstralError, ErrorCode, Language, Message, Result};
+use crate::evaluator::{Connection, Evaluator};
+use crate::icmp::IcmpType;
+use crate::ipset::IpSet;
+use crate::ports::PortList;
+use crate::services;
+use crate::validation::{validate_rule, validate_rules, Diagnostic, RuleDraft, Severity};
+use crate::logging::{LogConfig, LogRotation};
+use crate::metadata::{group_records, RuleMetadata};
+use crate::plan::{layers_for_rule, render_plan, rule_families, EnforcementMode, FilterSpec, MatchType, PlanCompiler};
+use crate::reconcile::{content_hash, ReconcileStep, ReplaceReason};
+use crate::provider::{ProviderConfig, ASTRAL_PROVIDER_KEY, ASTRAL_SUBLAYER_KEY};
+use crate::weight::{WeightAllocator, PRIORITY_SHIFT};
+use std::net::{IpAddr, SocketAddr};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use uuid::Uuid;
+use windows::core::GUID;
+use windows::Win32::Foundation::{
+    FWP_E_CONDITION_NOT_FOUND, FWP_E_FILTER_NOT_FOUND, FWP_E_LAYER_NOT_FOUND,
+    FWP_E_ALREADY_EXISTS, FWP_E_DYNAMIC_SESSION_IN_PROGRESS, FWP_E_LIFETIME_MISMATCH,
+    FWP_E_NO_TXN_IN_PROGRESS, FWP_E_PROVIDER_NOT_FOUND, FWP_E_TXN_IN_PROGRESS, FWP_E_TYPE_MISMATCH,
+};
+use windows::Win32::NetworkManagement::WindowsFilteringPlatform::{
+    FWPM_LAYER_ALE_AUTH_CONNECT_V4, FWPM_LAYER_ALE_AUTH_CONNECT_V6,
+    FWPM_LAYER_ALE_AUTH_LISTEN_V4, FWPM_LAYER_ALE_AUTH_RECV_ACCEPT_V4, FWPM_SUBLAYER_UNIVERSAL,
+};
 
 /// 测试 WFP 控制器的创建和初始化
 #[test]
@@ -55,8 +88,8 @@ fn test_filter_rule_builder() {
     
     assert_eq!(rule.name, "Test_Rule");
     assert_eq!(rule.app_path, Some("C:\\test\\app.exe".to_string()));
-    assert_eq!(rule.local_port, Some(80));
-    assert_eq!(rule.remote_port, Some(443));
+    assert_eq!(rule.local_ports.as_ref().map(|p| p.to_string()).as_deref(), Some("80"));
+    assert_eq!(rule.remote_ports.as_ref().map(|p| p.to_string()).as_deref(), Some("443"));
     assert!(matches!(rule.protocol, Some(Protocol::Tcp)));
     assert!(matches!(rule.direction, Direction::Inbound));
     assert!(matches!(rule.action, FilterAction::Block));
@@ -77,7 +110,7 @@ fn test_add_filter_rules() -> Result<()> {
             
         // 阻止特定 IP 地址
         FilterRule::new("Block_IP")
-            .remote_ip(IpAddr::V4("1.2.3.4".parse().unwrap()))
+            .remote_ip(IpAddr::V4("1.2.3.4".parse().unwrap()))?
             .action(FilterAction::Block),
             
         // 控制特定端口的流量
@@ -160,6 +193,7 @@ fn test_complex_rule_combination() -> Result<()> {
 }
 
 /// 测试NT路径转换功能
+#[cfg(windows)]
 #[test]
 fn test_nt_path_conversion() {
     // 测试常见的Windows路径转换
@@ -205,10 +239,1957 @@ fn test_filter_rule_with_nt_path() -> Result<()> {
     
     let rule = FilterRule::new("NT路径测试")
         .app_path(&nt_path)
-        .remote_ip("8.8.8.8")
+        .remote_ip("8.8.8.8")?
         .action(FilterAction::Block);
     
     controller.add_advanced_filters(&[rule])?;
     controller.cleanup()?;
     Ok(())
 }
+
+/// 把规则编译后的第一个过滤器放到指定层上
+fn spec_on(rule: &FilterRule, layer_key: GUID) -> Result<FilterSpec> {
+    let mut spec = PlanCompiler::new().compile(rule)?.remove(0);
+    spec.layer_key = layer_key;
+    spec.filter_key = ProviderConfig::default().filter_key(&rule.id, &layer_key);
+    Ok(spec)
+}
+
+/// 测试模拟引擎分配过滤器ID并记录调用
+#[test]
+fn test_simulated_backend_records_calls() -> Result<()> {
+    let mut controller = WfpController::with_backend(SimulatedBackend::new());
+    controller.initialize()?;
+
+    let rule = FilterRule::new("Block_HTTP")
+        .remote_port(80)
+        .protocol(Protocol::Tcp)
+        .direction(Direction::Both)
+        .action(FilterAction::Block);
+    let ids = controller.add_advanced_filters(&[rule])?;
+
+    assert_eq!(ids, vec![1, 2]);
+    let records = controller.backend_mut().enum_filters(FilterScope::Provider(ASTRAL_PROVIDER_KEY))?;
+    assert_eq!(records.len(), 2);
+    assert_eq!(records[0].layer_key, FWPM_LAYER_ALE_AUTH_CONNECT_V4);
+    assert_eq!(records[1].layer_key, FWPM_LAYER_ALE_AUTH_RECV_ACCEPT_V4);
+
+    controller.cleanup()?;
+    assert!(controller.backend().filters().is_empty());
+    assert_eq!(controller.backend().calls()[0], BackendCall::OpenSession(EnforcementMode::Dynamic));
+    assert_eq!(controller.backend().calls().last(), Some(&BackendCall::CloseSession));
+    Ok(())
+}
+
+/// 测试模拟引擎的层与条件兼容性检查
+#[test]
+fn test_simulated_backend_layer_compatibility() -> Result<()> {
+    let mut backend = SimulatedBackend::new();
+    let rule = FilterRule::new("Remote_Port").remote_port(443);
+
+    // 会话未打开
+    assert!(backend.add_filter(&spec_on(&rule, FWPM_LAYER_ALE_AUTH_CONNECT_V4)?).is_err());
+    backend.open_session(EnforcementMode::Dynamic)?;
+
+    // 提供者和子层尚未注册
+    let err = backend.add_filter(&spec_on(&rule, FWPM_LAYER_ALE_AUTH_CONNECT_V4)?).unwrap_err();
+    assert!(err.is(FWP_E_PROVIDER_NOT_FOUND));
+    backend.register_provider(&ProviderConfig::default(), EnforcementMode::Dynamic)?;
+
+    // 监听层没有远程端口字段
+    let err = backend.add_filter(&spec_on(&rule, FWPM_LAYER_ALE_AUTH_LISTEN_V4)?).unwrap_err();
+    assert!(err.is(FWP_E_CONDITION_NOT_FOUND));
+
+    // IPv6 地址不能用在 IPv4 层
+    let v6_rule = FilterRule::new("Remote_V6").remote_ip("2001:db8::1")?;
+    let err = backend.add_filter(&spec_on(&v6_rule, FWPM_LAYER_ALE_AUTH_CONNECT_V4)?).unwrap_err();
+    assert!(err.is(FWP_E_TYPE_MISMATCH));
+    backend.add_filter(&spec_on(&v6_rule, FWPM_LAYER_ALE_AUTH_CONNECT_V6)?)?;
+
+    // 未知层
+    let err = backend.add_filter(&spec_on(&rule, FWPM_SUBLAYER_UNIVERSAL)?).unwrap_err();
+    assert!(err.is(FWP_E_LAYER_NOT_FOUND));
+
+    // 删除不存在的过滤器
+    assert!(backend.delete_filter(999).unwrap_err().is(FWP_E_FILTER_NOT_FOUND));
+    Ok(())
+}
+
+/// 测试模拟引擎的事务回滚
+#[test]
+fn test_simulated_backend_transaction() -> Result<()> {
+    let mut backend = SimulatedBackend::new();
+    backend.open_session(EnforcementMode::Dynamic)?;
+    backend.register_provider(&ProviderConfig::default(), EnforcementMode::Dynamic)?;
+    let rule = FilterRule::new("Txn").remote_port(53).protocol(Protocol::Udp);
+
+    let kept = backend.add_filter(&spec_on(&rule, FWPM_LAYER_ALE_AUTH_CONNECT_V4)?)?;
+    backend.begin_transaction()?;
+    assert!(backend.begin_transaction().unwrap_err().is(FWP_E_TXN_IN_PROGRESS));
+    backend.add_filter(&spec_on(&rule, FWPM_LAYER_ALE_AUTH_RECV_ACCEPT_V4)?)?;
+    backend.delete_filter(kept)?;
+    backend.abort_transaction()?;
+
+    let ids: Vec<u64> = backend.enum_filters(FilterScope::Sublayer(ASTRAL_SUBLAYER_KEY))?.iter().map(|r| r.filter_id).collect();
+    assert_eq!(ids, vec![kept]);
+    assert!(backend.commit_transaction().unwrap_err().is(FWP_E_NO_TXN_IN_PROGRESS));
+
+    backend.begin_transaction()?;
+    backend.add_filter(&spec_on(&rule, FWPM_LAYER_ALE_AUTH_RECV_ACCEPT_V4)?)?;
+    backend.commit_transaction()?;
+    assert_eq!(backend.filters().len(), 2);
+    Ok(())
+}
+
+/// 对比编译出的过滤计划与 tests/golden 下的期望文本
+fn assert_golden_plan(rules: &[FilterRule], golden: &str) -> Result<()> {
+    let plan = render_plan(&PlanCompiler::new().compile_all(rules)?);
+    assert_eq!(plan, golden.replace("\r\n", "\n"));
+    Ok(())
+}
+
+/// 测试 APP_ID + 远程IP 规则的过滤计划
+#[test]
+fn test_plan_golden_app_remote_ip() -> Result<()> {
+    let rules = vec![
+        FilterRule::new("阻止Chrome访问")
+            .app_path("\\device\\harddiskvolume3\\chrome.exe")
+            .remote_ip("183.131.147.29")?
+            .direction(Direction::Outbound)
+            .action(FilterAction::Block),
+    ];
+    assert_golden_plan(&rules, include_str!("../tests/golden/app_remote_ip.plan"))
+}
+
+/// 测试网段、端口范围和协议组合的过滤计划
+#[test]
+fn test_plan_golden_cidr_ports() -> Result<()> {
+    let rules = vec![
+        FilterRule::new("阻止游戏端口")
+            .remote_ip("10.1.2.3/16")?
+            .remote_port_range(27015, 27020)?
+            .protocol(Protocol::Udp)
+            .direction(Direction::Both)
+            .action(FilterAction::Block),
+        FilterRule::new("允许本地Web")
+            .local_port(80)
+            .protocol(Protocol::Tcp)
+            .direction(Direction::Inbound)
+            .action(FilterAction::Allow),
+    ];
+    assert_golden_plan(&rules, include_str!("../tests/golden/cidr_ports.plan"))
+}
+
+/// 测试 IPv6 规则的过滤计划
+#[test]
+fn test_plan_golden_ipv6() -> Result<()> {
+    let rules = vec![
+        FilterRule::new("阻止IPv6 DNS")
+            .remote_ip("2001:4860:4860::8888")?
+            .remote_port(53)
+            .protocol(Protocol::Udp)
+            .direction(Direction::Outbound)
+            .action(FilterAction::Block),
+        FilterRule::new("阻止IPv6网段")
+            .remote_ip("2001:db8:abcd:12::1/48")?
+            .direction(Direction::Both)
+            .action(FilterAction::Block),
+    ];
+    assert_golden_plan(&rules, include_str!("../tests/golden/ipv6.plan"))
+}
+
+/// 测试地址列表、范围和排除项的过滤计划
+#[test]
+fn test_plan_golden_address_lists() -> Result<()> {
+    let rules = vec![
+        FilterRule::new("阻止内网但放过管理网段")
+            .remote_ip("10.0.0.0/8, !10.1.0.0/16")?
+            .direction(Direction::Outbound)
+            .action(FilterAction::Block),
+        FilterRule::new("阻止除网关外的局域网")
+            .remote_ips(["192.168.0.0/16", "!192.168.0.1"])?
+            .direction(Direction::Outbound)
+            .action(FilterAction::Block),
+        FilterRule::new("只允许外网")
+            .remote_ip("!192.168.0.0/16")?
+            .direction(Direction::Outbound)
+            .action(FilterAction::Allow),
+        FilterRule::new("阻止DNS服务器")
+            .remote_ips(["8.8.8.8", "1.1.1.1-1.1.1.3", "2001:4860:4860::8888"])?
+            .remote_port(53)
+            .direction(Direction::Outbound)
+            .action(FilterAction::Block),
+    ];
+    assert_golden_plan(&rules, include_str!("../tests/golden/address_lists.plan"))
+}
+
+/// 测试 ICMP 类型和代码规则的过滤计划
+#[test]
+fn test_plan_golden_icmp() -> Result<()> {
+    assert_golden_plan(&icmp_policy()?, include_str!("../tests/golden/icmp.plan"))
+}
+
+/// 放行回显应答和“需要分片”，阻止回显请求和重定向
+fn icmp_policy() -> Result<Vec<FilterRule>> {
+    Ok(vec![
+        FilterRule::new("允许回显应答")
+            .protocol(Protocol::Icmp)
+            .icmp_type(IcmpType::EchoReply)
+            .direction(Direction::Inbound)
+            .action(FilterAction::Allow),
+        FilterRule::new("允许需要分片")
+            .protocol(Protocol::Icmp)
+            .icmp_type(IcmpType::DestinationUnreachable)
+            .icmp_code(4)
+            .direction(Direction::Inbound)
+            .action(FilterAction::Allow),
+        FilterRule::new("阻止回显请求")
+            .protocol(Protocol::Icmp)
+            .icmp_type(IcmpType::EchoRequest)
+            .direction(Direction::Inbound)
+            .action(FilterAction::Block),
+        FilterRule::new("阻止ICMPv6重定向")
+            .protocol(Protocol::IcmpV6)
+            .icmp_type(IcmpType::Redirect)
+            .remote_ip("fe80::/10")?
+            .direction(Direction::Inbound)
+            .action(FilterAction::Block),
+        FilterRule::new("阻止其余目标不可达")
+            .protocol(Protocol::Icmp)
+            .icmp_type(IcmpType::DestinationUnreachable)
+            .direction(Direction::Inbound)
+            .action(FilterAction::Block),
+    ])
+}
+
+fn addr(s: &str) -> SocketAddr {
+    s.parse().unwrap()
+}
+
+/// 测试连接评估：同一子层内权重最高的匹配过滤器决定结果
+#[test]
+fn test_evaluate_weight_order() -> Result<()> {
+    let rules = vec![
+        FilterRule::new("阻止HTTPS")
+            .remote_port(443)
+            .protocol(Protocol::Tcp)
+            .direction(Direction::Outbound)
+            .action(FilterAction::Block),
+        // 条件更精确的规则权重更高
+        FilterRule::new("允许内网HTTPS")
+            .remote_ip("10.0.0.0/8")?
+            .remote_port(443)
+            .protocol(Protocol::Tcp)
+            .direction(Direction::Outbound)
+            .action(FilterAction::Allow),
+    ];
+    let evaluator = Evaluator::from_rules(&rules)?;
+
+    let verdict = evaluator.evaluate(&Connection::outbound(Protocol::Tcp, addr("10.1.2.3:443")))?;
+    assert!(!verdict.is_blocked());
+    assert_eq!(verdict.decided_by.as_ref().unwrap().rule_id, rules[1].id);
+    assert_eq!(verdict.decided_by.as_ref().unwrap().rule_name, "允许内网HTTPS");
+    assert_eq!(verdict.matched.iter().map(|d| d.rule_id).collect::<Vec<_>>(), [rules[1].id, rules[0].id]);
+    assert_eq!(verdict.to_string(), format!("Allow (规则 '允许内网HTTPS' ({}), 层 ALE_AUTH_CONNECT_V4, 权重 {})", rules[1].id, verdict.matched[0].weight));
+
+    // 同名规则按ID区分
+    let twin = rules[0].clone().id(Uuid::new_v4()).action(FilterAction::Allow).priority(1);
+    let verdict = Evaluator::from_rules(&[rules[0].clone(), twin.clone()])?.evaluate(&Connection::outbound(Protocol::Tcp, addr("8.8.8.8:443")))?;
+    assert_eq!(verdict.decided_by.as_ref().unwrap().rule_name, rules[0].name);
+    assert_eq!(verdict.decided_by.as_ref().unwrap().rule_id, twin.id);
+
+    let verdict = evaluator.evaluate(&Connection::outbound(Protocol::Tcp, addr("8.8.8.8:443")))?;
+    assert!(verdict.is_blocked());
+    assert_eq!(verdict.decided_by.as_ref().unwrap().rule_id, rules[0].id);
+
+    // 协议、端口、方向不匹配时使用默认动作
+    let verdict = evaluator.evaluate(&Connection::outbound(Protocol::Udp, addr("8.8.8.8:443")))?;
+    assert!(!verdict.is_blocked());
+    assert!(verdict.decided_by.is_none());
+    let inbound = Connection::inbound(Protocol::Tcp, addr("192.168.1.2:443"), addr("8.8.8.8:50000"));
+    assert!(evaluator.evaluate(&inbound)?.decided_by.is_none());
+    Ok(())
+}
+
+/// 测试连接评估：应用程序路径匹配和跨子层的阻止优先
+#[test]
+fn test_evaluate_app_and_sublayers() -> Result<()> {
+    let chrome = "\\device\\harddiskvolume3\\chrome.exe";
+    let block = FilterRule::new("阻止Chrome")
+        .app_path(chrome)
+        .remote_ip("183.131.147.29")?
+        .direction(Direction::Outbound)
+        .action(FilterAction::Block);
+    let mut evaluator = Evaluator::from_rules(std::slice::from_ref(&block))?;
+
+    let target = addr("183.131.147.29:80");
+    let verdict = evaluator.evaluate(&Connection::outbound(Protocol::Tcp, target).app_path(&chrome.to_uppercase()))?;
+    assert!(verdict.is_blocked());
+    assert_eq!(verdict.decided_by.unwrap().layer_key, FWPM_LAYER_ALE_AUTH_CONNECT_V4);
+    let other = Connection::outbound(Protocol::Tcp, target).app_path("\\device\\harddiskvolume3\\edge.exe");
+    assert!(!evaluator.evaluate(&other)?.is_blocked());
+
+    // 另一个子层中权重更高的允许过滤器不能推翻阻止
+    let mut permit = spec_on(&FilterRule::new("其他子层允许").remote_ip("183.131.147.29")?, FWPM_LAYER_ALE_AUTH_CONNECT_V4)?;
+    permit.sublayer_key = GUID::from_u128(0x5f0e_0c5a_3c1e_4d4b_9a51_6a1c_2b7d_0001);
+    permit.weight = u64::MAX;
+    evaluator.add_filter(permit, Some(42));
+    let verdict = evaluator.evaluate(&Connection::outbound(Protocol::Tcp, target).app_path(chrome))?;
+    assert!(verdict.is_blocked());
+    assert_eq!(verdict.decided_by.unwrap().rule_id, block.id);
+    assert_eq!(verdict.matched.len(), 2);
+
+    // 地址族不一致、方向为 Both 的连接无法评估
+    let mixed = Connection::inbound(Protocol::Tcp, addr("[::1]:80"), target);
+    assert!(evaluator.evaluate(&mixed).is_err());
+    let both = Connection::new(Direction::Both, Protocol::Tcp, addr("0.0.0.0:0"), target);
+    assert!(evaluator.evaluate(&both).is_err());
+    Ok(())
+}
+
+/// 测试基于模拟引擎中已添加的过滤器评估连接
+#[test]
+fn test_evaluate_from_backend() -> Result<()> {
+    let mut controller = WfpController::with_backend(SimulatedBackend::new());
+    controller.initialize()?;
+    let rule = FilterRule::new("阻止DNS")
+        .remote_port(53)
+        .protocol(Protocol::Udp)
+        .direction(Direction::Both)
+        .action(FilterAction::Block);
+    controller.add_advanced_filters(&[rule])?;
+
+    let evaluator = Evaluator::from_backend(controller.backend());
+    let inbound = Connection::inbound(Protocol::Udp, addr("192.168.1.2:5353"), addr("192.168.1.1:53"));
+    let verdict = evaluator.evaluate(&inbound)?;
+    assert!(verdict.is_blocked());
+    assert_eq!(verdict.decided_by.unwrap().filter_id, Some(2));
+    Ok(())
+}
+
+/// 在第 N 次添加过滤器时注入失败的后端，其余操作交给模拟引擎
+struct FailingBackend {
+    inner: SimulatedBackend,
+    fail_on_add: usize,
+    adds: usize,
+}
+
+impl FailingBackend {
+    fn new(fail_on_add: usize) -> Self {
+        Self { inner: SimulatedBackend::new(), fail_on_add, adds: 0 }
+    }
+}
+
+impl FirewallBackend for FailingBackend {
+    fn open_session(&mut self, mode: EnforcementMode) -> Result<()> { self.inner.open_session(mode) }
+    fn close_session(&mut self) -> Result<()> { self.inner.close_session() }
+    fn register_provider(&mut self, provider: &ProviderConfig, mode: EnforcementMode) -> Result<()> {
+        self.inner.register_provider(provider, mode)
+    }
+    fn find_filter(&mut self, filter_key: &GUID) -> Result<Option<u64>> { self.inner.find_filter(filter_key) }
+    fn add_filter(&mut self, spec: &FilterSpec) -> Result<u64> {
+        self.adds += 1;
+        if self.adds == self.fail_on_add {
+            return Err(AstralError::wfp(0x8032_0009, Message::plain("注入的失败")));
+        }
+        self.inner.add_filter(spec)
+    }
+    fn delete_filter(&mut self, filter_id: u64) -> Result<()> { self.inner.delete_filter(filter_id) }
+    fn enum_filters(&mut self, scope: FilterScope) -> Result<Vec<FilterRecord>> { self.inner.enum_filters(scope) }
+    fn begin_transaction(&mut self) -> Result<()> { self.inner.begin_transaction() }
+    fn commit_transaction(&mut self) -> Result<()> { self.inner.commit_transaction() }
+    fn abort_transaction(&mut self) -> Result<()> { self.inner.abort_transaction() }
+}
+
+fn two_bidirectional_rules() -> Vec<FilterRule> {
+    vec![
+        FilterRule::new("阻止HTTP")
+            .remote_port(80)
+            .protocol(Protocol::Tcp)
+            .direction(Direction::Both)
+            .action(FilterAction::Block),
+        FilterRule::new("阻止DNS")
+            .remote_port(53)
+            .protocol(Protocol::Udp)
+            .direction(Direction::Both)
+            .action(FilterAction::Block),
+    ]
+}
+
+/// 测试中途失败时整批回滚，报告逐条规则、逐个层的结果
+#[test]
+fn test_apply_rolls_back_on_partial_failure() -> Result<()> {
+    // 第3个过滤器（第二条规则的出站层）失败
+    let mut controller = WfpController::with_backend(FailingBackend::new(3));
+    controller.initialize()?;
+    let report = controller.apply_rules(&two_bidirectional_rules())?;
+
+    assert!(!report.committed);
+    assert!(report.filter_ids().is_empty());
+    assert_eq!(report.rules.len(), 2);
+    let statuses: Vec<&LayerStatus> = report.rules.iter().flat_map(|r| &r.layers).map(|l| &l.status).collect();
+    assert_eq!(statuses[0], &LayerStatus::RolledBack(1));
+    assert_eq!(statuses[1], &LayerStatus::RolledBack(2));
+    assert!(matches!(statuses[2], LayerStatus::Failed(e) if e.status() == Some(0x8032_0009)));
+    assert_eq!(statuses[3], &LayerStatus::Skipped);
+    assert_eq!(report.rules[1].layers[0].layer_key, FWPM_LAYER_ALE_AUTH_CONNECT_V4);
+
+    // 引擎和控制器状态都没有改变
+    assert!(controller.backend().inner.filters().is_empty());
+    assert!(controller.filter_ids.is_empty());
+    assert_eq!(controller.backend().inner.calls().last(), Some(&BackendCall::AbortTransaction));
+
+    // add_advanced_filters 返回导致回滚的错误
+    controller.backend_mut().adds = 0;
+    let err = controller.add_advanced_filters(&two_bidirectional_rules()).unwrap_err();
+    assert_eq!(err.status(), Some(0x8032_0009));
+    Ok(())
+}
+
+/// 测试全部成功时在一个事务中提交
+#[test]
+fn test_apply_commits_batch() -> Result<()> {
+    let mut controller = WfpController::with_backend(SimulatedBackend::new());
+    controller.initialize()?;
+    let report = controller.apply_rules(&two_bidirectional_rules())?;
+
+    assert!(report.committed);
+    assert!(report.rules.iter().all(|r| r.is_applied()));
+    assert_eq!(report.filter_ids(), vec![1, 2, 3, 4]);
+    assert_eq!(controller.filter_ids, vec![1, 2, 3, 4]);
+    let calls = controller.backend().calls();
+    assert_eq!(calls[2], BackendCall::BeginTransaction);
+    assert_eq!(calls.last(), Some(&BackendCall::CommitTransaction));
+    Ok(())
+}
+
+/// 测试规则验证失败时不触碰引擎
+#[test]
+fn test_apply_validation_failure_leaves_engine_untouched() -> Result<()> {
+    let mut controller = WfpController::with_backend(SimulatedBackend::new());
+    controller.initialize()?;
+    let mut rules = two_bidirectional_rules();
+    rules.push(FilterRule::new("排除全部地址").remote_ip("!0.0.0.0/0")?);
+    let report = controller.apply_rules(&rules)?;
+
+    assert!(!report.committed);
+    let error = report.rules[2].error.as_ref().unwrap();
+    assert_eq!(error.code(), ErrorCode::Validation);
+    assert!(matches!(error, AstralError::Validation { field, .. } if field == "remote"));
+    assert!(report.rules[0].layers.iter().all(|l| l.status == LayerStatus::Skipped));
+    assert_eq!(controller.backend().calls().len(), 2); // 只有打开会话和注册提供者
+    Ok(())
+}
+
+/// 测试过滤器挂在我们的提供者和子层下，filterKey 由规则ID和层确定性生成
+#[test]
+fn test_filter_keys_are_deterministic() -> Result<()> {
+    let rule = FilterRule::new("阻止HTTP")
+        .id(Uuid::from_u128(0x1b7e_0d4c_6a2f_4e8b_9c3d_5f1a_2b6e_8d40))
+        .remote_port(80)
+        .direction(Direction::Both);
+    let first = PlanCompiler::new().compile(&rule)?;
+    let second = PlanCompiler::new().compile(&rule.clone().action(FilterAction::Allow))?;
+
+    assert_eq!(first.len(), 2);
+    for (a, b) in first.iter().zip(&second) {
+        assert_eq!(a.filter_key, b.filter_key);
+        assert_eq!(a.provider_key, ASTRAL_PROVIDER_KEY);
+        assert_eq!(a.sublayer_key, ASTRAL_SUBLAYER_KEY);
+    }
+    assert_ne!(first[0].filter_key, first[1].filter_key);
+
+    // 规则ID不同则 filterKey 不同
+    let other = PlanCompiler::new().compile(&rule.clone().id(Uuid::new_v4()))?;
+    assert_ne!(other[0].filter_key, first[0].filter_key);
+    Ok(())
+}
+
+/// 测试规则ID与名称无关：同名规则互不影响，改名后仍替换原来的过滤器
+#[test]
+fn test_rule_ids_are_independent_of_names() -> Result<()> {
+    let mut controller = WfpController::with_backend(SimulatedBackend::new());
+    controller.initialize()?;
+    let first = FilterRule::new("阻止端口").remote_port(80);
+    let second = FilterRule::new("阻止端口").remote_port(443);
+    assert_ne!(first.id, second.id);
+    let report = controller.apply_rules(&[first.clone(), second.clone()])?;
+    assert_eq!(report.rule(&second.id).unwrap().rule_name, "阻止端口");
+    assert_eq!(controller.installed_rules()?.len(), 2);
+
+    // 改名后重新应用，按ID替换原来的过滤器
+    let renamed = first.clone().name("阻止HTTP");
+    let report = controller.apply_rules(std::slice::from_ref(&renamed))?;
+    assert!(report.rules[0].layers.iter().all(|l| l.replaced.is_some()));
+    let installed = controller.get_rules()?;
+    assert_eq!(installed.len(), 2);
+    assert!(installed.contains(&renamed) && installed.contains(&second));
+
+    // 按ID删除只影响该规则
+    assert_eq!(controller.remove_rule(&renamed.id)?, 2);
+    assert_eq!(controller.get_rules()?, vec![second.clone()]);
+    assert!(controller.remove_rule(&renamed.id).is_err());
+
+    // 旧版配置中的字符串ID换算成固定的 UUID，缺少ID时分配新的ID
+    let legacy = |id: &str| -> Result<FilterRule> {
+        let json = serde_json::to_string(&second)?.replace(&second.id.to_string(), id);
+        Ok(serde_json::from_str(&json)?)
+    };
+    assert_eq!(legacy("block-http")?.id, legacy_rule_id("block-http"));
+    assert_eq!(legacy("block-http")?.id, legacy("block-http")?.id);
+    assert_eq!(legacy(&second.id.to_string())?, second);
+    let mut json: serde_json::Value = serde_json::to_value(&second)?;
+    json.as_object_mut().unwrap().remove("id");
+    let fresh: FilterRule = serde_json::from_value(json.clone())?;
+    assert_ne!(fresh.id, serde_json::from_value::<FilterRule>(json)?.id);
+    Ok(())
+}
+
+/// 测试重复应用同一规则时替换旧过滤器，而不是叠加
+#[test]
+fn test_reapply_rule_is_idempotent() -> Result<()> {
+    let provider = ProviderConfig::default().sublayer_weight(0x1234);
+    let mut controller = WfpController::with_backend(SimulatedBackend::new()).with_provider(provider);
+    controller.initialize()?;
+    assert_eq!(controller.backend().providers()[0].config.sublayer_weight, 0x1234);
+
+    let rules = two_bidirectional_rules();
+    controller.apply_rules(&rules)?;
+    let report = controller.apply_rules(&rules)?;
+
+    assert!(report.committed);
+    assert_eq!(report.rules[0].layers[0].replaced, Some(1));
+    assert_eq!(controller.backend().filters().len(), 4);
+    assert_eq!(controller.filter_ids, vec![5, 6, 7, 8]);
+    let key = report.rules[0].layers[0].layer_key;
+    let filter_key = ProviderConfig::default().filter_key(&rules[0].id, &key);
+    assert_eq!(controller.backend_mut().find_filter(&filter_key)?, Some(5));
+
+    // 在引擎中直接重复添加同一 filterKey 会失败
+    let spec = controller.plan_filters(&rules[..1])?.remove(0);
+    assert!(controller.backend_mut().add_filter(&spec).unwrap_err().is(FWP_E_ALREADY_EXISTS));
+    Ok(())
+}
+
+/// 测试不同执行模式下过滤器的生存期
+#[test]
+fn test_enforcement_mode_lifetimes() -> Result<()> {
+    let rules = two_bidirectional_rules();
+
+    // 动态模式：会话关闭后过滤器消失
+    let mut controller = WfpController::with_backend(SimulatedBackend::new());
+    controller.initialize()?;
+    controller.apply_rules(&rules)?;
+    controller.backend_mut().close_session()?;
+    assert!(controller.backend().filters().is_empty());
+
+    // 持久模式：会话关闭和重启后仍然存在
+    let mut controller = WfpController::with_backend(SimulatedBackend::new()).with_mode(EnforcementMode::Persistent);
+    controller.initialize()?;
+    let report = controller.apply_rules(&rules)?;
+    assert_eq!(report.mode, EnforcementMode::Persistent);
+    assert!(report.to_string().contains("执行模式: 持久"));
+    controller.backend_mut().close_session()?;
+    controller.backend_mut().reboot();
+    let backend = controller.backend();
+    assert_eq!(backend.filters().len(), 4);
+    assert!(backend.filters().iter().all(|f| f.record.mode == EnforcementMode::Persistent));
+    assert!(backend.providers()[0].persistent);
+
+    // 启动时模式：每个层一个持久过滤器加一个启动时过滤器
+    let mut controller = WfpController::with_backend(SimulatedBackend::new()).with_mode(EnforcementMode::BootTime);
+    controller.initialize()?;
+    let report = controller.apply_rules(&rules[..1])?;
+    assert!(report.committed);
+    controller.backend_mut().reboot();
+    let modes: Vec<EnforcementMode> = controller.backend().filters().iter().map(|f| f.record.mode).collect();
+    assert_eq!(modes, vec![
+        EnforcementMode::Persistent, EnforcementMode::BootTime,
+        EnforcementMode::Persistent, EnforcementMode::BootTime,
+    ]);
+    Ok(())
+}
+
+/// 测试删除规则和切换模式时删除启动时过滤器
+#[test]
+fn test_boot_time_filter_removal() -> Result<()> {
+    let rule = FilterRule::new("阻止远程桌面").local_port(3389).direction(Direction::Inbound);
+
+    // 删除规则时启动时过滤器按固定的 filterKey 一起删除
+    let mut controller = WfpController::with_backend(SimulatedBackend::new()).with_mode(EnforcementMode::BootTime);
+    controller.initialize()?;
+    controller.apply_rules(std::slice::from_ref(&rule))?;
+    assert_eq!(controller.backend().filters().len(), 2);
+    assert_eq!(controller.remove_rule(&rule.id)?, 2);
+    assert!(controller.backend().filters().is_empty());
+    assert!(controller.filter_ids.is_empty());
+
+    // 以持久模式重新应用时删除以前的启动时过滤器
+    let mut controller = WfpController::with_backend(SimulatedBackend::new()).with_mode(EnforcementMode::BootTime);
+    controller.initialize()?;
+    controller.apply_rules(std::slice::from_ref(&rule))?;
+    let mut controller = controller.with_mode(EnforcementMode::Persistent);
+    let report = controller.apply_rules(std::slice::from_ref(&rule))?;
+    assert!(report.committed);
+    assert_eq!(report.removed.len(), 1);
+    let modes: Vec<EnforcementMode> = controller.backend().filters().iter().map(|f| f.record.mode).collect();
+    assert_eq!(modes, vec![EnforcementMode::Persistent]);
+    Ok(())
+}
+
+/// 测试 cleanup 只删除动态模式的过滤器，持久过滤器在清理和重启后仍然存在，remove_all 显式删除
+#[test]
+fn test_cleanup_keeps_persistent_filters() -> Result<()> {
+    let rules = two_bidirectional_rules();
+
+    let mut controller = WfpController::with_backend(SimulatedBackend::new()).with_mode(EnforcementMode::Persistent);
+    controller.initialize()?;
+    controller.apply_rules(&rules)?;
+    controller.cleanup()?;
+    assert!(controller.filter_ids.is_empty());
+    controller.backend_mut().reboot();
+    assert_eq!(controller.backend().filters().len(), 4);
+    controller.initialize()?;
+    assert_eq!(controller.get_rules()?, rules);
+
+    // 启动时模式同样保留
+    let mut controller = WfpController::with_backend(SimulatedBackend::new()).with_mode(EnforcementMode::BootTime);
+    controller.initialize()?;
+    controller.apply_rules(&rules[..1])?;
+    controller.cleanup()?;
+    controller.backend_mut().reboot();
+    assert_eq!(controller.backend().filters().len(), 4);
+
+    // 动态模式下 cleanup 删除跟踪的过滤器
+    let mut controller = WfpController::with_backend(SimulatedBackend::new());
+    controller.initialize()?;
+    controller.apply_rules(&rules)?;
+    controller.cleanup()?;
+    assert_eq!(controller.backend().calls().iter().filter(|call| matches!(call, BackendCall::DeleteFilter(_))).count(), 4);
+    assert!(controller.backend().filters().is_empty());
+
+    // remove_all 与执行模式无关
+    let mut controller = WfpController::with_backend(SimulatedBackend::new()).with_mode(EnforcementMode::Persistent);
+    controller.initialize()?;
+    controller.apply_rules(&rules)?;
+    assert_eq!(controller.remove_all(), 4);
+    controller.cleanup()?;
+    controller.backend_mut().reboot();
+    assert!(controller.backend().filters().is_empty());
+    Ok(())
+}
+
+/// 测试模拟引擎拒绝生存期不兼容的过滤器
+#[test]
+fn test_enforcement_mode_lifetime_mismatch() -> Result<()> {
+    let rule = FilterRule::new("阻止SSH").remote_port(22).direction(Direction::Outbound);
+    let persistent = PlanCompiler::new().with_mode(EnforcementMode::Persistent).compile(&rule)?.remove(0);
+
+    // 动态会话中不能添加持久过滤器
+    let mut backend = SimulatedBackend::new();
+    backend.open_session(EnforcementMode::Dynamic)?;
+    backend.register_provider(&ProviderConfig::default(), EnforcementMode::Dynamic)?;
+    assert!(backend.add_filter(&persistent).unwrap_err().is(FWP_E_DYNAMIC_SESSION_IN_PROGRESS));
+
+    // 持久过滤器不能挂在非持久的提供者下
+    let mut backend = SimulatedBackend::new();
+    backend.open_session(EnforcementMode::Persistent)?;
+    backend.register_provider(&ProviderConfig::default(), EnforcementMode::Dynamic)?;
+    assert!(backend.add_filter(&persistent).unwrap_err().is(FWP_E_LIFETIME_MISMATCH));
+
+    // 启动时过滤器不能引用我们的提供者
+    let mut boot_time = persistent.clone();
+    boot_time.mode = EnforcementMode::BootTime;
+    assert!(backend.add_filter(&boot_time).unwrap_err().is(FWP_E_LIFETIME_MISMATCH));
+    Ok(())
+}
+
+/// 测试启动时模式的过滤计划
+#[test]
+fn test_plan_golden_boot_time() -> Result<()> {
+    let rules = vec![
+        FilterRule::new("阻止远程桌面")
+            .local_port(3389)
+            .protocol(Protocol::Tcp)
+            .direction(Direction::Inbound)
+            .action(FilterAction::Block),
+    ];
+    let plan = render_plan(&PlanCompiler::new().with_mode(EnforcementMode::BootTime).compile_all(&rules)?);
+    assert_eq!(plan, include_str!("../tests/golden/boot_time.plan").replace("\r\n", "\n"));
+    Ok(())
+}
+
+/// 测试从引擎枚举的过滤器无损还原规则
+#[test]
+fn test_installed_rules_round_trip() -> Result<()> {
+    let mut rules = two_bidirectional_rules();
+    rules[0] = rules[0].clone()
+        .description("带时间控制的规则")
+        .time_control(TimeControl {
+            start_time: Some(1_700_000_000),
+            end_time: None,
+            days_of_week: Some(vec![1, 2, 3]),
+            hours: Some((9, 18)),
+        });
+    let mut controller = WfpController::with_backend(SimulatedBackend::new()).with_mode(EnforcementMode::Persistent);
+    controller.initialize()?;
+    controller.apply_rules(&rules)?;
+
+    // 模拟进程重启：新的控制器只能从引擎中读取规则
+    let backend = controller.backend().clone();
+    let mut controller = WfpController::with_backend(backend).with_mode(EnforcementMode::Persistent);
+    let installed = controller.installed_rules()?;
+    assert_eq!(installed.len(), 2);
+    assert!(installed.iter().all(|r| r.mode == EnforcementMode::Persistent));
+    assert_eq!(installed[0].filter_ids, vec![1, 2]);
+    assert_eq!(installed[0].rule.description.as_deref(), Some("带时间控制的规则"));
+    assert_eq!(installed[0].rule.time_control, rules[0].time_control);
+
+    // 还原的规则重新编译后与原计划完全一致
+    let restored = controller.get_rules()?;
+    assert_eq!(
+        render_plan(&PlanCompiler::new().compile_all(&restored)?),
+        render_plan(&PlanCompiler::new().compile_all(&rules)?)
+    );
+    assert_eq!(controller.get_filter_ids(&rules[1].id)?, vec![3, 4]);
+    Ok(())
+}
+
+/// 测试分组时忽略其他提供者和无法解析的 providerData
+#[test]
+fn test_group_records_skips_foreign_filters() -> Result<()> {
+    let rule = FilterRule::new("阻止SSH").remote_port(22).direction(Direction::Outbound);
+    let record = |filter_id: u64, provider_key: GUID, provider_data: Vec<u8>| FilterRecord {
+        filter_id,
+        filter_key: GUID::zeroed(),
+        provider_key,
+        provider_data,
+        layer_key: FWPM_LAYER_ALE_AUTH_CONNECT_V4,
+        sublayer_key: ASTRAL_SUBLAYER_KEY,
+        name: "测试".to_string(),
+        action: FilterAction::Block,
+        weight: 1000,
+        mode: EnforcementMode::Dynamic,
+    };
+    let metadata = RuleMetadata::new(&rule, EnforcementMode::Dynamic).to_bytes()?;
+    let records = vec![
+        record(7, ASTRAL_PROVIDER_KEY, metadata.clone()),
+        record(3, GUID::zeroed(), metadata.clone()),          // 其他产品的过滤器
+        record(5, ASTRAL_PROVIDER_KEY, b"not json".to_vec()), // 损坏的元数据
+        record(2, ASTRAL_PROVIDER_KEY, metadata),
+    ];
+    let installed = group_records(&records, &ASTRAL_PROVIDER_KEY);
+    assert_eq!(installed.len(), 1);
+    assert_eq!(installed[0].rule.id, rule.id);
+    assert_eq!(installed[0].filter_ids, vec![2, 7]);
+    Ok(())
+}
+
+/// 测试错误代码分类和多语言消息
+#[test]
+fn test_error_codes_and_localization() -> Result<()> {
+    let mut controller = WfpController::with_backend(SimulatedBackend::new());
+
+    // 会话未打开
+    let err = controller.apply_rules(&two_bidirectional_rules()).unwrap_err();
+    assert_eq!(err.code(), ErrorCode::SessionNotOpen);
+
+    // WFP 状态码保留原值，同时归入稳定的代码
+    controller.initialize()?;
+    let err = controller.remove_filter(42).unwrap_err();
+    assert!(err.is(FWP_E_FILTER_NOT_FOUND));
+    assert_eq!(err.code(), ErrorCode::FilterNotFound);
+    assert_eq!(err.code().as_str(), "wfp.filter_not_found");
+    assert_eq!(err.localized(Language::English), "filter not found: filter 42 does not exist (status: 0x80320003)");
+    assert_eq!(err.to_string(), "过滤器不存在: 过滤器 42 不存在 (错误代码: 0x80320003)");
+    assert_eq!("en-US".parse::<Language>()?, Language::English);
+    assert_eq!("fr".parse::<Language>().unwrap_err().code(), ErrorCode::Parse);
+
+    // 文件读写和解析错误
+    let missing = std::env::temp_dir().join("astral_wfp_missing_rules.json");
+    let err = controller.import_rules(&missing).unwrap_err();
+    assert_eq!(err.code(), ErrorCode::Io);
+    assert!(matches!(err, AstralError::Io { kind: std::io::ErrorKind::NotFound, .. }));
+
+    let garbled = std::env::temp_dir().join("astral_wfp_garbled_rules.json");
+    std::fs::write(&garbled, "{ not json").unwrap();
+    let err = controller.import_rules(&garbled).unwrap_err();
+    std::fs::remove_file(&garbled).unwrap();
+    assert_eq!(err.code(), ErrorCode::Parse);
+    assert_eq!("fast".parse::<EnforcementMode>().unwrap_err().code(), ErrorCode::Parse);
+
+    // 英文消息中不夹杂中文：参数在构造时分别代入两种模板，嵌套的错误和校验汇总也按语言生成
+    let is_english = |text: &str| !text.chars().any(|c| ('\u{4e00}'..='\u{9fff}').contains(&c));
+    let err = FilterRule::new("ssh").remote_ports("22, 70000").unwrap_err();
+    assert_eq!(err.localized(Language::English), r#"rule validation failed [remote_ports]: invalid remote port list "22, 70000": invalid port: 70000 (expected 0-65535)"#);
+    assert_eq!(err.to_string(), r#"规则验证失败 [remote_ports]: 无效的远程端口列表 "22, 70000": 无效的端口: 70000 (应为 0-65535)"#);
+    let invalid = FilterRule::new("ping").protocol(Protocol::Icmp).remote_port(80);
+    let err = controller.reconcile(&[invalid]).unwrap_err();
+    assert!(is_english(&err.localized(Language::English)), "{}", err.localized(Language::English));
+    assert!(err.localized(Language::English).contains("the desired rule set has 1 error(s)"));
+    for err in [
+        "fast".parse::<EnforcementMode>().unwrap_err(),
+        "10.0.0.0/33".parse::<IpSet>().unwrap_err(),
+        controller.remove_rule(&Uuid::new_v4()).unwrap_err(),
+        AstralError::from(serde_json::from_str::<FilterRule>(r#"{"name":"a","remote_ports":"99999"}"#).unwrap_err()),
+    ] {
+        assert!(is_english(&err.localized(Language::English)), "{}", err.localized(Language::English));
+    }
+    Ok(())
+}
+
+/// 测试 JSON Lines 日志包含规则和层的 span，库本身默认不输出
+#[test]
+fn test_json_lines_logging_spans() -> Result<()> {
+    let directory = std::env::temp_dir().join(format!("astral_wfp_logs_{}", std::process::id()));
+    let config = LogConfig::new("debug").json_lines(&directory, "test.jsonl", LogRotation::Never);
+    let (subscriber, guard) = config.build()?;
+    let rules = two_bidirectional_rules();
+    tracing::subscriber::with_default(subscriber, || -> Result<()> {
+        let mut controller = WfpController::with_backend(SimulatedBackend::new());
+        controller.initialize()?;
+        controller.apply_rules(&rules[..1])?;
+        Ok(())
+    })?;
+    drop(guard);
+
+    let content = std::fs::read_to_string(directory.join("test.jsonl")).unwrap();
+    std::fs::remove_dir_all(&directory).unwrap();
+    let events: Vec<serde_json::Value> = content.lines().map(|line| serde_json::from_str(line).unwrap()).collect();
+    let added: Vec<&serde_json::Value> = events
+        .iter()
+        .filter(|e| e["fields"]["message"] == "过滤器添加成功")
+        .collect();
+    assert_eq!(added.len(), 2);
+    assert_eq!(added[0]["level"], "INFO");
+    assert_eq!(added[0]["fields"]["filter_id"], 1);
+    let spans: Vec<&str> = added[0]["spans"].as_array().unwrap().iter().map(|s| s["name"].as_str().unwrap()).collect();
+    assert_eq!(spans, vec!["apply_rules", "rule", "layer"]);
+    assert_eq!(added[0]["spans"][1]["id"], rules[0].id.to_string());
+    assert_eq!(added[1]["span"]["layer"], "ALE_AUTH_RECV_ACCEPT_V4");
+    Ok(())
+}
+
+/// 测试权重由优先级、具体程度和动作三个区段决定
+#[test]
+fn test_weight_bands() -> Result<()> {
+    let weights = WeightAllocator::new();
+    let block_all = FilterRule::new("阻止所有出站").direction(Direction::Outbound).action(FilterAction::Block);
+    let allow_lan = FilterRule::new("允许本地网络")
+        .remote_ip("192.168.0.0/16")?
+        .direction(Direction::Outbound)
+        .action(FilterAction::Allow);
+
+    // 具体程度：地址按前缀长度折算，端口范围按大小折算
+    assert_eq!(weights.specificity(&block_all), 0);
+    assert_eq!(weights.specificity(&allow_lan), 128);
+    assert_eq!(weights.specificity(&FilterRule::new("单个地址").remote_ip("2001:db8::1")?), 256);
+    assert_eq!(weights.specificity(&FilterRule::new("端口").remote_port(443)), 64);
+    assert_eq!(weights.specificity(&FilterRule::new("小范围").remote_port_range(8000, 8003)?), 56);
+    assert_eq!(weights.specificity(&FilterRule::new("全部端口").remote_port_range(0, 65535)?), 0);
+    assert_eq!(weights.specificity(&FilterRule::new("任意协议").protocol(Protocol::Any)), 0);
+
+    // 优先级相同时更精确的规则优先，例外规则无需手动设置优先级
+    assert!(weights.weight(&allow_lan) > weights.weight(&block_all));
+    // 优先级总是高于具体程度
+    let urgent = block_all.clone().priority(1);
+    assert!(weights.weight(&urgent) > weights.weight(&allow_lan));
+    assert_eq!(weights.weight(&urgent) >> PRIORITY_SHIFT, 1);
+    // 其余都相同时阻止优先
+    let allow_all = block_all.clone().action(FilterAction::Allow);
+    assert_eq!(weights.weight(&block_all), weights.weight(&allow_all) + 1);
+    // 与名称、ID、方向无关
+    assert_eq!(weights.weight(&block_all), weights.weight(&block_all.clone().id(Uuid::new_v4()).direction(Direction::Inbound)));
+    Ok(())
+}
+
+/// 测试规则顺序不影响权重和评估结果
+#[test]
+fn test_weights_independent_of_order() -> Result<()> {
+    let rules = vec![
+        FilterRule::new("阻止所有出站").direction(Direction::Outbound).action(FilterAction::Block),
+        FilterRule::new("允许本地网络")
+            .remote_ip("192.168.0.0/16")?
+            .direction(Direction::Outbound)
+            .action(FilterAction::Allow),
+        FilterRule::new("阻止打印机")
+            .remote_ip("192.168.1.20")?
+            .direction(Direction::Outbound)
+            .action(FilterAction::Block),
+    ];
+    let mut reversed = rules.clone();
+    reversed.reverse();
+
+    let weight_of = |specs: &[FilterSpec], name: &str| specs.iter().find(|s| s.rule_name == name).unwrap().weight;
+    let forward = PlanCompiler::new().compile_all(&rules)?;
+    let backward = PlanCompiler::new().compile_all(&reversed)?;
+    for rule in &rules {
+        assert_eq!(weight_of(&forward, &rule.name), weight_of(&backward, &rule.name));
+    }
+
+    for evaluator in [Evaluator::from_rules(&rules)?, Evaluator::from_rules(&reversed)?] {
+        let verdict = evaluator.evaluate(&Connection::outbound(Protocol::Tcp, addr("192.168.1.5:445")))?;
+        assert_eq!(verdict.decided_by.unwrap().rule_id, rules[1].id);
+        let verdict = evaluator.evaluate(&Connection::outbound(Protocol::Tcp, addr("192.168.1.20:9100")))?;
+        assert_eq!(verdict.decided_by.unwrap().rule_id, rules[2].id);
+        assert!(evaluator.evaluate(&Connection::outbound(Protocol::Tcp, addr("1.1.1.1:443")))?.is_blocked());
+    }
+    Ok(())
+}
+
+/// 测试 IPv6 网段规范化，网段条件不会被丢弃
+#[test]
+fn test_ipv6_networks() -> Result<()> {
+    let network = IpNetwork::from_cidr("2001:db8:abcd:12::1/48")?;
+    assert_eq!(network.to_string(), "2001:db8:abcd::/48");
+    assert!(network.contains(&"2001:db8:abcd:ffff::1".parse().unwrap()));
+    assert!(!network.contains(&"2001:db8:abce::1".parse().unwrap()));
+    assert_eq!(IpNetwork::from_cidr("2001:db8::1/0")?.to_string(), "::/0");
+    assert_eq!(IpNetwork::from_cidr("10.1.2.3/0")?.to_string(), "0.0.0.0/0");
+    assert!(IpNetwork::from_cidr("10.1.2.3/0")?.contains(&"8.8.8.8".parse().unwrap()));
+    assert!(IpNetwork::from_cidr("2001:db8::/129").is_err());
+
+    // 网段规则只阻止网段内的地址，而不是全部流量
+    let rule = FilterRule::new("阻止IPv6网段")
+        .remote_ip("2001:db8:abcd::/48")?
+        .direction(Direction::Outbound)
+        .action(FilterAction::Block);
+    let evaluator = Evaluator::from_rules(&[rule])?;
+    assert!(evaluator.evaluate(&Connection::outbound(Protocol::Tcp, addr("[2001:db8:abcd:1::5]:443")))?.is_blocked());
+    assert!(!evaluator.evaluate(&Connection::outbound(Protocol::Tcp, addr("[2001:4860::8888]:443")))?.is_blocked());
+
+    // 模拟引擎接受 IPv6 层上的网段条件
+    let mut controller = WfpController::with_backend(SimulatedBackend::new());
+    controller.initialize()?;
+    assert!(controller.apply_rules(&[FilterRule::new("网段").local_ip("fe80::/10")?.direction(Direction::Inbound)])?.committed);
+
+    // 地址族不一致的规则被拒绝
+    let mixed = FilterRule::new("混合地址族").local_ip("192.168.1.0/24")?.remote_ip("2001:db8::/32")?;
+    let err = PlanCompiler::new().compile(&mixed).unwrap_err();
+    assert_eq!(err.code(), ErrorCode::Validation);
+    Ok(())
+}
+
+/// 测试地址列表规则的评估结果与集合语义一致
+#[test]
+fn test_address_list_rules() -> Result<()> {
+    let rules = vec![
+        FilterRule::new("阻止内网但放过管理网段")
+            .remote_ip("10.0.0.0/8, !10.1.0.0/16, 172.16.0.5-172.16.0.9")?
+            .direction(Direction::Outbound)
+            .action(FilterAction::Block),
+        FilterRule::new("阻止除单个地址外的IPv6")
+            .remote_ip("!2001:db8::1")?
+            .direction(Direction::Outbound)
+            .action(FilterAction::Block),
+    ];
+    let evaluator = Evaluator::from_rules(&rules)?;
+    let blocked = |target: &str| -> Result<bool> {
+        Ok(evaluator.evaluate(&Connection::outbound(Protocol::Tcp, addr(target)))?.is_blocked())
+    };
+    assert!(blocked("10.0.0.1:80")?);
+    assert!(!blocked("10.1.2.3:80")?);
+    assert!(blocked("10.200.0.1:80")?);
+    assert!(blocked("172.16.0.7:80")?);
+    assert!(!blocked("172.16.0.10:80")?);
+    assert!(!blocked("8.8.8.8:80")?);
+    assert!(blocked("[2001:4860::8888]:80")?);
+    assert!(!blocked("[2001:db8::1]:80")?);
+
+    // 单个排除项编译为一个 NOT_EQUAL 条件
+    let specs = PlanCompiler::new().compile(&rules[1])?;
+    assert_eq!(specs.len(), 1);
+    assert_eq!(specs[0].conditions.len(), 1);
+    assert_eq!(specs[0].conditions[0].match_type, MatchType::NotEqual);
+
+    // 两个地址族的列表在两组层上各有一个过滤器，每个过滤器只带本地址族的条件
+    let dual = FilterRule::new("双栈").remote_ip("10.0.0.0/8, 2001:db8::/32")?.direction(Direction::Outbound);
+    let specs = PlanCompiler::new().compile(&dual)?;
+    assert_eq!(specs.iter().map(|s| s.layer_key).collect::<Vec<_>>(), vec![FWPM_LAYER_ALE_AUTH_CONNECT_V4, FWPM_LAYER_ALE_AUTH_CONNECT_V6]);
+    assert!(specs.iter().all(|s| s.conditions.len() == 1));
+
+    // 模拟引擎接受 NOT_EQUAL 和 IPv6 范围条件
+    let mut controller = WfpController::with_backend(SimulatedBackend::new());
+    controller.initialize()?;
+    let ranged = FilterRule::new("IPv6范围").remote_ip("2001:db8::1-2001:db8::9, !2001:db8::5")?;
+    assert!(controller.apply_rules(&[rules[1].clone(), ranged])?.committed);
+
+    // 排除所有地址、地址族没有交集的规则在校验时被拒绝，无法解析的项在构建时就被拒绝
+    for rule in [
+        FilterRule::new("空").remote_ip("10.0.0.0/8, !0.0.0.0/0")?,
+        FilterRule::new("无交集").local_ip("192.168.1.0/24")?.remote_ip("!10.0.0.0/8, 2001:db8::/32")?,
+    ] {
+        let err = PlanCompiler::new().compile(&rule).unwrap_err();
+        assert_eq!(err.code(), ErrorCode::Validation, "{}", rule.name);
+    }
+    let err = FilterRule::new("格式错误").remote_ip("10.0.0.1, !bogus").unwrap_err();
+    assert!(matches!(err, AstralError::Validation { field, .. } if field == "remote"));
+    Ok(())
+}
+
+/// 测试端口列表的解析、校验和编译
+#[test]
+fn test_port_lists() -> Result<()> {
+    let list: PortList = "80, 443,8000-8100,!8080".parse()?;
+    assert_eq!(list.effective(), vec![(80, 80), (443, 443), (8000, 8079), (8081, 8100)]);
+    assert_eq!(list.size(), 2 + 100);
+    assert!(list.contains(8081) && !list.contains(8080));
+    assert_eq!(list.to_string(), "80,443,8000-8100,!8080");
+
+    // 空列表、空项、倒置的范围和越界端口都被拒绝
+    for bad in ["", "80,,443", "!", "8100-8000", "70000", "no-such-service"] {
+        assert!(PortList::parse(bad).is_err(), "{:?}", bad);
+    }
+    let err = FilterRule::new("倒置").remote_ports("443,100-90").unwrap_err();
+    assert!(matches!(err, AstralError::Validation { field, .. } if field == "remote_ports"));
+    assert!(FilterRule::new("倒置").remote_port_range(90, 80).is_err());
+    let err = PlanCompiler::new().compile(&FilterRule::new("全部排除").local_ports("!0-65535")?).unwrap_err();
+    assert_eq!(err.code(), ErrorCode::Validation);
+
+    // 同一字段的多个端口条件取“或”，单个排除项编译为 NOT_EQUAL
+    let rule = FilterRule::new("Web")
+        .remote_ports("80,443,8000-8100,!8080")?
+        .local_ports("!135")?
+        .direction(Direction::Outbound);
+    let specs = PlanCompiler::new().compile(&rule)?;
+    let conditions: Vec<String> = specs[0].conditions.iter().map(|c| c.to_string()).collect();
+    assert_eq!(conditions, vec![
+        "IP_LOCAL_PORT != 135",
+        "IP_REMOTE_PORT == 80",
+        "IP_REMOTE_PORT == 443",
+        "IP_REMOTE_PORT in 8000-8079",
+        "IP_REMOTE_PORT in 8081-8100",
+    ]);
+
+    let evaluator = Evaluator::from_rules(&[rule.action(FilterAction::Block)])?;
+    let blocked = |local: &str, remote: &str| -> Result<bool> {
+        let connection = Connection::new(Direction::Outbound, Protocol::Tcp, addr(local), addr(remote));
+        Ok(evaluator.evaluate(&connection)?.is_blocked())
+    };
+    assert!(blocked("10.0.0.2:50000", "8.8.8.8:443")?);
+    assert!(blocked("10.0.0.2:50000", "8.8.8.8:8100")?);
+    assert!(!blocked("10.0.0.2:50000", "8.8.8.8:8080")?);
+    assert!(!blocked("10.0.0.2:50000", "8.8.8.8:22")?);
+    assert!(!blocked("10.0.0.2:135", "8.8.8.8:443")?);
+
+    // 端口和端口范围可以多次追加，不再互相覆盖
+    let appended = FilterRule::new("追加").remote_port(53).remote_port_range(80, 89)?;
+    assert_eq!(appended.remote_ports.unwrap().to_string(), "53,80-89");
+
+    // 旧版导出的配置总是写出全部四个端口键（未设置的为 null），单个端口和端口范围读取为端口列表
+    let baseline_export = r#"{
+  "version": "1.0",
+  "rules": [
+    {
+      "name": "阻止DNS",
+      "app_path": null,
+      "local_ip": null,
+      "remote_ip": "10.0.0.1",
+      "local_port": null,
+      "remote_port": 53,
+      "local_port_range": null,
+      "remote_port_range": null,
+      "protocol": "UDP",
+      "direction": "Outbound",
+      "action": "Block",
+      "priority": 0,
+      "group": null,
+      "enabled": true,
+      "description": null
+    },
+    {
+      "name": "阻止Web",
+      "app_path": "C:\\Program Files\\app.exe",
+      "local_ip": null,
+      "remote_ip": null,
+      "local_port": 8080,
+      "remote_port": 443,
+      "local_port_range": null,
+      "remote_port_range": [
+        80,
+        89
+      ],
+      "protocol": "任意协议",
+      "direction": "Both",
+      "action": "Allow",
+      "priority": 10,
+      "group": null,
+      "enabled": true,
+      "description": "旧版规则"
+    }
+  ],
+  "groups": [],
+  "metadata": {
+    "created_at": "1700000000",
+    "created_by": "AstralWFP",
+    "description": "导出的WFP规则配置",
+    "tags": [
+      "wfp",
+      "firewall"
+    ]
+  }
+}"#;
+    let config: crate::RuleConfig = serde_json::from_str(baseline_export)?;
+    let [dns, web] = &config.rules[..] else { panic!("应读取两条规则") };
+    assert_eq!(dns.remote_ports.as_ref().unwrap().to_string(), "53");
+    assert_eq!(dns.local_ports, None);
+    assert_eq!(dns.remote.as_ref().unwrap().to_string(), "10.0.0.1");
+    assert_eq!(dns.protocol, Some(Protocol::Udp));
+    assert_eq!(web.remote_ports.as_ref().unwrap().to_string(), "443,80-89");
+    assert_eq!(web.local_ports.as_ref().unwrap().to_string(), "8080");
+    assert_eq!(web.protocol, Some(Protocol::Any));
+    assert_eq!((web.direction.clone(), web.action.clone(), web.priority), (Direction::Both, FilterAction::Allow, 10));
+
+    // 重新导出的规则使用新格式，读取后不变
+    let exported = serde_json::to_string(web)?;
+    assert!(!exported.contains("remote_port_range"));
+    assert_eq!(&serde_json::from_str::<FilterRule>(&exported)?, web);
+    Ok(())
+}
+
+/// 测试内置服务注册表在端口列表、构建器和显示中的使用
+#[test]
+fn test_service_registry() -> Result<()> {
+    let dns = services::lookup("DNS").unwrap();
+    assert_eq!((dns.name, dns.port), ("domain", 53));
+    assert_eq!(services::by_port(443, Some(&Protocol::Tcp)).unwrap().name, "https");
+    assert!(services::by_port(22, Some(&Protocol::Udp)).is_none());
+
+    // 端口列表中的服务名按端口编译，含 "-" 的服务名不会被当作范围
+    let list: PortList = "https, http-alt, !dns".parse()?;
+    assert_eq!(list.effective(), vec![(443, 443), (8080, 8080)]);
+    assert_eq!(list.to_string(), "https,http-alt,!domain");
+
+    // 只使用一种协议的服务同时设置协议，已设置的协议不会被覆盖
+    let ssh = FilterRule::new("SSH").remote_service("ssh")?;
+    assert_eq!(ssh.remote_ports.as_ref().unwrap().to_string(), "ssh");
+    assert_eq!(ssh.protocol, Some(Protocol::Tcp));
+    assert_eq!(FilterRule::new("DNS").remote_service("dns")?.protocol, None);
+    assert_eq!(FilterRule::new("NTP").protocol(Protocol::Tcp).remote_service("ntp")?.protocol, Some(Protocol::Tcp));
+    let conditions: Vec<String> = PlanCompiler::new().compile(&ssh)?[0].conditions.iter().map(|c| c.to_string()).collect();
+    assert_eq!(conditions, vec!["IP_REMOTE_PORT == 22", "IP_PROTOCOL == 6"]);
+
+    // 未知的服务名在构建时报错
+    let err = FilterRule::new("未知").remote_service("gopherx").unwrap_err();
+    assert_eq!(err.code(), ErrorCode::Validation);
+
+    // 显示时已知端口附带服务名
+    assert_eq!(services::describe_ports(&"443,8000-8100,!8080".parse()?, None), "443 (https), 8000-8100, !8080 (http-alt)");
+    let condition = &PlanCompiler::new().compile(&FilterRule::new("DNS").remote_port(53))?[0].conditions[0];
+    assert_eq!(services::describe_condition(condition), "IP_REMOTE_PORT == 53 (domain)");
+
+    // README 中的服务表与注册表一致
+    let readme = include_str!("../README.md");
+    for service in services::SERVICES {
+        let row = format!("| {} |", service.name);
+        let line = readme.lines().find(|line| line.starts_with(&row)).unwrap_or_else(|| panic!("README 缺少服务 {}", service.name));
+        assert!(line.contains(&format!("| {} | {} |", service.port, service.protocol_names())), "{}", line);
+    }
+    Ok(())
+}
+
+/// 测试协议号、名称和序列化形式，防止线上取值被意外改变
+#[test]
+fn test_protocol_wire_values() -> Result<()> {
+    let pinned = [
+        (Protocol::Icmp, 1, "icmp"),
+        (Protocol::Igmp, 2, "igmp"),
+        (Protocol::Tcp, 6, "tcp"),
+        (Protocol::Udp, 17, "udp"),
+        (Protocol::Gre, 47, "gre"),
+        (Protocol::Esp, 50, "esp"),
+        (Protocol::Ah, 51, "ah"),
+        (Protocol::IcmpV6, 58, "icmpv6"),
+        (Protocol::L2tp, 115, "l2tp"),
+        (Protocol::Sctp, 132, "sctp"),
+    ];
+    for (protocol, number, keyword) in pinned {
+        assert_eq!(protocol.ip_protocol(), Some(number));
+        assert_eq!(Protocol::from_number(number), protocol);
+        assert_eq!(keyword.parse::<Protocol>()?, protocol);
+        assert_eq!(number.to_string().parse::<Protocol>()?, protocol);
+        assert_eq!(protocol.to_string().parse::<Protocol>()?, protocol);
+        assert_eq!(serde_json::to_string(&protocol)?, format!("\"{}\"", keyword));
+    }
+
+    // 没有名称的协议号序列化为数字，名称和数字两种形式都能反序列化
+    let other = Protocol::Other(254);
+    assert_eq!(other.ip_protocol(), Some(254));
+    assert_eq!(other.to_string(), "254");
+    assert_eq!(serde_json::to_string(&other)?, "254");
+    assert_eq!(serde_json::from_str::<Protocol>("254")?, other);
+    assert_eq!(serde_json::from_str::<Protocol>("132")?, Protocol::Sctp);
+    assert_eq!(serde_json::from_str::<Protocol>("\"SCTP\"")?, Protocol::Sctp);
+    assert_eq!(serde_json::from_str::<Protocol>("\"6\"")?, Protocol::Tcp);
+    assert!(serde_json::from_str::<Protocol>("256").is_err());
+    assert!(serde_json::from_str::<Protocol>("\"tcpp\"").is_err());
+    assert_eq!(Protocol::Other(6), Protocol::Tcp);
+    assert_eq!(Protocol::Other(6).to_string(), "TCP");
+
+    // IPSEC 按旧版的取值解析为 ESP；协议号 0 是 HOPOPT 而不是任意协议
+    assert_eq!("IPSEC".parse::<Protocol>()?, Protocol::Esp);
+    assert_eq!("0".parse::<Protocol>()?, Protocol::Other(0));
+    assert_eq!(serde_json::to_string(&Protocol::Any)?, "\"any\"");
+    assert_eq!("任意协议".parse::<Protocol>()?, Protocol::Any);
+    assert_eq!("bogus".parse::<Protocol>().unwrap_err().code(), ErrorCode::Parse);
+
+    // 任意协议不生成条件，其他协议号生成对应的条件
+    let any = PlanCompiler::new().compile(&FilterRule::new("任意").remote_ip("10.0.0.1")?.protocol(Protocol::Any))?;
+    assert!(any[0].conditions.iter().all(|c| c.to_string().starts_with("IP_REMOTE_ADDRESS")));
+    let sctp = PlanCompiler::new().compile(&FilterRule::new("SCTP").protocol("sctp".parse()?))?;
+    assert_eq!(sctp[0].conditions[0].to_string(), "IP_PROTOCOL == 132");
+    let evaluator = Evaluator::from_rules(&[FilterRule::new("阻止任意").remote_ip("10.0.0.1")?.protocol(Protocol::Any)])?;
+    assert!(evaluator.evaluate(&Connection::outbound(Protocol::Udp, addr("10.0.0.1:53")))?.is_blocked());
+
+    // 配置文件中的协议使用同样的形式
+    let rule = FilterRule::new("L2TP").protocol(Protocol::Other(115));
+    assert!(serde_json::to_string(&rule)?.contains("\"protocol\":\"l2tp\""));
+
+    // 用协议号写出的 ICMP/ICMPv6 与命名变体一样选择地址族、层和 ICMP 编号，并通过同样的校验
+    assert!(matches!(Protocol::Other(58).normalized(), Protocol::IcmpV6));
+    assert!(matches!(FilterRule::new("ICMPv6").protocol(Protocol::Other(58)).protocol, Some(Protocol::IcmpV6)));
+    for (number, named) in [(1, Protocol::Icmp), (58, Protocol::IcmpV6)] {
+        let mut other = FilterRule::new("回显").icmp_type(IcmpType::EchoRequest).direction(Direction::Inbound);
+        other.protocol = Some(Protocol::Other(number));
+        let mut expected = other.clone();
+        expected.protocol = Some(named);
+        assert_eq!(rule_families(&other)?, rule_families(&expected)?);
+        assert_eq!(layers_for_rule(&other), layers_for_rule(&expected));
+        let conditions = |rule: &FilterRule| -> Result<Vec<String>> {
+            Ok(PlanCompiler::new().compile(rule)?.iter().flat_map(|s| s.conditions.iter().map(|c| c.to_string())).collect())
+        };
+        assert_eq!(conditions(&other)?, conditions(&expected)?);
+        assert!(validate_rule(&other).is_empty());
+    }
+    Ok(())
+}
+
+/// 测试 ICMP 类型和代码的评估、校验和配置格式
+#[test]
+fn test_icmp_type_and_code() -> Result<()> {
+    let evaluator = Evaluator::from_rules(&icmp_policy()?)?;
+    let icmp = |protocol: Protocol, remote: &str, icmp_type: u8, code: u8| -> Result<bool> {
+        let local = if protocol == Protocol::IcmpV6 { ip("fe80::1") } else { ip("192.168.1.2") };
+        let packet = Connection::icmp(Direction::Inbound, protocol, local, ip(remote), icmp_type, code);
+        Ok(evaluator.evaluate(&packet)?.is_blocked())
+    };
+    assert!(!icmp(Protocol::Icmp, "8.8.8.8", 0, 0)?);  // 回显应答
+    assert!(!icmp(Protocol::Icmp, "8.8.8.8", 3, 4)?);  // 需要分片，代码更具体的允许规则优先
+    assert!(icmp(Protocol::Icmp, "8.8.8.8", 3, 1)?);   // 主机不可达
+    assert!(icmp(Protocol::Icmp, "8.8.8.8", 8, 0)?);   // 回显请求
+    assert!(icmp(Protocol::IcmpV6, "fe80::2", 137, 0)?);
+    assert!(!icmp(Protocol::IcmpV6, "fe80::2", 128, 0)?);
+
+    // 同名类型在 ICMP 和 ICMPv6 中编号不同
+    assert_eq!(IcmpType::EchoRequest.number(false), Some(8));
+    assert_eq!(IcmpType::EchoRequest.number(true), Some(128));
+    assert_eq!(IcmpType::PacketTooBig.number(false), None);
+    assert_eq!(IcmpType::from_number(129, true), IcmpType::EchoReply);
+    assert_eq!("Echo Request".parse::<IcmpType>()?, IcmpType::EchoRequest);
+    assert_eq!("200".parse::<IcmpType>()?, IcmpType::Other(200));
+    assert!("echo".parse::<IcmpType>().is_err());
+
+    // 非 ICMP 协议、只有代码、地址族中没有的类型、同时设置端口都被拒绝
+    for (rule, field) in [
+        (FilterRule::new("TCP").protocol(Protocol::Tcp).icmp_type(IcmpType::EchoRequest), "icmp_type"),
+        (FilterRule::new("只有代码").protocol(Protocol::Icmp).icmp_code(4), "icmp_code"),
+        (FilterRule::new("地址族").protocol(Protocol::Icmp).icmp_type(IcmpType::PacketTooBig), "icmp_type"),
+        (FilterRule::new("端口").protocol(Protocol::Icmp).icmp_type(IcmpType::EchoRequest).remote_port(80), "icmp_type"),
+    ] {
+        match PlanCompiler::new().compile(&rule) {
+            Err(AstralError::Validation { field: actual, .. }) => assert_eq!(actual, field, "{}", rule.name),
+            other => panic!("{}: {:?}", rule.name, other),
+        }
+    }
+
+    // 没有地址的 ICMPv6 规则使用 IPv6 层；ICMP 不能用于 IPv6 地址
+    let v6_echo = FilterRule::new("ICMPv6").protocol(Protocol::IcmpV6).icmp_type(IcmpType::EchoRequest).direction(Direction::Outbound);
+    let specs = PlanCompiler::new().compile(&v6_echo)?;
+    assert_eq!(specs.iter().map(|s| s.layer_key).collect::<Vec<_>>(), vec![FWPM_LAYER_ALE_AUTH_CONNECT_V6]);
+    assert_eq!(specs[0].conditions[0].to_string(), "ICMP_TYPE == 128");
+    let mismatched = FilterRule::new("ICMP到IPv6").protocol(Protocol::Icmp).remote_ip("2001:db8::1")?;
+    assert!(matches!(PlanCompiler::new().compile(&mismatched), Err(AstralError::Validation { field, .. }) if field == "protocol"));
+
+    // 配置格式使用类型名，未命名的类型使用编号
+    let json = serde_json::to_string(&icmp_policy()?[1])?;
+    assert!(json.contains("\"icmp_type\":\"destination-unreachable\",\"icmp_code\":4"), "{}", json);
+    assert_eq!(serde_json::from_str::<FilterRule>(&json)?.icmp_type, Some(IcmpType::DestinationUnreachable));
+    assert_eq!(serde_json::to_string(&IcmpType::Other(200))?, "200");
+
+    // 模拟引擎接受 ICMP 条件
+    let mut controller = WfpController::with_backend(SimulatedBackend::new());
+    controller.initialize()?;
+    assert!(controller.apply_rules(&icmp_policy()?)?.committed);
+    Ok(())
+}
+
+fn ip(s: &str) -> IpAddr {
+    s.parse().unwrap()
+}
+
+/// 测试 IpSet 的集合运算和成员检查
+#[test]
+fn test_ip_set_algebra() -> Result<()> {
+    // 10.0.0.0/8 中除去 10.1.0.0/16
+    let private: IpSet = "10.0.0.0/8".parse()?;
+    let excluded: IpSet = "10.1.0.0/16".parse()?;
+    let policy = private.difference(&excluded);
+    assert!(policy.contains(&ip("10.0.255.255")));
+    assert!(!policy.contains(&ip("10.1.2.3")));
+    assert!(policy.contains(&ip("10.2.0.0")));
+    assert!(!policy.contains(&ip("11.0.0.0")));
+    assert_eq!(policy.to_string(), "10.0.0.0/16, 10.2.0.0-10.255.255.255");
+    assert_eq!(policy.size(), (1 << 24) - (1 << 16));
+
+    // 并集合并相邻区间，结果是规范形式
+    assert_eq!(policy.union(&excluded), private);
+    assert_eq!("1.2.3.0-1.2.3.9, 1.2.3.10-1.2.3.20".parse::<IpSet>()?, "1.2.3.0-1.2.3.20".parse::<IpSet>()?);
+    assert_eq!(policy.intersection(&excluded), IpSet::new());
+    assert!(policy.is_disjoint(&excluded));
+    assert!(private.is_superset(&policy));
+    assert!(!policy.is_superset(&private));
+
+    // 两个地址族互不影响
+    let mixed: IpSet = "192.168.0.0/16, 2001:db8::/32".parse()?;
+    assert!(mixed.has_v4() && mixed.has_v6());
+    assert!(mixed.contains(&ip("2001:db8:ffff::1")));
+    assert!(!mixed.contains(&ip("2001:db9::")));
+    assert_eq!(mixed.intersection(&IpSet::all_v6()).to_string(), "2001:db8::/32");
+    assert_eq!(IpSet::all().difference(&IpSet::all_v4()), IpSet::all_v6());
+    assert_eq!(IpSet::all_v6().size(), u128::MAX);
+    assert_eq!(IpSet::all().difference(&mixed).union(&mixed), IpSet::all());
+
+    // 非法输入
+    assert!("1.2.3.9-1.2.3.0".parse::<IpSet>().is_err());
+    assert!("1.2.3.4-::1".parse::<IpSet>().is_err());
+    assert!("1.2.3".parse::<IpSet>().is_err());
+    Ok(())
+}
+
+/// 测试任意区间与最少 CIDR 网段之间的转换
+#[test]
+fn test_ip_set_cidr_conversion() -> Result<()> {
+    let cidrs = |s: &str| -> Result<Vec<String>> {
+        Ok(s.parse::<IpSet>()?.to_cidrs().iter().map(|n| n.to_string()).collect())
+    };
+    assert_eq!(cidrs("1.2.3.4-1.2.3.77")?, vec![
+        "1.2.3.4/30", "1.2.3.8/29", "1.2.3.16/28", "1.2.3.32/27", "1.2.3.64/29", "1.2.3.72/30", "1.2.3.76/31",
+    ]);
+    assert_eq!(cidrs("0.0.0.0-255.255.255.255")?, vec!["0.0.0.0/0"]);
+    assert_eq!(cidrs("::-ffff:ffff:ffff:ffff:ffff:ffff:ffff:ffff")?, vec!["::/0"]);
+    assert_eq!(cidrs("255.255.255.255")?, vec!["255.255.255.255/32"]);
+    assert_eq!(cidrs("2001:db8::1-2001:db8::2")?, vec!["2001:db8::1/128", "2001:db8::2/128"]);
+
+    // CIDR 列表重新组成的集合与原区间相同
+    let range: IpSet = "10.0.0.3-10.0.9.250".parse()?;
+    let rebuilt: IpSet = range.to_cidrs().iter().map(IpSet::from_network).collect();
+    assert_eq!(rebuilt, range);
+    assert_eq!(IpNetwork::from_cidr("10.1.0.0/16")?.range(), (ip("10.1.0.0"), ip("10.1.255.255")));
+    Ok(())
+}
+
+/// 测试规则通过 JSON 无损往返，配置文件和过滤器元数据使用同一格式
+#[test]
+fn test_rule_json_round_trip() -> Result<()> {
+    let rule = FilterRule::new("完整规则")
+        .app_path("\\device\\harddiskvolume3\\app.exe")
+        .local_ip("192.168.1.0/24, !192.168.1.1")?
+        .remote_ips(["8.8.8.8", "1.1.1.1-1.1.1.3"])?
+        .remote_ports("https, 8000-8100, !8080")?
+        .local_port(5353)
+        .protocol(Protocol::Tcp)
+        .direction(Direction::Outbound)
+        .action(FilterAction::Allow)
+        .priority(3)
+        .group("web")
+        .enabled(false)
+        .time_control(TimeControl::new().days_of_week(vec![1, 2, 3]).hours(9, 18))
+        .description("往返测试");
+    let json = serde_json::to_string(&rule)?;
+    assert!(json.contains("\"remote\":\"8.8.8.8, 1.1.1.1-1.1.1.3\",\"local_ports\":\"5353\""), "{}", json);
+    assert!(json.contains("\"direction\":\"Outbound\",\"action\":\"Allow\""), "{}", json);
+    let parsed: FilterRule = serde_json::from_str(&json)?;
+    assert_eq!(parsed, rule);
+    assert_eq!(serde_json::to_string(&parsed)?, json);
+    let unique: std::collections::HashSet<FilterRule> = [rule.clone(), parsed].into_iter().collect();
+    assert_eq!(unique.len(), 1);
+
+    // 无效的地址、端口和方向在读取时就被拒绝
+    for invalid in [
+        json.replace("8.8.8.8", "8.8.8.300"),
+        json.replace("8000-8100", "8100-8000"),
+        json.replace("\"Outbound\"", "\"Sideways\""),
+    ] {
+        assert!(serde_json::from_str::<FilterRule>(&invalid).is_err(), "{}", invalid);
+    }
+
+    // 导出再导入后规则不变，包括时间控制
+    let path = std::env::temp_dir().join(format!("astral_wfp_rules_{}.json", std::process::id()));
+    let mut exporter = WfpController::with_backend(SimulatedBackend::new());
+    exporter.initialize()?;
+    exporter.add_advanced_filters(std::slice::from_ref(&rule))?;
+    exporter.export_rules(&path)?;
+    let mut importer = WfpController::with_backend(SimulatedBackend::new());
+    importer.initialize()?;
+    importer.import_rules(&path)?;
+    std::fs::remove_file(&path).unwrap();
+    assert_eq!(importer.get_rules()?, vec![rule]);
+    Ok(())
+}
+
+/// 测试校验一次报告规则的全部问题，每条诊断带有严重程度、字段和建议
+#[test]
+fn test_validation_reports_all_diagnostics() -> Result<()> {
+    let fields = |diagnostics: &[Diagnostic], severity: Severity| -> Vec<String> {
+        diagnostics.iter().filter(|d| d.severity == severity).map(|d| d.field.clone()).collect()
+    };
+
+    // 空路径、地址族不一致、ICMP 带端口和倒置的时间段同时报告
+    let rule = FilterRule::new("多处错误")
+        .app_path(" ")
+        .local_ip("10.0.0.1")?
+        .remote_ip("2001:db8::1")?
+        .remote_port(80)
+        .protocol(Protocol::Icmp)
+        .time_control(TimeControl::new().start_time(200).end_time(100));
+    let diagnostics = validate_rule(&rule);
+    assert_eq!(fields(&diagnostics, Severity::Error), ["app_path", "remote", "remote_ports", "time_control"]);
+    assert!(diagnostics.iter().all(|d| d.suggestion.is_some()), "{:?}", diagnostics);
+    let err = rule.validate().unwrap_err();
+    assert!(matches!(&err, AstralError::Validation { field, .. } if field == "app_path"), "{:?}", err);
+
+    // 只有警告的规则可以编译
+    let rule = FilterRule::new("只有警告")
+        .remote_ip("10.0.0.0/8, 10.1.0.0/16, !192.168.0.0/16, 224.0.0.1")?
+        .remote_ports("53, 80, 80")?
+        .protocol(Protocol::Tcp);
+    let diagnostics = validate_rule(&rule);
+    assert_eq!(fields(&diagnostics, Severity::Warning), ["remote", "remote", "remote", "remote_ports"]);
+    assert!(diagnostics[0].to_string().starts_with("警告 [remote]: 10.1.0.0/16 已被远程地址列表的其他项包含"), "{}", diagnostics[0]);
+    rule.validate()?;
+    assert_eq!(PlanCompiler::new().compile(&rule)?.len(), 2);
+
+    // 单个未指定地址和不用端口的协议是错误
+    let rule = FilterRule::new("GRE").remote_ip("0.0.0.0")?.local_port(1723).protocol(Protocol::Gre);
+    assert_eq!(fields(&validate_rule(&rule), Severity::Error), ["remote", "local_ports"]);
+
+    // 规则集中重复的ID
+    let first = FilterRule::new("第一条").remote_ip("10.0.0.1")?;
+    let copy = first.clone().name("副本");
+    let diagnostics = validate_rules(&[first.clone(), copy.clone()]);
+    assert_eq!(diagnostics.len(), 1);
+    assert_eq!((diagnostics[0].rule_name.as_str(), diagnostics[0].diagnostic.field.as_str()), ("副本", "id"));
+
+    // 表单草稿报告每个无法解析的字段，并提示网段中的主机位
+    let mut draft = RuleDraft::new("表单");
+    draft.remote = "10.0.0.300".to_string();
+    draft.remote_ports = "80-70".to_string();
+    draft.protocol = Some(Protocol::Icmp);
+    draft.icmp_type = Some(IcmpType::EchoRequest);
+    draft.icmp_code = "abc".to_string();
+    let (rule, diagnostics) = draft.build();
+    assert!(rule.is_none());
+    assert_eq!(fields(&diagnostics, Severity::Error), ["icmp_code", "remote", "remote_ports"]);
+    let mut draft = RuleDraft::new("主机位");
+    draft.remote = "10.1.2.3/16".to_string();
+    let (rule, diagnostics) = draft.build();
+    assert_eq!(rule.map(|r| r.remote.unwrap().to_string()), Some("10.1.0.0/16".to_string()));
+    assert_eq!(diagnostics[0].suggestion.as_ref().map(|s| s.get(Language::Chinese)), Some("写作 10.1.0.0/16"));
+
+    // 导入时任何一条规则有错误都不应用整个文件
+    let path = std::env::temp_dir().join(format!("astral_wfp_invalid_{}.json", std::process::id()));
+    let config = crate::RuleConfig {
+        version: "1.0".to_string(),
+        rules: vec![first, FilterRule::new("空路径").app_path("")],
+        groups: vec![],
+        metadata: crate::MetadataConfig {
+            created_at: "0".to_string(),
+            created_by: "test".to_string(),
+            description: None,
+            tags: vec![],
+        },
+    };
+    std::fs::write(&path, serde_json::to_string(&config)?).unwrap();
+    let mut controller = WfpController::with_backend(SimulatedBackend::new());
+    controller.initialize()?;
+    let err = controller.import_rules(&path).unwrap_err();
+    std::fs::remove_file(&path).unwrap();
+    assert_eq!(err.code(), ErrorCode::Validation);
+    assert!(err.message().get(Language::Chinese).contains("空路径"), "{}", err.message());
+    assert!(controller.get_rules()?.is_empty());
+    Ok(())
+}
+
+/// 测试冲突分析报告遮蔽、重复、冗余和部分重叠，并与评估结果一致
+#[test]
+fn test_conflict_analysis() -> Result<()> {
+    let chrome = "\\device\\harddiskvolume3\\chrome.exe";
+    let block_lan = FilterRule::new("阻止内网").remote_ip("10.0.0.0/8")?.priority(5).direction(Direction::Outbound);
+    let allow_host = FilterRule::new("允许主机")
+        .remote_ip("10.1.2.3")?
+        .remote_port(443)
+        .protocol(Protocol::Tcp)
+        .direction(Direction::Outbound)
+        .action(FilterAction::Allow);
+    let block_lan_copy = block_lan.clone().id(Uuid::new_v4()).name("阻止内网副本");
+    let block_subnet = FilterRule::new("阻止子网").remote_ip("10.1.0.0/16")?.priority(5).direction(Direction::Outbound);
+    let allow_chrome = FilterRule::new("允许浏览器").app_path(chrome).priority(5).action(FilterAction::Allow);
+    // 方向不同、地址族不同的规则互不影响
+    let allow_inbound = FilterRule::new("允许入站").remote_ip("10.0.0.0/8")?.priority(5).direction(Direction::Inbound).action(FilterAction::Allow);
+    let allow_v6 = FilterRule::new("允许IPv6").remote_ip("2001:db8::/32")?.priority(9).action(FilterAction::Allow);
+    let rules = [block_lan.clone(), allow_host.clone(), block_lan_copy, block_subnet, allow_chrome, allow_inbound, allow_v6];
+
+    let conflicts = find_conflicts(&rules);
+    let found: Vec<(ConflictKind, &str, &str)> =
+        conflicts.iter().map(|c| (c.kind, c.rule.name.as_str(), c.by.name.as_str())).collect();
+    assert_eq!(
+        found,
+        [
+            (ConflictKind::Shadowed, "允许主机", "阻止内网"),
+            (ConflictKind::Duplicate, "阻止内网副本", "阻止内网"),
+            (ConflictKind::Redundant, "阻止子网", "阻止内网"),
+            (ConflictKind::PartialOverlap, "阻止内网", "允许浏览器"),
+            (ConflictKind::Shadowed, "允许主机", "阻止内网副本"),
+            (ConflictKind::Shadowed, "允许主机", "阻止子网"),
+            (ConflictKind::Redundant, "阻止子网", "阻止内网副本"),
+            (ConflictKind::PartialOverlap, "阻止内网副本", "允许浏览器"),
+            (ConflictKind::PartialOverlap, "阻止子网", "允许浏览器"),
+        ]
+    );
+    assert_eq!(conflicts[0].overlap.to_string(), "出站, 远程地址 10.1.2.3, 协议 TCP, 远程端口 443");
+    assert!(conflicts[3].to_string().contains(&format!("程序 {}, 远程地址 10.0.0.0/8", chrome)), "{}", conflicts[3]);
+
+    // 被遮蔽的允许规则确实不起作用：连接由阻止规则决定
+    let evaluator = Evaluator::from_rules(&[block_lan.clone(), allow_host.clone()])?;
+    let verdict = evaluator.evaluate(&Connection::outbound(Protocol::Tcp, addr("10.1.2.3:443")))?;
+    assert_eq!(verdict.decided_by.map(|d| d.rule_id), Some(block_lan.id));
+
+    // 更具体的相反规则是有意的例外；中间夹着相反动作时，子集不再多余
+    let allow_exception = allow_host.clone().priority(5);
+    let allow_range = FilterRule::new("允许网段").remote_ip("10.0.0.0/12")?.priority(5).direction(Direction::Outbound).action(FilterAction::Allow);
+    let block_subnet = FilterRule::new("阻止子网").remote_ip("10.1.0.0/16")?.priority(5).direction(Direction::Outbound);
+    let conflicts = find_conflicts(&[block_lan, allow_exception, allow_range, block_subnet]);
+    assert!(conflicts.iter().all(|c| c.kind != ConflictKind::Shadowed && c.kind != ConflictKind::Redundant), "{:?}", conflicts);
+
+    // ICMP 类型按端口维度比较
+    let block_echo = FilterRule::new("阻止回显").protocol(Protocol::Icmp).icmp_type(IcmpType::EchoRequest);
+    let block_icmp = FilterRule::new("阻止ICMP").protocol(Protocol::Icmp);
+    let conflicts = find_conflicts(&[block_icmp, block_echo]);
+    assert_eq!(conflicts.len(), 1);
+    assert_eq!((conflicts[0].kind, conflicts[0].overlap.to_string()), (ConflictKind::Redundant, "双向, 协议 ICMP, 本地端口 8".to_string()));
+    Ok(())
+}
+
+/// 测试优化器去重、合并方向和列表，并在模拟引擎中与原规则集逐个连接比较结果
+#[test]
+fn test_optimizer_is_equivalent() -> Result<()> {
+    let web = |name: &str, remote: &str| -> Result<FilterRule> {
+        Ok(FilterRule::new(name).remote_ip(remote)?.remote_port(80).protocol(Protocol::Tcp).direction(Direction::Outbound))
+    };
+    let host = |name: &str, direction: Direction| -> Result<FilterRule> {
+        Ok(FilterRule::new(name).remote_ip("192.168.1.1")?.direction(direction))
+    };
+    let rules = vec![
+        web("阻止A", "10.0.0.0/25")?,
+        web("阻止B", "10.0.0.128/25")?,
+        web("阻止A副本", "10.0.0.0/25")?,
+        host("入站", Direction::Inbound)?,
+        host("出站", Direction::Outbound)?,
+        FilterRule::new("阻止低端口").remote_ip("172.16.0.1")?.remote_ports("8000-8099")?,
+        FilterRule::new("阻止高端口").remote_ip("172.16.0.1")?.remote_ports("8100-8199")?,
+        // 合并后权重低于这条允许规则，会改变结果，因此这两条不合并
+        FilterRule::new("阻止C").remote_ip("203.0.113.0/25")?,
+        FilterRule::new("阻止D").remote_ip("203.0.113.128/25")?,
+        FilterRule::new("允许网段").remote_ip("203.0.113.0/24")?.remote_ports("0-32767")?.action(FilterAction::Allow),
+    ];
+
+    let report = optimize(&rules)?;
+    let kinds: Vec<ChangeKind> = report.changes.iter().map(|c| c.kind).collect();
+    assert_eq!(kinds, [ChangeKind::Duplicate, ChangeKind::DirectionFold, ChangeKind::Merge("remote"), ChangeKind::Merge("remote_ports")]);
+    let names: Vec<&str> = report.rules.iter().map(|r| r.name.as_str()).collect();
+    assert_eq!(names, ["阻止A", "入站", "阻止低端口", "阻止C", "阻止D", "允许网段"]);
+    assert_eq!(report.rules[0].remote.as_ref().unwrap().to_string(), "10.0.0.0/24");
+    assert_eq!(report.rules[0].id, rules[0].id);
+    assert_eq!(report.rules[1].direction, Direction::Both);
+    assert_eq!(report.rules[2].remote_ports.as_ref().unwrap().to_string(), "8000-8199");
+    assert_eq!((report.filters_before, report.filters_after, report.filters_saved()), (15, 11, 4));
+    let text = report.to_string();
+    assert!(text.contains("[合并 remote] 2 条规则 → 1 条"), "{}", text);
+    assert!(text.contains("  + '阻止A'"), "{}", text);
+    assert!(text.ends_with("规则 10 → 6，过滤器 15 → 11（减少 4 个）\n"), "{}", text);
+
+    // 两组规则分别装入模拟引擎，逐个连接比较评估结果
+    let install = |rules: &[FilterRule]| -> Result<Evaluator> {
+        let mut controller = WfpController::with_backend(SimulatedBackend::new());
+        controller.initialize()?;
+        controller.add_advanced_filters(rules)?;
+        Ok(Evaluator::from_backend(controller.backend()))
+    };
+    let (original, optimized) = (install(&rules)?, install(&report.rules)?);
+    let remotes = ["10.0.0.1", "10.0.0.200", "10.0.1.1", "192.168.1.1", "172.16.0.1", "203.0.113.5", "203.0.113.200", "8.8.8.8"];
+    let mut checked = 0;
+    for remote in remotes {
+        for port in [80, 443, 8000, 8150, 8200, 40000] {
+            for protocol in [Protocol::Tcp, Protocol::Udp] {
+                let remote = SocketAddr::new(ip(remote), port);
+                for connection in [Connection::outbound(protocol, remote), Connection::inbound(protocol, addr("192.168.0.2:5000"), remote)] {
+                    let (before, after) = (original.evaluate(&connection)?, optimized.evaluate(&connection)?);
+                    assert_eq!(before.action, after.action, "{:?}: {} / {}", connection, before, after);
+                    checked += 1;
+                }
+            }
+        }
+    }
+    assert_eq!(checked, 192);
+    // 未合并的两条规则确实需要分开：合并后允许规则会胜出
+    let blocked = Connection::outbound(Protocol::Tcp, addr("203.0.113.5:80"));
+    assert!(optimized.evaluate(&blocked)?.is_blocked());
+    let merged = vec![FilterRule::new("阻止CD").remote_ip("203.0.113.0/24")?, rules[9].clone()];
+    assert!(!Evaluator::from_rules(&merged)?.evaluate(&blocked)?.is_blocked());
+    Ok(())
+}
+
+/// 测试同步得到最少的添加/替换/删除计划，在一个事务中执行，重复同步不产生变化
+#[test]
+fn test_reconcile_with_desired_rules() -> Result<()> {
+    let [http, dns] = two_bidirectional_rules().try_into().unwrap();
+    let ssh = FilterRule::new("阻止SSH").remote_port(22).protocol(Protocol::Tcp).direction(Direction::Outbound);
+    let mut controller = WfpController::with_backend(SimulatedBackend::new());
+    controller.initialize()?;
+    controller.add_advanced_filters(&[http.clone(), dns.clone(), ssh.clone()])?;
+
+    // 同一规则改了端口和名称：替换；不在期望中的删除；新规则添加
+    let dns_changed = dns.clone().name("阻止DNS和DoT").remote_ports("53, 853")?;
+    let rdp = FilterRule::new("阻止RDP").remote_port(3389).protocol(Protocol::Tcp).direction(Direction::Inbound);
+    let desired = vec![http.clone(), dns_changed.clone(), rdp.clone()];
+    let plan = controller.plan_reconcile(&desired)?;
+    assert_eq!(plan.unchanged, [http.id]);
+    assert_eq!(
+        plan.to_string(),
+        format!(
+            "~ 替换 '阻止DNS和DoT' ({}): 内容已改变，原名称 '阻止DNS'\n+ 添加 '阻止RDP' ({})\n- 删除 '阻止SSH' ({})，1 个过滤器\n添加 1，替换 1，删除 1，不变 1\n",
+            dns.id, rdp.id, ssh.id
+        )
+    );
+    assert_eq!(plan.filters_to_remove().len(), 3);
+
+    let before = controller.backend().calls().len();
+    let report = controller.apply_reconcile(&plan)?;
+    assert!(report.committed);
+    assert_eq!(report.removed.len(), 3);
+    let calls = &controller.backend().calls()[before..];
+    assert_eq!(calls.iter().filter(|c| **c == BackendCall::BeginTransaction).count(), 1);
+    assert_eq!(calls.last(), Some(&BackendCall::CommitTransaction));
+    assert_eq!(controller.get_rules()?, desired);
+    assert_eq!(controller.backend().filters().len(), 5);
+
+    // 再次同步没有任何变更，过滤器不会翻倍
+    let (plan, _) = controller.reconcile(&desired)?;
+    assert!(plan.is_empty(), "{}", plan);
+    assert_eq!(controller.backend().filters().len(), 5);
+
+    // 没有写ID的规则文件每次读取得到新ID，按内容哈希仍能配对
+    let json = r#"{"name":"阻止Telnet","remote_ports":"23","protocol":"tcp","direction":"Outbound","action":"Block","priority":0,"group":null,"enabled":true,"description":null}"#;
+    let first: FilterRule = serde_json::from_str(json)?;
+    let second: FilterRule = serde_json::from_str(json)?;
+    assert_ne!(first.id, second.id);
+    assert_eq!(content_hash(&first)?, content_hash(&second)?);
+    controller.reconcile(std::slice::from_ref(&first))?;
+    let (plan, _) = controller.reconcile(&[second])?;
+    assert!(plan.is_empty(), "{}", plan);
+    assert_eq!(plan.unchanged, [first.id]);
+
+    // 被外部删除的过滤器：过滤器数量不符，替换整条规则
+    let mut controller = WfpController::with_backend(SimulatedBackend::new());
+    controller.initialize()?;
+    let ids = controller.add_advanced_filters(std::slice::from_ref(&http))?;
+    controller.backend_mut().delete_filter(ids[0])?;
+    let plan = controller.plan_reconcile(std::slice::from_ref(&http))?;
+    assert!(matches!(&plan.steps[..], [ReconcileStep::Replace { reason: ReplaceReason::Filters { installed: 1, expected: 2 }, .. }]), "{}", plan);
+
+    // 添加失败时删除也一起回滚
+    // 前4个过滤器安装成功，同步时添加的第一个过滤器失败
+    let mut controller = WfpController::with_backend(FailingBackend::new(5));
+    controller.initialize()?;
+    controller.add_advanced_filters(&[http.clone(), dns.clone()])?;
+    let report = controller.reconcile(&[dns_changed])?.1;
+    assert!(!report.committed);
+    assert_eq!(controller.get_rules()?, [http, dns]);
+
+    // 启动时模式下删除和替换的规则，它们的启动时过滤器也一起删除
+    let mut controller = WfpController::with_backend(SimulatedBackend::new()).with_mode(EnforcementMode::BootTime);
+    controller.initialize()?;
+    controller.add_advanced_filters(&[ssh.clone(), rdp.clone()])?;
+    assert_eq!(controller.backend().filters().len(), 4);
+    let rdp_changed = rdp.clone().remote_port(3390);
+    let plan = controller.plan_reconcile(std::slice::from_ref(&rdp_changed))?;
+    assert_eq!(plan.boot_time_filters.len(), 2);
+    assert_eq!(plan.filters_to_remove().len(), 4);
+    let report = controller.apply_reconcile(&plan)?;
+    assert!(report.committed);
+    assert_eq!(report.removed.len(), 4);
+    assert_eq!(controller.get_rules()?, [rdp_changed]);
+    let modes: Vec<EnforcementMode> = controller.backend().filters().iter().map(|f| f.record.mode).collect();
+    assert_eq!(modes, vec![EnforcementMode::Persistent, EnforcementMode::BootTime]);
+    assert!(controller.reconcile(&[])?.1.committed);
+    assert!(controller.backend().filters().is_empty());
+    Ok(())
+}
+
+/// 测试审计发现缺失、被修改和外来的过滤器（外来过滤器只在按子层枚举时出现），自动修复后恢复记录的状态，外来过滤器只在明确选择时删除，后台审计发送漂移事件
+#[test]
+fn test_drift_audit_and_self_heal() -> Result<()> {
+    let http = two_bidirectional_rules().remove(0);
+    let ssh = FilterRule::new("阻止SSH").remote_port(22).protocol(Protocol::Tcp).direction(Direction::Outbound);
+    let mut controller = WfpController::with_backend(SimulatedBackend::new());
+    controller.initialize()?;
+    let ids = controller.add_advanced_filters(&[http.clone(), ssh.clone()])?;
+    let report = controller.audit(HealPolicy::Report)?;
+    assert!(report.is_clean());
+    assert_eq!(report.checked, 3);
+
+    // 删除 HTTP 的一个过滤器；把 SSH 的过滤器换成权重不同的；往我们的子层中添加其他产品的允许过滤器
+    controller.backend_mut().delete_filter(ids[0])?;
+    let mut tampered = controller.applied_filters()[2].1.clone();
+    tampered.weight -= 1;
+    let backend = controller.backend_mut();
+    backend.delete_filter(ids[2])?;
+    let tampered_id = backend.add_filter(&tampered)?;
+    let mut foreign = PlanCompiler::new().compile(&FilterRule::new("后门").remote_port(4444).direction(Direction::Outbound))?.remove(0);
+    foreign.provider_key = GUID::zeroed();
+    foreign.filter_key = GUID::from_u128(0x1234);
+    foreign.action = FilterAction::Allow;
+    let foreign_id = controller.backend_mut().add_filter(&foreign)?;
+
+    // 按提供者枚举不包含外来过滤器，按子层枚举才能找到它；只有审计需要按子层枚举
+    let provider = FilterScope::Provider(ASTRAL_PROVIDER_KEY);
+    let sublayer = FilterScope::Sublayer(ASTRAL_SUBLAYER_KEY);
+    assert!(controller.backend_mut().enum_filters(provider)?.iter().all(|r| r.filter_id != foreign_id));
+    assert!(controller.backend_mut().enum_filters(sublayer)?.iter().any(|r| r.filter_id == foreign_id));
+    controller.get_rules()?;
+    assert_eq!(controller.backend().calls().last(), Some(&BackendCall::EnumFilters(provider)));
+
+    let report = controller.audit(HealPolicy::Report)?;
+    assert!(controller.backend().calls().ends_with(&[BackendCall::EnumFilters(provider), BackendCall::EnumFilters(sublayer)]));
+    let kinds: Vec<(DriftKind, u64)> = report.events.iter().map(|e| (e.kind.clone(), e.filter_id)).collect();
+    assert_eq!(
+        kinds,
+        [
+            (DriftKind::Missing, ids[0]),
+            (DriftKind::Modified(vec!["filter_id", "weight"]), tampered_id),
+            (DriftKind::Foreign, foreign_id),
+        ]
+    );
+    assert_eq!(report.events[0].rule_id, Some(http.id));
+    assert_eq!(report.events[2].rule_id, None);
+    assert_eq!(report.events[2].to_string(), format!("[外来] 过滤器 '后门' (ID: {}, ALE_AUTH_CONNECT_V4)", foreign_id));
+    assert!(report.to_string().contains("审计 3 个过滤器: 缺失 1，被修改 1，外来 1"));
+    assert!(report.healed.is_none());
+
+    // 恢复规则时外来过滤器只报告，不删除
+    let report = controller.audit(HealPolicy::Restore)?;
+    assert!(report.healed.as_ref().is_some_and(|healed| healed.committed && healed.removed.is_empty()));
+    let report = controller.audit(HealPolicy::Restore)?;
+    assert_eq!(report.events.len(), 1);
+    assert_eq!(report.events[0].kind, DriftKind::Foreign);
+    assert!(report.healed.is_none());
+    assert_eq!(controller.backend().filters().len(), 4);
+    assert_eq!(controller.filter_ids.len(), 3);
+    assert_eq!(controller.get_rules()?, [http.clone(), ssh.clone()]);
+
+    // 明确选择后才删除外来过滤器
+    let report = controller.audit(HealPolicy::RestoreAndRemoveForeign)?;
+    assert!(report.healed.as_ref().is_some_and(|healed| healed.committed && healed.removed == [foreign_id]));
+    assert!(controller.audit(HealPolicy::Report)?.is_clean());
+    assert_eq!(controller.backend().filters().len(), 3);
+    assert_eq!(controller.filter_ids.len(), 3);
+    assert_eq!(controller.get_rules()?, [http, ssh]);
+
+    // 后台审计：外部删除过滤器后收到漂移事件并自动修复
+    let controller = Arc::new(Mutex::new(controller));
+    let options = AuditOptions { interval: Duration::from_millis(10), heal: HealPolicy::Restore };
+    let (monitor, reports) = DriftMonitor::spawn(Arc::clone(&controller), options);
+    {
+        let mut controller = controller.lock().unwrap();
+        let filter_id = controller.filter_ids[0];
+        controller.backend_mut().delete_filter(filter_id)?;
+    }
+    let report = reports.recv_timeout(Duration::from_secs(5)).expect("没有收到漂移事件");
+    assert_eq!(report.events[0].kind, DriftKind::Missing);
+    assert!(report.healed.is_some_and(|healed| healed.committed));
+    monitor.stop();
+    let mut controller = controller.lock().unwrap();
+    assert!(controller.audit(HealPolicy::Report)?.is_clean());
+    assert_eq!(controller.backend().filters().len(), 3);
+    Ok(())
+}
+
+/// 测试重启后新的控制器审计持久引擎：按存储的规则重建记录状态，发现缺失、被修改和无法识别的过滤器并修复
+#[test]
+fn test_drift_audit_rebuilds_recorded_state() -> Result<()> {
+    let rules = two_bidirectional_rules();
+    let mut controller = WfpController::with_backend(SimulatedBackend::new()).with_mode(EnforcementMode::Persistent);
+    controller.initialize()?;
+    controller.apply_rules(&rules)?;
+    controller.cleanup()?;
+    let mut backend = controller.backend().clone();
+    backend.reboot();
+
+    let mut controller = WfpController::with_backend(backend).with_mode(EnforcementMode::Persistent);
+    controller.initialize()?;
+    assert!(controller.applied_filters().is_empty());
+    let report = controller.audit(HealPolicy::Report)?;
+    assert!(report.is_clean());
+    assert_eq!(report.checked, 4);
+
+    // 删除 HTTP 的一个过滤器；把 SSH 的一个过滤器换成权重不同的；再添加元数据损坏的和不属于存储规则的过滤器
+    let records = controller.backend_mut().enum_filters(FilterScope::Provider(ASTRAL_PROVIDER_KEY))?;
+    let specs = controller.plan_filters(&rules)?;
+    let backend = controller.backend_mut();
+    backend.delete_filter(records[0].filter_id)?;
+    backend.delete_filter(records[2].filter_id)?;
+    let mut tampered = specs[2].clone();
+    tampered.weight -= 1;
+    let tampered_id = backend.add_filter(&tampered)?;
+    let mut corrupt = specs[3].clone();
+    corrupt.filter_key = GUID::from_u128(0x5678);
+    corrupt.provider_data = b"{".to_vec();
+    let corrupt_id = backend.add_filter(&corrupt)?;
+    let mut stray = specs[1].clone();
+    stray.filter_key = GUID::from_u128(0x9abc);
+    let stray_id = backend.add_filter(&stray)?;
+
+    let report = controller.audit(HealPolicy::Report)?;
+    let kinds: Vec<(DriftKind, u64, Option<Uuid>)> = report.events.iter().map(|e| (e.kind.clone(), e.filter_id, e.rule_id)).collect();
+    assert_eq!(
+        kinds,
+        [
+            (DriftKind::Missing, 0, Some(rules[0].id)),
+            (DriftKind::Modified(vec!["weight"]), tampered_id, Some(rules[1].id)),
+            (DriftKind::Unrecognized, corrupt_id, None),
+            (DriftKind::Unrecognized, stray_id, Some(rules[0].id)),
+        ]
+    );
+    assert_eq!(report.checked, 4);
+    assert!(report.to_string().contains("审计 4 个过滤器: 缺失 1，被修改 1，外来 0，无法识别 2"));
+
+    // 修复从存储的元数据取得规则并重新应用，无法识别的过滤器属于我们的提供者，一起删除
+    let report = controller.audit(HealPolicy::Restore)?;
+    assert!(report.healed.as_ref().is_some_and(|healed| healed.committed && healed.removed == [corrupt_id, stray_id]));
+    assert!(controller.audit(HealPolicy::Report)?.is_clean());
+    assert_eq!(controller.backend().filters().len(), 4);
+    assert_eq!(controller.applied_filters().len(), 4);
+    assert_eq!(controller.get_rules()?, rules);
+    Ok(())
+}
+
+/// 测试语义差异：按ID和内容配对，逐字段判断放宽或收紧，等价写法不算修改范围，并估算新可达的（程序, 端口）
+#[test]
+fn test_semantic_diff() -> Result<()> {
+    let chrome = r"\device\harddiskvolume3\program files\google\chrome\application\chrome.exe";
+    let http = FilterRule::new("阻止HTTP").remote_ports("80, 81")?.protocol(Protocol::Tcp).direction(Direction::Outbound);
+    let browser = FilterRule::new("允许浏览器")
+        .app_path(chrome)
+        .remote_port(443)
+        .protocol(Protocol::Tcp)
+        .direction(Direction::Outbound)
+        .action(FilterAction::Allow)
+        .priority(5);
+    let telnet = FilterRule::new("阻止Telnet").remote_port(23).protocol(Protocol::Tcp).direction(Direction::Outbound);
+    let intranet = FilterRule::new("阻止内网").remote_ip_cidr("10.0.0.0/8")?.protocol(Protocol::Tcp).direction(Direction::Outbound);
+    let old = vec![http.clone(), browser.clone(), telnet.clone(), intranet.clone()];
+
+    // 浏览器规则换了位置、没有写ID（重新生成ID）但内容相同；HTTP 规则改名并换成等价的端口写法；
+    // 内网规则缩小为 10.0.0.0/16；删除 Telnet 规则；新增 SSH 规则
+    let browser_again = FilterRule { id: Uuid::new_v4(), ..browser.clone() };
+    let http_renamed = http.clone().name("阻止HTTP(80-81)").remote_ports("80-81")?;
+    let intranet_narrowed = intranet.clone().remote_ip_cidr("10.0.0.0/16")?;
+    let ssh = FilterRule::new("阻止SSH").remote_port(22).protocol(Protocol::Tcp).direction(Direction::Outbound);
+    let new = vec![browser_again, http_renamed, intranet_narrowed, ssh.clone()];
+
+    let diff = diff_rules(&old, &new)?;
+    assert_eq!(diff.unchanged, 1);
+    let effects: Vec<(&str, Effect)> = diff
+        .changes
+        .iter()
+        .map(|c| match c {
+            RuleChange::Added(rule) | RuleChange::Removed(rule) => (rule.name.as_str(), c.effect()),
+            RuleChange::Modified { new, .. } => (new.name.as_str(), c.effect()),
+        })
+        .collect();
+    assert_eq!(
+        effects,
+        [
+            ("阻止HTTP(80-81)", Effect::Neutral),
+            ("阻止内网", Effect::Loosening),
+            ("阻止SSH", Effect::Tightening),
+            ("阻止Telnet", Effect::Loosening),
+        ]
+    );
+    assert!(matches!(diff.changes[3], RuleChange::Removed(ref rule) if rule.id == telnet.id));
+    let RuleChange::Modified { fields, .. } = &diff.changes[0] else { panic!("{}", diff) };
+    assert_eq!(fields.len(), 2);
+    assert_eq!(fields[0].to_string(), "name: 阻止HTTP → 阻止HTTP(80-81)（无影响）");
+    assert!(fields[1].equivalent);
+    assert_eq!(fields[1].to_string(), "remote_ports: 80,81 → 80-81（等价）");
+    let RuleChange::Modified { fields, .. } = &diff.changes[1] else { panic!("{}", diff) };
+    assert_eq!(fields[0].to_string(), "remote: 10.0.0.0/8 → 10.0.0.0/16（放宽）");
+    assert_eq!(diff.effect(), Effect::Mixed);
+
+    // 内网规则缩小后 10.255.255.255 的其他端口对所有程序可达（浏览器的 443 本来就允许）；
+    // Telnet 对所有程序新可达；SSH 新被阻止；结果与其他端口相同的端口样本不单独列出
+    let reachable: Vec<(Option<&str>, Option<u16>)> = diff.newly_reachable().map(|r| (r.app_path.as_deref(), r.port)).collect();
+    assert_eq!(reachable, [(None, None), (None, Some(23)), (Some(chrome), None), (Some(chrome), Some(23))]);
+    let other = diff.newly_reachable().next().unwrap();
+    assert_eq!(other.addresses, [ip("10.255.255.255")]);
+    assert_eq!(other.to_string(), "+ 新可达 其他程序 出站 TCP 其他端口（远程地址 10.255.255.255）");
+    assert_eq!(diff.impact[2].to_string(), "+ 新可达 其他程序 出站 TCP 远程端口 23（远程地址 192.0.2.1, 10.255.255.255）");
+    assert!(diff.impact.iter().any(|r| !r.reachable && r.port == Some(22) && r.app_path.is_none()));
+    assert!(!diff.impact.iter().any(|r| r.port == Some(80) || r.port == Some(81)));
+    assert!(!diff.truncated);
+    assert!(diff.to_string().ends_with("新增 1，删除 1，修改 2，不变 1；整体有松有紧\n"));
+
+    // 只调整顺序和字段别名时没有差异
+    let json = r#"{"name":"阻止内网","remote_ip":"10.0.0.0/8","protocol":"tcp","direction":"Outbound","action":"Block","priority":0,"group":null,"enabled":true,"description":null}"#;
+    let aliased: FilterRule = serde_json::from_str(json)?;
+    let diff = diff_rules(&[intranet, telnet, http.clone()], &[http, aliased])?;
+    assert_eq!(diff.unchanged, 2);
+    assert_eq!(diff.changes.len(), 1);
+
+    // 阻止所有端口缩小为只阻止 80：规则中没有列出的其他端口新可达，79 和 81 的结果相同不单独列出
+    let block_all = FilterRule::new("阻止出站").protocol(Protocol::Tcp).direction(Direction::Outbound);
+    let block_http = block_all.clone().remote_port(80);
+    let diff = diff_rules(std::slice::from_ref(&block_all), std::slice::from_ref(&block_http))?;
+    let reachable: Vec<Option<u16>> = diff.newly_reachable().map(|r| r.port).collect();
+    assert_eq!(reachable, [None]);
+    assert!(!diff.impact.iter().any(|r| r.port == Some(80)));
+    // 阻止 80 扩大为 80-81：只有 81 新被阻止
+    let diff = diff_rules(&[block_http], &[block_all.remote_ports("80-81")?])?;
+    let blocked: Vec<Option<u16>> = diff.impact.iter().map(|r| r.port).collect();
+    assert_eq!(blocked, [Some(81)]);
+    assert!(!diff.impact[0].reachable);
+
+    // 出站规则的本地端口和入站规则的远程端口也被采样
+    let outbound = FilterRule::new("阻止本地端口").protocol(Protocol::Tcp).direction(Direction::Outbound).local_ports("5000-5010")?;
+    let diff = diff_rules(std::slice::from_ref(&outbound), &[outbound.clone().local_ports("5000-5005")?])?;
+    let reachable: Vec<(Direction, Option<u16>, PortSide)> = diff.newly_reachable().map(|r| (r.direction.clone(), r.port, r.port_side)).collect();
+    assert_eq!(reachable, [(Direction::Outbound, Some(5010), PortSide::Local), (Direction::Outbound, Some(5006), PortSide::Local)]);
+    assert!(diff.to_string().contains("+ 新可达 其他程序 出站 TCP 本地端口 5010"), "{}", diff);
+    let inbound = FilterRule::new("阻止来自源端口").protocol(Protocol::Udp).direction(Direction::Inbound).remote_port(53);
+    let diff = diff_rules(std::slice::from_ref(&inbound), &[inbound.clone().remote_ports("123")?])?;
+    let changed: Vec<(Option<u16>, PortSide, bool)> = diff.impact.iter().map(|r| (r.port, r.port_side, r.reachable)).collect();
+    assert_eq!(changed, [(Some(53), PortSide::Remote, true), (Some(123), PortSide::Remote, false)], "{}", diff);
+    // 两端都列出端口时，另一端使用同一规则的端口
+    let both = FilterRule::new("阻止两端").protocol(Protocol::Tcp).direction(Direction::Outbound).local_port(5000).remote_port(80);
+    let diff = diff_rules(&[], std::slice::from_ref(&both))?;
+    assert!(diff.impact.iter().any(|r| !r.reachable && r.port == Some(5000) && r.port_side == PortSide::Local));
+
+    // 样本数有上限
+    let many: Vec<FilterRule> = (0..60u16)
+        .map(|i| FilterRule::new(&format!("阻止{}", i)).remote_port(1000 + i * 10).remote_ip(format!("10.0.{}.1", i)).unwrap())
+        .collect();
+    let diff = diff_rules(&many, &many[..30])?;
+    assert!(diff.truncated);
+    assert!(diff.to_string().contains("样本过多已截断"));
+    Ok(())
+}
@@ -0,0 +1,603 @@
+// 规则校验
+//
+// 一次报告规则的全部问题，而不是遇到第一个错误就停止。每条诊断带有严重程度、涉及的字段和修改建议：
+// 错误会阻止规则编译，警告表示规则可以安装但很可能与预期不符。
+// FilterRule::validate、GUI 表单（RuleDraft）、命令行 --validate 和导入都使用这里的检查。
+
+use std::fmt;
+use std::net::IpAddr;
+use uuid::Uuid;
+use crate::astral_wfp::{Direction, FilterAction, FilterRule, IpNetwork, Protocol};
+use crate::error::{AstralError, Language, Message};
+use crate::icmp::IcmpType;
+use crate::ipset::{AddressList, IpSet, Ranges};
+use crate::plan::{address_families, rule_families};
+use crate::message;
+use crate::ports::PortList;
+use crate::services;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Severity {
+    Warning, // 规则可以安装，但很可能与预期不符
+    Error,   // 规则不能编译
+}
+
+impl Severity {
+    pub fn name(&self, language: Language) -> &'static str {
+        match (self, language) {
+            (Severity::Warning, Language::Chinese) => "警告",
+            (Severity::Error, Language::Chinese) => "错误",
+            (Severity::Warning, Language::English) => "warning",
+            (Severity::Error, Language::English) => "error",
+        }
+    }
+}
+
+impl fmt::Display for Severity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name(Language::Chinese))
+    }
+}
+
+// 一条诊断：字段名与 FilterRule 的字段（及 AstralError::Validation 的 field）一致
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    pub field: String,
+    pub message: Message,
+    pub suggestion: Option<Message>,
+}
+
+impl Diagnostic {
+    pub fn error(field: impl Into<String>, message: Message) -> Self {
+        Self { severity: Severity::Error, field: field.into(), message, suggestion: None }
+    }
+
+    pub fn warning(field: impl Into<String>, message: Message) -> Self {
+        Self { severity: Severity::Warning, field: field.into(), message, suggestion: None }
+    }
+
+    pub fn suggest(mut self, suggestion: Message) -> Self {
+        self.suggestion = Some(suggestion);
+        self
+    }
+
+    pub fn is_error(&self) -> bool {
+        self.severity == Severity::Error
+    }
+
+    // 转换成 AstralError::Validation，用于只需要第一个错误的调用方
+    pub fn to_error(&self) -> AstralError {
+        AstralError::validation(&self.field, self.message.clone())
+    }
+
+    // 以指定语言生成诊断文本
+    pub fn localized(&self, language: Language) -> String {
+        let text = format!("{} [{}]: {}", self.severity.name(language), self.field, self.message.get(language));
+        match (&self.suggestion, language) {
+            (Some(suggestion), Language::Chinese) => format!("{}（建议: {}）", text, suggestion.get(language)),
+            (Some(suggestion), Language::English) => format!("{} (suggestion: {})", text, suggestion.get(language)),
+            (None, _) => text,
+        }
+    }
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.localized(Language::Chinese))
+    }
+}
+
+// 构建器返回的校验错误转换成诊断，保留字段名
+impl From<AstralError> for Diagnostic {
+    fn from(error: AstralError) -> Self {
+        match &error {
+            AstralError::Validation { field, message } => Diagnostic::error(field.clone(), message.clone()),
+            other => Diagnostic::error("rule", Message::new(other.localized(Language::Chinese), other.localized(Language::English))),
+        }
+    }
+}
+
+// 规则集中某条规则的诊断
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RuleDiagnostic {
+    pub rule_id: Uuid,
+    pub rule_name: String,
+    pub diagnostic: Diagnostic,
+}
+
+impl RuleDiagnostic {
+    pub fn localized(&self, language: Language) -> String {
+        let diagnostic = self.diagnostic.localized(language);
+        match language {
+            Language::Chinese => format!("规则 '{}' ({}): {}", self.rule_name, self.rule_id, diagnostic),
+            Language::English => format!("rule '{}' ({}): {}", self.rule_name, self.rule_id, diagnostic),
+        }
+    }
+}
+
+impl fmt::Display for RuleDiagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.localized(Language::Chinese))
+    }
+}
+
+pub fn has_errors<'a>(diagnostics: impl IntoIterator<Item = &'a Diagnostic>) -> bool {
+    diagnostics.into_iter().any(Diagnostic::is_error)
+}
+
+// 校验一条规则，按字段顺序返回全部诊断
+pub fn validate_rule(rule: &FilterRule) -> Vec<Diagnostic> {
+    let mut diagnostics = Vec::new();
+    check_identity(rule, &mut diagnostics);
+    for (field, name, list) in [("local", "本地", &rule.local), ("remote", "远程", &rule.remote)] {
+        if let Some(list) = list {
+            check_addresses(rule, field, name, list, &mut diagnostics);
+        }
+    }
+    for (field, name, list) in [("local_ports", "本地", &rule.local_ports), ("remote_ports", "远程", &rule.remote_ports)] {
+        if let Some(list) = list {
+            check_ports(rule, field, name, list, &mut diagnostics);
+        }
+    }
+    check_icmp(rule, &mut diagnostics);
+    check_families(rule, &mut diagnostics);
+    check_port_protocol(rule, &mut diagnostics);
+    check_time_control(rule, &mut diagnostics);
+    diagnostics
+}
+
+// 校验规则集：逐条规则的诊断，以及重复的规则ID
+pub fn validate_rules(rules: &[FilterRule]) -> Vec<RuleDiagnostic> {
+    let mut result = Vec::new();
+    for (index, rule) in rules.iter().enumerate() {
+        let mut diagnostics = validate_rule(rule);
+        if let Some(first) = rules[..index].iter().find(|other| other.id == rule.id) {
+            diagnostics.insert(
+                0,
+                Diagnostic::error("id", message!("规则ID {} 与规则 '{}' 重复", "rule ID {} duplicates rule '{}'", rule.id, first.name))
+                    .suggest(message!("删除重复的规则，或为它分配新的ID", "remove the duplicate rule or give it a new ID")),
+            );
+        }
+        result.extend(diagnostics.into_iter().map(|diagnostic| RuleDiagnostic {
+            rule_id: rule.id,
+            rule_name: rule.name.clone(),
+            diagnostic,
+        }));
+    }
+    result
+}
+
+fn check_identity(rule: &FilterRule, diagnostics: &mut Vec<Diagnostic>) {
+    if rule.name.trim().is_empty() {
+        diagnostics.push(
+            Diagnostic::error("name", message!("规则名称为空", "the rule name is empty"))
+                .suggest(message!("为规则起一个能说明用途的名称", "give the rule a name that describes its purpose")),
+        );
+    }
+    if let Some(app_path) = &rule.app_path {
+        if app_path.trim().is_empty() {
+            diagnostics.push(
+                Diagnostic::error("app_path", message!("应用程序路径为空，规则不会匹配任何程序", "the application path is empty, the rule matches no program"))
+                    .suggest(message!(
+                        "填写程序的完整路径，或删除 app_path 以匹配所有程序",
+                        "enter the full path of the program, or remove app_path to match all programs"
+                    )),
+            );
+        } else if !app_path.to_lowercase().starts_with("\\device\\") {
+            diagnostics.push(
+                Diagnostic::warning(
+                    "app_path",
+                    message!(
+                        "应用程序路径 {} 不是 NT 路径，ALE_APP_ID 条件不会匹配",
+                        "application path {} is not an NT path, the ALE_APP_ID condition will not match",
+                        app_path
+                    ),
+                )
+                .suggest(message!(
+                    "先用 nt::get_nt_path 转换成 \\device\\harddiskvolumeN\\... 形式",
+                    "convert it to the \\device\\harddiskvolumeN\\... form with nt::get_nt_path first"
+                )),
+            );
+        }
+    }
+}
+
+fn check_addresses(rule: &FilterRule, field: &str, name: &str, list: &AddressList, diagnostics: &mut Vec<Diagnostic>) {
+    if list.effective().is_empty() {
+        diagnostics.push(
+            Diagnostic::error(field, Message::new(format!("{}地址列表排除了所有地址: {}", name, list), format!("the {} address list excludes every address: {}", field, list)))
+                .suggest(message!("删除覆盖全部包含项的排除项", "remove the exclusions that cover all included entries")),
+        );
+        return;
+    }
+
+    let included: IpSet = list.entries.iter().filter(|e| !e.negated).map(|e| e.set.clone()).collect();
+    for (index, entry) in list.entries.iter().enumerate() {
+        if entry.negated {
+            if !included.is_empty() && included.intersection(&entry.set).is_empty() {
+                diagnostics.push(
+                    Diagnostic::warning(
+                        field,
+                        Message::new(
+                            format!("排除项 !{} 不在{}地址列表的包含项中，没有作用", entry.set, name),
+                            format!("exclusion !{} is outside the included entries of the {} address list and has no effect", entry.set, field),
+                        ),
+                    )
+                    .suggest(message!("删除 !{}", "remove !{}", entry.set)),
+                );
+            }
+            continue;
+        }
+        // 与其他包含项重复时只报告后出现的一项
+        let others: IpSet = list
+            .entries
+            .iter()
+            .enumerate()
+            .filter(|(i, e)| *i != index && !e.negated && (e.set != entry.set || *i < index))
+            .map(|(_, e)| e.set.clone())
+            .collect();
+        if entry.set.difference(&others).is_empty() {
+            diagnostics.push(
+                Diagnostic::warning(
+                    field,
+                    Message::new(
+                        format!("{} 已被{}地址列表的其他项包含", entry.set, name),
+                        format!("{} is already covered by other entries of the {} address list", entry.set, field),
+                    ),
+                )
+                .suggest(message!("删除重复的 {}", "remove the duplicate {}", entry.set)),
+            );
+        }
+        if let [(start, end)] = entry.set.ranges().as_slice()
+            && start == end {
+            check_address(rule, field, name, start, diagnostics);
+        }
+    }
+}
+
+// 单个地址：未指定地址从不出现在连接中，TCP 没有多播和广播
+fn check_address(rule: &FilterRule, field: &str, name: &str, ip: &IpAddr, diagnostics: &mut Vec<Diagnostic>) {
+    if ip.is_unspecified() {
+        diagnostics.push(
+            Diagnostic::error(
+                field,
+                Message::new(
+                    format!("无效的{} IP 地址: {} 是未指定地址，不会出现在任何连接中", name, ip),
+                    format!("invalid {} IP address: {} is the unspecified address and never appears in a connection", field, ip),
+                ),
+            )
+            .suggest(message!("要匹配所有地址，删除 {} 字段", "remove the {} field to match every address", field)),
+        );
+        return;
+    }
+    let broadcast = matches!(ip, IpAddr::V4(v4) if v4.is_broadcast());
+    if (ip.is_multicast() || broadcast) && rule.protocol == Some(Protocol::Tcp) {
+        diagnostics.push(
+            Diagnostic::warning(field, message!("{} 是多播或广播地址，TCP 连接不会使用它", "{} is a multicast or broadcast address, TCP connections never use it", ip))
+                .suggest(message!("把协议改为 UDP，或改用单播地址", "change the protocol to UDP or use a unicast address")),
+        );
+    }
+}
+
+fn check_ports(rule: &FilterRule, field: &str, name: &str, list: &PortList, diagnostics: &mut Vec<Diagnostic>) {
+    let side = field.trim_end_matches("_ports");
+    if list.effective().is_empty() {
+        diagnostics.push(
+            Diagnostic::error(field, Message::new(format!("{}端口列表排除了所有端口: {}", name, list), format!("the {} port list excludes every port: {}", side, list)))
+                .suggest(message!("删除覆盖全部端口的排除项", "remove the exclusions that cover all ports")),
+        );
+        return;
+    }
+
+    let has_included = list.entries.iter().any(|e| !e.negated);
+    for (index, entry) in list.entries.iter().enumerate() {
+        if entry.negated {
+            let overlaps = list.entries.iter().any(|e| !e.negated && e.start <= entry.end && entry.start <= e.end);
+            if has_included && !overlaps {
+                diagnostics.push(
+                    Diagnostic::warning(
+                        field,
+                        Message::new(
+                            format!("排除项 {} 不在{}端口列表的包含项中，没有作用", entry, name),
+                            format!("exclusion {} is outside the included entries of the {} port list and has no effect", entry, side),
+                        ),
+                    )
+                    .suggest(message!("删除 {}", "remove {}", entry)),
+                );
+            }
+            continue;
+        }
+        // 与其他包含项重复时只报告后出现的一项
+        let others = Ranges::normalized(
+            list.entries
+                .iter()
+                .enumerate()
+                .filter(|(i, e)| *i != index && !e.negated && ((e.start, e.end) != (entry.start, entry.end) || *i < index))
+                .map(|(_, e)| (e.start as u128, e.end as u128))
+                .collect(),
+        );
+        let own = Ranges::normalized(vec![(entry.start as u128, entry.end as u128)]);
+        if own.difference(&others).iter().next().is_none() {
+            diagnostics.push(
+                Diagnostic::warning(
+                    field,
+                    Message::new(
+                        format!("{} 已被{}端口列表的其他项包含", entry, name),
+                        format!("{} is already covered by other entries of the {} port list", entry, side),
+                    ),
+                )
+                .suggest(message!("删除重复的 {}", "remove the duplicate {}", entry)),
+            );
+        }
+        // 按服务名书写的端口与规则协议不符
+        if let (Some(service), Some(protocol)) = (entry.service.and_then(services::lookup), rule.protocol)
+            && (protocol == Protocol::Tcp || protocol == Protocol::Udp)
+            && !service.protocols.contains(&protocol) {
+            diagnostics.push(
+                Diagnostic::warning(
+                    field,
+                    message!(
+                        "服务 {} 使用 {}，但规则协议为 {}",
+                        "service {} uses {}, but the rule protocol is {}",
+                        service.name,
+                        service.protocol_names(),
+                        protocol
+                    ),
+                )
+                .suggest(message!(
+                    "把协议改为 {}，或删除协议限制",
+                    "change the protocol to {} or remove the protocol restriction",
+                    service.protocol_names()
+                )),
+            );
+        }
+    }
+}
+
+// ICMP 类型和代码只用于 ICMP/ICMPv6，并且占用端口字段
+fn check_icmp(rule: &FilterRule, diagnostics: &mut Vec<Diagnostic>) {
+    if rule.icmp_type.is_none() && rule.icmp_code.is_none() {
+        return;
+    }
+    let field = if rule.icmp_type.is_some() { "icmp_type" } else { "icmp_code" };
+    let is_v6 = match rule.protocol.map(Protocol::normalized) {
+        Some(Protocol::Icmp) => false,
+        Some(Protocol::IcmpV6) => true,
+        _ => {
+            diagnostics.push(
+                Diagnostic::error(field, message!("ICMP 类型和代码只能用于 ICMP 或 ICMPv6 协议", "ICMP type and code can only be used with ICMP or ICMPv6"))
+                    .suggest(message!("把协议设为 Protocol::Icmp 或 Protocol::IcmpV6", "set the protocol to Protocol::Icmp or Protocol::IcmpV6")),
+            );
+            return;
+        },
+    };
+    match rule.icmp_type {
+        None => diagnostics.push(
+            Diagnostic::error("icmp_code", message!("设置 ICMP 代码时必须同时设置 ICMP 类型", "an ICMP code requires an ICMP type"))
+                .suggest(message!("设置 icmp_type，例如 IcmpType::DestinationUnreachable", "set icmp_type, e.g. IcmpType::DestinationUnreachable")),
+        ),
+        Some(icmp_type) if icmp_type.number(is_v6).is_none() => {
+            let other = if is_v6 { Protocol::Icmp } else { Protocol::IcmpV6 };
+            diagnostics.push(
+                Diagnostic::error(
+                    "icmp_type",
+                    message!("{} 中没有 ICMP 类型 {}", "{} has no ICMP type {}", rule.protocol.unwrap_or(Protocol::Any), icmp_type),
+                )
+                .suggest(message!(
+                    "该类型属于 {}，或者用 IcmpType::Other(编号) 指定编号",
+                    "the type belongs to {}, or give the number with IcmpType::Other(number)",
+                    other
+                )),
+            );
+        },
+        Some(_) => {},
+    }
+    if rule.local_ports.is_some() || rule.remote_ports.is_some() {
+        diagnostics.push(
+            Diagnostic::error(
+                field,
+                message!("ICMP 类型和代码通过端口字段匹配，不能同时设置端口", "ICMP type and code are matched through the port fields and cannot be combined with ports"),
+            )
+            .suggest(message!("删除 local_ports 和 remote_ports", "remove local_ports and remote_ports")),
+        );
+    }
+}
+
+// 本地和远程地址必须有共同的地址族，协议也必须能用于这些地址
+fn check_families(rule: &FilterRule, diagnostics: &mut Vec<Diagnostic>) {
+    let usable = |list: &Option<AddressList>| list.as_ref().is_none_or(|l| !l.effective().is_empty());
+    if !usable(&rule.local) || !usable(&rule.remote) {
+        return;
+    }
+    if let (Some(local), Some(remote)) = (&rule.local, &rule.remote)
+        && address_families(rule).unwrap_or((true, false)) == (false, false) {
+        diagnostics.push(
+            Diagnostic::error(
+                "remote",
+                message!("本地地址 {} 和远程地址 {} 的IP版本不一致", "local address {} and remote address {} use different IP versions", local, remote),
+            )
+            .suggest(message!(
+                "两端使用同一地址族的地址，或拆成 IPv4 和 IPv6 两条规则",
+                "use addresses of the same family on both ends, or split into an IPv4 and an IPv6 rule"
+            )),
+        );
+        return;
+    }
+    if rule_families(rule).unwrap_or((true, false)) == (false, false) {
+        let suggestion = match rule.protocol.map(Protocol::normalized) {
+            Some(Protocol::Icmp) => message!("IPv6 地址请使用 Protocol::IcmpV6", "use Protocol::IcmpV6 for IPv6 addresses"),
+            Some(Protocol::IcmpV6) => message!("IPv4 地址请使用 Protocol::Icmp", "use Protocol::Icmp for IPv4 addresses"),
+            _ => message!("修改协议或地址", "change the protocol or the addresses"),
+        };
+        diagnostics.push(
+            Diagnostic::error(
+                "protocol",
+                message!("协议 {} 不能用于规则中的地址", "protocol {} cannot be used with the addresses of the rule", rule.protocol.unwrap_or(Protocol::Any)),
+            )
+                .suggest(suggestion),
+        );
+    }
+}
+
+// 端口只存在于 TCP、UDP 等传输协议中；ICMP 规则的端口与类型冲突已在 check_icmp 中报告
+fn check_port_protocol(rule: &FilterRule, diagnostics: &mut Vec<Diagnostic>) {
+    let Some(protocol) = rule.protocol else { return };
+    if rule.icmp_type.is_some() || rule.icmp_code.is_some() {
+        return;
+    }
+    if [Protocol::Tcp, Protocol::Udp, Protocol::Sctp, Protocol::Any].contains(&protocol) {
+        return;
+    }
+    for (field, ports) in [("local_ports", &rule.local_ports), ("remote_ports", &rule.remote_ports)] {
+        if ports.is_some() {
+            let suggestion = match protocol.normalized() {
+                Protocol::Icmp | Protocol::IcmpV6 => {
+                    message!("用 icmp_type / icmp_code 匹配 ICMP 报文，并删除端口", "match ICMP messages with icmp_type / icmp_code and remove the ports")
+                },
+                _ => message!("把协议改为 TCP 或 UDP，或删除端口", "change the protocol to TCP or UDP, or remove the ports"),
+            };
+            diagnostics.push(
+                Diagnostic::error(field, message!("协议 {} 没有端口，端口条件永远不会匹配", "protocol {} has no ports, the port condition never matches", protocol))
+                    .suggest(suggestion),
+            );
+        }
+    }
+}
+
+fn check_time_control(rule: &FilterRule, diagnostics: &mut Vec<Diagnostic>) {
+    let Some(time_control) = &rule.time_control else { return };
+    if let (Some(start), Some(end)) = (time_control.start_time, time_control.end_time)
+        && start >= end {
+        diagnostics.push(
+            Diagnostic::error("time_control", message!("开始时间 {} 不早于结束时间 {}", "start time {} is not before end time {}", start, end))
+                .suggest(message!("交换开始和结束时间", "swap the start and end times")),
+        );
+    }
+    if let Some(days) = &time_control.days_of_week
+        && let Some(day) = days.iter().find(|&&day| day > 6) {
+        diagnostics.push(
+            Diagnostic::error("time_control", message!("无效的星期 {}（应为 0-6，0 表示周日）", "invalid day of week {} (expected 0-6, 0 is Sunday)", day))
+                .suggest(message!("使用 0-6 表示周日到周六", "use 0-6 for Sunday to Saturday")),
+        );
+    }
+    if let Some((start, end)) = time_control.hours
+        && (start > 23 || end > 24 || start >= end) {
+        diagnostics.push(
+            Diagnostic::error("time_control", message!("无效的小时范围 {}-{}", "invalid hour range {}-{}", start, end))
+                .suggest(message!(
+                    "开始小时为 0-23，结束小时为 1-24 且晚于开始小时",
+                    "the start hour is 0-23, the end hour is 1-24 and later than the start hour"
+                )),
+        );
+    }
+}
+
+// GUI 表单或命令行输入的未解析规则。build 一次检查所有字段，
+// 除构建器的解析错误外，还报告只能从原始文本看出的问题（例如网段含主机位）
+#[derive(Debug, Clone)]
+pub struct RuleDraft {
+    pub name: String,
+    pub app_path: String,     // 为空表示不限制程序
+    pub local: String,        // 为空表示不限制地址
+    pub remote: String,
+    pub local_ports: String,  // 为空表示不限制端口
+    pub remote_ports: String,
+    pub protocol: Option<Protocol>,
+    pub icmp_type: Option<IcmpType>,
+    pub icmp_code: String,    // 为空表示不限制代码
+    pub direction: Direction,
+    pub action: FilterAction,
+}
+
+impl RuleDraft {
+    pub fn new(name: &str) -> Self {
+        Self {
+            name: name.to_string(),
+            app_path: String::new(),
+            local: String::new(),
+            remote: String::new(),
+            local_ports: String::new(),
+            remote_ports: String::new(),
+            protocol: None,
+            icmp_type: None,
+            icmp_code: String::new(),
+            direction: Direction::Both,
+            action: FilterAction::Block,
+        }
+    }
+
+    // 构建规则并返回全部诊断；有错误时不返回规则
+    pub fn build(&self) -> (Option<FilterRule>, Vec<Diagnostic>) {
+        let mut diagnostics = Vec::new();
+        let mut rule = FilterRule::new(&self.name).direction(self.direction.clone()).action(self.action.clone());
+        if !self.app_path.trim().is_empty() {
+            rule = rule.app_path(self.app_path.trim());
+        }
+        if let Some(protocol) = self.protocol {
+            rule = rule.protocol(protocol);
+        }
+        if let Some(icmp_type) = self.icmp_type {
+            rule = rule.icmp_type(icmp_type);
+        }
+        if !self.icmp_code.trim().is_empty() {
+            match self.icmp_code.trim().parse::<u8>() {
+                Ok(code) => rule = rule.icmp_code(code),
+                Err(_) => diagnostics.push(
+                    Diagnostic::error("icmp_code", message!("无效的 ICMP 代码: {}", "invalid ICMP code: {}", self.icmp_code.trim()))
+                        .suggest(message!("填写 0-255 的数字", "enter a number 0-255")),
+                ),
+            }
+        }
+
+        type Setter = fn(FilterRule, &str) -> crate::error::Result<FilterRule>;
+        let addresses = message!(
+            "地址、网段或 \"起始-结束\" 范围，用逗号分隔，例如 10.0.0.0/8, !10.1.0.0/16",
+            "addresses, networks or \"start-end\" ranges separated by commas, e.g. 10.0.0.0/8, !10.1.0.0/16"
+        );
+        let ports = message!(
+            "端口、\"起始-结束\" 范围或服务名，用逗号分隔，例如 80,443,8000-8100",
+            "ports, \"start-end\" ranges or service names separated by commas, e.g. 80,443,8000-8100"
+        );
+        let fields: [(&str, &str, Setter, &Message); 4] = [
+            ("local", &self.local, |r, t| r.local_ip(t), &addresses),
+            ("remote", &self.remote, |r, t| r.remote_ip(t), &addresses),
+            ("local_ports", &self.local_ports, |r, t| r.local_ports(t), &ports),
+            ("remote_ports", &self.remote_ports, |r, t| r.remote_ports(t), &ports),
+        ];
+        for (field, text, set, hint) in fields {
+            if text.trim().is_empty() {
+                continue;
+            }
+            match set(rule.clone(), text) {
+                Ok(updated) => rule = updated,
+                Err(e) => diagnostics.push(Diagnostic::from(e).suggest(hint.clone())),
+            }
+            if field == "local" || field == "remote" {
+                diagnostics.extend(host_bit_warnings(field, text));
+            }
+        }
+
+        if has_errors(&diagnostics) {
+            return (None, diagnostics);
+        }
+        diagnostics.extend(validate_rule(&rule));
+        if has_errors(&diagnostics) { (None, diagnostics) } else { (Some(rule), diagnostics) }
+    }
+}
+
+// 网段的主机位在解析时被清除，例如 10.1.2.3/16 按 10.1.0.0/16 处理
+fn host_bit_warnings(field: &str, text: &str) -> Vec<Diagnostic> {
+    text.split(',')
+        .map(|item| item.trim().trim_start_matches('!').trim())
+        .filter(|item| item.contains('/'))
+        .filter_map(|item| {
+            let network = IpNetwork::from_cidr(item).ok()?;
+            let written: IpAddr = item.split('/').next()?.parse().ok()?;
+            (written != network.ip).then(|| {
+                Diagnostic::warning(field, message!("网段 {} 含主机位，按 {} 处理", "network {} has host bits set and is treated as {}", item, network))
+                    .suggest(message!("写作 {}", "write {}", network))
+            })
+        })
+        .collect()
+}
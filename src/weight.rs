@@ -0,0 +1,89 @@
+// 过滤器权重分配
+//
+// 权重只由规则本身决定，与规则的添加顺序无关。64 位权重分成三个区段（从高到低）：
+//   - 第 32-63 位：规则优先级（FilterRule::priority），优先级高的规则总是先评估；
+//   - 第 1-31 位：规则的具体程度，优先级相同时条件更精确的规则先评估，
+//     因此“阻止所有出站 + 允许本地网段”这类例外规则无需手动调整优先级；
+//   - 第 0 位：动作，优先级和具体程度都相同时阻止优先于允许。
+// 优先级、具体程度和动作都相同的两条规则权重相同，此时它们的动作也相同，评估结果与顺序无关。
+
+use crate::astral_wfp::{FilterAction, FilterRule};
+use crate::ipset::{AddressList, IpSet};
+use crate::ports::PortList;
+
+// 各区段的起始位
+pub const PRIORITY_SHIFT: u32 = 32;
+pub const SPECIFICITY_SHIFT: u32 = 1;
+
+// 各类条件对具体程度的贡献
+const APP_SPECIFICITY: u32 = 256;
+const ADDRESS_SPECIFICITY: u32 = 256;  // 单个地址；网段按前缀长度折算
+const PORT_SPECIFICITY: u32 = 64;      // 单个端口；端口列表按端口数量折算
+const PROTOCOL_SPECIFICITY: u32 = 32;
+
+// 权重分配器
+#[derive(Debug, Clone, Copy, Default)]
+pub struct WeightAllocator;
+
+impl WeightAllocator {
+    pub fn new() -> Self {
+        Self
+    }
+
+    // 规则的过滤器权重，规则的所有过滤器使用同一权重
+    pub fn weight(&self, rule: &FilterRule) -> u64 {
+        let action = match rule.action {
+            FilterAction::Block => 1,
+            FilterAction::Allow => 0,
+        };
+        ((rule.priority as u64) << PRIORITY_SHIFT) | ((self.specificity(rule) as u64) << SPECIFICITY_SHIFT) | action
+    }
+
+    // 规则的具体程度：条件越多、地址网段越小、端口范围越窄，值越大
+    pub fn specificity(&self, rule: &FilterRule) -> u32 {
+        let mut score = 0;
+        if rule.app_path.is_some() {
+            score += APP_SPECIFICITY;
+        }
+        for address in [&rule.local, &rule.remote].into_iter().flatten() {
+            score += address_specificity(address);
+        }
+        score += port_specificity(rule.local_ports.as_ref());
+        score += port_specificity(rule.remote_ports.as_ref());
+        // ICMP 类型和代码与单个端口同等具体
+        if rule.icmp_type.is_some() {
+            score += PORT_SPECIFICITY;
+        }
+        if rule.icmp_code.is_some() {
+            score += PORT_SPECIFICITY;
+        }
+        if rule.protocol.is_some_and(|p| p.ip_protocol().is_some()) {
+            score += PROTOCOL_SPECIFICITY;
+        }
+        score
+    }
+}
+
+// 按地址列表实际表示的地址数量折算到 0-256：单个地址得满分，地址数每扩大一倍按地址族位数等比例减少，
+// 即网段按前缀长度折算；同时包含两个地址族时取较宽的一侧
+fn address_specificity(list: &AddressList) -> u32 {
+    let effective = list.effective();
+    let family_specificity = |family: IpSet, width: u32| {
+        let size = effective.intersection(&family).size();
+        let bits = u128::BITS - size.saturating_sub(1).leading_zeros(); // ceil(log2(size))
+        ADDRESS_SPECIFICITY * width.saturating_sub(bits) / width
+    };
+    match (effective.has_v4(), effective.has_v6()) {
+        (true, false) => family_specificity(IpSet::all_v4(), 32),
+        (false, true) => family_specificity(IpSet::all_v6(), 128),
+        (true, true) => family_specificity(IpSet::all_v4(), 32).min(family_specificity(IpSet::all_v6(), 128)),
+        (false, false) => 0,
+    }
+}
+
+// 单个端口得满分，端口数每扩大一倍减少 4 分，覆盖全部 65536 个端口时为 0
+fn port_specificity(ports: Option<&PortList>) -> u32 {
+    let Some(size) = ports.map(PortList::size) else { return 0 };
+    let bits = u32::BITS - size.saturating_sub(1).leading_zeros(); // ceil(log2(size))
+    PORT_SPECIFICITY.saturating_sub(bits * 4)
+}